@@ -0,0 +1,50 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use stoctopus::Board;
+
+/// Random playout to the end of the game, the same loop `simulate` runs.
+fn playout(mut board: Board, rng: &mut StdRng, get_moves: fn(&Board) -> u128) -> Board {
+    while !board.game_over() {
+        let moves = get_moves(&board);
+        let k = rng.gen_range(0..moves.count_ones());
+        let index = (0..81u8)
+            .filter(|i| moves & (1 << i) != 0)
+            .nth(k as usize)
+            .unwrap();
+        board = board.unchecked_play(Board::move_from_index(index));
+    }
+    board
+}
+
+fn bench_playout(c: &mut Criterion) {
+    let mut group = c.benchmark_group("playout");
+    let mut rng = StdRng::seed_from_u64(0);
+    group.bench_function("cached", |b| {
+        b.iter(|| playout(black_box(Board::default()), &mut rng, Board::get_moves))
+    });
+    let mut rng = StdRng::seed_from_u64(0);
+    group.bench_function("uncached", |b| {
+        b.iter(|| {
+            playout(
+                black_box(Board::default()),
+                &mut rng,
+                Board::get_moves_uncached,
+            )
+        })
+    });
+    group.finish();
+
+    let boards: Vec<Board> = (0..64)
+        .map(|_| Board::random_position(60, &mut rng))
+        .collect();
+    c.bench_function("global_board_mask", |b| {
+        b.iter(|| {
+            boards
+                .iter()
+                .fold(0, |acc, board| acc ^ black_box(board).global_board_mask())
+        })
+    });
+}
+
+criterion_group!(benches, bench_playout);
+criterion_main!(benches);
@@ -0,0 +1,10 @@
+fn main() {
+    // The gRPC service is generated from `proto/stoctopus.proto`, with a
+    // bundled `protoc` so building doesn't need one installed.
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("Bundled protoc");
+        std::env::set_var("PROTOC", protoc);
+        tonic_build::compile_protos("proto/stoctopus.proto").expect("Valid proto file");
+    }
+}
@@ -0,0 +1,95 @@
+//! Plays the move sequence encoded by the input (each byte picks one of the
+//! currently legal moves) and checks the board invariants after every ply.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use stoctopus::{Board, GameState};
+
+const ALL_CELLS: u128 = 0x1ffffffffffffffffffff;
+const LINES: [u16; 8] = [
+    0b111_000_000,
+    0b000_111_000,
+    0b000_000_111,
+    0b100_100_100,
+    0b010_010_010,
+    0b001_001_001,
+    0b100_010_001,
+    0b001_010_100,
+];
+
+fn has_line(bits: u16) -> bool {
+    LINES.iter().any(|line| bits & line == *line)
+}
+
+fn local_bits(cells: u128, global: u8) -> u16 {
+    ((cells >> (global * 9)) & 0b111_111_111) as u16
+}
+
+/// Boards that are won or full, computed from the cells alone.
+fn completed_boards(board: &Board) -> u16 {
+    (0..9)
+        .filter(|&g| {
+            let (x, o) = (local_bits(board.x, g), local_bits(board.o, g));
+            has_line(x) || has_line(o) || x | o == 0b111_111_111
+        })
+        .fold(0, |acc, g| acc | (1 << g))
+}
+
+fn check_invariants(board: &Board) {
+    assert_eq!(board.x & board.o, 0, "x and o overlap");
+    assert_eq!((board.x | board.o) & !ALL_CELLS, 0, "cells outside the grid");
+
+    for g in 0..9 {
+        let (x, o) = (local_bits(board.x, g), local_bits(board.o, g));
+        let bit = 1 << g;
+        if has_line(x) {
+            assert!(board.gx & bit != 0 && board.go & bit == 0, "board {g} won by X");
+        } else if has_line(o) {
+            assert!(board.go & bit != 0 && board.gx & bit == 0, "board {g} won by O");
+        } else if x | o == 0b111_111_111 {
+            assert!(board.gx & bit != 0 && board.go & bit != 0, "board {g} drawn");
+        } else {
+            assert!((board.gx | board.go) & bit == 0, "board {g} in progress");
+        }
+    }
+
+    let completed = completed_boards(board);
+    let open_boards = (0..9u8)
+        .filter(|g| completed & (1 << g) == 0)
+        .fold(0u128, |acc, g| acc | (0b111_111_111 << (g * 9)));
+    let allowed = match board.last_move {
+        Some(m) if completed & (1 << (m & 0b1111)) == 0 => {
+            0b111_111_111u128 << ((m & 0b1111) * 9)
+        }
+        _ => open_boards,
+    };
+    let empty = !(board.x | board.o) & ALL_CELLS;
+    let moves = board.get_moves();
+    assert_eq!(moves & !(empty & allowed), 0, "move outside allowed region");
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut board = Board::default();
+    let mut was_over = false;
+
+    for &choice in data {
+        check_invariants(&board);
+
+        let over = board.game_over();
+        assert!(!was_over || over, "game_over went from true to false");
+        was_over = over;
+
+        let moves = board.get_moves();
+        if moves == 0 {
+            assert!(!matches!(board.check_game_state(), GameState::InProgress));
+            break;
+        }
+        let k = choice as u32 % moves.count_ones();
+        let index = (0..81u8)
+            .filter(|i| moves & (1 << i) != 0)
+            .nth(k as usize)
+            .unwrap();
+        board = board.unchecked_play(Board::move_from_index(index));
+    }
+});
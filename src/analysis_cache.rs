@@ -0,0 +1,163 @@
+//! Results of earlier searches, keyed by position, so that analysing a
+//! position again (e.g. while reviewing a game) doesn't start from scratch.
+//!
+//! The cache lives outside the search tree and survives
+//! [`Engine::new_game`](crate::Engine::new_game). It is written as a flat
+//! little-endian file: the magic bytes, the entry count as a `u64`, then per
+//! entry the position hash (`u64`), best move (`u8`), wins and visits of the
+//! best move (`f32` each) and the search's iteration count (`u32`).
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use crate::error::StoctopusError;
+
+const MAGIC: &[u8; 4] = b"STAC";
+
+/// What a search found for one position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CacheEntry {
+    pub best_move: u8,
+    /// Wins of the best move, from the perspective of the side to move.
+    pub wins: f32,
+    pub best_visits: f32,
+    /// Iterations the position was searched for.
+    pub iterations: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AnalysisCache {
+    entries: HashMap<u64, CacheEntry>,
+}
+
+impl AnalysisCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, hash: u64) -> Option<&CacheEntry> {
+        self.entries.get(&hash)
+    }
+
+    /// Stores `entry` unless a deeper search of the position is known.
+    pub fn insert(&mut self, hash: u64, entry: CacheEntry) {
+        match self.entries.get(&hash) {
+            Some(old) if old.iterations >= entry.iterations => {}
+            _ => {
+                self.entries.insert(hash, entry);
+            }
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), StoctopusError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for (hash, entry) in &self.entries {
+            writer.write_all(&hash.to_le_bytes())?;
+            writer.write_all(&[entry.best_move])?;
+            writer.write_all(&entry.wins.to_le_bytes())?;
+            writer.write_all(&entry.best_visits.to_le_bytes())?;
+            writer.write_all(&entry.iterations.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, StoctopusError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(StoctopusError::Protocol(
+                "Not an analysis cache file".to_string(),
+            ));
+        }
+        let mut buf = [0; 8];
+        reader.read_exact(&mut buf)?;
+        let len = u64::from_le_bytes(buf);
+
+        let mut cache = Self::new();
+        for _ in 0..len {
+            let mut entry = [0; 21];
+            reader.read_exact(&mut entry)?;
+            let hash = u64::from_le_bytes(entry[0..8].try_into().expect("8 bytes"));
+            let entry = CacheEntry {
+                best_move: entry[8],
+                wins: f32::from_le_bytes(entry[9..13].try_into().expect("4 bytes")),
+                best_visits: f32::from_le_bytes(entry[13..17].try_into().expect("4 bytes")),
+                iterations: u32::from_le_bytes(entry[17..21].try_into().expect("4 bytes")),
+            };
+            // The win rate divides by the visits.
+            let visited = entry.best_visits.is_finite() && entry.best_visits > 0.0;
+            if !visited || !(0.0..=entry.best_visits).contains(&entry.wins) {
+                return Err(StoctopusError::Protocol(format!(
+                    "Analysis cache entry {hash:#x} has {} wins in {} visits",
+                    entry.wins, entry.best_visits
+                )));
+            }
+            cache.entries.insert(hash, entry);
+        }
+        Ok(cache)
+    }
+}
+
+#[cfg(test)]
+mod analysis_cache_tests {
+    use crate::analysis_cache::{AnalysisCache, CacheEntry};
+    use crate::StoctopusError;
+
+    fn entry(iterations: u32) -> CacheEntry {
+        CacheEntry {
+            best_move: 0x44,
+            wins: 3.5,
+            best_visits: 6.0,
+            iterations,
+        }
+    }
+
+    #[test]
+    fn test_keeps_deepest() {
+        let mut cache = AnalysisCache::new();
+        cache.insert(1, entry(100));
+        cache.insert(1, entry(50));
+        assert_eq!(cache.get(1).unwrap().iterations, 100);
+        cache.insert(1, entry(200));
+        assert_eq!(cache.get(1).unwrap().iterations, 200);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut cache = AnalysisCache::new();
+        cache.insert(1, entry(100));
+        cache.insert(u64::MAX, entry(7));
+        let mut bytes = vec![];
+        cache.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 4 + 8 + 2 * 21);
+        assert_eq!(AnalysisCache::read_from(&mut &bytes[..]).unwrap(), cache);
+
+        let mut unvisited = AnalysisCache::new();
+        unvisited.insert(
+            1,
+            CacheEntry {
+                wins: 0.0,
+                best_visits: 0.0,
+                ..entry(100)
+            },
+        );
+        let mut zero = vec![];
+        unvisited.write_to(&mut zero).unwrap();
+        assert!(AnalysisCache::read_from(&mut &zero[..]).is_err());
+
+        bytes[0] = b'X';
+        assert!(matches!(
+            AnalysisCache::read_from(&mut &bytes[..]),
+            Err(StoctopusError::Protocol(_))
+        ));
+    }
+}
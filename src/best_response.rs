@@ -0,0 +1,451 @@
+//! Searching for the best reply to a known opponent rather than a perfect
+//! one, for bots meant to beat a particular book, heuristic or player.
+//!
+//! The search is a UCT tree over the moves of the side to move, while the
+//! opponent's moves are drawn from an [`OpponentPolicy`], in the tree and in
+//! playouts alike. Its value is what the best response scores against that
+//! policy; how much that beats the engine's own evaluation, which assumes a
+//! strong opponent, is the policy's exploitability, see
+//! [`crate::Engine::best_response`].
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::error::SearchError;
+use crate::eval;
+use crate::game::{find_kth_high_bit_index, Board, GameState, Player};
+use crate::probe::BookProvider;
+use crate::record::GameRecord;
+use crate::Evaluation;
+
+/// How an opponent picks its moves.
+pub trait OpponentPolicy: Send + Sync {
+    /// Weights of the opponent's moves in `board`, which needn't sum to 1.
+    /// Illegal moves are ignored, and without weight on any legal move the
+    /// opponent plays uniformly at random.
+    fn policy(&self, board: &Board) -> Vec<(u8, f32)>;
+}
+
+/// Plays from an opening book, at random once out of book.
+pub struct BookPolicy(pub Arc<dyn BookProvider>);
+
+impl OpponentPolicy for BookPolicy {
+    fn policy(&self, board: &Board) -> Vec<(u8, f32)> {
+        self.0
+            .probe_all(board)
+            .into_iter()
+            .map(|(m, weight)| (m, weight as f32))
+            .collect()
+    }
+}
+
+/// Plays in proportion to the engine's move ordering heuristic, like a
+/// player going by intuition alone.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeuristicPolicy;
+
+impl OpponentPolicy for HeuristicPolicy {
+    fn policy(&self, board: &Board) -> Vec<(u8, f32)> {
+        let moves: Vec<u8> = legal_moves(board).collect();
+        let priors = eval::move_priors(board, &moves);
+        moves.into_iter().zip(priors).collect()
+    }
+}
+
+/// Plays the moves a player was seen to play, as often as they played
+/// them. Random in positions it has no record of.
+#[derive(Clone, Debug, Default)]
+pub struct RecordedPolicy {
+    /// Move counts by Zobrist hash of the position.
+    moves: HashMap<u64, Vec<(u8, f32)>>,
+}
+
+impl RecordedPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Positions with at least one recorded move.
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    /// Counts `mve` being played in `board`.
+    pub fn record(&mut self, board: &Board, mve: u8) {
+        let moves = self.moves.entry(board.zobrist_hash()).or_default();
+        match moves.iter_mut().find(|(m, _)| *m == mve) {
+            Some((_, count)) => *count += 1.0,
+            None => moves.push((mve, 1.0)),
+        }
+    }
+
+    /// Counts the moves `player` made in the main line of `record`, up to
+    /// the first illegal one.
+    pub fn learn(&mut self, record: &GameRecord, player: Player) {
+        let mut board = Board::with_rules(record.rules);
+        for &(global, local) in &record.moves {
+            if !is_legal(&board, Board::move_from_gl(global, local)) {
+                break;
+            }
+            let m = Board::move_from_gl(global, local);
+            if board.next_player == player {
+                self.record(&board, m);
+            }
+            board = board.unchecked_play(m);
+        }
+    }
+}
+
+impl OpponentPolicy for RecordedPolicy {
+    fn policy(&self, board: &Board) -> Vec<(u8, f32)> {
+        self.moves
+            .get(&board.zobrist_hash())
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BestResponse {
+    pub iterations: u32,
+    /// UCB1 exploration constant for the moves of the side to move.
+    pub exploration: f32,
+    pub seed: u64,
+}
+
+impl Default for BestResponse {
+    fn default() -> Self {
+        Self {
+            iterations: 10_000,
+            exploration: std::f32::consts::SQRT_2,
+            seed: 0,
+        }
+    }
+}
+
+/// Outcome of a [`BestResponse`] search.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BestResponseResult {
+    /// `(global, local)` coordinates.
+    pub best_move: (u8, u8),
+    /// Win percentage of the side to move with the best move against the
+    /// policy. Draws count as losses, as in [`Evaluation::confidence`].
+    pub win_rate: f32,
+    /// Root moves as `((global, local), visits, win percentage)`, most
+    /// visited first.
+    pub moves: Vec<((u8, u8), u32, f32)>,
+}
+
+/// What [`crate::Engine::best_response`] found.
+#[derive(Debug)]
+pub struct Exploitation {
+    pub response: BestResponseResult,
+    /// The engine's own analysis of the position.
+    pub evaluation: Evaluation,
+    /// Percentage points the best response scores above
+    /// [`Evaluation::confidence`]. Negative when the search budget was too
+    /// small for the best response to find anything better.
+    pub exploitability: f32,
+}
+
+impl BestResponse {
+    /// Searches for the best reply of `board`'s side to move when the
+    /// opponent plays `policy`.
+    pub fn run(
+        &self,
+        board: &Board,
+        policy: &dyn OpponentPolicy,
+    ) -> Result<BestResponseResult, SearchError> {
+        if board.game_over() {
+            return Err(SearchError::GameOver);
+        }
+        let mut driver = Driver {
+            player: board.next_player,
+            policy,
+            exploration: self.exploration,
+            rng: StdRng::seed_from_u64(self.seed),
+            nodes: vec![Node::new(*board)],
+        };
+        for _ in 0..self.iterations.max(1) {
+            driver.iterate()?;
+        }
+
+        let mut moves: Vec<_> = driver.nodes[0]
+            .children
+            .iter()
+            .map(|&(m, child)| {
+                let child = &driver.nodes[child];
+                let win_rate = child.wins / child.visits.max(1.0) * 100.0;
+                ((m >> 4, m & 0b1111), child.visits as u32, win_rate)
+            })
+            .collect();
+        moves.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.total_cmp(&a.2)));
+        let &(best_move, _, win_rate) = moves.first().ok_or(SearchError::NoChildren)?;
+        Ok(BestResponseResult {
+            best_move,
+            win_rate,
+            moves,
+        })
+    }
+}
+
+struct Node {
+    board: Board,
+    visits: f32,
+    /// Wins of the searching player.
+    wins: f32,
+    children: Vec<(u8, usize)>,
+}
+
+impl Node {
+    fn new(board: Board) -> Self {
+        Self {
+            board,
+            visits: 0.0,
+            wins: 0.0,
+            children: vec![],
+        }
+    }
+}
+
+struct Driver<'a> {
+    player: Player,
+    policy: &'a dyn OpponentPolicy,
+    exploration: f32,
+    rng: StdRng,
+    nodes: Vec<Node>,
+}
+
+impl Driver<'_> {
+    fn iterate(&mut self) -> Result<(), SearchError> {
+        let mut path = vec![0];
+        let mut id = 0;
+        // Down to a terminal or new node, which the playout starts from.
+        while !self.nodes[id].board.game_over() && (id == 0 || self.nodes[id].visits > 0.0) {
+            let board = self.nodes[id].board;
+            let m = if board.next_player == self.player {
+                self.select(id)?
+            } else {
+                self.opponent_move(&board)?
+            };
+            id = self.child(id, m);
+            path.push(id);
+        }
+        let result = self.playout(self.nodes[id].board)?;
+        for id in path {
+            self.nodes[id].visits += 1.0;
+            self.nodes[id].wins += result;
+        }
+        Ok(())
+    }
+
+    /// UCB1 over the moves of the searching player, untried moves first.
+    fn select(&mut self, id: usize) -> Result<u8, SearchError> {
+        let node = &self.nodes[id];
+        let untried: Vec<u8> = legal_moves(&node.board)
+            .filter(|m| node.children.iter().all(|(c, _)| c != m))
+            .collect();
+        if !untried.is_empty() {
+            return Ok(untried[self.rng.gen_range(0..untried.len())]);
+        }
+        let log_visits = node.visits.max(1.0).ln();
+        node.children
+            .iter()
+            .map(|&(m, child)| {
+                let child = &self.nodes[child];
+                let visits = child.visits.max(1.0);
+                let score = child.wins / visits + self.exploration * (log_visits / visits).sqrt();
+                (m, score)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(m, _)| m)
+            .ok_or(SearchError::NoChildren)
+    }
+
+    fn child(&mut self, id: usize, m: u8) -> usize {
+        if let Some(&(_, child)) = self.nodes[id].children.iter().find(|(c, _)| *c == m) {
+            return child;
+        }
+        let board = self.nodes[id].board.unchecked_play(m);
+        self.nodes.push(Node::new(board));
+        let child = self.nodes.len() - 1;
+        self.nodes[id].children.push((m, child));
+        child
+    }
+
+    fn opponent_move(&mut self, board: &Board) -> Result<u8, SearchError> {
+        let weighted: Vec<(u8, f32)> = self
+            .policy
+            .policy(board)
+            .into_iter()
+            .filter(|&(m, weight)| weight > 0.0 && is_legal(board, m))
+            .collect();
+        let total: f32 = weighted.iter().map(|(_, weight)| weight).sum();
+        if total > 0.0 {
+            let mut target = self.rng.gen::<f32>() * total;
+            for &(m, weight) in &weighted {
+                target -= weight;
+                if target < 0.0 {
+                    return Ok(m);
+                }
+            }
+            // Rounding left a little of the total over.
+            return Ok(weighted[weighted.len() - 1].0);
+        }
+        self.random_move(board)
+    }
+
+    fn random_move(&mut self, board: &Board) -> Result<u8, SearchError> {
+        let moves = board.get_moves();
+        let k = self.rng.gen_range(0..moves.count_ones());
+        let index = find_kth_high_bit_index(moves, k).ok_or(SearchError::NoMove)?;
+        Ok(Board::move_from_index(index))
+    }
+
+    /// 1 for a win of the searching player, else 0. Its own moves are
+    /// random, the opponent's follow the policy.
+    fn playout(&mut self, mut board: Board) -> Result<f32, SearchError> {
+        while !board.game_over() {
+            let m = if board.next_player == self.player {
+                self.random_move(&board)?
+            } else {
+                self.opponent_move(&board)?
+            };
+            board = board.unchecked_play(m);
+        }
+        Ok(match board.check_game_state() {
+            GameState::Won(winner) if winner == self.player => 1.0,
+            _ => 0.0,
+        })
+    }
+}
+
+fn legal_moves(board: &Board) -> impl Iterator<Item = u8> {
+    let moves = board.get_moves();
+    (0..81)
+        .filter(move |i| moves & (1 << i) != 0)
+        .map(Board::move_from_index)
+}
+
+fn is_legal(board: &Board, m: u8) -> bool {
+    let (global, local) = (m >> 4, m & 0b1111);
+    global < 9 && local < 9 && board.get_moves() & (1 << (global * 9 + local)) != 0
+}
+
+#[cfg(test)]
+mod best_response_tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::best_response::{BestResponse, HeuristicPolicy, OpponentPolicy, RecordedPolicy};
+    use crate::game::{Board, GameState, Player};
+    use crate::record::GameRecord;
+    use crate::{Engine, MCTSConfig, Rules, SearchError, SearchMode};
+
+    /// Always the first legal move, as predictable as an opponent gets.
+    struct FirstMove;
+
+    impl OpponentPolicy for FirstMove {
+        fn policy(&self, board: &Board) -> Vec<(u8, f32)> {
+            let moves = board.get_moves();
+            let first = (0..81).find(|i| moves & (1 << i) != 0);
+            first
+                .map(|i| (Board::move_from_index(i), 1.0))
+                .into_iter()
+                .collect()
+        }
+    }
+
+    /// Whether the side to move in `board` beats [`FirstMove`].
+    fn beats_first_move(board: &Board, player: Player) -> bool {
+        if board.game_over() {
+            return board.check_game_state() == GameState::Won(player);
+        }
+        let moves = board.get_moves();
+        if board.next_player != player {
+            return beats_first_move(&board.unchecked_play(FirstMove.policy(board)[0].0), player);
+        }
+        (0..81)
+            .filter(|i| moves & (1 << i) != 0)
+            .any(|i| beats_first_move(&board.unchecked_play(Board::move_from_index(i)), player))
+    }
+
+    /// A few plies before the end of a random game, where the side to move
+    /// can beat [`FirstMove`].
+    fn exploitable_position() -> Board {
+        let mut rng = StdRng::seed_from_u64(1);
+        loop {
+            let mut line = vec![Board::default()];
+            while !line.last().unwrap().game_over() {
+                let board = line.last().unwrap();
+                let moves = board.get_moves();
+                let index = (0..81)
+                    .filter(|i| moves & (1 << i) != 0)
+                    .nth(rng.gen_range(0..moves.count_ones()) as usize);
+                line.push(board.unchecked_play(Board::move_from_index(index.unwrap())));
+            }
+            let board = line[line.len().saturating_sub(6)];
+            if !board.game_over() && beats_first_move(&board, board.next_player) {
+                return board;
+            }
+        }
+    }
+
+    #[test]
+    fn test_exploits_predictable_opponent() {
+        let board = exploitable_position();
+        let search = BestResponse {
+            iterations: 2000,
+            seed: 1,
+            ..Default::default()
+        };
+        let result = search.run(&board, &FirstMove).unwrap();
+        assert!(result.win_rate > 90.0, "{}", result.win_rate);
+        assert_eq!(result.moves[0].0, result.best_move);
+        assert!(result.moves.windows(2).all(|w| w[0].1 >= w[1].1));
+        let (global, local) = result.best_move;
+        let after = board.unchecked_play(Board::move_from_gl(global, local));
+        assert!(beats_first_move(&after, board.next_player));
+
+        let mut end = after;
+        while !end.game_over() {
+            end = end.unchecked_play(FirstMove.policy(&end)[0].0);
+        }
+        assert_eq!(search.run(&end, &FirstMove), Err(SearchError::GameOver));
+    }
+
+    #[test]
+    fn test_exploitability() {
+        let mut engine = Engine::with_config(MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 1 },
+            ..MCTSConfig::default()
+        });
+        engine.play((4, 4)).unwrap();
+        let board = *engine.board();
+        let exploitation = engine.best_response(&HeuristicPolicy, 300).unwrap();
+        assert_eq!(exploitation.evaluation.board, board);
+        assert_eq!(
+            exploitation.exploitability,
+            exploitation.response.win_rate - exploitation.evaluation.confidence
+        );
+        assert!((0.0..=100.0).contains(&exploitation.response.win_rate));
+    }
+
+    #[test]
+    fn test_recorded_policy() {
+        let mut record = GameRecord::new(Rules::default());
+        record.moves = vec![(4, 4), (4, 0), (0, 4), (4, 8)];
+        let mut policy = RecordedPolicy::new();
+        policy.learn(&record, Player::O);
+        policy.learn(&record, Player::O);
+        assert_eq!(policy.len(), 2);
+        let after_center = Board::default().unchecked_play(0x44);
+        assert_eq!(policy.policy(&after_center), vec![(0x40, 2.0)]);
+        assert!(policy.policy(&Board::default()).is_empty());
+    }
+}
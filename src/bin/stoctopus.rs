@@ -0,0 +1,54 @@
+//! UGI engine binary: reads commands from stdin and answers on stdout.
+//! `--config <file>` sets the engine up from a TOML file, see
+//! [`EngineConfig`]. `--relay` comments on a game relayed on stdin instead
+//! of playing, see [`stoctopus::relay`].
+
+use std::io::{BufRead, Write};
+
+use stoctopus::relay::Relay;
+use stoctopus::ugi::Ugi;
+use stoctopus::{EngineConfig, StoctopusError};
+
+fn main() -> Result<(), StoctopusError> {
+    let usage = || StoctopusError::Protocol("Usage: stoctopus [--config <file>] [--relay]".into());
+    let mut config = EngineConfig::default();
+    let mut relay = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config = EngineConfig::from_toml(args.next().ok_or_else(usage)?)?,
+            "--relay" => relay = true,
+            _ => return Err(usage()),
+        }
+    }
+
+    if relay {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(config.threads())
+            .build()
+            .map_err(StoctopusError::ThreadPool)?;
+        let mut relay = Relay::new(config.engine()?);
+        let stdin = std::io::BufReader::new(std::io::stdin());
+        return pool.install(|| relay.run(stdin, std::io::stdout()));
+    }
+
+    let mut ugi = Ugi::with_config(&config)?;
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if line.trim() == "quit" {
+            break;
+        }
+        match ugi.handle(&line) {
+            Ok(answer) => {
+                for out in answer {
+                    writeln!(stdout, "{out}")?;
+                }
+            }
+            Err(err) => writeln!(stdout, "info string {err}")?,
+        }
+        stdout.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,428 @@
+//! Opening books in a compact binary format that is searched in place, so
+//! a book can be memory-mapped instead of loaded into RAM.
+//!
+//! Layout, all integers little-endian:
+//!
+//! - header: magic `STBK`, format version (`u32`), entry count (`u64`)
+//! - entries of 12 bytes, sorted by hash and then by descending weight:
+//!   position hash (`u64`, [`Board::zobrist_hash`]), weight (`u16`), move
+//!   (`u8`, `(global << 4) | local`) and a reserved zero byte
+//!
+//! A position may have several entries, one per book move.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rayon::prelude::*;
+
+use crate::error::StoctopusError;
+use crate::game::Board;
+use crate::probe::BookProvider;
+use crate::symmetry::SYMMETRIES;
+use crate::Engine;
+
+const MAGIC: &[u8; 4] = b"STBK";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 16;
+const ENTRY_LEN: usize = 12;
+
+enum BookData {
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped(memmap2::Mmap),
+    Owned(Vec<u8>),
+}
+
+impl BookData {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Mapped(map) => map,
+            Self::Owned(bytes) => bytes,
+        }
+    }
+}
+
+pub struct OpeningBook {
+    data: BookData,
+    len: usize,
+}
+
+fn invalid(msg: &str) -> StoctopusError {
+    StoctopusError::Protocol(format!("Invalid book: {msg}"))
+}
+
+impl OpeningBook {
+    /// Memory-maps the book at `path`. Only the header is checked, see
+    /// [`Self::verify`]. The file must not be modified while the book is
+    /// open.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoctopusError> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: Books are written once and then only read; modifying the
+        // file while it is mapped is ruled out in the documentation above.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Self::new(BookData::Mapped(map))
+    }
+
+    /// Book held in memory, e.g. when memory-mapping isn't available.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, StoctopusError> {
+        Self::new(BookData::Owned(bytes))
+    }
+
+    fn new(data: BookData) -> Result<Self, StoctopusError> {
+        let bytes = data.bytes();
+        if bytes.len() < HEADER_LEN || &bytes[0..4] != MAGIC {
+            return Err(invalid("missing header"));
+        }
+        let version = u32::from_le_bytes(bytes[4..8].try_into().expect("4 bytes"));
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {version}")));
+        }
+        let count = u64::from_le_bytes(bytes[8..16].try_into().expect("8 bytes"));
+        // The count may be corrupt, so count the entries instead of
+        // multiplying it out.
+        let (len, rest) = (
+            (bytes.len() - HEADER_LEN) / ENTRY_LEN,
+            (bytes.len() - HEADER_LEN) % ENTRY_LEN,
+        );
+        if rest != 0 || len as u64 != count {
+            return Err(invalid("length doesn't match the entry count"));
+        }
+        Ok(Self { data, len })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn hash_at(&self, index: usize) -> u64 {
+        let start = HEADER_LEN + index * ENTRY_LEN;
+        u64::from_le_bytes(
+            self.data.bytes()[start..start + 8]
+                .try_into()
+                .expect("8 bytes"),
+        )
+    }
+
+    /// `(move, weight)` of entry `index`.
+    fn entry_at(&self, index: usize) -> (u8, u16) {
+        let entry = &self.data.bytes()[HEADER_LEN + index * ENTRY_LEN..][..ENTRY_LEN];
+        let weight = u16::from_le_bytes(entry[8..10].try_into().expect("2 bytes"));
+        (entry[10], weight)
+    }
+
+    /// Book moves of the position with `hash` and their weights, heaviest
+    /// first.
+    pub fn moves(&self, hash: u64) -> Vec<(u8, u16)> {
+        // First entry whose hash isn't smaller than `hash`.
+        let (mut lo, mut hi) = (0, self.len);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.hash_at(mid) < hash {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        (lo..self.len)
+            .take_while(|&i| self.hash_at(i) == hash)
+            .map(|i| self.entry_at(i))
+            .collect()
+    }
+
+    /// Checks every entry: sorted order, reserved bytes and move encoding.
+    pub fn verify(&self) -> Result<(), StoctopusError> {
+        let mut previous: Option<(u64, u16)> = None;
+        for i in 0..self.len {
+            let hash = self.hash_at(i);
+            let (mve, weight) = self.entry_at(i);
+            let reserved = self.data.bytes()[HEADER_LEN + i * ENTRY_LEN + 11];
+            if reserved != 0 {
+                return Err(invalid(&format!("entry {i} has a nonzero reserved byte")));
+            }
+            if mve >> 4 > 8 || mve & 0b1111 > 8 {
+                return Err(invalid(&format!("entry {i} has move {mve:#04x}")));
+            }
+            if let Some((prev_hash, prev_weight)) = previous {
+                if prev_hash > hash || (prev_hash == hash && prev_weight < weight) {
+                    return Err(invalid(&format!("entry {i} is out of order")));
+                }
+            }
+            previous = Some((hash, weight));
+        }
+        Ok(())
+    }
+}
+
+impl BookProvider for OpeningBook {
+    fn probe(&self, board: &Board) -> Option<u8> {
+        self.moves(board.zobrist_hash())
+            .first()
+            .map(|&(mve, _)| mve)
+    }
+
+    fn probe_all(&self, board: &Board) -> Vec<(u8, u16)> {
+        self.moves(board.zobrist_hash())
+    }
+}
+
+/// Collects book moves and writes them in the book format.
+#[derive(Default)]
+pub struct BookBuilder {
+    /// Weight per position hash and move.
+    entries: HashMap<(u64, u8), u16>,
+}
+
+impl BookBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `mve` as a book move of `board`. Adding the same move again
+    /// adds up the weights.
+    pub fn add(&mut self, board: &Board, mve: u8, weight: u16) {
+        let entry = self.entries.entry((board.zobrist_hash(), mve)).or_default();
+        *entry = entry.saturating_add(weight);
+    }
+
+    /// Adds the entries of `other`, adding up the weights of moves both
+    /// have.
+    pub fn merge(&mut self, other: BookBuilder) {
+        for (key, weight) in other.entries {
+            let entry = self.entries.entry(key).or_default();
+            *entry = entry.saturating_add(weight);
+        }
+    }
+
+    /// Adds `mve` in `board` and in every position symmetric to it, each
+    /// entry once even where symmetries coincide.
+    fn add_symmetric(&mut self, board: &Board, mve: u8, weight: u16) {
+        let mut seen = HashSet::new();
+        for symmetry in 0..SYMMETRIES.len() {
+            let transformed = board.transformed(symmetry);
+            let mve = board
+                .unchecked_play(mve)
+                .transformed(symmetry)
+                .last_move
+                .expect("A move was just played");
+            if seen.insert((transformed.zobrist_hash(), mve)) {
+                self.add(&transformed, mve, weight);
+            }
+        }
+    }
+
+    pub fn write_to<W: Write>(self, writer: &mut W) -> Result<(), StoctopusError> {
+        let mut entries: Vec<_> = self.entries.into_iter().collect();
+        entries.sort_by(|((hash_a, mve_a), weight_a), ((hash_b, mve_b), weight_b)| {
+            hash_a
+                .cmp(hash_b)
+                .then(weight_b.cmp(weight_a))
+                .then(mve_a.cmp(mve_b))
+        });
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+        for ((hash, mve), weight) in entries {
+            writer.write_all(&hash.to_le_bytes())?;
+            writer.write_all(&weight.to_le_bytes())?;
+            writer.write_all(&[mve, 0])?;
+        }
+        Ok(())
+    }
+}
+
+/// How far [`Engine::build_book`] has got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BookProgress {
+    /// Plies from the start position of the positions being searched.
+    pub depth: u32,
+    /// Positions searched at this depth so far.
+    pub searched: usize,
+    /// Positions to search at this depth.
+    pub positions: usize,
+}
+
+impl Engine {
+    /// Builds a book by searching the current position for `iterations`
+    /// iterations, then the positions after its `width` most visited
+    /// moves, and so on for `depth` plies. Book weights are root visits.
+    ///
+    /// Each depth is searched in parallel on the current rayon pool, whose
+    /// idle threads steal positions from busy ones. Positions are searched
+    /// once per symmetry class and their moves entered for every symmetric
+    /// position. Every thread fills a builder of its own, merged at the end
+    /// of each depth. `progress` is called after every search.
+    pub fn build_book(
+        &self,
+        depth: u32,
+        width: usize,
+        iterations: u32,
+        progress: impl Fn(BookProgress) + Sync,
+    ) -> Result<BookBuilder, StoctopusError> {
+        let mut book = BookBuilder::new();
+        let mut frontier = vec![*self.board()];
+        for ply in 0..depth {
+            frontier.retain(|board| !board.game_over());
+            let searched = AtomicUsize::new(0);
+            let positions = frontier.len();
+            let (builder, next) = frontier
+                .par_iter()
+                .map(|&board| -> Result<_, StoctopusError> {
+                    let mut engine = self.engine_for(board)?;
+                    let mut visits = engine.analyze(iterations)?.root_visits;
+                    visits.sort_by_key(|&(mve, visits)| (std::cmp::Reverse(visits), mve));
+                    visits.truncate(width);
+                    progress(BookProgress {
+                        depth: ply,
+                        searched: searched.fetch_add(1, Ordering::Relaxed) + 1,
+                        positions,
+                    });
+                    Ok((board, visits))
+                })
+                .try_fold(
+                    || (BookBuilder::new(), vec![]),
+                    |(mut builder, mut next), result| -> Result<_, StoctopusError> {
+                        let (board, visits) = result?;
+                        for (mve, visits) in visits {
+                            let weight = visits.min(u16::MAX as u32) as u16;
+                            builder.add_symmetric(&board, mve, weight);
+                            next.push(board.unchecked_play(mve));
+                        }
+                        Ok((builder, next))
+                    },
+                )
+                .try_reduce(
+                    || (BookBuilder::new(), vec![]),
+                    |(mut builder, mut next), (other, other_next)| {
+                        builder.merge(other);
+                        next.extend(other_next);
+                        Ok((builder, next))
+                    },
+                )?;
+            book.merge(builder);
+
+            let mut classes = HashSet::new();
+            frontier = next
+                .into_iter()
+                .filter(|board| classes.insert(board.canonical_hash()))
+                .collect();
+        }
+        Ok(book)
+    }
+}
+
+#[cfg(test)]
+mod book_tests {
+    use std::sync::Mutex;
+
+    use crate::book::{BookBuilder, BookProgress, OpeningBook, HEADER_LEN};
+    use crate::game::Board;
+    use crate::probe::BookProvider;
+    use crate::Engine;
+
+    fn book_bytes() -> Vec<u8> {
+        let start = Board::default();
+        let center = Board::move_from_gl(4, 4);
+        let mut builder = BookBuilder::new();
+        builder.add(&start, Board::move_from_gl(0, 0), 5);
+        builder.add(&start, center, 10);
+        builder.add(&start, center, 3);
+        let reply = start.unchecked_play(center);
+        builder.add(&reply, Board::move_from_gl(4, 0), 1);
+        let mut bytes = vec![];
+        builder.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_build_and_probe() {
+        let book = OpeningBook::from_bytes(book_bytes()).unwrap();
+        book.verify().unwrap();
+        assert_eq!(book.len(), 3);
+
+        let start = Board::default();
+        let center = Board::move_from_gl(4, 4);
+        assert_eq!(
+            book.moves(start.zobrist_hash()),
+            vec![(center, 13), (Board::move_from_gl(0, 0), 5)]
+        );
+        assert_eq!(book.probe(&start), Some(center));
+        let reply = start.unchecked_play(center);
+        assert_eq!(book.probe(&reply), Some(Board::move_from_gl(4, 0)));
+        assert_eq!(book.probe(&reply.unchecked_play(0x40)), None);
+    }
+
+    #[test]
+    fn test_open_mapped() {
+        let path = std::env::temp_dir().join(format!("stoctopus-book-{}", std::process::id()));
+        std::fs::write(&path, book_bytes()).unwrap();
+        let book = OpeningBook::open(&path).unwrap();
+        assert_eq!(
+            book.probe(&Board::default()),
+            Some(Board::move_from_gl(4, 4))
+        );
+        drop(book);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_verify_rejects_corruption() {
+        let mut bytes = book_bytes();
+        bytes.pop();
+        assert!(OpeningBook::from_bytes(bytes).is_err());
+
+        // A count that wraps around to the real length when multiplied out.
+        let mut bytes = book_bytes();
+        let count = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+        bytes[8..16].copy_from_slice(&(count + (1 << 62)).to_le_bytes());
+        assert!(OpeningBook::from_bytes(bytes).is_err());
+        let mut bytes = book_bytes();
+        bytes[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(OpeningBook::from_bytes(bytes).is_err());
+
+        let mut bytes = book_bytes();
+        bytes[HEADER_LEN + 10] = 0x99;
+        let book = OpeningBook::from_bytes(bytes).unwrap();
+        assert!(book.verify().is_err());
+
+        let mut bytes = book_bytes();
+        bytes[HEADER_LEN..HEADER_LEN + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(OpeningBook::from_bytes(bytes).unwrap().verify().is_err());
+    }
+
+    #[test]
+    fn test_build_book() {
+        let reports = Mutex::new(vec![]);
+        let builder = Engine::init()
+            .build_book(2, 2, 60, |progress| reports.lock().unwrap().push(progress))
+            .unwrap();
+        let mut bytes = vec![];
+        builder.write_to(&mut bytes).unwrap();
+        let book = OpeningBook::from_bytes(bytes).unwrap();
+        book.verify().unwrap();
+
+        // One search of the start, then one of each of its two book moves
+        // up to symmetry. The replies are in the book for every symmetric
+        // position.
+        let reports = reports.into_inner().unwrap();
+        assert_eq!(reports.iter().filter(|p| p.depth == 0).count(), 1);
+        let replies: Vec<&BookProgress> = reports.iter().filter(|p| p.depth == 1).collect();
+        assert!((1..=2).contains(&replies.len()));
+        assert!(replies.iter().any(|p| p.searched == p.positions));
+
+        let start = Board::default();
+        let first = book.probe(&start).unwrap();
+        assert!(start.get_moves() & (1 << ((first >> 4) * 9 + (first & 0b1111))) != 0);
+        for symmetry in 0..8 {
+            let after = start.unchecked_play(first).transformed(symmetry);
+            assert!(book.probe(&after).is_some(), "symmetry {symmetry}");
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Runs the engine as an online bot. A [`GameTransport`] talks to the game
+//! service (long polling, a websocket, a chat bot API...); [`Bot`] keeps the
+//! engine in sync with the reported game, budgets its clock and submits
+//! moves.
+
+use std::io::{BufRead, Write};
+use std::time::Duration;
+
+use crate::game::{GameState, Player};
+use crate::ugi::{format_move, parse_move};
+use crate::{CancellationToken, Engine, SearchLimits, StoctopusError};
+
+/// What the game service reports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GameEvent {
+    /// A game started in which the bot plays `player`.
+    Start { player: Player },
+    /// The full move list so far and the bot's clock.
+    State {
+        moves: Vec<(u8, u8)>,
+        remaining: Duration,
+        increment: Duration,
+    },
+    /// The game ended, e.g. by resignation or timeout.
+    End,
+}
+
+pub trait GameTransport {
+    /// Waits for the next event, or returns `None` once the connection is
+    /// closed.
+    fn next_event(&mut self) -> Result<Option<GameEvent>, StoctopusError>;
+    fn submit_move(&mut self, mve: (u8, u8)) -> Result<(), StoctopusError>;
+}
+
+/// Reference transport speaking a line protocol over any byte stream, e.g.
+/// a TCP connection to a relay or a child process' pipes. Incoming lines:
+///
+/// - `start x` or `start o`
+/// - `state <remaining ms> <increment ms> [moves...]`, moves as in UGI
+/// - `end`
+///
+/// Outgoing lines are `move <move>`.
+pub struct LineTransport<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: BufRead, W: Write> LineTransport<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self { reader, writer }
+    }
+
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+}
+
+fn parse_event(line: &str) -> Result<GameEvent, StoctopusError> {
+    let bad = || StoctopusError::Protocol(format!("Bad event {line:?}"));
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["start", "x"] => Ok(GameEvent::Start { player: Player::X }),
+        ["start", "o"] => Ok(GameEvent::Start { player: Player::O }),
+        ["state", remaining, increment, moves @ ..] => Ok(GameEvent::State {
+            remaining: Duration::from_millis(remaining.parse().map_err(|_| bad())?),
+            increment: Duration::from_millis(increment.parse().map_err(|_| bad())?),
+            moves: moves
+                .iter()
+                .map(|m| parse_move(m))
+                .collect::<Result<_, _>>()?,
+        }),
+        ["end"] => Ok(GameEvent::End),
+        _ => Err(bad()),
+    }
+}
+
+impl<R: BufRead, W: Write> GameTransport for LineTransport<R, W> {
+    fn next_event(&mut self) -> Result<Option<GameEvent>, StoctopusError> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.trim().is_empty() {
+                return parse_event(line.trim()).map(Some);
+            }
+        }
+    }
+
+    fn submit_move(&mut self, mve: (u8, u8)) -> Result<(), StoctopusError> {
+        writeln!(self.writer, "move {}", format_move(mve))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub struct Bot<T> {
+    transport: T,
+    engine: Engine,
+    /// Upper bound on the iterations of one search, whatever the clock.
+    pub max_iterations: u32,
+}
+
+impl<T: GameTransport> Bot<T> {
+    pub fn new(transport: T, engine: Engine) -> Self {
+        Self {
+            transport,
+            engine,
+            max_iterations: u32::MAX,
+        }
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Plays one game to the end. Returns its result, which is
+    /// [`GameState::InProgress`] when the service ended it early.
+    pub fn play_game(&mut self) -> Result<GameState, StoctopusError> {
+        let mut player = None;
+        while let Some(event) = self.transport.next_event()? {
+            match event {
+                GameEvent::Start { player: p } => {
+                    player = Some(p);
+                    self.engine.new_game();
+                }
+                GameEvent::State {
+                    moves,
+                    remaining,
+                    increment,
+                } => {
+                    self.sync(&moves)?;
+                    if self.engine.is_game_over() {
+                        break;
+                    }
+                    if player == Some(self.engine.board().next_player) {
+                        let mut limits = SearchLimits::from_clock(remaining, increment);
+                        limits.iterations = limits.iterations.min(self.max_iterations);
+                        let mve = self.search(limits)?;
+                        self.transport.submit_move(mve)?;
+                    }
+                }
+                GameEvent::End => break,
+            }
+        }
+        Ok(self.engine.game_state())
+    }
+
+    /// Brings the engine to the position after `moves`.
+    fn sync(&mut self, moves: &[(u8, u8)]) -> Result<(), StoctopusError> {
+        self.engine.new_game();
+        for &mve in moves {
+            self.engine.play(mve)?;
+        }
+        Ok(())
+    }
+
+    fn search(&mut self, limits: SearchLimits) -> Result<(u8, u8), StoctopusError> {
+        let ev = self
+            .engine
+            .analyze_with_limits(limits, &CancellationToken::new())?;
+        let best = ev
+            .best_move
+            .map(|id| self.engine.try_resolve_node(&id))
+            .transpose()?
+            .and_then(|node| node.board.last_move)
+            .ok_or_else(|| StoctopusError::Protocol("Search found no move".to_string()))?;
+        Ok((best >> 4, best & 0b1111))
+    }
+}
+
+#[cfg(test)]
+mod bot_tests {
+    use std::time::Duration;
+
+    use crate::bot::{parse_event, Bot, GameEvent, LineTransport};
+    use crate::game::{GameState, Player};
+    use crate::ugi::parse_move;
+    use crate::Engine;
+
+    #[test]
+    fn test_parse_event() {
+        assert_eq!(
+            parse_event("state 1500 20 44 40").unwrap(),
+            GameEvent::State {
+                moves: vec![(4, 4), (4, 0)],
+                remaining: Duration::from_millis(1500),
+                increment: Duration::from_millis(20),
+            }
+        );
+        assert_eq!(
+            parse_event("start o").unwrap(),
+            GameEvent::Start { player: Player::O }
+        );
+        assert!(parse_event("state soon 0").is_err());
+        assert!(parse_event("resign").is_err());
+    }
+
+    #[test]
+    fn test_plays_its_turns() {
+        let input = "start o\nstate 900 0 44\n\nstate 900 0 44 40 04\nend\n";
+        let mut bot = Bot::new(
+            LineTransport::new(input.as_bytes(), Vec::new()),
+            Engine::init(),
+        );
+        bot.max_iterations = 20;
+        assert_eq!(bot.play_game().unwrap(), GameState::InProgress);
+
+        let (_, output) = bot.into_transport().into_inner();
+        let output = String::from_utf8(output).unwrap();
+        let moves: Vec<_> = output.lines().collect();
+        assert_eq!(moves.len(), 2);
+        // Answers to 44 have to be in the center sub-board.
+        let first = parse_move(moves[0].strip_prefix("move ").unwrap()).unwrap();
+        assert_eq!(first.0, 4);
+        let second = parse_move(moves[1].strip_prefix("move ").unwrap()).unwrap();
+        assert_eq!(second.0, 4);
+    }
+}
@@ -0,0 +1,133 @@
+//! Spreads a per-game iteration budget over the moves of a game, thinking
+//! longer where it matters instead of spending the same on every move.
+
+use crate::eval;
+use crate::game::{Board, Phase, Player};
+
+/// Plies a game is assumed to last when estimating the moves left, until
+/// a search expects otherwise.
+const TYPICAL_GAME_PLIES: u32 = 60;
+/// Never plan for fewer moves than this, so long games don't starve.
+const MIN_MOVES_LEFT: u32 = 5;
+/// Bounds of the factor applied to an even share of the budget.
+const MIN_FACTOR: f32 = 0.5;
+const MAX_FACTOR: f32 = 2.5;
+/// Top two move priors closer than this count as a hard choice.
+const CLOSE_PRIORS: f32 = 0.1;
+/// Opening moves matter less than the fights that follow.
+const OPENING_FACTOR: f32 = 0.75;
+
+/// Iterations left for the rest of a game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameBudget {
+    remaining: u32,
+    /// Stones on the board when the game ends, as last expected.
+    expected_length: Option<u32>,
+}
+
+impl GameBudget {
+    pub fn new(total_iterations: u32) -> Self {
+        Self {
+            remaining: total_iterations,
+            expected_length: None,
+        }
+    }
+
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Iterations for the next move in `board`. An even share of what is
+    /// left, scaled up for sharp positions (macro threats, a close choice
+    /// between the best candidate moves) and down in the opening and when
+    /// there is little to choose from. A single legal move gets one iteration, and every move
+    /// gets at least one so it can be chosen at all.
+    pub fn allocate(&self, board: &Board) -> u32 {
+        let moves = board.get_moves();
+        let legal: Vec<u8> = (0..81)
+            .filter(|&i| moves & (1 << i) != 0)
+            .map(Board::move_from_index)
+            .collect();
+        if legal.len() <= 1 {
+            return 1;
+        }
+
+        let plies = (board.x | board.o).count_ones();
+        let length = self.expected_length.unwrap_or(TYPICAL_GAME_PLIES);
+        let moves_left = (length.saturating_sub(plies) / 2).max(MIN_MOVES_LEFT);
+        let share = self.remaining as f32 / moves_left as f32;
+
+        let mut factor = 1.0;
+        let threats = board.macro_threats(Player::X) | board.macro_threats(Player::O);
+        factor += 0.25 * threats.count_ones().min(2) as f32;
+        let mut priors = eval::move_priors(board, &legal);
+        priors.sort_by(|a, b| b.total_cmp(a));
+        if priors[0] - priors[1] < CLOSE_PRIORS {
+            factor += 0.5;
+        }
+        if legal.len() <= 3 {
+            factor *= 0.5;
+        }
+        if board.phase() == Phase::Opening {
+            factor *= OPENING_FACTOR;
+        }
+        let factor = factor.clamp(MIN_FACTOR, MAX_FACTOR);
+        ((share * factor) as u32).clamp(1, self.remaining.max(1))
+    }
+
+    /// Plans for the game to end with `length` stones on the board, e.g.
+    /// from [`crate::Evaluation::expected_plies`], instead of after a
+    /// typical number of plies.
+    pub fn set_expected_length(&mut self, length: Option<u32>) {
+        self.expected_length = length;
+    }
+
+    /// Takes `iterations` spent on a move off the budget.
+    pub fn spend(&mut self, iterations: u32) {
+        self.remaining = self.remaining.saturating_sub(iterations);
+    }
+}
+
+#[cfg(test)]
+mod budget_tests {
+    use crate::budget::GameBudget;
+    use crate::game::Board;
+
+    fn play(moves: &[(u8, u8)]) -> Board {
+        moves.iter().fold(Board::default(), |board, m| {
+            board.unchecked_play(Board::move_from_gl(m.0, m.1))
+        })
+    }
+
+    #[test]
+    fn test_allocate() {
+        let mut budget = GameBudget::new(30_000);
+        let opening = budget.allocate(&Board::default());
+        assert!(opening > 0 && opening < 30_000);
+
+        // Same position, but X owns sub-boards 0 and 1 and threatens the
+        // top row of sub-boards.
+        let quiet = play(&[(4, 0), (0, 4), (4, 1), (1, 4)]);
+        let mut sharp = quiet;
+        sharp.gx = 0b011;
+        sharp.refresh_completed();
+        assert!(budget.allocate(&sharp) > budget.allocate(&quiet));
+
+        // Only cell 0 is left in the forced sub-board, nobody won it.
+        let mut forced = play(&[(4, 0)]);
+        forced.x |= 0b101_100_010;
+        forced.o |= 0b010_011_100;
+        assert_eq!(forced.get_moves().count_ones(), 1);
+        assert_eq!(budget.allocate(&forced), 1);
+
+        // A game expected to end soon leaves fewer moves to share with.
+        let mut short = budget;
+        short.set_expected_length(Some(20));
+        assert!(short.allocate(&quiet) > budget.allocate(&quiet));
+
+        budget.spend(29_999);
+        assert_eq!(budget.allocate(&Board::default()), 1);
+        budget.spend(5);
+        assert_eq!(budget.remaining(), 0);
+    }
+}
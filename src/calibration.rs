@@ -0,0 +1,154 @@
+//! Maps raw search values to calibrated win probabilities.
+//!
+//! Raw MCTS values are overconfident. A calibration is fit from pairs of
+//! `(raw win probability, outcome)` gathered in self-play, where the outcome
+//! is 1 for a win, 0.5 for a draw and 0 for a loss of the side the raw value
+//! belongs to.
+
+/// Raw values are clamped this far away from 0 and 1 before taking logits.
+const EPSILON: f32 = 1e-4;
+/// Newton steps when fitting a logistic calibration.
+const NEWTON_STEPS: usize = 50;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Calibration {
+    /// Report raw values unchanged.
+    #[default]
+    Identity,
+    /// `sigmoid(slope * logit(raw) + intercept)`. A slope below 1 tempers
+    /// overconfident values.
+    Logistic { slope: f32, intercept: f32 },
+    /// Linear interpolation between `(raw, calibrated)` points sorted by raw
+    /// value. Values outside the table use the nearest point.
+    Table(Vec<(f32, f32)>),
+}
+
+fn logit(p: f32) -> f32 {
+    let p = p.clamp(EPSILON, 1.0 - EPSILON);
+    (p / (1.0 - p)).ln()
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+impl Calibration {
+    /// Calibrated win probability for a raw one, both in `[0, 1]`.
+    pub fn apply(&self, raw: f32) -> f32 {
+        match self {
+            Self::Identity => raw,
+            Self::Logistic { slope, intercept } => sigmoid(slope * logit(raw) + intercept),
+            Self::Table(points) => {
+                let Some(&(first_raw, first)) = points.first() else {
+                    return raw;
+                };
+                if raw <= first_raw {
+                    return first;
+                }
+                for pair in points.windows(2) {
+                    let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+                    if raw <= x1 {
+                        let t = if x1 > x0 { (raw - x0) / (x1 - x0) } else { 1.0 };
+                        return y0 + t * (y1 - y0);
+                    }
+                }
+                points.last().expect("Checked non-empty above").1
+            }
+        }
+    }
+
+    /// Maximum likelihood logistic calibration of `samples`.
+    pub fn fit_logistic(samples: &[(f32, f32)]) -> Self {
+        let (mut slope, mut intercept) = (1.0f64, 0.0f64);
+        for _ in 0..NEWTON_STEPS {
+            // Gradient and Hessian of the negative log likelihood.
+            let (mut gs, mut gi) = (0.0, 0.0);
+            let (mut hss, mut hsi, mut hii) = (0.0, 0.0, 0.0);
+            for &(raw, outcome) in samples {
+                let x = logit(raw) as f64;
+                let p = 1.0 / (1.0 + (-(slope * x + intercept)).exp());
+                let error = p - outcome as f64;
+                let weight = p * (1.0 - p);
+                gs += error * x;
+                gi += error;
+                hss += weight * x * x;
+                hsi += weight * x;
+                hii += weight;
+            }
+            let det = hss * hii - hsi * hsi;
+            if det.abs() < 1e-12 {
+                break;
+            }
+            let step_s = (hii * gs - hsi * gi) / det;
+            let step_i = (hss * gi - hsi * gs) / det;
+            slope -= step_s;
+            intercept -= step_i;
+            if step_s.abs() + step_i.abs() < 1e-9 {
+                break;
+            }
+        }
+        Self::Logistic {
+            slope: slope as f32,
+            intercept: intercept as f32,
+        }
+    }
+
+    /// Table calibration with the average outcome of each of `bins` equal
+    /// width raw value ranges. Empty ranges are left out.
+    pub fn fit_table(samples: &[(f32, f32)], bins: usize) -> Self {
+        let bins = bins.max(1);
+        let mut sums = vec![(0.0, 0.0, 0usize); bins];
+        for &(raw, outcome) in samples {
+            let bin = ((raw.clamp(0.0, 1.0) * bins as f32) as usize).min(bins - 1);
+            sums[bin].0 += raw;
+            sums[bin].1 += outcome;
+            sums[bin].2 += 1;
+        }
+        Self::Table(
+            sums.into_iter()
+                .filter(|&(_, _, n)| n > 0)
+                .map(|(raw, outcome, n)| (raw / n as f32, outcome / n as f32))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod calibration_tests {
+    use crate::calibration::Calibration;
+
+    /// Raw values of 0.9 that only win 70% of the time, and the mirror.
+    fn overconfident() -> Vec<(f32, f32)> {
+        let mut samples = vec![];
+        for i in 0..100 {
+            samples.push((0.9, if i < 70 { 1.0 } else { 0.0 }));
+            samples.push((0.1, if i < 30 { 1.0 } else { 0.0 }));
+        }
+        samples
+    }
+
+    #[test]
+    fn test_identity() {
+        assert_eq!(Calibration::Identity.apply(0.83), 0.83);
+    }
+
+    #[test]
+    fn test_fit_logistic() {
+        let calibration = Calibration::fit_logistic(&overconfident());
+        assert!((calibration.apply(0.9) - 0.7).abs() < 1e-3);
+        assert!((calibration.apply(0.1) - 0.3).abs() < 1e-3);
+        assert!((calibration.apply(0.5) - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_fit_table() {
+        let calibration = Calibration::fit_table(&overconfident(), 10);
+        let Calibration::Table(points) = &calibration else {
+            panic!("Expected a table, got {calibration:?}");
+        };
+        assert_eq!(points.len(), 2);
+        assert!((calibration.apply(0.5) - 0.5).abs() < 1e-5);
+        assert!((calibration.apply(0.0) - 0.3).abs() < 1e-5);
+        assert!((calibration.apply(1.0) - 0.7).abs() < 1e-5);
+    }
+}
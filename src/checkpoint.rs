@@ -0,0 +1,379 @@
+//! Search trees saved to disk, so hours of book building or solving survive
+//! a crash. A checkpoint is written to a temporary file that then replaces
+//! the old one, so a crash while writing leaves the previous checkpoint
+//! intact.
+//!
+//! Layout, all integers little-endian:
+//!
+//! - magic `STCP`, format version (`u32`)
+//! - header length (`u32`) and the header as JSON: the root position, the
+//!   configuration, the iterations run and the node the engine is at
+//! - node count (`u64`), then the nodes in arena order, the root first:
+//!   parent index (`u32`, `u32::MAX` for the root), move (`u8`), whether
+//!   it was expanded (`u8`), wins, squared wins, visits, prior, summed
+//!   game lengths and playouts (`f32`), pending move count (`u8`) and the
+//!   pending moves as move (`u8`) and prior (`f32`)
+//!
+//! Boards aren't stored, they are replayed from the root. Neither are the
+//! trace, the endgame cache or the best move history.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::debug_bundle::BoardData;
+use crate::mcts::{MCTSArena, MCTSConfig, MCTSNode, NodeId, PendingMove, SearchLimits};
+use crate::{CancellationToken, Engine, EvalSource, Evaluation, StoctopusError};
+
+const MAGIC: &[u8; 4] = b"STCP";
+const VERSION: u32 = 2;
+const NO_PARENT: u32 = u32::MAX;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    root: BoardData,
+    config: MCTSConfig,
+    iterations: u32,
+    current_node: usize,
+}
+
+fn invalid(msg: &str) -> StoctopusError {
+    StoctopusError::Protocol(format!("Bad checkpoint: {msg}"))
+}
+
+pub(crate) fn encode(engine: &Engine) -> Vec<u8> {
+    let nodes = engine.arena.nodes();
+    let header = Header {
+        root: BoardData::new(&nodes[0].board),
+        config: engine.config,
+        iterations: engine.arena.iterations(),
+        current_node: engine
+            .arena
+            .position(&engine.current_node)
+            .expect("The current node is never collected"),
+    };
+    let header = serde_json::to_vec(&header).expect("Headers always serialize");
+
+    let mut bytes = Vec::with_capacity(header.len() + nodes.len() * 32);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&header);
+    bytes.extend_from_slice(&(nodes.len() as u64).to_le_bytes());
+    for node in nodes {
+        let parent = node.parent.map_or(NO_PARENT, |id| {
+            engine.arena.position(&id).expect("Parents are live") as u32
+        });
+        bytes.extend_from_slice(&parent.to_le_bytes());
+        bytes.push(node.board.last_move.unwrap_or(0));
+        bytes.push(node.children.is_some() as u8);
+        let values = [
+            node.wins,
+            node.wins_squared,
+            node.visits,
+            node.prior,
+            node.plies,
+            node.playouts,
+        ];
+        for value in values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.push(node.pending.len() as u8);
+        for pending in &node.pending {
+            bytes.push(pending.mve);
+            bytes.extend_from_slice(&pending.prior.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+/// Reads little-endian values off the front of a byte slice.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StoctopusError> {
+        if self.0.len() < len {
+            return Err(invalid("truncated"));
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, StoctopusError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, StoctopusError> {
+        Ok(u32::from_le_bytes(
+            self.take(4)?.try_into().expect("4 bytes"),
+        ))
+    }
+
+    fn u64(&mut self) -> Result<u64, StoctopusError> {
+        Ok(u64::from_le_bytes(
+            self.take(8)?.try_into().expect("8 bytes"),
+        ))
+    }
+
+    fn f32(&mut self) -> Result<f32, StoctopusError> {
+        Ok(f32::from_le_bytes(
+            self.take(4)?.try_into().expect("4 bytes"),
+        ))
+    }
+}
+
+pub(crate) fn decode(bytes: &[u8]) -> Result<Engine, StoctopusError> {
+    let mut reader = Reader(bytes);
+    if reader.take(4)? != MAGIC {
+        return Err(invalid("not a checkpoint"));
+    }
+    if reader.u32()? != VERSION {
+        return Err(invalid("unsupported version"));
+    }
+    let header_len = reader.u32()? as usize;
+    let header: Header = serde_json::from_slice(reader.take(header_len)?)
+        .map_err(|err| invalid(&err.to_string()))?;
+    if let Some(problem) = header.config.backup.problem() {
+        return Err(invalid(problem));
+    }
+    let root = header.root.board()?;
+
+    let count = reader.u64()? as usize;
+    // Every node takes at least 31 bytes, don't trust a corrupt count.
+    if count == 0 || count > reader.0.len() / 31 {
+        return Err(invalid("bad node count"));
+    }
+    let mut nodes: Vec<MCTSNode> = Vec::with_capacity(count);
+    for index in 0..count {
+        let parent = reader.u32()?;
+        let mve = reader.u8()?;
+        let expanded = reader.u8()? != 0;
+        let (wins, wins_squared, visits, prior) =
+            (reader.f32()?, reader.f32()?, reader.f32()?, reader.f32()?);
+        let (plies, playouts) = (reader.f32()?, reader.f32()?);
+        let pending = (0..reader.u8()?)
+            .map(|_| {
+                Ok(PendingMove {
+                    mve: reader.u8()?,
+                    prior: reader.f32()?,
+                })
+            })
+            .collect::<Result<_, StoctopusError>>()?;
+
+        let (board, parent) = match (index, parent) {
+            (0, NO_PARENT) => (root, None),
+            (0, _) | (_, NO_PARENT) => return Err(invalid("misplaced root")),
+            (_, parent) if parent as usize >= index => {
+                return Err(invalid("child before its parent"))
+            }
+            (_, parent) => {
+                let parent_node = &mut nodes[parent as usize];
+                let (global, local) = (mve >> 4, mve & 0b1111);
+                let legal = global < 9
+                    && local < 9
+                    && !parent_node.board.game_over()
+                    && parent_node.board.get_moves() & (1 << (global * 9 + local)) != 0;
+                let Some(siblings) = parent_node.children.as_mut().filter(|_| legal) else {
+                    return Err(invalid("child of an unexpanded node or illegal move"));
+                };
+                siblings.push(NodeId::new(index));
+                (
+                    parent_node.board.unchecked_play(mve),
+                    Some(NodeId::new(parent as usize)),
+                )
+            }
+        };
+        nodes.push(MCTSNode {
+            board,
+            wins,
+            wins_squared,
+            visits,
+            plies,
+            playouts,
+            parent,
+            children: expanded.then(Vec::new),
+            prior,
+            pending,
+            // Proofs aren't saved, the search finds them again.
+            solved: None,
+        });
+    }
+    if !reader.0.is_empty() {
+        return Err(invalid("trailing bytes"));
+    }
+    if header.current_node >= nodes.len() {
+        return Err(invalid("current node out of range"));
+    }
+
+    let mut engine = Engine::with_config(header.config);
+    engine.arena = MCTSArena::from_nodes(nodes, header.config, header.iterations);
+    engine.current_node = engine.arena.node_id(header.current_node);
+    Ok(engine)
+}
+
+impl Engine {
+    /// Saves the search tree to `path`, replacing any earlier checkpoint
+    /// only once the new one is completely written.
+    pub fn save_checkpoint(&self, path: impl AsRef<Path>) -> Result<(), StoctopusError> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(&encode(self))?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// An engine with the tree of a checkpoint written by
+    /// [`Self::save_checkpoint`], ready to search on with
+    /// [`Self::analyze_with_checkpoints`]. Book, tablebase, cache and
+    /// calibration aren't part of the checkpoint and need setting again.
+    pub fn resume_from_checkpoint(path: impl AsRef<Path>) -> Result<Self, StoctopusError> {
+        decode(&fs::read(path)?)
+    }
+
+    /// Searches like [`Self::analyze_with_limits`], but grows the current
+    /// tree instead of starting a new one and saves it to `path` every
+    /// `every` and at the end. Resume after a crash with
+    /// [`Self::resume_from_checkpoint`] and the iterations that were left.
+    pub fn analyze_with_checkpoints(
+        &mut self,
+        limits: SearchLimits,
+        cancel: &CancellationToken,
+        path: impl AsRef<Path>,
+        every: Duration,
+    ) -> Result<Evaluation, StoctopusError> {
+        let deadline = limits.time.map(|time| Instant::now() + time);
+        let start = self.arena.iterations();
+        let mut best = None;
+        loop {
+            let done = self.arena.iterations() - start;
+            let left = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if done >= limits.iterations || left == Some(Duration::ZERO) || cancel.is_cancelled() {
+                break;
+            }
+            let chunk = SearchLimits {
+                iterations: limits.iterations - done,
+                time: Some(left.map_or(every, |left| left.min(every))),
+            };
+            best = Some(self.arena.analyze(self.current_node, chunk, cancel)?);
+            self.save_checkpoint(&path)?;
+            if self.arena.iterations() - start == done {
+                // The tree is full or the search can't go on.
+                break;
+            }
+        }
+        match best {
+            Some((confidence, best_node)) => {
+                Ok(self.evaluation(confidence, Some(best_node), EvalSource::Search)?)
+            }
+            None => Ok(self.current_evaluation()?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod checkpoint_tests {
+    use std::time::Duration;
+
+    use crate::checkpoint::{decode, encode};
+    use crate::{
+        Backup, CancellationToken, Engine, MCTSConfig, SearchLimits, SearchMode, Widening,
+    };
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("stoctopus-{name}-{}", std::process::id()))
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let mut engine = Engine::with_config(MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 4 },
+            widening: Some(Widening::default()),
+            ..MCTSConfig::default()
+        });
+        engine.play((4, 4)).unwrap();
+        let path = temp_path("checkpoint");
+        let ev = engine
+            .analyze_with_checkpoints(
+                SearchLimits::iterations(300),
+                &CancellationToken::new(),
+                &path,
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        assert_eq!(ev.info.iterations, 300);
+
+        let mut resumed = Engine::resume_from_checkpoint(&path).unwrap();
+        assert_eq!(resumed.board(), engine.board());
+        assert_eq!(resumed.tree_size(), engine.tree_size());
+        assert_eq!(resumed.tree_stats(), engine.tree_stats());
+        // Same children, under handles of the restored tree.
+        let children = |engine: &Engine| {
+            let stats = engine.child_stats(engine.current_node());
+            stats
+                .into_iter()
+                .map(|child| {
+                    assert!(engine.try_resolve_node(&child.node).is_ok());
+                    (
+                        child.mve,
+                        child.visits,
+                        child.win_rate,
+                        child.prior,
+                        child.expected_plies,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(children(&resumed), children(&engine));
+
+        // Searching on grows the restored tree.
+        let more = resumed
+            .analyze_with_checkpoints(
+                SearchLimits::iterations(100),
+                &CancellationToken::new(),
+                &path,
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        assert_eq!(more.info.iterations, 400);
+        assert!(resumed.tree_size() > engine.tree_size());
+        assert_eq!(
+            Engine::resume_from_checkpoint(&path).unwrap().tree_size(),
+            resumed.tree_size()
+        );
+
+        // A damaged file is rejected, and no temporary file is left over.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.truncate(bytes.len() - 3);
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(Engine::resume_from_checkpoint(&path).is_err());
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        assert!(!std::path::Path::new(&temporary).exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_rejects_bad_backup() {
+        let mut engine = Engine::init();
+        engine.analyze(20).unwrap();
+        for p in [0.0, -1.0, f32::NAN] {
+            engine.set_config(MCTSConfig {
+                backup: Backup::PowerMean { p },
+                ..MCTSConfig::default()
+            });
+            assert!(decode(&encode(&engine)).is_err(), "{p}");
+        }
+        engine.set_config(MCTSConfig {
+            backup: Backup::PowerMean { p: 2.0 },
+            ..MCTSConfig::default()
+        });
+        assert!(decode(&encode(&engine)).is_ok());
+    }
+}
@@ -0,0 +1,181 @@
+//! Engine configuration files, so deployments don't need a pile of command
+//! line options. The format is TOML:
+//!
+//! ```toml
+//! threads = 4
+//! strength = 8
+//! book = "openings.book"
+//!
+//! [search]
+//! symmetry_plies = 4
+//! endgame_cells = 8
+//! selection = { Puct = { c = 1.5 } }
+//! mode = { Deterministic = { seed = 7 } }
+//! ```
+//!
+//! Every key is optional. `[search]` takes the fields of [`MCTSConfig`],
+//! enums written as a variant name or a table holding one variant. Unknown
+//! keys are an error, so typos don't go unnoticed.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use toml_edit::{DocumentMut, Item, Table};
+
+use crate::ugi::MAX_STRENGTH;
+use crate::{Engine, MCTSConfig, OpeningBook, StoctopusError};
+
+/// Settings of an engine and the process running it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    pub search: MCTSConfig,
+    /// Threads of the search pool, all cores when unset.
+    pub threads: Option<usize>,
+    /// As the UGI `Strength` option, 0 for full strength.
+    pub strength: u32,
+    /// Opening book to load, see [`OpeningBook::open`].
+    pub book: Option<PathBuf>,
+}
+
+fn invalid(msg: impl Into<String>) -> StoctopusError {
+    StoctopusError::Protocol(format!("Bad config: {}", msg.into()))
+}
+
+/// TOML as the JSON value serde reads the configuration from.
+fn table_to_json(table: &Table) -> Result<Value, StoctopusError> {
+    table
+        .iter()
+        .map(|(key, item)| Ok((key.to_string(), item_to_json(item)?)))
+        .collect::<Result<Map<_, _>, _>>()
+        .map(Value::Object)
+}
+
+fn item_to_json(item: &Item) -> Result<Value, StoctopusError> {
+    match item {
+        Item::None => Ok(Value::Null),
+        Item::Value(value) => value_to_json(value),
+        Item::Table(table) => table_to_json(table),
+        Item::ArrayOfTables(tables) => tables.iter().map(table_to_json).collect(),
+    }
+}
+
+fn value_to_json(value: &toml_edit::Value) -> Result<Value, StoctopusError> {
+    use toml_edit::Value as Toml;
+    Ok(match value {
+        Toml::String(text) => Value::from(text.value().as_str()),
+        Toml::Integer(n) => Value::from(*n.value()),
+        Toml::Float(x) => Value::from(*x.value()),
+        Toml::Boolean(flag) => Value::from(*flag.value()),
+        Toml::Datetime(_) => return Err(invalid("dates aren't supported")),
+        Toml::Array(values) => values.iter().map(value_to_json).collect::<Result<_, _>>()?,
+        Toml::InlineTable(table) => table
+            .iter()
+            .map(|(key, value)| Ok((key.to_string(), value_to_json(value)?)))
+            .collect::<Result<Map<_, _>, StoctopusError>>()
+            .map(Value::Object)?,
+    })
+}
+
+impl FromStr for EngineConfig {
+    type Err = StoctopusError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let document: DocumentMut = text.parse().map_err(|err| invalid(format!("{err}")))?;
+        let config: Self = serde_json::from_value(table_to_json(document.as_table())?)
+            .map_err(|err| invalid(err.to_string()))?;
+        if config.strength > MAX_STRENGTH {
+            return Err(invalid(format!("strength above {MAX_STRENGTH}")));
+        }
+        if config.threads == Some(0) {
+            return Err(invalid("threads must be at least 1"));
+        }
+        if let Some(problem) = config.search.backup.problem() {
+            return Err(invalid(problem));
+        }
+        Ok(config)
+    }
+}
+
+impl EngineConfig {
+    /// Reads a configuration file.
+    pub fn from_toml(path: impl AsRef<Path>) -> Result<Self, StoctopusError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Threads to search with.
+    pub fn threads(&self) -> usize {
+        self.threads
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// An engine with the search settings and the book.
+    pub fn engine(&self) -> Result<Engine, StoctopusError> {
+        let mut engine = Engine::with_config(self.search);
+        if let Some(book) = &self.book {
+            engine.set_book(Some(Arc::new(OpeningBook::open(book)?)));
+        }
+        Ok(engine)
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use crate::config::EngineConfig;
+    use crate::{SearchMode, SelectionPolicy};
+
+    #[test]
+    fn test_engine_config() {
+        let config: EngineConfig = "
+            # Analysis server.
+            threads = 4
+            strength = 8
+
+            [search]
+            symmetry_plies = 4
+            max_nodes = 100000
+            selection = { Puct = { c = 1.5 } }
+            mode = { Deterministic = { seed = 7 } }
+            widening = { coefficient = 2, exponent = 0.5 }
+            backup = { PowerMean = { p = 2.0 } }
+        "
+        .parse()
+        .unwrap();
+        assert_eq!((config.threads(), config.strength), (4, 8));
+        assert_eq!(config.book, None);
+        let search = config.search;
+        assert_eq!(search.symmetry_plies, 4);
+        assert_eq!(search.max_nodes, Some(100_000));
+        assert_eq!(search.selection, SelectionPolicy::Puct { c: 1.5 });
+        assert_eq!(search.mode, SearchMode::Deterministic { seed: 7 });
+        assert_eq!(search.widening.unwrap().coefficient, 2.0);
+        // Everything else keeps its default.
+        assert_eq!(search.batch_size, EngineConfig::default().search.batch_size);
+        config.engine().unwrap().analyze(20).unwrap();
+
+        assert_eq!("".parse::<EngineConfig>().unwrap().strength, 0);
+        for bad in [
+            "threads = 0",
+            "strength = 11",
+            "stength = 3",
+            "[search]\nbatch = 3",
+            "threads = \"four\"",
+            "threads = ",
+            "book = \"/no/such/book\"",
+            "[search]\nbackup = { PowerMean = { p = 0.0 } }",
+            "[search]\nbackup = { PowerMean = { p = -2.0 } }",
+            "[search]\nbackup = { PowerMean = { p = inf } }",
+        ] {
+            let config = bad.parse::<EngineConfig>();
+            assert!(
+                config.is_err() || config.unwrap().engine().is_err(),
+                "{bad}"
+            );
+        }
+        assert!(EngineConfig::from_toml("/no/such/config.toml").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//! Everything needed to look into a search someone else ran: the position,
+//! the configuration (seed included), how far the search got and the shape
+//! of its tree, in one JSON file to attach to a bug report.
+
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::{Board, Player, Rules};
+use crate::mcts::{MCTSConfig, SearchMode, TreeStats};
+use crate::{Engine, StoctopusError};
+
+/// Bumped when the bundle layout changes incompatibly.
+const BUNDLE_FORMAT: u32 = 1;
+
+/// The board without what can be recomputed from it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct BoardData {
+    #[serde(with = "hex_cells")]
+    x: u128,
+    #[serde(with = "hex_cells")]
+    o: u128,
+    next_player: Player,
+    last_move: Option<u8>,
+    rules: Rules,
+}
+
+/// Cell sets as hex strings, since many JSON readers lose precision on
+/// numbers this large and serde can't buffer them inside tagged enums.
+mod hex_cells {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(cells: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{cells:x}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        u128::from_str_radix(&text, 16).map_err(D::Error::custom)
+    }
+}
+
+impl BoardData {
+    pub(crate) fn new(board: &Board) -> Self {
+        Self {
+            x: board.x,
+            o: board.o,
+            next_player: board.next_player,
+            last_move: board.last_move,
+            rules: board.rules,
+        }
+    }
+
+    /// The board, checking that it can arise from legal play.
+    pub(crate) fn board(&self) -> Result<Board, StoctopusError> {
+        let mut board = Board::with_rules(self.rules);
+        board.x = self.x;
+        board.o = self.o;
+        board.next_player = self.next_player;
+        board.last_move = self.last_move;
+        board.recompute_macro();
+        board.validate()?;
+        Ok(board)
+    }
+}
+
+/// Contents of a file written by [`Engine::dump_debug_bundle`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DebugBundle {
+    pub format: u32,
+    /// Version of the crate that wrote the bundle.
+    pub version: String,
+    /// The position in the grid notation, for reading the report.
+    pub grid: String,
+    board: BoardData,
+    pub config: MCTSConfig,
+    /// Iterations of the search the tree came from, 0 if there was none.
+    pub iterations: u32,
+    /// Shape of the tree at the time of the dump.
+    pub tree: TreeStats,
+    /// Visits of every root move as `(move, visits)`, sorted by move.
+    pub root_visits: Vec<(u8, u32)>,
+}
+
+fn invalid(msg: impl Into<String>) -> StoctopusError {
+    StoctopusError::Protocol(format!("Bad debug bundle: {}", msg.into()))
+}
+
+impl DebugBundle {
+    pub fn board(&self) -> Result<Board, StoctopusError> {
+        self.board.board()
+    }
+
+    /// Whether searching the bundle's position again with its configuration
+    /// and iterations rebuilds the same tree.
+    pub fn is_reproducible(&self) -> bool {
+        matches!(self.config.mode, SearchMode::Deterministic { .. })
+    }
+}
+
+impl Engine {
+    /// Writes the current position, the configuration and the state of
+    /// the last search to `path`, see [`DebugBundle`].
+    pub fn dump_debug_bundle(&self, path: impl AsRef<Path>) -> Result<(), StoctopusError> {
+        let board = *self.board();
+        let bundle = DebugBundle {
+            format: BUNDLE_FORMAT,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            grid: board.to_grid_string(),
+            board: BoardData::new(&board),
+            config: self.config,
+            iterations: self.arena.iterations(),
+            tree: self.tree_stats(),
+            root_visits: self.current_evaluation()?.root_visits,
+        };
+        let json = serde_json::to_string_pretty(&bundle).expect("Bundles always serialize");
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Reads a bundle written by [`Self::dump_debug_bundle`], returning an
+    /// engine set up with its position and configuration. Run
+    /// `analyze(bundle.iterations)` on it to repeat the search.
+    pub fn load_debug_bundle(
+        path: impl AsRef<Path>,
+    ) -> Result<(Engine, DebugBundle), StoctopusError> {
+        let text = fs::read_to_string(path)?;
+        let bundle: DebugBundle =
+            serde_json::from_str(&text).map_err(|err| invalid(err.to_string()))?;
+        if bundle.format != BUNDLE_FORMAT {
+            return Err(invalid(format!("unknown format {}", bundle.format)));
+        }
+        let engine = Engine::from_board(bundle.board()?, bundle.config)?;
+        Ok((engine, bundle))
+    }
+}
+
+#[cfg(test)]
+mod debug_bundle_tests {
+    use crate::{Engine, MCTSConfig, SearchMode, StoctopusError};
+
+    #[test]
+    fn test_debug_bundle_round_trip() {
+        let config = MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 11 },
+            ..MCTSConfig::default()
+        };
+        let mut engine = Engine::with_config(config);
+        engine.play((4, 4)).unwrap();
+        engine.play((4, 0)).unwrap();
+        let ev = engine.analyze(120).unwrap();
+
+        let path = std::env::temp_dir().join(format!("stoctopus-bundle-{}", std::process::id()));
+        engine.dump_debug_bundle(&path).unwrap();
+        let (mut loaded, bundle) = Engine::load_debug_bundle(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.board(), engine.board());
+        assert_eq!(bundle.iterations, 120);
+        assert_eq!(bundle.tree, engine.tree_stats());
+        assert_eq!(bundle.root_visits, ev.root_visits);
+        assert!(bundle.is_reproducible());
+        // The same search again gives the same tree.
+        let again = loaded.analyze(bundle.iterations).unwrap();
+        assert_eq!(again.fingerprint(), ev.fingerprint());
+
+        let missing = Engine::load_debug_bundle(std::env::temp_dir().join("no-such-bundle"));
+        assert!(matches!(missing, Err(StoctopusError::Io(_))));
+    }
+}
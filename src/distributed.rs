@@ -0,0 +1,447 @@
+//! Spreads work over several machines. A [`Coordinator`] hands out
+//! [`Job`]s over TCP: positions to search, or games for the worker to play
+//! against itself, e.g. to generate training data. Workers started with
+//! [`run_worker`] do them with their own engine and send the results back.
+//!
+//! Both sides exchange one JSON object per line, tagged by `type`:
+//!
+//! - worker: `{"type": "get"}` for the next job, and
+//!   `{"type": "result", "id": 3, "evaluation": {...}}`,
+//!   `{"type": "game", "id": 3, "game": {...}}` or
+//!   `{"type": "failed", "id": 3, "error": "..."}` once it is done
+//! - coordinator: `{"type": "job", "id": 3, "iterations": 800, "board":
+//!   {...}}`, `{"type": "self_play", "id": 3, "iterations": 800, "start":
+//!   {...}}`, `{"type": "wait", "millis": 100}` while other workers still
+//!   hold the last jobs, or `{"type": "done"}`
+//!
+//! A job whose worker disconnects, asks for another job before answering,
+//! or stays silent for longer than the coordinator's timeout, is handed out
+//! again.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::debug_bundle::BoardData;
+use crate::game::Board;
+use crate::match_runner::{GameOutcome, MatchRunner};
+use crate::{Engine, PositionEvaluation, SearchLimits, StoctopusError};
+
+/// How long a worker waits before asking again when no job is free.
+const WAIT: Duration = Duration::from_millis(100);
+/// How often the coordinator checks for new workers.
+const ACCEPT_POLL: Duration = Duration::from_millis(20);
+/// How long a worker may stay silent, see [`Coordinator::with_timeout`].
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Work for a worker.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Job {
+    /// Search `board` for `iterations` iterations.
+    Search { board: Board, iterations: u32 },
+    /// Play a game from `start` with the worker's engine on both sides,
+    /// searching every move for `iterations` iterations.
+    SelfPlay { start: Board, iterations: u32 },
+}
+
+/// What a worker did with a [`Job`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum JobOutput {
+    Evaluation(PositionEvaluation),
+    Game(GameOutcome),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    Get,
+    Job {
+        id: usize,
+        iterations: u32,
+        board: BoardData,
+    },
+    SelfPlay {
+        id: usize,
+        iterations: u32,
+        start: BoardData,
+    },
+    Wait {
+        millis: u64,
+    },
+    Done,
+    Result {
+        id: usize,
+        evaluation: PositionEvaluation,
+    },
+    Game {
+        id: usize,
+        game: GameOutcome,
+    },
+    Failed {
+        id: usize,
+        error: String,
+    },
+}
+
+fn protocol(msg: impl Into<String>) -> StoctopusError {
+    StoctopusError::Protocol(msg.into())
+}
+
+fn send(stream: &mut TcpStream, message: &Message) -> Result<(), StoctopusError> {
+    let mut line = serde_json::to_string(message).expect("Messages always serialize");
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Next message, `None` once the other side hung up.
+fn receive(reader: &mut impl BufRead) -> Result<Option<Message>, StoctopusError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    serde_json::from_str(&line)
+        .map(Some)
+        .map_err(|err| protocol(format!("Bad message {:?}: {err}", line.trim())))
+}
+
+type JobResult = Result<JobOutput, StoctopusError>;
+
+/// Jobs not handed out yet and the results so far.
+struct Jobs {
+    queue: VecDeque<usize>,
+    results: Vec<Option<JobResult>>,
+}
+
+/// Jobs to hand out to workers.
+pub struct Coordinator {
+    jobs: Vec<Job>,
+    timeout: Duration,
+}
+
+impl Coordinator {
+    pub fn new(jobs: Vec<Job>) -> Self {
+        Self {
+            jobs,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Drops workers that send nothing for `timeout`, handing their job to
+    /// another worker. Workers are silent while they work on a job, so
+    /// this has to be longer than any job takes. Ten minutes by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Serves the jobs to workers connecting to `listener` until every
+    /// job has a result, returned in the order of the jobs.
+    pub fn run(self, listener: TcpListener) -> Result<Vec<JobResult>, StoctopusError> {
+        let state = Mutex::new(Jobs {
+            queue: (0..self.jobs.len()).collect(),
+            results: (0..self.jobs.len()).map(|_| None).collect(),
+        });
+        let finished = || {
+            let state = state.lock().expect("Job state poisoned");
+            state.results.iter().all(Option::is_some)
+        };
+        listener.set_nonblocking(true)?;
+        thread::scope(|scope| {
+            while !finished() {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        stream.set_nonblocking(false)?;
+                        stream.set_read_timeout(Some(self.timeout))?;
+                        let (jobs, state) = (&self.jobs, &state);
+                        // A worker that breaks the protocol or times out only
+                        // loses its connection, its job goes back to the
+                        // queue.
+                        scope.spawn(move || serve(stream, jobs, state));
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                        thread::sleep(ACCEPT_POLL)
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Ok::<_, StoctopusError>(())
+        })?;
+        let state = state.into_inner().expect("Job state poisoned");
+        Ok(state
+            .results
+            .into_iter()
+            .map(|result| result.expect("Every job has a result"))
+            .collect())
+    }
+}
+
+/// Talks to one worker until it leaves.
+fn serve(mut stream: TcpStream, jobs: &[Job], state: &Mutex<Jobs>) -> Result<(), StoctopusError> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut assigned: Option<usize> = None;
+    let outcome = (|| loop {
+        let Some(message) = receive(&mut reader)? else {
+            return Ok(());
+        };
+        let reply = match message {
+            Message::Get => {
+                let mut state = state.lock().expect("Job state poisoned");
+                // A worker asking again gave up on its job, someone else
+                // gets it.
+                if let Some(id) = assigned.take() {
+                    state.queue.push_back(id);
+                }
+                match state.queue.pop_front() {
+                    Some(id) => {
+                        assigned = Some(id);
+                        match jobs[id] {
+                            Job::Search { board, iterations } => Message::Job {
+                                id,
+                                iterations,
+                                board: BoardData::new(&board),
+                            },
+                            Job::SelfPlay { start, iterations } => Message::SelfPlay {
+                                id,
+                                iterations,
+                                start: BoardData::new(&start),
+                            },
+                        }
+                    }
+                    None if state.results.iter().all(Option::is_some) => Message::Done,
+                    None => Message::Wait {
+                        millis: WAIT.as_millis() as u64,
+                    },
+                }
+            }
+            Message::Result { id, evaluation }
+                if assigned == Some(id) && matches!(jobs[id], Job::Search { .. }) =>
+            {
+                assigned = None;
+                state.lock().expect("Job state poisoned").results[id] =
+                    Some(Ok(JobOutput::Evaluation(evaluation)));
+                continue;
+            }
+            Message::Game { id, game }
+                if assigned == Some(id) && matches!(jobs[id], Job::SelfPlay { .. }) =>
+            {
+                assigned = None;
+                state.lock().expect("Job state poisoned").results[id] =
+                    Some(Ok(JobOutput::Game(game)));
+                continue;
+            }
+            Message::Failed { id, error } if assigned == Some(id) => {
+                assigned = None;
+                state.lock().expect("Job state poisoned").results[id] = Some(Err(protocol(error)));
+                continue;
+            }
+            _ => return Err(protocol("Unexpected message from worker")),
+        };
+        send(&mut stream, &reply)?;
+    })();
+    if let Some(id) = assigned {
+        state
+            .lock()
+            .expect("Job state poisoned")
+            .queue
+            .push_back(id);
+    }
+    outcome
+}
+
+/// Game from `start` between two engines configured like `engine`.
+fn self_play(
+    engine: &Engine,
+    start: Board,
+    iterations: u32,
+) -> Result<GameOutcome, StoctopusError> {
+    let limits = SearchLimits::iterations(iterations);
+    let runner = MatchRunner::new(rayon::current_num_threads(), limits)?;
+    let (mut x, mut o) = (engine.engine_for(start)?, engine.engine_for(start)?);
+    runner.play_game_from(start, &mut x, &mut o)
+}
+
+/// Does jobs from the coordinator at `addr` with engines configured like
+/// `engine` until there are none left, returning how many it did.
+pub fn run_worker(addr: impl ToSocketAddrs, engine: &Engine) -> Result<usize, StoctopusError> {
+    let mut stream = TcpStream::connect(addr)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut done = 0;
+    loop {
+        send(&mut stream, &Message::Get)?;
+        match receive(&mut reader)? {
+            Some(Message::Job {
+                id,
+                iterations,
+                board,
+            }) => {
+                let result = board
+                    .board()
+                    .map(|board| engine.evaluate_positions(&[board], iterations))
+                    .and_then(|mut results| results.pop().expect("One position"));
+                let reply = match result {
+                    Ok(evaluation) => Message::Result { id, evaluation },
+                    Err(err) => Message::Failed {
+                        id,
+                        error: err.to_string(),
+                    },
+                };
+                send(&mut stream, &reply)?;
+                done += 1;
+            }
+            Some(Message::SelfPlay {
+                id,
+                iterations,
+                start,
+            }) => {
+                let result = start
+                    .board()
+                    .and_then(|start| self_play(engine, start, iterations));
+                let reply = match result {
+                    Ok(game) => Message::Game { id, game },
+                    Err(err) => Message::Failed {
+                        id,
+                        error: err.to_string(),
+                    },
+                };
+                send(&mut stream, &reply)?;
+                done += 1;
+            }
+            Some(Message::Wait { millis }) => thread::sleep(Duration::from_millis(millis)),
+            Some(Message::Done) | None => return Ok(done),
+            Some(_) => return Err(protocol("Unexpected message from coordinator")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod distributed_tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::distributed::{run_worker, Coordinator, Job, JobOutput};
+    use crate::{Board, Engine, GameState};
+
+    /// Connects and takes a job without ever answering it.
+    fn take_job(addr: std::net::SocketAddr) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"{\"type\": \"get\"}\n").unwrap();
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line).unwrap();
+        assert!(
+            line.contains("\"job\"") || line.contains("self_play"),
+            "{line}"
+        );
+        stream
+    }
+
+    #[test]
+    fn test_coordinator_and_workers() {
+        let mut jobs: Vec<_> = (0..5)
+            .map(|plies| Job::Search {
+                board: Board::random_position(plies * 4, &mut StdRng::seed_from_u64(plies as u64)),
+                iterations: 50,
+            })
+            .collect();
+        // Finished, so the search fails.
+        jobs.push(Job::Search {
+            board: Board::random_position(81, &mut StdRng::seed_from_u64(3)),
+            iterations: 50,
+        });
+        let start = Board::random_position(30, &mut StdRng::seed_from_u64(4));
+        jobs.push(Job::SelfPlay {
+            start,
+            iterations: 10,
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let results = thread::scope(|scope| {
+            let coordinator = scope.spawn(|| {
+                Coordinator::new(jobs.clone())
+                    .with_timeout(Duration::from_secs(2))
+                    .run(listener)
+            });
+            // A worker that takes a job and vanishes doesn't lose it, and
+            // neither does one that takes a job and hangs.
+            drop(take_job(addr));
+            let hung = take_job(addr);
+
+            let workers: Vec<_> = (0..2)
+                .map(|_| scope.spawn(|| run_worker(addr, &Engine::init()).unwrap()))
+                .collect();
+            let done: usize = workers.into_iter().map(|w| w.join().unwrap()).sum();
+            assert_eq!(done, jobs.len());
+            let results = coordinator.join().unwrap().unwrap();
+            drop(hung);
+            results
+        });
+
+        assert_eq!(results.len(), jobs.len());
+        for (job, result) in jobs.iter().zip(&results) {
+            match (job, result) {
+                (Job::Search { board, .. }, _) if board.game_over() => assert!(result.is_err()),
+                (Job::Search { board, .. }, Ok(JobOutput::Evaluation(evaluation))) => {
+                    let (global, local) = evaluation.best_move.unwrap();
+                    assert!(board.get_moves() & (1 << (global * 9 + local)) != 0);
+                }
+                (Job::SelfPlay { .. }, Ok(JobOutput::Game(game))) => {
+                    assert!(!game.moves.is_empty());
+                    assert_eq!(game.root_visits.len(), game.moves.len());
+                    assert_ne!(game.result, GameState::InProgress);
+                }
+                _ => panic!("{job:?} got {result:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_second_get_requeues() {
+        let jobs: Vec<_> = (0..2)
+            .map(|plies| Job::Search {
+                board: Board::random_position(plies * 4, &mut StdRng::seed_from_u64(plies as u64)),
+                iterations: 20,
+            })
+            .collect();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let coordinator_jobs = jobs.clone();
+        thread::spawn(move || {
+            let results = Coordinator::new(coordinator_jobs)
+                .with_timeout(Duration::from_secs(2))
+                .run(listener);
+            sender.send(results).unwrap();
+        });
+
+        // Takes both jobs in turn on one connection and leaves without
+        // answering either, then a real worker has to do both.
+        let mut greedy = take_job(addr);
+        greedy.write_all(b"{\"type\": \"get\"}\n").unwrap();
+        let mut line = String::new();
+        BufReader::new(&greedy).read_line(&mut line).unwrap();
+        assert!(line.contains("\"job\""), "{line}");
+        drop(greedy);
+
+        let worker = thread::spawn(move || run_worker(addr, &Engine::init()));
+        let results = receiver
+            .recv_timeout(Duration::from_secs(30))
+            .expect("The coordinator lost a job")
+            .unwrap();
+        assert_eq!(worker.join().unwrap().unwrap(), jobs.len());
+        assert_eq!(results.len(), jobs.len());
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, Ok(JobOutput::Evaluation(_)))));
+    }
+}
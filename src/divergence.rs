@@ -0,0 +1,110 @@
+//! Why two searches of the same position came out differently, e.g. runs on
+//! two machines or with different thread counts.
+
+use std::fmt::{self, Display};
+
+use crate::{Evaluation, SearchMode};
+
+/// FNV-1a, which unlike the std hashers is guaranteed to give the same
+/// result on every machine and Rust version.
+pub(crate) fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Differences between two [`Evaluation`]s, see
+/// [`Engine::explain_divergence`](crate::Engine::explain_divergence).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    /// The searches were configured differently, so they may disagree.
+    pub config_changed: bool,
+    /// At least one search ran in [`SearchMode::Parallel`], whose trees
+    /// depend on thread timing.
+    pub nondeterministic: bool,
+    /// Most visited root move of each evaluation.
+    pub best_moves: (Option<u8>, Option<u8>),
+    /// Root moves visited differently, as `(move, visits in a, visits in b)`.
+    pub visits: Vec<(u8, u32, u32)>,
+    /// `b.confidence - a.confidence`.
+    pub confidence_delta: f32,
+}
+
+fn most_visited(ev: &Evaluation) -> Option<u8> {
+    ev.root_visits
+        .iter()
+        .max_by_key(|&&(mve, visits)| (visits, std::cmp::Reverse(mve)))
+        .map(|&(mve, _)| mve)
+}
+
+impl Divergence {
+    pub(crate) fn between(a: &Evaluation, b: &Evaluation) -> Self {
+        let mut visits = vec![];
+        let mut moves: Vec<u8> = a
+            .root_visits
+            .iter()
+            .chain(&b.root_visits)
+            .map(|&(mve, _)| mve)
+            .collect();
+        moves.sort_unstable();
+        moves.dedup();
+        let visits_of = |ev: &Evaluation, mve| {
+            ev.root_visits
+                .iter()
+                .find(|&&(m, _)| m == mve)
+                .map_or(0, |&(_, visits)| visits)
+        };
+        for mve in moves {
+            let (in_a, in_b) = (visits_of(a, mve), visits_of(b, mve));
+            if in_a != in_b {
+                visits.push((mve, in_a, in_b));
+            }
+        }
+        Self {
+            config_changed: a.config != b.config,
+            nondeterministic: a.config.mode == SearchMode::Parallel
+                || b.config.mode == SearchMode::Parallel,
+            best_moves: (most_visited(a), most_visited(b)),
+            visits,
+            confidence_delta: b.confidence - a.confidence,
+        }
+    }
+
+    /// Both searches built the same root visit distribution.
+    pub fn is_identical(&self) -> bool {
+        self.visits.is_empty()
+    }
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_identical() {
+            return writeln!(f, "Identical root visits");
+        }
+        if self.config_changed {
+            writeln!(f, "The configurations differ")?;
+        }
+        if self.nondeterministic {
+            writeln!(
+                f,
+                "Parallel searches aren't reproducible, use a deterministic seed"
+            )?;
+        }
+        let name = |mve: Option<u8>| {
+            mve.map_or("none".to_string(), |m| format!("{}{}", m >> 4, m & 0b1111))
+        };
+        writeln!(
+            f,
+            "Best move {} vs {}, confidence {:+.1}%",
+            name(self.best_moves.0),
+            name(self.best_moves.1),
+            self.confidence_delta
+        )?;
+        for &(mve, a, b) in &self.visits {
+            writeln!(f, "  {}: {a} vs {b} visits", name(Some(mve)))?;
+        }
+        Ok(())
+    }
+}
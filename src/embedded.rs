@@ -0,0 +1,262 @@
+//! Single-threaded search in a node buffer the caller provides, for
+//! targets without `std` or an allocator, e.g. the microcontroller of a
+//! physical game board. Randomness comes from the caller as well, so any
+//! [`Rng`], say one fed by a hardware source, will do.
+//!
+//! The search is plain UCT with random playouts. Once the buffer is full
+//! the tree stops growing and the remaining iterations refine the nodes it
+//! has. This module and [`Board`] are all that's left with `default-features
+//! = false`; targets without dynamic libraries, like the usual embedded
+//! ones, skip the crate's `cdylib` build.
+//!
+//! ```
+//! use rand::{rngs::StdRng, SeedableRng};
+//! use stoctopus::embedded::{FixedSearch, SearchNode};
+//! use stoctopus::Board;
+//!
+//! let mut nodes = [SearchNode::default(); 1024];
+//! let mut search = FixedSearch::new(Board::default(), &mut nodes).unwrap();
+//! search.run(2000, &mut StdRng::seed_from_u64(1));
+//! assert!(search.best_move().is_some());
+//! ```
+
+use rand::Rng;
+
+use crate::game::{find_kth_high_bit_index, Board, GameState};
+
+/// UCT exploration constant.
+const EXPLORATION: f32 = 1.4;
+/// A game has at most 81 moves, so the line from the root to any node
+/// holds at most 82 nodes.
+const MAX_LINE: usize = 82;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SearchNode {
+    board: Board,
+    /// Index of the first child, 0 until the node is expanded. The root is
+    /// nobody's child.
+    first_child: u32,
+    children: u8,
+    visits: u32,
+    /// Results of the player who moved into the node: 1 per win, 0.5 per
+    /// draw.
+    score: f32,
+}
+
+impl SearchNode {
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn visits(&self) -> u32 {
+        self.visits
+    }
+
+    /// Wins per visit of the player who moved into the node, draws counting
+    /// half. 0 before the first visit.
+    pub fn win_rate(&self) -> f32 {
+        if self.visits == 0 {
+            0.0
+        } else {
+            self.score / self.visits as f32
+        }
+    }
+}
+
+/// Search tree laid out in a borrowed buffer, children of a node next to
+/// each other.
+pub struct FixedSearch<'a> {
+    nodes: &'a mut [SearchNode],
+    len: usize,
+}
+
+impl<'a> FixedSearch<'a> {
+    /// Starts a search of `board` in `nodes`, or `None` when there's no room
+    /// for even the root.
+    pub fn new(board: Board, nodes: &'a mut [SearchNode]) -> Option<Self> {
+        *nodes.first_mut()? = SearchNode {
+            board,
+            ..SearchNode::default()
+        };
+        Some(Self { nodes, len: 1 })
+    }
+
+    pub fn run<R: Rng + ?Sized>(&mut self, iterations: u32, rng: &mut R) {
+        for _ in 0..iterations {
+            self.iterate(rng);
+        }
+    }
+
+    pub fn root(&self) -> &SearchNode {
+        &self.nodes[0]
+    }
+
+    /// Children of the root, one per legal move once it's expanded.
+    pub fn root_children(&self) -> &[SearchNode] {
+        self.children(0)
+    }
+
+    /// Most visited move at the root as `(global, local)`, `None` before the
+    /// root is expanded.
+    pub fn best_move(&self) -> Option<(u8, u8)> {
+        let best = self
+            .root_children()
+            .iter()
+            .max_by_key(|child| child.visits)?;
+        let m = best.board.last_move?;
+        Some((m >> 4, m & 0b1111))
+    }
+
+    /// Nodes of the buffer in use.
+    pub fn nodes_used(&self) -> usize {
+        self.len
+    }
+
+    fn children(&self, id: usize) -> &[SearchNode] {
+        let node = &self.nodes[id];
+        let first = node.first_child as usize;
+        &self.nodes[first..first + node.children as usize]
+    }
+
+    fn iterate<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        let mut line = [0; MAX_LINE];
+        let mut depth = 0;
+        let mut id = 0;
+        while self.nodes[id].children > 0 {
+            id = self.select(id);
+            depth += 1;
+            line[depth] = id;
+        }
+        if !self.nodes[id].board.game_over() && self.expand(id) {
+            let k = rng.gen_range(0..self.nodes[id].children as usize);
+            id = self.nodes[id].first_child as usize + k;
+            depth += 1;
+            line[depth] = id;
+        }
+
+        let result = playout(self.nodes[id].board, rng);
+        for &id in &line[..=depth] {
+            let node = &mut self.nodes[id];
+            node.visits += 1;
+            node.score += match result {
+                GameState::Won(winner) if winner != node.board.next_player => 1.0,
+                GameState::Draw => 0.5,
+                _ => 0.0,
+            };
+        }
+    }
+
+    /// Child of `id` with the highest UCT value, unvisited children first.
+    fn select(&self, id: usize) -> usize {
+        // `core` has no `ln` or `sqrt` of its own.
+        let log_visits = libm::logf(self.nodes[id].visits.max(1) as f32);
+        let uct = |child: &SearchNode| {
+            if child.visits == 0 {
+                return f32::INFINITY;
+            }
+            child.win_rate() + EXPLORATION * libm::sqrtf(log_visits / child.visits as f32)
+        };
+        let (best, _) = self.children(id).iter().enumerate().fold(
+            (0, f32::NEG_INFINITY),
+            |(best, value), (i, child)| {
+                let child = uct(child);
+                if child > value {
+                    (i, child)
+                } else {
+                    (best, value)
+                }
+            },
+        );
+        self.nodes[id].first_child as usize + best
+    }
+
+    /// Gives `id` a child per legal move, if they fit in the buffer.
+    fn expand(&mut self, id: usize) -> bool {
+        let board = self.nodes[id].board;
+        let mut moves = board.get_moves();
+        let count = moves.count_ones() as usize;
+        if count == 0 || self.len + count > self.nodes.len() {
+            return false;
+        }
+        let first = self.len;
+        for child in &mut self.nodes[first..first + count] {
+            let cell = moves.trailing_zeros() as u8;
+            moves &= moves - 1;
+            *child = SearchNode {
+                board: board.unchecked_play(Board::move_from_index(cell)),
+                ..SearchNode::default()
+            };
+        }
+        self.nodes[id].first_child = first as u32;
+        self.nodes[id].children = count as u8;
+        self.len += count;
+        true
+    }
+}
+
+/// Plays random moves from `board` to the end of the game.
+fn playout<R: Rng + ?Sized>(mut board: Board, rng: &mut R) -> GameState {
+    loop {
+        let state = board.check_game_state();
+        if state != GameState::InProgress {
+            return state;
+        }
+        let moves = board.get_moves();
+        let k = rng.gen_range(0..moves.count_ones());
+        let index = find_kth_high_bit_index(moves, k).expect("k is below the move count");
+        board = board.unchecked_play(Board::move_from_index(index));
+    }
+}
+
+#[cfg(test)]
+mod embedded_tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::embedded::{FixedSearch, SearchNode};
+    use crate::game::{Board, GameState, Rules, Variant};
+
+    #[test]
+    fn test_buffer_limit() {
+        let mut rng = StdRng::seed_from_u64(1);
+        assert!(FixedSearch::new(Board::default(), &mut []).is_none());
+
+        let mut nodes = [SearchNode::default(); 100];
+        let mut search = FixedSearch::new(Board::default(), &mut nodes).unwrap();
+        assert_eq!(search.best_move(), None);
+        search.run(500, &mut rng);
+        assert!(search.nodes_used() <= 100);
+        assert_eq!(search.root().visits(), 500);
+        let visits: u32 = search.root_children().iter().map(SearchNode::visits).sum();
+        assert_eq!(visits, 500);
+        let (global, local) = search.best_move().unwrap();
+        assert!(Board::default().get_moves() & 1 << (global * 9 + local) != 0);
+    }
+
+    #[test]
+    fn test_finds_wins() {
+        let rules = Rules {
+            variant: Variant::NineBoard,
+            ..Rules::default()
+        };
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut nodes = vec![SearchNode::default(); 4096];
+        let mut found = 0;
+        while found < 5 {
+            let board = Board::random_position_with_rules(rules, 12, &mut rng);
+            let moves = board.get_moves();
+            let wins = |index: u8| {
+                let after = board.unchecked_play(Board::move_from_index(index));
+                after.check_game_state() == GameState::Won(board.next_player)
+            };
+            if board.game_over() || !(0..81).any(|i| moves & 1 << i != 0 && wins(i)) {
+                continue;
+            }
+            found += 1;
+
+            let mut search = FixedSearch::new(board, &mut nodes).unwrap();
+            search.run(2000, &mut rng);
+            let (global, local) = search.best_move().unwrap();
+            assert!(wins(global * 9 + local), "Missed the win in {board:?}");
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt::Display;
+
+use crate::game::BoardError;
+
+/// Errors surfaced by the public API.
+#[derive(Debug)]
+pub enum StoctopusError {
+    IllegalMove,
+    InvalidBoard(BoardError),
+    Search(SearchError),
+    Io(std::io::Error),
+    /// Malformed input to a text protocol or file format.
+    Protocol(String),
+    /// An argument to an API call outside the values it accepts.
+    InvalidArgument(String),
+    ThreadPool(rayon::ThreadPoolBuildError),
+    Session(SessionError),
+}
+
+/// Problems with a request to a [`SessionManager`](crate::session::SessionManager).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionError {
+    /// No session has this id, or it was evicted.
+    Unknown(u64),
+    /// The session limit is reached.
+    TooManySessions,
+    /// Too many searches are running already.
+    Overloaded,
+    /// The manager is shutting down and takes no new work.
+    ShuttingDown,
+}
+
+/// Conditions that stop a search from producing a result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchError {
+    /// The position is already decided, so there is no move to search.
+    GameOver,
+    /// A node that isn't terminal was expanded without any children.
+    NoChildren,
+    /// Move generation and move selection disagreed during a playout.
+    NoMove,
+    /// The search was cancelled before it expanded the root.
+    Cancelled,
+    /// A fixed-capacity tree is full.
+    OutOfNodes,
+    /// A node handle outlived its node, which garbage collection freed, or
+    /// belongs to another search tree, e.g. one a later search replaced.
+    StaleNode,
+    /// A playout's result was backpropagated before its game was over.
+    Unfinished,
+}
+
+impl Display for StoctopusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IllegalMove => f.write_str("Illegal move"),
+            Self::InvalidBoard(err) => write!(f, "Invalid board: {err}"),
+            Self::Search(err) => write!(f, "Search failed: {err}"),
+            Self::Io(err) => write!(f, "IO error: {err}"),
+            Self::Protocol(msg) => write!(f, "Protocol error: {msg}"),
+            Self::InvalidArgument(msg) => write!(f, "Invalid argument: {msg}"),
+            Self::ThreadPool(err) => write!(f, "Couldn't build thread pool: {err}"),
+            Self::Session(err) => write!(f, "Session error: {err}"),
+        }
+    }
+}
+
+impl Display for SearchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::GameOver => f.write_str("Game is over"),
+            Self::NoChildren => f.write_str("Non terminal node has no children"),
+            Self::NoMove => f.write_str("No move found for playout"),
+            Self::Cancelled => f.write_str("Search was cancelled"),
+            Self::OutOfNodes => f.write_str("Search tree is full"),
+            Self::StaleNode => f.write_str("Node was collected or is from another tree"),
+            Self::Unfinished => f.write_str("Playout ended before the game did"),
+        }
+    }
+}
+
+impl Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown(id) => write!(f, "No session {id}"),
+            Self::TooManySessions => f.write_str("Too many sessions"),
+            Self::Overloaded => f.write_str("Too many searches running"),
+            Self::ShuttingDown => f.write_str("Shutting down"),
+        }
+    }
+}
+
+impl std::error::Error for StoctopusError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidBoard(err) => Some(err),
+            Self::Search(err) => Some(err),
+            Self::Io(err) => Some(err),
+            Self::ThreadPool(err) => Some(err),
+            Self::Session(err) => Some(err),
+            Self::IllegalMove | Self::Protocol(_) | Self::InvalidArgument(_) => None,
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+impl std::error::Error for SessionError {}
+
+impl From<BoardError> for StoctopusError {
+    fn from(err: BoardError) -> Self {
+        Self::InvalidBoard(err)
+    }
+}
+
+impl From<SearchError> for StoctopusError {
+    fn from(err: SearchError) -> Self {
+        Self::Search(err)
+    }
+}
+
+impl From<SessionError> for StoctopusError {
+    fn from(err: SessionError) -> Self {
+        Self::Session(err)
+    }
+}
+
+impl From<std::io::Error> for StoctopusError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
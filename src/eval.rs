@@ -0,0 +1,167 @@
+//! Static position evaluation. Used where running a search is too
+//! expensive, e.g. for an evaluation bar that updates on every move.
+
+use crate::game::{Board, GameState, Player, WIN_MASKS};
+
+/// Value of owning each sub-board: center, then corners, then edges.
+const SQUARE_WEIGHTS: [f32; 9] = [3.0, 2.0, 3.0, 2.0, 4.0, 2.0, 3.0, 2.0, 3.0];
+/// Value of an open line that needs one more square.
+const THREAT: f32 = 4.0;
+/// Weight of local cells relative to whole sub-boards.
+const LOCAL_SCALE: f32 = 0.1;
+/// Score difference that maps to roughly 73% win probability.
+const LOGISTIC_SCALE: f32 = 6.0;
+
+/// Prior bonus for a move that wins its sub-board.
+const LOCAL_WIN: f32 = 6.0;
+/// Prior bonus for a move that wins the game.
+const GAME_WIN: f32 = 100.0;
+/// Prior penalty for sending the opponent to a sub-board they can win
+/// right away.
+const SEND_TO_THREAT: f32 = 5.0;
+/// Prior penalty for giving the opponent a free choice of sub-board.
+const SEND_TO_ANY: f32 = 3.0;
+/// Temperature of the softmax turning prior scores into probabilities.
+const PRIOR_TEMPERATURE: f32 = 2.0;
+
+/// Counts lines of `mine` that need one more square and aren't blocked by
+/// `blocked`.
+fn threats(mine: u16, blocked: u16) -> f32 {
+    WIN_MASKS
+        .to_array()
+        .iter()
+        .filter(|&&line| (mine & line).count_ones() == 2 && blocked & line == 0)
+        .count() as f32
+}
+
+/// Heuristic score from X's point of view, positive when X is better.
+fn score_for_x(board: &Board) -> f32 {
+    let drawn = board.gx & board.go;
+    let (gx, go) = (board.gx & !drawn, board.go & !drawn);
+
+    let mut score = 0.0;
+    for (global, weight) in SQUARE_WEIGHTS.iter().enumerate() {
+        if gx & (1 << global) != 0 {
+            score += weight;
+        } else if go & (1 << global) != 0 {
+            score -= weight;
+        } else if drawn & (1 << global) == 0 {
+            let xbits = ((board.x >> (global * 9)) & 0b111_111_111) as u16;
+            let obits = ((board.o >> (global * 9)) & 0b111_111_111) as u16;
+            let local = (0..9)
+                .map(|cell| {
+                    if xbits & (1 << cell) != 0 {
+                        SQUARE_WEIGHTS[cell]
+                    } else if obits & (1 << cell) != 0 {
+                        -SQUARE_WEIGHTS[cell]
+                    } else {
+                        0.0
+                    }
+                })
+                .sum::<f32>()
+                + threats(xbits, obits) * THREAT
+                - threats(obits, xbits) * THREAT;
+            score += local * LOCAL_SCALE * weight;
+        }
+    }
+
+    score + (threats(gx, go | drawn) - threats(go, gx | drawn)) * THREAT
+}
+
+/// Win probability of the side to move in `[0, 1]`, without any search.
+pub fn static_eval(board: &Board) -> f32 {
+    let for_x = match board.check_game_state() {
+        GameState::Won(Player::X) => 1.0,
+        GameState::Won(Player::O) => 0.0,
+        GameState::Draw => 0.5,
+        GameState::InProgress => {
+            // Owning sub-boards is a liability when completing a line loses.
+            let score = match board.rules.misere {
+                false => score_for_x(board),
+                true => -score_for_x(board),
+            };
+            1.0 / (1.0 + (-score / LOGISTIC_SCALE).exp())
+        }
+    };
+    match board.next_player {
+        Player::X => for_x,
+        Player::O => 1.0 - for_x,
+    }
+}
+
+/// Cheap probabilities that each of `moves` is the best move in `board`.
+/// Prefers central cells and local wins, and avoids sending the opponent
+/// somewhere they can win or choose freely. Sums to 1.
+pub fn move_priors(board: &Board, moves: &[u8]) -> Vec<f32> {
+    let me = board.next_player;
+    // Completing sub-boards and lines is what loses a misère game.
+    let win_sign = if board.rules.misere { -1.0 } else { 1.0 };
+    let scores: Vec<f32> = moves
+        .iter()
+        .map(|&m| {
+            let (global, local) = (m >> 4, m & 0b1111);
+            let mut score = SQUARE_WEIGHTS[local as usize];
+            if board.sub_board_threats(global, me) & (1 << local) != 0 {
+                score += win_sign * LOCAL_WIN;
+                if board.macro_threats(me) & (1 << global) != 0 {
+                    score += win_sign * GAME_WIN;
+                }
+            }
+            let child = board.unchecked_play(m);
+            if (child.gx | child.go) & (1 << local) != 0 {
+                score -= SEND_TO_ANY;
+            } else if child.sub_board_threats(local, me.other()) != 0 {
+                score -= SEND_TO_THREAT;
+            }
+            score
+        })
+        .collect();
+
+    let max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let weights: Vec<f32> = scores
+        .iter()
+        .map(|score| ((score - max) / PRIOR_TEMPERATURE).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.into_iter().map(|weight| weight / total).collect()
+}
+
+#[cfg(test)]
+mod eval_tests {
+    use crate::eval::{move_priors, static_eval};
+    use crate::game::Board;
+
+    #[test]
+    fn test_static_eval() {
+        let board = Board::default();
+        assert_eq!(static_eval(&board), 0.5);
+
+        // X owns the center sub-board, O to move.
+        let mut board = board;
+        for m in [(4, 0), (0, 4), (4, 4), (4, 5), (4, 8)] {
+            board = board.unchecked_play(Board::move_from_gl(m.0, m.1));
+        }
+        assert!(static_eval(&board) < 0.5);
+    }
+
+    #[test]
+    fn test_move_priors() {
+        let moves: Vec<u8> = (0..9).map(|l| Board::move_from_gl(4, l)).collect();
+        let priors = move_priors(&Board::default(), &moves);
+        assert!((priors.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        // The center beats a corner, which beats an edge.
+        assert!(priors[4] > priors[0] && priors[0] > priors[1]);
+
+        // X threatens 0-1-2 in the center sub-board and is sent back there.
+        let mut board = Board::default();
+        for (g, l) in [(4, 0), (0, 4), (4, 1), (1, 4)] {
+            board = board.unchecked_play(Board::move_from_gl(g, l));
+        }
+        let moves: Vec<u8> = [2, 3, 5]
+            .into_iter()
+            .map(|l| Board::move_from_gl(4, l))
+            .collect();
+        let priors = move_priors(&board, &moves);
+        assert!(priors[0] > priors[1] && priors[0] > priors[2]);
+    }
+}
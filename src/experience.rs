@@ -0,0 +1,192 @@
+//! What earlier searches learned about positions, kept across games so the
+//! engine gets better at the positions it keeps meeting.
+//!
+//! After every search the well visited nodes of the tree are added to the
+//! table, keyed by canonical position hash so symmetric positions share an
+//! entry. New searches start nodes the table knows with some visits at the
+//! learned value, see [`Experience::max_prior_visits`]. It is written as a
+//! flat little-endian file: the magic bytes, the entry count as a `u64`,
+//! then per entry the hash (`u64`), visits and value (`f32` each).
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use crate::error::StoctopusError;
+use crate::mcts::MCTSNode;
+
+const MAGIC: &[u8; 4] = b"STEX";
+
+/// Nodes with fewer visits are too noisy to learn from.
+const MIN_LEARN_VISITS: f32 = 32.0;
+
+/// Aggregated results of one position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExperienceEntry {
+    pub visits: f32,
+    /// Win rate of the player who moved into the position, from 0 to 1.
+    pub value: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Experience {
+    entries: HashMap<u64, ExperienceEntry>,
+    /// Visits a node known to the table starts with, at most. Fewer if the
+    /// table has seen the position less often.
+    pub max_prior_visits: f32,
+    /// Visits an entry keeps at most, so that newer results still move the
+    /// value once a position was seen many times.
+    pub max_visits: f32,
+}
+
+impl Default for Experience {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_prior_visits: 20.0,
+            max_visits: 100_000.0,
+        }
+    }
+}
+
+impl deepsize::DeepSizeOf for Experience {
+    fn deep_size_of_children(&self, _context: &mut deepsize::Context) -> usize {
+        self.entries.capacity() * std::mem::size_of::<(u64, ExperienceEntry)>()
+    }
+}
+
+impl Experience {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, hash: u64) -> Option<&ExperienceEntry> {
+        self.entries.get(&hash)
+    }
+
+    /// Adds `visits` visits with an average of `value` to the entry of
+    /// `hash`.
+    pub fn record(&mut self, hash: u64, visits: f32, value: f32) {
+        let entry = self.entries.entry(hash).or_insert(ExperienceEntry {
+            visits: 0.0,
+            value: 0.0,
+        });
+        let total = entry.visits + visits;
+        if total > 0.0 {
+            entry.value = (entry.value * entry.visits + value * visits) / total;
+            entry.visits = total.min(self.max_visits);
+        }
+    }
+
+    /// Virtual `(visits, value)` a new node for the position starts with.
+    pub(crate) fn prior(&self, hash: u64) -> Option<(f32, f32)> {
+        let entry = self.get(hash)?;
+        Some((entry.visits.min(self.max_prior_visits), entry.value))
+    }
+
+    /// Records the nodes of a search tree whose root is `nodes[0]`. Wins
+    /// are counted for the root's player, as in the search.
+    pub(crate) fn learn(&mut self, nodes: &[MCTSNode]) {
+        let Some(root) = nodes.first() else {
+            return;
+        };
+        let player = root.board.next_player;
+        for node in &nodes[1..] {
+            if node.visits < MIN_LEARN_VISITS {
+                continue;
+            }
+            let win_rate = node.wins / node.visits;
+            let value = if node.board.next_player == player {
+                1.0 - win_rate
+            } else {
+                win_rate
+            };
+            self.record(node.board.canonical_hash(), node.visits, value);
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), StoctopusError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for (hash, entry) in &self.entries {
+            writer.write_all(&hash.to_le_bytes())?;
+            writer.write_all(&entry.visits.to_le_bytes())?;
+            writer.write_all(&entry.value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, StoctopusError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(StoctopusError::Protocol(
+                "Not an experience file".to_string(),
+            ));
+        }
+        let mut buf = [0; 8];
+        reader.read_exact(&mut buf)?;
+        let len = u64::from_le_bytes(buf);
+
+        let mut experience = Self::new();
+        for _ in 0..len {
+            let mut entry = [0; 16];
+            reader.read_exact(&mut entry)?;
+            let hash = u64::from_le_bytes(entry[0..8].try_into().expect("8 bytes"));
+            experience.entries.insert(
+                hash,
+                ExperienceEntry {
+                    visits: f32::from_le_bytes(entry[8..12].try_into().expect("4 bytes")),
+                    value: f32::from_le_bytes(entry[12..16].try_into().expect("4 bytes")),
+                },
+            );
+        }
+        Ok(experience)
+    }
+}
+
+#[cfg(test)]
+mod experience_tests {
+    use crate::experience::Experience;
+    use crate::StoctopusError;
+
+    #[test]
+    fn test_record() {
+        let mut experience = Experience::new();
+        experience.record(1, 10.0, 1.0);
+        experience.record(1, 30.0, 0.0);
+        let entry = experience.get(1).unwrap();
+        assert_eq!((entry.visits, entry.value), (40.0, 0.25));
+        assert_eq!(experience.prior(1), Some((20.0, 0.25)));
+        assert_eq!(experience.prior(2), None);
+
+        experience.max_visits = 50.0;
+        experience.record(1, 40.0, 1.0);
+        let entry = experience.get(1).unwrap();
+        assert_eq!((entry.visits, entry.value), (50.0, 0.625));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut experience = Experience::new();
+        experience.record(1, 10.0, 0.5);
+        experience.record(u64::MAX, 3.0, 0.75);
+        let mut bytes = vec![];
+        experience.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 4 + 8 + 2 * 16);
+        assert_eq!(Experience::read_from(&mut &bytes[..]).unwrap(), experience);
+
+        bytes[0] = b'X';
+        assert!(matches!(
+            Experience::read_from(&mut &bytes[..]),
+            Err(StoctopusError::Protocol(_))
+        ));
+    }
+}
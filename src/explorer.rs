@@ -0,0 +1,228 @@
+//! Walks a search tree by hand, to inspect why a line was preferred.
+//!
+//! [`TreeExplorer::command`] takes one line of text and returns the text to
+//! print, so a front end only has to forward its `tree` command input:
+//!
+//! - `ls`: children of the current node with their statistics
+//! - `cd <n>`: descend into the `n`th child as listed by `ls`
+//! - `up`: go back to the parent
+//! - `root`: go back to where exploration started
+//! - `pv`: jump to the end of the principal variation
+
+use std::fmt::Write;
+
+use crate::mcts::NodeId;
+use crate::Engine;
+
+/// Statistics of one child of a node.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChildStats {
+    pub node: NodeId,
+    /// `(global, local)` coordinates of the move leading to the child.
+    pub mve: (u8, u8),
+    pub visits: f32,
+    /// Wins per visit, from the perspective of the search's root player.
+    pub win_rate: f32,
+    pub prior: f32,
+    /// Plies the games simulated through the child lasted after the
+    /// parent's position, on average. `None` before any was.
+    pub expected_plies: Option<f32>,
+}
+
+/// Root moves laid out on the 9x9 grid, indexed `row * 9 + col`, for
+/// drawing an analysis overlay. Cells without a searched move are 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RootHeatmap {
+    /// Share of the root's visits. Moves the search skipped for being
+    /// symmetric to a searched one split its visits with it.
+    pub visit_share: [f32; 81],
+    /// Win rate of the side to move when playing the move.
+    pub value: [f32; 81],
+}
+
+impl Default for RootHeatmap {
+    fn default() -> Self {
+        Self {
+            visit_share: [0.0; 81],
+            value: [0.0; 81],
+        }
+    }
+}
+
+pub struct TreeExplorer<'a> {
+    engine: &'a Engine,
+    /// Nodes from where exploration started to the current one.
+    path: Vec<NodeId>,
+}
+
+impl<'a> TreeExplorer<'a> {
+    pub fn new(engine: &'a Engine) -> Self {
+        Self {
+            engine,
+            path: vec![engine.current_node()],
+        }
+    }
+
+    /// Picks up an exploration left with [`Self::into_path`]. Starts over
+    /// when the path doesn't begin at the engine's current node.
+    pub fn resume(engine: &'a Engine, path: Vec<NodeId>) -> Self {
+        match path.first() {
+            Some(&start) if start == engine.current_node() => Self { engine, path },
+            _ => Self::new(engine),
+        }
+    }
+
+    /// Nodes from where exploration started to the current one, to
+    /// [`Self::resume`] from later.
+    pub fn into_path(self) -> Vec<NodeId> {
+        self.path
+    }
+
+    pub fn current(&self) -> NodeId {
+        *self.path.last().expect("Path always holds the start node")
+    }
+
+    pub fn depth(&self) -> usize {
+        self.path.len() - 1
+    }
+
+    /// Children of the current node, most visited first.
+    pub fn children(&self) -> Vec<ChildStats> {
+        self.engine.child_stats(self.current())
+    }
+
+    /// Moves to the `index`th child as returned by [`Self::children`].
+    pub fn descend(&mut self, index: usize) -> Option<NodeId> {
+        let child = self.children().get(index)?.node;
+        self.path.push(child);
+        Some(child)
+    }
+
+    /// Moves to the parent, unless already at the start node.
+    pub fn ascend(&mut self) -> Option<NodeId> {
+        if self.path.len() == 1 {
+            return None;
+        }
+        self.path.pop();
+        Some(self.current())
+    }
+
+    /// Follows the most visited child until reaching a leaf.
+    pub fn jump_to_pv(&mut self) {
+        while self.descend(0).is_some() {}
+    }
+
+    pub fn reset(&mut self) {
+        self.path.truncate(1);
+    }
+
+    /// Runs one text command, returning the output to show.
+    pub fn command(&mut self, line: &str) -> String {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("ls"), None) => self.listing(),
+            (Some("cd"), Some(index)) => match index.parse().ok().and_then(|i| self.descend(i)) {
+                Some(_) => self.listing(),
+                None => format!("no child {index}"),
+            },
+            (Some("up"), None) => match self.ascend() {
+                Some(_) => self.listing(),
+                None => "already at the root".to_string(),
+            },
+            (Some("root"), None) => {
+                self.reset();
+                self.listing()
+            }
+            (Some("pv"), None) => {
+                self.jump_to_pv();
+                self.listing()
+            }
+            _ => "commands: ls, cd <n>, up, root, pv".to_string(),
+        }
+    }
+
+    fn listing(&self) -> String {
+        let Ok(node) = self.engine.try_resolve_node(&self.current()) else {
+            return "node was collected, back to the root with `root`".to_string();
+        };
+        let mut out = format!(
+            "depth {} visits {} win rate {:.3}\n",
+            self.depth(),
+            node.visits,
+            node.wins / node.visits.max(1.0)
+        );
+        for (i, child) in self.children().iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "{i:>2}: ({}, {}) visits {:>7} win rate {:.3} prior {:.3}",
+                child.mve.0, child.mve.1, child.visits, child.win_rate, child.prior
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod explorer_tests {
+    use crate::explorer::TreeExplorer;
+    use crate::{Engine, MCTSConfig, SearchMode};
+
+    #[test]
+    fn test_explore() {
+        let mut engine = Engine::with_config(MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 2 },
+            ..Default::default()
+        });
+        engine.analyze(200).unwrap();
+
+        let mut explorer = TreeExplorer::new(&engine);
+        let children = explorer.children();
+        assert_eq!(children.len(), 15);
+        assert!(children.windows(2).all(|w| w[0].visits >= w[1].visits));
+
+        explorer.descend(0).unwrap();
+        assert_eq!(explorer.depth(), 1);
+        assert_eq!(explorer.current(), children[0].node);
+        explorer.ascend().unwrap();
+        assert!(explorer.ascend().is_none());
+
+        explorer.jump_to_pv();
+        assert!(explorer.depth() > 1);
+        assert!(explorer.children().is_empty());
+
+        assert!(explorer.command("root").starts_with("depth 0"));
+        assert!(explorer.command("cd 99").starts_with("no child"));
+        assert!(explorer.command("cd 1").starts_with("depth 1"));
+        assert!(explorer.command("frobnicate").starts_with("commands"));
+    }
+
+    #[test]
+    fn test_root_heatmap() {
+        let mut engine = Engine::with_config(MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 2 },
+            ..Default::default()
+        });
+        assert_eq!(engine.root_heatmap().visit_share, [0.0; 81]);
+        engine.analyze(300).unwrap();
+
+        // Only 15 opening moves are searched, but symmetry covers all 81.
+        let heatmap = engine.root_heatmap();
+        assert!(heatmap.visit_share.iter().all(|&share| share > 0.0));
+        let total: f32 = heatmap.visit_share.iter().sum();
+        assert!((total - 1.0).abs() < 1e-4, "{total}");
+        // Corners of the corner sub-boards are all alike.
+        for cell in [8, 72, 80] {
+            assert_eq!(heatmap.visit_share[cell], heatmap.visit_share[0]);
+            assert_eq!(heatmap.value[cell], heatmap.value[0]);
+        }
+
+        engine.play((4, 4)).unwrap();
+        engine.analyze(100).unwrap();
+        let heatmap = engine.root_heatmap();
+        // Only sub-board 4 is open, and its center is taken.
+        let open = heatmap.visit_share.iter().filter(|&&share| share > 0.0);
+        assert_eq!(open.count(), 8);
+        assert_eq!(heatmap.visit_share[40], 0.0);
+        assert_eq!(heatmap.value[0], 0.0);
+    }
+}
@@ -0,0 +1,156 @@
+//! Snapshots of a running search for external visualizers, e.g. a web page
+//! animating how the root moves converge. Every `every` iterations (checked
+//! between batches) and once at the end, the search hands a [`SearchFrame`]
+//! to a [`FrameSink`]. Frames serialize to JSON; [`JsonLinesSink`] writes one
+//! per line, and an mpsc [`Sender`] passes them on to another thread, e.g.
+//! one serving a WebSocket.
+
+use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+/// One root move in a [`SearchFrame`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FrameMove {
+    /// `(global, local)` coordinates.
+    pub mve: (u8, u8),
+    pub visits: f32,
+    /// Win rate of the side to move at the root when playing the move.
+    pub win_rate: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SearchFrame {
+    /// Iterations so far, over the tree's lifetime.
+    pub iterations: u32,
+    pub nodes: usize,
+    /// Deepest selection so far, in plies below the root.
+    pub depth: usize,
+    /// Most visited root moves, most visited first.
+    pub top_moves: Vec<FrameMove>,
+    /// Most visited line from the root.
+    pub pv: Vec<(u8, u8)>,
+    /// Whether this is the last frame of the search.
+    pub last: bool,
+}
+
+/// Root moves a frame lists at most.
+pub(crate) const FRAME_MOVES: usize = 5;
+
+pub trait FrameSink: Send + Sync {
+    fn frame(&self, frame: &SearchFrame);
+}
+
+/// Writes every frame as a line of JSON. Write errors drop the frame, the
+/// search goes on.
+pub struct JsonLinesSink<W>(Mutex<W>);
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self(Mutex::new(writer))
+    }
+
+    pub fn into_inner(self) -> W {
+        self.0.into_inner().expect("Frame sink poisoned")
+    }
+}
+
+impl<W: Write + Send> FrameSink for JsonLinesSink<W> {
+    fn frame(&self, frame: &SearchFrame) {
+        let mut writer = self.0.lock().expect("Frame sink poisoned");
+        let line = serde_json::to_string(frame).expect("Frames always serialize");
+        let _ = writeln!(writer, "{line}").and_then(|_| writer.flush());
+    }
+}
+
+impl FrameSink for Mutex<Sender<SearchFrame>> {
+    fn frame(&self, frame: &SearchFrame) {
+        // Nobody listening any more is fine.
+        let _ = self
+            .lock()
+            .expect("Frame sink poisoned")
+            .send(frame.clone());
+    }
+}
+
+/// Sink of a search and how often it gets frames.
+#[derive(Clone)]
+pub(crate) struct FrameOutput {
+    pub sink: Arc<dyn FrameSink>,
+    pub every: u32,
+}
+
+impl std::fmt::Debug for FrameOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FrameOutput every {}", self.every)
+    }
+}
+
+impl deepsize::DeepSizeOf for FrameOutput {
+    fn deep_size_of_children(&self, _context: &mut deepsize::Context) -> usize {
+        // Owned by whoever supplied it, not by the search tree.
+        0
+    }
+}
+
+#[cfg(test)]
+mod frames_tests {
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+
+    use crate::frames::{JsonLinesSink, SearchFrame};
+    use crate::{Engine, MCTSConfig, SearchMode};
+
+    fn engine() -> Engine {
+        Engine::with_config(MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 3 },
+            batch_size: 50,
+            ..MCTSConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_channel_frames() {
+        let mut engine = engine();
+        let (sender, receiver) = mpsc::channel();
+        engine.set_frame_sink(Some(Arc::new(Mutex::new(sender))), 200);
+        let ev = engine.analyze(1000).unwrap();
+        let frames: Vec<SearchFrame> = receiver.try_iter().collect();
+        // One after every 200 iterations, the last one at the end.
+        assert_eq!(frames.len(), 5);
+        assert!(frames.windows(2).all(|w| w[0].iterations < w[1].iterations));
+        assert_eq!(frames[0].iterations, 200);
+        let last = frames.last().unwrap();
+        assert!(last.last && !frames[0].last);
+        assert_eq!(last.iterations, 1000);
+        assert_eq!(last.pv, ev.pv);
+        assert_eq!(last.top_moves[0].mve, ev.pv[0]);
+        assert!(last.top_moves.len() <= 5);
+        // The PV ends with a child of the deepest selection at most.
+        assert!(last.depth > 0 && last.depth + 1 >= last.pv.len());
+
+        engine.set_frame_sink(None, 200);
+        engine.analyze(100).unwrap();
+        assert!(receiver.try_iter().next().is_none());
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let mut engine = engine();
+        let sink = Arc::new(JsonLinesSink::new(Vec::new()));
+        engine.set_frame_sink(Some(sink.clone()), 100);
+        engine.analyze(200).unwrap();
+        drop(engine);
+        let bytes = Arc::into_inner(sink).unwrap().into_inner();
+        let lines: Vec<SearchFrame> = String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].iterations, 100);
+        assert!(lines[1].last);
+    }
+}
@@ -1,24 +1,59 @@
-use std::simd::{cmp::SimdPartialEq, u16x8};
+use core::simd::{cmp::SimdPartialEq, num::SimdUint, u16x8, Select};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, deepsize::DeepSizeOf)]
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "std", derive(deepsize::DeepSizeOf))]
 pub enum Player {
+    #[default]
     X,
     O,
 }
 
-#[derive(Debug)]
+/// Reasons a [`Board`] cannot arise from legal play.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoardError {
+    /// A cell is marked by both players.
+    Overlap,
+    /// A bit is set outside of the 81 cells.
+    OutOfRange,
+    /// Piece counts do not match the side to move.
+    WrongSideToMove,
+    /// `gx`/`go` disagree with the sub-board cells.
+    InconsistentMacro(u8),
+    /// `last_move` is malformed or not a mark of the side that just moved.
+    BadLastMove,
+    /// Handicap stones of the rules are missing from the board.
+    MissingHandicap,
+}
+
+impl core::fmt::Display for BoardError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Overlap => f.write_str("Cell marked by both players"),
+            Self::OutOfRange => f.write_str("Bits set outside the board"),
+            Self::WrongSideToMove => f.write_str("Piece counts don't match the side to move"),
+            Self::InconsistentMacro(global) => {
+                write!(f, "Macro state of board {global} doesn't match its cells")
+            }
+            Self::BadLastMove => f.write_str("Last move isn't a mark of the previous player"),
+            Self::MissingHandicap => f.write_str("Handicap stones missing from the board"),
+        }
+    }
+}
+
+impl core::error::Error for BoardError {}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "std", derive(deepsize::DeepSizeOf))]
 pub enum GameState {
     Won(Player),
     Draw,
+    #[default]
     InProgress,
 }
 
-impl Default for Player {
-    fn default() -> Self {
-        Self::X
-    }
-}
-
 impl Player {
     pub fn other(&self) -> Self {
         match self {
@@ -28,7 +63,96 @@ impl Player {
     }
 }
 
-#[derive(Clone, Copy, Default, Debug, deepsize::DeepSizeOf)]
+/// Which game is being played.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "std", derive(deepsize::DeepSizeOf))]
+pub enum Variant {
+    /// Win three sub-boards in a row.
+    #[default]
+    Ultimate,
+    /// Nine-board tic-tac-toe: winning any sub-board wins the game. Moves
+    /// are constrained exactly as in ultimate tic-tac-toe.
+    NineBoard,
+}
+
+/// Rough stage of a game, see [`Board::phase`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Phase {
+    Opening,
+    Middlegame,
+    Endgame,
+}
+
+/// The opening lasts this many plies, unless a sub-board is decided first.
+const OPENING_PLIES: u32 = 12;
+/// The endgame starts once this many sub-boards are decided...
+const ENDGAME_DECIDED: u32 = 5;
+/// ...or this few cells are left to play.
+const ENDGAME_OPEN_CELLS: u32 = 24;
+
+/// Rules of a game, carried by every [`Board`] of it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "std", derive(deepsize::DeepSizeOf))]
+pub struct Rules {
+    pub variant: Variant,
+    /// Completing what would normally win the game loses it instead.
+    pub misere: bool,
+    pub handicap: Option<Handicap>,
+    /// After the first move, the second player may take it over and swap
+    /// sides instead of answering it.
+    pub pie_rule: bool,
+}
+
+/// Head start for the weaker side, e.g. when a human plays the engine.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "std", derive(deepsize::DeepSizeOf))]
+pub struct Handicap {
+    /// Side getting the head start. X still makes the first move.
+    pub player: Player,
+    /// Cells marked for `player` before the first move, see [`Self::cells`].
+    pub stones: u128,
+    /// How many of `player`'s first turns are two moves in a row.
+    pub double_moves: u8,
+}
+
+impl Handicap {
+    /// Cell mask of `(global, local)` pairs, for [`Self::stones`].
+    pub fn cells(cells: &[(u8, u8)]) -> u128 {
+        cells
+            .iter()
+            .fold(0, |mask, &(global, local)| mask | 1 << (global * 9 + local))
+    }
+}
+
+impl Rules {
+    /// Side to move once X and O have `xs` and `os` marks on the board, or
+    /// `None` if no game under these rules has those counts.
+    pub fn side_to_move(&self, xs: u32, os: u32) -> Option<Player> {
+        let handicap = self.handicap.unwrap_or_default();
+        let (mine, theirs) = match handicap.player {
+            Player::X => (xs, os),
+            Player::O => (os, xs),
+        };
+        let mine = mine.checked_sub(handicap.stones.count_ones())?;
+        // Moves the handicapped side makes in its first `turns` turns.
+        let moves = |turns: u32| turns + turns.min(handicap.double_moves as u32);
+        // Turns the handicapped side has had when the other side is next.
+        let turns = match handicap.player {
+            Player::X => theirs + 1,
+            Player::O => theirs,
+        };
+        if mine == moves(turns) {
+            Some(handicap.player.other())
+        } else if turns > 0 && (moves(turns - 1)..moves(turns)).contains(&mine) {
+            Some(handicap.player)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(deepsize::DeepSizeOf))]
 pub struct Board {
     pub x: u128,
     pub o: u128,
@@ -36,9 +160,42 @@ pub struct Board {
     pub go: u16,
     pub next_player: Player,
     pub last_move: Option<u8>,
+    pub rules: Rules,
+    /// Cells of every completed sub-board, kept in sync with `gx`/`go` by
+    /// [`Board::make`]. Same as [`Board::global_board_mask`], without the
+    /// cost of expanding the macro bits on every call.
+    completed: u128,
+}
+
+/// Expands a 9-bit set of sub-boards to the 81-bit mask of their cells.
+const BOARD_MASKS: [u128; 512] = {
+    let mut masks = [0; 512];
+    let mut boards = 0;
+    while boards < 512 {
+        let mut global = 0;
+        while global < 9 {
+            if boards & (1 << global) != 0 {
+                masks[boards] |= 0b111_111_111 << (global * 9);
+            }
+            global += 1;
+        }
+        boards += 1;
+    }
+    masks
+};
+
+/// State needed to take back a move made with [`Board::make`].
+#[derive(Clone, Copy, Debug)]
+pub struct Undo {
+    mve: u8,
+    next_player: Player,
+    gx: u16,
+    go: u16,
+    completed: u128,
+    last_move: Option<u8>,
 }
 
-const WIN_MASKS: u16x8 = u16x8::from_array([
+pub(crate) const WIN_MASKS: u16x8 = u16x8::from_array([
     // Horizontal
     0b111_000_000,
     0b000_111_000,
@@ -53,6 +210,23 @@ const WIN_MASKS: u16x8 = u16x8::from_array([
 ]);
 
 impl Board {
+    /// Start position of a game played under `rules`, with the handicap
+    /// stones if there are any.
+    pub fn with_rules(rules: Rules) -> Self {
+        let mut board = Self {
+            rules,
+            ..Self::default()
+        };
+        if let Some(handicap) = rules.handicap {
+            match handicap.player {
+                Player::X => board.x = handicap.stones,
+                Player::O => board.o = handicap.stones,
+            }
+            board.recompute_macro();
+        }
+        board
+    }
+
     pub fn move_from_gl(global: u8, local: u8) -> u8 {
         (global << 4) | local
     }
@@ -71,8 +245,8 @@ impl Board {
     }
 
     fn update_board_state(&mut self, global: u8) -> GameState {
-        let xbits = (self.x >> global * 9) & 0b111_111_111;
-        let obits = (self.o >> global * 9) & 0b111_111_111;
+        let xbits = (self.x >> (global * 9)) & 0b111_111_111;
+        let obits = (self.o >> (global * 9)) & 0b111_111_111;
         // let board = match self.next_player {
         //     Player::X => xbits,
         //     Player::O => obits,
@@ -99,16 +273,10 @@ impl Board {
         //         }
         //     }
         // }
-        if (u16x8::splat(xbits as u16) & WIN_MASKS)
-            .simd_eq(WIN_MASKS)
-            .any()
-        {
+        if has_line(xbits as u16) {
             self.gx |= 1 << global;
             GameState::Won(Player::X)
-        } else if (u16x8::splat(obits as u16) & WIN_MASKS)
-            .simd_eq(WIN_MASKS)
-            .any()
-        {
+        } else if has_line(obits as u16) {
             self.go |= 1 << global;
             GameState::Won(Player::O)
         } else {
@@ -123,18 +291,12 @@ impl Board {
     }
 
     fn check_board_state(&self, global: u8) -> GameState {
-        let xbits = (self.x >> global * 9) & 0b111_111_111;
-        let obits = (self.o >> global * 9) & 0b111_111_111;
+        let xbits = (self.x >> (global * 9)) & 0b111_111_111;
+        let obits = (self.o >> (global * 9)) & 0b111_111_111;
 
-        if (u16x8::splat(xbits as u16) & WIN_MASKS)
-            .simd_eq(WIN_MASKS)
-            .any()
-        {
+        if has_line(xbits as u16) {
             GameState::Won(Player::X)
-        } else if (u16x8::splat(obits as u16) & WIN_MASKS)
-            .simd_eq(WIN_MASKS)
-            .any()
-        {
+        } else if has_line(obits as u16) {
             GameState::Won(Player::O)
         } else {
             if xbits | obits == 0b111_111_111 {
@@ -146,16 +308,31 @@ impl Board {
     }
 
     pub fn check_game_state(&self) -> GameState {
+        match self.completed_line() {
+            GameState::Won(player) if self.rules.misere => GameState::Won(player.other()),
+            state => state,
+        }
+    }
+
+    /// Game state as if the variant weren't misère: who completed a winning
+    /// pattern, if anyone did.
+    fn completed_line(&self) -> GameState {
         let drawn_boards = self.gx & self.go;
-        if (u16x8::splat(self.gx & !drawn_boards) & WIN_MASKS)
-            .simd_eq(WIN_MASKS)
-            .any()
-        {
+        if self.rules.variant == Variant::NineBoard {
+            // Only one sub-board can ever be won, the game ends right away.
+            return if self.gx & !drawn_boards != 0 {
+                GameState::Won(Player::X)
+            } else if self.go & !drawn_boards != 0 {
+                GameState::Won(Player::O)
+            } else if drawn_boards == 0b111_111_111 {
+                GameState::Draw
+            } else {
+                GameState::InProgress
+            };
+        }
+        if has_line(self.gx & !drawn_boards) {
             GameState::Won(Player::X)
-        } else if (u16x8::splat(self.go & !drawn_boards) & WIN_MASKS)
-            .simd_eq(WIN_MASKS)
-            .any()
-        {
+        } else if has_line(self.go & !drawn_boards) {
             GameState::Won(Player::O)
         } else if self.gx | self.go == 0b111_111_111 {
             GameState::Draw
@@ -164,40 +341,255 @@ impl Board {
         }
     }
 
+    /// Checks that the position can arise from legal play. Positions coming
+    /// from outside the engine should pass through this (or [`Self::sanitize`])
+    /// before being searched.
+    pub fn validate(&self) -> Result<(), BoardError> {
+        if self.x & self.o != 0 {
+            return Err(BoardError::Overlap);
+        }
+        if (self.x | self.o) & !0x1ffffffffffffffffffff != 0
+            || (self.gx | self.go) & !0b111_111_111 != 0
+        {
+            return Err(BoardError::OutOfRange);
+        }
+
+        if let Some(handicap) = self.rules.handicap {
+            let marks = match handicap.player {
+                Player::X => self.x,
+                Player::O => self.o,
+            };
+            if marks & handicap.stones != handicap.stones {
+                return Err(BoardError::MissingHandicap);
+            }
+        }
+
+        let (xs, os) = (self.x.count_ones(), self.o.count_ones());
+        if self.rules.side_to_move(xs, os) != Some(self.next_player) {
+            return Err(BoardError::WrongSideToMove);
+        }
+
+        for global in 0..9 {
+            let bit = 1 << global;
+            let (gx, go) = match self.check_board_state(global) {
+                GameState::Won(Player::X) => (bit, 0),
+                GameState::Won(Player::O) => (0, bit),
+                GameState::Draw => (bit, bit),
+                GameState::InProgress => (0, 0),
+            };
+            if self.gx & bit != gx || self.go & bit != go {
+                return Err(BoardError::InconsistentMacro(global));
+            }
+            let completed = (self.completed >> (global * 9)) & 0b111_111_111;
+            if (completed != 0) != (gx | go != 0) {
+                return Err(BoardError::InconsistentMacro(global));
+            }
+        }
+
+        let stones = self.rules.handicap.map_or(0, |h| h.stones.count_ones());
+        match self.last_move {
+            None if xs + os != stones => Err(BoardError::BadLastMove),
+            None => Ok(()),
+            Some(m) => {
+                let (global, local) = (m >> 4, m & 0b1111);
+                if global > 8 || local > 8 {
+                    return Err(BoardError::BadLastMove);
+                }
+                // The side that made the last move must have been to move
+                // without it.
+                let cell = 1u128 << (global * 9 + local);
+                let before = if self.x & cell != 0 {
+                    (Player::X, xs - 1, os)
+                } else if self.o & cell != 0 {
+                    (Player::O, xs, os - 1)
+                } else {
+                    return Err(BoardError::BadLastMove);
+                };
+                let (mover, xs, os) = before;
+                if self.rules.side_to_move(xs, os) != Some(mover) {
+                    return Err(BoardError::BadLastMove);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Recomputes the macro board from the cells and validates the result.
+    pub fn sanitize(mut self) -> Result<Self, BoardError> {
+        self.recompute_macro();
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Derives `gx`/`go` from the cells.
+    pub(crate) fn recompute_macro(&mut self) {
+        self.gx = 0;
+        self.go = 0;
+        for global in 0..9 {
+            self.update_board_state(global);
+        }
+        self.refresh_completed();
+    }
+
+    /// Whether the side to move may swap sides under the pie rule, i.e. the
+    /// first move has just been played.
+    pub fn can_swap(&self) -> bool {
+        let stones = self.rules.handicap.map_or(0, |h| h.stones.count_ones());
+        self.rules.pie_rule && (self.x | self.o).count_ones() == stones + 1 && !self.game_over()
+    }
+
+    /// Plays up to `plies` uniformly random legal moves from the start
+    /// position, stopping early if the game ends.
+    pub fn random_position<R: Rng>(plies: u32, rng: &mut R) -> Self {
+        Self::random_position_with_rules(Rules::default(), plies, rng)
+    }
+
+    /// Same as [`Self::random_position`] for a game under `rules`.
+    pub fn random_position_with_rules<R: Rng>(rules: Rules, plies: u32, rng: &mut R) -> Self {
+        let mut board = Self::with_rules(rules);
+        for _ in 0..plies {
+            if board.game_over() {
+                break;
+            }
+            let moves = board.get_moves();
+            let k = rng.gen_range(0..moves.count_ones());
+            let index = find_kth_high_bit_index(moves, k).expect("k is below the move count");
+            board = board.unchecked_play(Self::move_from_index(index));
+        }
+        board
+    }
+
+    /// Sub-boards that would win the game for `player`, i.e. undecided
+    /// sub-boards completing a macro line in which `player` already owns
+    /// the other two. Bit `g` stands for sub-board `g`.
+    pub fn macro_threats(&self, player: Player) -> u16 {
+        let drawn = self.gx & self.go;
+        let mine = match player {
+            Player::X => self.gx & !drawn,
+            Player::O => self.go & !drawn,
+        };
+        threat_squares(mine, !(self.gx | self.go) & 0b111_111_111)
+    }
+
+    /// Empty cells of sub-board `global` that would win it for `player`.
+    /// Bit `l` stands for local cell `l`. Always 0 for decided sub-boards.
+    pub fn sub_board_threats(&self, global: u8, player: Player) -> u16 {
+        if (self.gx | self.go) & (1 << global) != 0 {
+            return 0;
+        }
+        let xbits = ((self.x >> (global * 9)) & 0b111_111_111) as u16;
+        let obits = ((self.o >> (global * 9)) & 0b111_111_111) as u16;
+        let mine = match player {
+            Player::X => xbits,
+            Player::O => obits,
+        };
+        threat_squares(mine, !(xbits | obits) & 0b111_111_111)
+    }
+
     /// Does not check validity of the moves
     pub fn unchecked_play(&self, m: u8) -> Self {
-        let mut board = self.clone();
+        let mut board = *self;
+        board.make(m);
+        board
+    }
+
+    /// Plays `m` in place, returning what [`Self::unmake`] needs to take it
+    /// back. Does not check validity of the move.
+    pub fn make(&mut self, m: u8) -> Undo {
+        let undo = Undo {
+            mve: m,
+            next_player: self.next_player,
+            gx: self.gx,
+            go: self.go,
+            completed: self.completed,
+            last_move: self.last_move,
+        };
 
         let local = m & 0b1111;
         let global = (m >> 4) & 0b1111;
 
         match self.next_player {
-            Player::X => board.x = self.x | ((1 << (global * 9)) << local),
-            Player::O => board.o = self.o | ((1 << (global * 9)) << local),
+            Player::X => self.x |= (1 << (global * 9)) << local,
+            Player::O => self.o |= (1 << (global * 9)) << local,
+        }
+        if !matches!(self.update_board_state(global), GameState::InProgress) {
+            self.completed |= 0b111_111_111 << (global * 9);
         }
-        board.update_board_state(global);
-        board.last_move = Some(m);
-        board.next_player = self.next_player.other();
+        self.last_move = Some(m);
+        self.next_player = match self.rules.handicap {
+            None => self.next_player.other(),
+            Some(_) => self
+                .rules
+                .side_to_move(self.x.count_ones(), self.o.count_ones())
+                .expect("Legal moves keep the mark counts consistent"),
+        };
 
-        board
+        undo
+    }
+
+    /// Takes back the move recorded in `undo`, which must be the last move
+    /// made on this board.
+    pub fn unmake(&mut self, undo: Undo) {
+        let local = undo.mve & 0b1111;
+        let global = (undo.mve >> 4) & 0b1111;
+
+        self.next_player = undo.next_player;
+        match self.next_player {
+            Player::X => self.x &= !((1 << (global * 9)) << local),
+            Player::O => self.o &= !((1 << (global * 9)) << local),
+        }
+        self.gx = undo.gx;
+        self.go = undo.go;
+        self.completed = undo.completed;
+        self.last_move = undo.last_move;
+    }
+
+    /// Rederives the cached completed-board mask from `gx`/`go`.
+    pub(crate) fn refresh_completed(&mut self) {
+        self.completed = self.global_board_mask();
     }
 
     pub fn global_board_mask(&self) -> u128 {
-        let mut mask = 0;
-        for i in 0..9 {
-            if (self.gx | self.go) & (1 << (8 - i)) != 0 {
-                mask <<= 9;
-                mask |= 0b111_111_111;
-            } else {
-                mask <<= 9;
-            }
+        BOARD_MASKS[((self.gx | self.go) & 0b111_111_111) as usize]
+    }
+
+    /// Stage of the game by plies played and sub-boards decided. Handicap
+    /// stones don't count as plies.
+    pub fn phase(&self) -> Phase {
+        let stones = self.rules.handicap.map_or(0, |h| h.stones.count_ones());
+        let plies = (self.x | self.o).count_ones() - stones;
+        let decided = (self.gx | self.go).count_ones();
+        let open = (!(self.x | self.o | self.completed) & 0x1ffffffffffffffffffff).count_ones();
+        if decided >= ENDGAME_DECIDED || open <= ENDGAME_OPEN_CELLS {
+            Phase::Endgame
+        } else if plies < OPENING_PLIES && decided == 0 {
+            Phase::Opening
+        } else {
+            Phase::Middlegame
         }
-        mask
+    }
+
+    /// Sub-board the side to move has to play in, or `None` if it may play
+    /// in any undecided sub-board: on the first move, and when the last move
+    /// sends it to a sub-board that is already won or drawn.
+    pub fn forced_board(&self) -> Option<u8> {
+        let local = self.last_move? & 0b1111;
+        ((self.gx | self.go) & (1 << local) == 0).then_some(local)
     }
 
     pub fn get_moves(&self) -> u128 {
+        let open = !(self.x | self.o) & 0x1ffffffffffffffffffff;
+        match self.forced_board() {
+            Some(global) => open & (0b111_111_111 << (9 * global)),
+            None => open & !self.completed,
+        }
+    }
+
+    /// Same as [`Self::get_moves`], but derives the sub-board states from
+    /// the cells instead of the cached macro state.
+    pub fn get_moves_uncached(&self) -> u128 {
         match self.last_move {
-            None => 0x1ffffffffffffffffffff,
+            None => !(self.x | self.o | self.global_board_mask()) & 0x1ffffffffffffffffffff,
             Some(m) => {
                 let local = m & 0b1111;
 
@@ -206,7 +598,9 @@ impl Board {
                         !(self.x | self.o | self.global_board_mask()) & 0x1ffffffffffffffffffff
                     }
                     GameState::InProgress => {
-                        !(self.x | self.o) & 0x1ffffffffffffffffffff & (0b111_111_111 << 9 * local)
+                        !(self.x | self.o)
+                            & 0x1ffffffffffffffffffff
+                            & (0b111_111_111 << (9 * local))
                     }
                 }
             }
@@ -214,11 +608,158 @@ impl Board {
     }
 }
 
+/// Squares in `open` that complete a line together with two squares of
+/// `mine`.
+fn threat_squares(mine: u16, open: u16) -> u16 {
+    let zero = u16x8::splat(0);
+    let missing = WIN_MASKS & !u16x8::splat(mine);
+    let one_missing = (missing & (missing - u16x8::splat(1))).simd_eq(zero) & missing.simd_ne(zero);
+    let missing_is_open = (missing & u16x8::splat(open)).simd_ne(zero);
+    (one_missing & missing_is_open)
+        .select(missing, zero)
+        .reduce_or()
+}
+
+/// Implementations of the bit tricks on the hot path of every playout,
+/// picked once for the CPU the process runs on.
+struct Kernels {
+    find_kth_bit: fn(u128, u32) -> Option<u8>,
+    has_line: fn(u16) -> bool,
+}
+
+impl Kernels {
+    /// Without `std` there's no runtime detection, so the choice follows the
+    /// target features the crate was compiled for.
+    #[cfg(not(feature = "std"))]
+    const COMPILED: Self = Self {
+        find_kth_bit: find_kth_bit_scalar,
+        has_line: if cfg!(any(
+            target_feature = "sse2",
+            target_feature = "neon",
+            target_feature = "simd128"
+        )) {
+            has_line_simd
+        } else {
+            has_line_scalar
+        },
+    };
+
+    /// BMI2's `pdep` for bit selection and 128-bit vectors for the win
+    /// checks when the CPU has them, scalar loops otherwise, so one binary
+    /// runs well on old and new machines alike.
+    #[cfg(feature = "std")]
+    fn detect() -> Self {
+        #[allow(unused_mut)]
+        let mut kernels = Self {
+            find_kth_bit: find_kth_bit_scalar,
+            has_line: has_line_scalar,
+        };
+        #[cfg(target_arch = "x86_64")]
+        {
+            if std::arch::is_x86_feature_detected!("bmi2") {
+                kernels.find_kth_bit = find_kth_bit_bmi2;
+            }
+            if std::arch::is_x86_feature_detected!("sse2") {
+                kernels.has_line = has_line_simd;
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            kernels.has_line = has_line_simd;
+        }
+        #[cfg(target_arch = "wasm32")]
+        if cfg!(target_feature = "simd128") {
+            kernels.has_line = has_line_simd;
+        }
+        kernels
+    }
+}
+
+#[cfg(feature = "std")]
+fn kernels() -> &'static Kernels {
+    static KERNELS: std::sync::OnceLock<Kernels> = std::sync::OnceLock::new();
+    KERNELS.get_or_init(Kernels::detect)
+}
+
+#[cfg(not(feature = "std"))]
+fn kernels() -> &'static Kernels {
+    &Kernels::COMPILED
+}
+
+/// Whether `bits` cover one of the win lines of a 3×3 grid.
+fn has_line(bits: u16) -> bool {
+    (kernels().has_line)(bits)
+}
+
+fn has_line_simd(bits: u16) -> bool {
+    (u16x8::splat(bits) & WIN_MASKS).simd_eq(WIN_MASKS).any()
+}
+
+fn has_line_scalar(bits: u16) -> bool {
+    WIN_MASKS
+        .to_array()
+        .into_iter()
+        .any(|line| line & !bits == 0)
+}
+
+/// Index of the `k`-th set bit of `n` (from 0, lowest first) among the 81
+/// cells.
+pub(crate) fn find_kth_high_bit_index(n: u128, k: u32) -> Option<u8> {
+    (kernels().find_kth_bit)(n, k)
+}
+
+fn find_kth_bit_scalar(n: u128, k: u32) -> Option<u8> {
+    let mut count = 0;
+
+    for i in 0..81 {
+        if n & (1 << i) != 0 {
+            if count == k {
+                return Some(i);
+            }
+            count += 1;
+        }
+    }
+
+    None
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "std"))]
+#[target_feature(enable = "bmi2")]
+unsafe fn find_kth_bit_pdep(n: u128, k: u32) -> Option<u8> {
+    use core::arch::x86_64::_pdep_u64;
+
+    let n = n & 0x1ffffffffffffffffffff;
+    let (low, high) = (n as u64, (n >> 64) as u64);
+    let in_low = low.count_ones();
+    // Depositing a single bit into `n` lands it on the k-th set bit.
+    let index = if k < in_low {
+        _pdep_u64(1 << k, low).trailing_zeros()
+    } else if k - in_low < high.count_ones() {
+        64 + _pdep_u64(1 << (k - in_low), high).trailing_zeros()
+    } else {
+        return None;
+    };
+    Some(index as u8)
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "std"))]
+fn find_kth_bit_bmi2(n: u128, k: u32) -> Option<u8> {
+    // SAFETY: only picked by `Kernels::detect` once BMI2 support is detected.
+    unsafe { find_kth_bit_pdep(n, k) }
+}
+
 #[cfg(test)]
 mod board_tests {
-    use crate::game::Board;
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::game::{
+        find_kth_bit_scalar, find_kth_high_bit_index, has_line, has_line_scalar, has_line_simd,
+        kernels, Board, BoardError, GameState, Handicap, Phase, Player, Rules, Variant,
+    };
+    use crate::test_support::{board_and_move, reachable_board, CI_CASES};
 
-    // #[test]
+    #[test]
     fn test_valid_moves() {
         let board = Board::default();
 
@@ -230,4 +771,338 @@ mod board_tests {
 
         assert_eq!(board.get_moves(), 0x1ff000000000);
     }
+
+    #[test]
+    fn test_global_board_mask() {
+        let mut board = Board::default();
+        for boards in 0..512u16 {
+            board.gx = boards;
+            let expected = (0..9)
+                .filter(|g| boards & (1 << g) != 0)
+                .fold(0u128, |mask, g| mask | (0b111_111_111 << (g * 9)));
+            assert_eq!(board.global_board_mask(), expected);
+        }
+    }
+
+    #[test]
+    fn test_threats() {
+        let mut board = Board::default();
+        // X: 0 and 1 in board 4 (threatens 2), O: 4 in board 0 and 8 in board 1.
+        for m in [(4, 0), (0, 4), (4, 1), (1, 8)] {
+            board = board.unchecked_play(Board::move_from_gl(m.0, m.1));
+        }
+        assert_eq!(board.sub_board_threats(4, Player::X), 0b100);
+        assert_eq!(board.sub_board_threats(4, Player::O), 0);
+        assert_eq!(board.sub_board_threats(0, Player::O), 0);
+
+        // X owns sub-boards 0 and 4, but O blocks the diagonal at 8.
+        board.gx = 0b000_010_001;
+        board.go = 0b100_000_000;
+        assert_eq!(board.macro_threats(Player::X), 0b000_000_000);
+        // X owns 0 and 1, so 2 completes the top row.
+        board.gx = 0b000_000_011;
+        assert_eq!(board.macro_threats(Player::X), 0b000_000_100);
+        assert_eq!(board.macro_threats(Player::O), 0);
+    }
+
+    #[test]
+    fn test_validate() {
+        let board = Board::default()
+            .unchecked_play(Board::move_from_gl(0, 0))
+            .unchecked_play(Board::move_from_gl(0, 4));
+        assert_eq!(board.validate(), Ok(()));
+
+        let mut overlap = board;
+        overlap.o |= 1;
+        assert_eq!(overlap.validate(), Err(BoardError::Overlap));
+
+        let mut parity = board;
+        parity.next_player = Player::O;
+        assert_eq!(parity.validate(), Err(BoardError::WrongSideToMove));
+
+        let mut last_move = board;
+        last_move.last_move = Some(Board::move_from_gl(0, 0));
+        assert_eq!(last_move.validate(), Err(BoardError::BadLastMove));
+
+        let mut stale_macro = board;
+        stale_macro.gx = 0b1;
+        assert_eq!(
+            stale_macro.validate(),
+            Err(BoardError::InconsistentMacro(0))
+        );
+        assert_eq!(stale_macro.sanitize().map(|b| b.gx), Ok(0));
+    }
+
+    #[test]
+    fn test_random_position() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let board = Board::random_position(10, &mut rng);
+        assert_eq!((board.x | board.o).count_ones(), 10);
+        assert_eq!(board.validate(), Ok(()));
+
+        let board = Board::random_position(200, &mut rng);
+        assert!(board.game_over());
+    }
+
+    #[test]
+    fn test_nine_board() {
+        let rules = Rules {
+            variant: Variant::NineBoard,
+            ..Rules::default()
+        };
+        // X takes the top row of the center board while O plays elsewhere.
+        let mut board = Board::with_rules(rules);
+        for (g, l) in [(4, 0), (0, 4), (4, 1), (1, 4), (4, 2)] {
+            assert_eq!(board.check_game_state(), GameState::InProgress);
+            board = board.unchecked_play(Board::move_from_gl(g, l));
+        }
+        assert_eq!(board.check_game_state(), GameState::Won(Player::X));
+        assert_eq!(board.rules, rules);
+        assert_eq!(board.validate(), Ok(()));
+
+        // Random games end as soon as a sub-board is won, by whoever won it.
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..20 {
+            let mut board = Board::with_rules(rules);
+            loop {
+                let drawn = board.gx & board.go;
+                let (gx, go) = (board.gx & !drawn, board.go & !drawn);
+                if gx | go != 0 {
+                    let winner = if gx != 0 { Player::X } else { Player::O };
+                    assert_eq!(board.check_game_state(), GameState::Won(winner));
+                    assert_eq!((gx | go).count_ones(), 1);
+                    break;
+                }
+                assert_eq!(board.check_game_state(), GameState::InProgress);
+                let moves = board.get_moves();
+                let k = rng.gen_range(0..moves.count_ones());
+                let index = find_kth_high_bit_index(moves, k).unwrap();
+                board = board.unchecked_play(Board::move_from_index(index));
+            }
+        }
+
+        // Without a won sub-board, the game is drawn once all are full.
+        //   X O X
+        //   X O O
+        //   O X X
+        let (xs, os) = (0b110_001_101u128, 0b001_110_010u128);
+        let mut board = Board::with_rules(rules);
+        for global in 0..8 {
+            board.x |= xs << (global * 9);
+            board.o |= os << (global * 9);
+        }
+        board.x |= (xs & !(1 << 8)) << 72;
+        board.o |= os << 72;
+        board.recompute_macro();
+        assert_eq!(board.check_game_state(), GameState::InProgress);
+        let board = board.unchecked_play(Board::move_from_gl(8, 8));
+        assert_eq!((board.gx, board.go), (0b111_111_111, 0b111_111_111));
+        assert_eq!(board.check_game_state(), GameState::Draw);
+    }
+
+    #[test]
+    fn test_handicap() {
+        let rules = Rules {
+            handicap: Some(Handicap {
+                player: Player::O,
+                stones: Handicap::cells(&[(4, 4)]),
+                double_moves: 1,
+            }),
+            ..Rules::default()
+        };
+        let start = Board::with_rules(rules);
+        assert_eq!(start.validate(), Ok(()));
+        assert_eq!(start.next_player, Player::X);
+        assert_eq!(start.get_moves().count_ones(), 80);
+
+        // O answers X's first move with two moves in a row.
+        let mut board = start;
+        let mut players = vec![];
+        for m in [(0, 4), (4, 0), (0, 1), (1, 0)] {
+            players.push(board.next_player);
+            let undo = board.make(Board::move_from_gl(m.0, m.1));
+            assert_eq!(board.validate(), Ok(()));
+            let mut undone = board;
+            undone.unmake(undo);
+            assert_eq!(undone.next_player, *players.last().unwrap());
+        }
+        assert_eq!(players, [Player::X, Player::O, Player::O, Player::X]);
+        assert_eq!(board.next_player, Player::O);
+
+        assert_eq!(
+            Board {
+                rules,
+                ..Board::default()
+            }
+            .validate(),
+            Err(BoardError::MissingHandicap)
+        );
+        let mut parity = board;
+        parity.next_player = Player::X;
+        assert_eq!(parity.validate(), Err(BoardError::WrongSideToMove));
+        assert_ne!(start.zobrist_hash(), Board::default().zobrist_hash());
+    }
+
+    #[test]
+    fn test_find_kth_bit() {
+        let mut rng = StdRng::seed_from_u64(6);
+        for _ in 0..200 {
+            let n = rng.gen::<u128>();
+            for k in 0..82 {
+                let expected = find_kth_bit_scalar(n, k);
+                assert_eq!(find_kth_high_bit_index(n, k), expected);
+                #[cfg(target_arch = "x86_64")]
+                if std::arch::is_x86_feature_detected!("bmi2") {
+                    // SAFETY: BMI2 support was just detected.
+                    assert_eq!(unsafe { crate::game::find_kth_bit_pdep(n, k) }, expected);
+                }
+            }
+        }
+        assert_eq!(find_kth_high_bit_index(1 << 80 | 1 << 3, 1), Some(80));
+        assert_eq!(find_kth_high_bit_index(1 << 100, 0), None);
+    }
+
+    #[test]
+    fn test_has_line() {
+        for bits in 0..1 << 9 {
+            let expected = has_line_scalar(bits);
+            assert_eq!(has_line_simd(bits), expected, "{bits:09b}");
+            assert_eq!(has_line(bits), expected, "{bits:09b}");
+        }
+        assert!(has_line(0b100_010_001));
+        assert!(!has_line(0b110_001_101));
+        // Detection runs once, every later call gets the same kernels.
+        assert!(std::ptr::eq(kernels(), kernels()));
+    }
+
+    #[test]
+    fn test_phase() {
+        let mut rng = StdRng::seed_from_u64(8);
+        assert_eq!(Board::default().phase(), Phase::Opening);
+        assert_eq!(Board::random_position(11, &mut rng).phase(), Phase::Opening);
+        let board = Board::random_position(30, &mut rng);
+        assert_ne!(board.phase(), Phase::Opening);
+        let mut decided = board;
+        decided.gx = 0b000_011_011;
+        decided.go = 0b000_100_100;
+        decided.refresh_completed();
+        assert_eq!(decided.phase(), Phase::Endgame);
+        assert_eq!(Board::random_position(81, &mut rng).phase(), Phase::Endgame);
+    }
+
+    #[test]
+    fn test_forced_board() {
+        let board = Board::default();
+        assert_eq!(board.forced_board(), None);
+        let board = board.unchecked_play(Board::move_from_gl(4, 2));
+        assert_eq!(board.forced_board(), Some(2));
+
+        // X wins sub-board 4, then O is sent there and may play anywhere.
+        let mut board = Board::default();
+        for m in [(4, 0), (0, 4), (4, 1), (1, 4), (4, 2), (2, 4)] {
+            board = board.unchecked_play(Board::move_from_gl(m.0, m.1));
+        }
+        assert_eq!(board.gx, 0b10000);
+        assert_eq!(board.forced_board(), None);
+        assert_eq!(board.get_moves(), board.get_moves_uncached());
+    }
+
+    #[test]
+    fn test_misere() {
+        let moves = [(4, 0), (0, 4), (4, 1), (1, 4), (4, 2)];
+        for variant in [Variant::Ultimate, Variant::NineBoard] {
+            let rules = Rules {
+                variant,
+                misere: true,
+                ..Rules::default()
+            };
+            let board = moves.iter().fold(Board::with_rules(rules), |board, m| {
+                board.unchecked_play(Board::move_from_gl(m.0, m.1))
+            });
+            match variant {
+                // Only one sub-board is won, the game goes on.
+                Variant::Ultimate => assert_eq!(board.check_game_state(), GameState::InProgress),
+                // Winning a sub-board loses the nine-board game.
+                Variant::NineBoard => {
+                    assert_eq!(board.check_game_state(), GameState::Won(Player::O))
+                }
+            }
+        }
+
+        // Random misère games end with the player who completed a line losing.
+        let rules = Rules {
+            misere: true,
+            ..Rules::default()
+        };
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..20 {
+            let board = Board::random_position_with_rules(rules, 81, &mut rng);
+            let normal = Board {
+                rules: Rules::default(),
+                ..board
+            };
+            match normal.check_game_state() {
+                GameState::Won(player) => {
+                    assert_eq!(board.check_game_state(), GameState::Won(player.other()))
+                }
+                state => assert_eq!(board.check_game_state(), state),
+            }
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(CI_CASES))]
+
+        #[test]
+        fn prop_play_flips_next_player((board, m) in board_and_move(81)) {
+            let next = board.unchecked_play(m);
+            prop_assert_eq!(next.next_player, board.next_player.other());
+            prop_assert_eq!(next.last_move, Some(m));
+        }
+
+        #[test]
+        fn prop_play_fills_one_cell((board, m) in board_and_move(81)) {
+            let next = board.unchecked_play(m);
+            let before = (board.x | board.o).count_ones();
+            prop_assert_eq!((next.x | next.o).count_ones(), before + 1);
+            prop_assert_eq!(next.x & next.o, 0);
+        }
+
+        #[test]
+        fn prop_make_unmake_round_trips((board, m) in board_and_move(81)) {
+            let mut made = board;
+            let undo = made.make(m);
+            prop_assert_eq!(made, board.unchecked_play(m));
+            made.unmake(undo);
+            prop_assert_eq!(made, board);
+        }
+
+        #[test]
+        fn prop_cached_moves_match_uncached(board in reachable_board(81)) {
+            prop_assert_eq!(board.get_moves(), board.get_moves_uncached());
+        }
+
+        #[test]
+        fn prop_reachable_boards_validate(board in reachable_board(81)) {
+            prop_assert_eq!(board.validate(), Ok(()));
+        }
+
+        #[test]
+        fn prop_no_moves_means_game_over(board in reachable_board(81)) {
+            if board.get_moves() == 0 {
+                prop_assert!(!matches!(board.check_game_state(), GameState::InProgress));
+            }
+        }
+
+        #[test]
+        fn prop_game_over_is_monotonic((board, m) in board_and_move(81)) {
+            let next = board.unchecked_play(m);
+            if next.game_over() {
+                let moves = next.get_moves();
+                for i in (0..81).filter(|i| (moves >> i) & 1 == 1) {
+                    prop_assert!(next.unchecked_play(Board::move_from_index(i)).game_over());
+                }
+            }
+        }
+    }
 }
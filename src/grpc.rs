@@ -0,0 +1,428 @@
+//! gRPC access to the engine with the `grpc` feature, for services that
+//! would rather not pay for JSON over HTTP. The service is defined in
+//! `proto/stoctopus.proto`, from which [`proto`] is generated; clients in
+//! other languages can be generated from the same file.
+//!
+//! Requests are independent: each carries the moves played from the empty
+//! board, and every search starts from a fresh tree, so one service can
+//! answer any number of clients. Searches run on rayon's pool, outside the
+//! async runtime. `AnalyzeStream` hands on the [`frames`](crate::frames) of
+//! the search as it runs and the evaluation last; a client going away
+//! cancels the search.
+//!
+//! ```no_run
+//! # async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+//! use stoctopus::grpc::{EngineService, GrpcConfig};
+//!
+//! tonic::transport::Server::builder()
+//!     .add_service(EngineService::new(GrpcConfig::default()).into_server())
+//!     .serve("127.0.0.1:50051".parse()?)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::{Request, Response, Status};
+
+use crate::frames::{FrameSink, SearchFrame};
+use crate::game::{GameState, Player};
+use crate::{CancellationToken, Engine, Evaluation, MCTSConfig, SearchError, SearchMode};
+use crate::{SearchLimits, StoctopusError};
+
+/// Messages and service traits generated from `proto/stoctopus.proto`.
+pub mod proto {
+    #![allow(clippy::all)]
+    tonic::include_proto!("stoctopus");
+}
+
+use proto::analyze_update::Update;
+use proto::engine_server::{Engine as EngineRpc, EngineServer};
+use proto::{AnalyzeRequest, AnalyzeUpdate, Game, Outcome, PlayRequest, PositionState};
+
+#[derive(Clone, Copy, Debug)]
+pub struct GrpcConfig {
+    /// Configuration of every search. A request's seed replaces the mode
+    /// with [`SearchMode::Deterministic`].
+    pub engine: MCTSConfig,
+    /// Requested iterations are capped at this.
+    pub max_iterations: u32,
+    /// Iterations between the frames of `AnalyzeStream` when the request
+    /// doesn't say.
+    pub frame_every: u32,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            engine: MCTSConfig::default(),
+            max_iterations: 100_000,
+            frame_every: 1_000,
+        }
+    }
+}
+
+/// Implementation of the `Engine` service.
+#[derive(Clone, Debug, Default)]
+pub struct EngineService {
+    config: GrpcConfig,
+}
+
+impl EngineService {
+    pub fn new(config: GrpcConfig) -> Self {
+        Self { config }
+    }
+
+    /// The service, ready for `tonic::transport::Server::add_service`.
+    pub fn into_server(self) -> EngineServer<Self> {
+        EngineServer::new(self)
+    }
+
+    /// Engine at the end of `game`, searching as `request` asks.
+    fn engine(&self, request: &AnalyzeRequest) -> Result<(Engine, u32), StoctopusError> {
+        let mut config = self.config.engine;
+        if let Some(seed) = request.seed {
+            config.mode = SearchMode::Deterministic { seed };
+        }
+        let engine = replay(request.game.as_ref(), config)?;
+        Ok((engine, request.iterations.min(self.config.max_iterations)))
+    }
+}
+
+/// Plays `game` from the empty board.
+fn replay(game: Option<&Game>, config: MCTSConfig) -> Result<Engine, StoctopusError> {
+    let mut engine = Engine::with_config(config);
+    for mve in game.map_or(&[][..], |game| &game.moves) {
+        engine.play(move_pair(mve)?)?;
+    }
+    Ok(engine)
+}
+
+fn move_pair(mve: &proto::Move) -> Result<(u8, u8), StoctopusError> {
+    match (u8::try_from(mve.global), u8::try_from(mve.local)) {
+        (Ok(global), Ok(local)) => Ok((global, local)),
+        _ => Err(StoctopusError::IllegalMove),
+    }
+}
+
+fn proto_move((global, local): (u8, u8)) -> proto::Move {
+    proto::Move {
+        global: global.into(),
+        local: local.into(),
+    }
+}
+
+fn outcome(state: GameState) -> Outcome {
+    match state {
+        GameState::InProgress => Outcome::InProgress,
+        GameState::Won(Player::X) => Outcome::X,
+        GameState::Won(Player::O) => Outcome::O,
+        GameState::Draw => Outcome::Draw,
+    }
+}
+
+/// Bad requests are the client's fault, anything else the server's.
+fn status(err: StoctopusError) -> Status {
+    match err {
+        StoctopusError::IllegalMove
+        | StoctopusError::InvalidBoard(_)
+        | StoctopusError::InvalidArgument(_) => Status::invalid_argument(err.to_string()),
+        StoctopusError::Search(SearchError::Cancelled) => Status::cancelled(err.to_string()),
+        err => Status::internal(err.to_string()),
+    }
+}
+
+/// `engine`'s position, reached by `game`.
+fn position_state(engine: &Engine, game: Game) -> PositionState {
+    let board = engine.board();
+    let moves = if board.game_over() {
+        0
+    } else {
+        board.get_moves()
+    };
+    PositionState {
+        game: Some(game),
+        result: outcome(engine.game_state()).into(),
+        legal_moves: (0..81u8)
+            .filter(|&cell| moves & (1 << cell) != 0)
+            .map(|cell| proto_move((cell / 9, cell % 9)))
+            .collect(),
+    }
+}
+
+impl From<&Evaluation> for proto::Evaluation {
+    fn from(ev: &Evaluation) -> Self {
+        Self {
+            best_move: ev.pv.first().copied().map(proto_move),
+            confidence: ev.confidence,
+            calibrated: ev.calibrated,
+            score: ev.score,
+            margin: ev.margin.is_finite().then_some(ev.margin),
+            expected_plies: ev.expected_plies,
+            iterations: ev.info.iterations,
+            pv: ev.pv.iter().copied().map(proto_move).collect(),
+            proven: outcome(ev.proven.unwrap_or(GameState::InProgress)).into(),
+        }
+    }
+}
+
+impl From<&SearchFrame> for proto::Frame {
+    fn from(frame: &SearchFrame) -> Self {
+        Self {
+            iterations: frame.iterations,
+            nodes: frame.nodes as u64,
+            depth: frame.depth as u32,
+            top_moves: frame
+                .top_moves
+                .iter()
+                .map(|top| proto::FrameMove {
+                    r#move: Some(proto_move(top.mve)),
+                    visits: top.visits,
+                    win_rate: top.win_rate,
+                })
+                .collect(),
+            pv: frame.pv.iter().copied().map(proto_move).collect(),
+        }
+    }
+}
+
+type Updates = mpsc::UnboundedSender<Result<AnalyzeUpdate, Status>>;
+
+/// Passes frames on to an `AnalyzeStream` response.
+struct StreamSink {
+    updates: Updates,
+    cancel: CancellationToken,
+}
+
+impl FrameSink for StreamSink {
+    fn frame(&self, frame: &SearchFrame) {
+        let update = AnalyzeUpdate {
+            update: Some(Update::Frame(frame.into())),
+        };
+        // The client is gone, nobody wants the rest of the search.
+        if self.updates.send(Ok(update)).is_err() {
+            self.cancel.cancel();
+        }
+    }
+}
+
+/// Runs `search` off the async runtime, where it can't hold up other
+/// requests.
+async fn blocking<T: Send + 'static>(
+    search: impl FnOnce() -> Result<T, StoctopusError> + Send + 'static,
+) -> Result<T, Status> {
+    tokio::task::spawn_blocking(search)
+        .await
+        .map_err(|err| Status::internal(err.to_string()))?
+        .map_err(status)
+}
+
+#[tonic::async_trait]
+impl EngineRpc for EngineService {
+    async fn analyze(
+        &self,
+        request: Request<AnalyzeRequest>,
+    ) -> Result<Response<proto::Evaluation>, Status> {
+        let (mut engine, iterations) = self.engine(request.get_ref()).map_err(status)?;
+        let ev = blocking(move || engine.analyze(iterations)).await?;
+        Ok(Response::new((&ev).into()))
+    }
+
+    async fn play(&self, request: Request<PlayRequest>) -> Result<Response<PositionState>, Status> {
+        let PlayRequest { game, r#move } = request.into_inner();
+        let mve = r#move.ok_or_else(|| Status::invalid_argument("No move to play"))?;
+        let mut engine = replay(game.as_ref(), self.config.engine).map_err(status)?;
+        engine
+            .play(move_pair(&mve).map_err(status)?)
+            .map_err(status)?;
+        let mut game = game.unwrap_or_default();
+        game.moves.push(mve);
+        Ok(Response::new(position_state(&engine, game)))
+    }
+
+    async fn legal_moves(&self, request: Request<Game>) -> Result<Response<PositionState>, Status> {
+        let game = request.into_inner();
+        let engine = replay(Some(&game), self.config.engine).map_err(status)?;
+        Ok(Response::new(position_state(&engine, game)))
+    }
+
+    type AnalyzeStreamStream = UnboundedReceiverStream<Result<AnalyzeUpdate, Status>>;
+
+    async fn analyze_stream(
+        &self,
+        request: Request<AnalyzeRequest>,
+    ) -> Result<Response<Self::AnalyzeStreamStream>, Status> {
+        let request = request.into_inner();
+        let (mut engine, iterations) = self.engine(&request).map_err(status)?;
+        let every = match request.every {
+            0 => self.config.frame_every,
+            every => every,
+        };
+        let (updates, receiver) = mpsc::unbounded_channel();
+        let cancel = CancellationToken::new();
+        let sink = StreamSink {
+            updates: updates.clone(),
+            cancel: cancel.clone(),
+        };
+        engine.set_frame_sink(Some(Arc::new(sink)), every);
+        tokio::spawn(async move {
+            let result = blocking(move || {
+                engine.analyze_with_limits(SearchLimits::iterations(iterations), &cancel)
+            })
+            .await;
+            let update = result.map(|ev| AnalyzeUpdate {
+                update: Some(Update::Evaluation((&ev).into())),
+            });
+            // As above, the client may be gone.
+            let _ = updates.send(update);
+        });
+        Ok(Response::new(UnboundedReceiverStream::new(receiver)))
+    }
+}
+
+#[cfg(test)]
+mod grpc_tests {
+    use tokio_stream::StreamExt;
+    use tonic::{Code, Request};
+
+    use crate::grpc::proto::analyze_update::Update;
+    use crate::grpc::proto::engine_server::Engine as _;
+    use crate::grpc::proto::{AnalyzeRequest, Game, Move, Outcome, PlayRequest};
+    use crate::grpc::{status, EngineService, GrpcConfig};
+    use crate::StoctopusError;
+
+    fn game(moves: &[(u32, u32)]) -> Game {
+        Game {
+            moves: moves
+                .iter()
+                .map(|&(global, local)| Move { global, local })
+                .collect(),
+        }
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Runtime::new().unwrap().block_on(future)
+    }
+
+    #[test]
+    fn test_play_and_legal_moves() {
+        let service = EngineService::default();
+        block_on(async {
+            let state = service
+                .play(Request::new(PlayRequest {
+                    game: Some(game(&[(4, 4)])),
+                    r#move: Some(Move {
+                        global: 4,
+                        local: 0,
+                    }),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(state.game, Some(game(&[(4, 4), (4, 0)])));
+            assert_eq!(state.result(), Outcome::InProgress);
+            // Sent to the top left sub-board.
+            assert_eq!(state.legal_moves.len(), 9);
+            assert!(state.legal_moves.iter().all(|mve| mve.global == 0));
+
+            let legal = service
+                .legal_moves(Request::new(game(&[(4, 4), (4, 0)])))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(legal, state);
+
+            let err = service
+                .play(Request::new(PlayRequest {
+                    game: Some(game(&[(4, 4)])),
+                    r#move: Some(Move {
+                        global: 3,
+                        local: 0,
+                    }),
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), Code::InvalidArgument);
+            let err = service
+                .legal_moves(Request::new(game(&[(4, 4), (4, 9)])))
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), Code::InvalidArgument);
+        });
+        let err = StoctopusError::InvalidArgument("Bad priors".to_string());
+        assert_eq!(status(err).code(), Code::InvalidArgument);
+    }
+
+    #[test]
+    fn test_analyze() {
+        let service = EngineService::new(GrpcConfig {
+            max_iterations: 500,
+            ..GrpcConfig::default()
+        });
+        let request = AnalyzeRequest {
+            game: Some(game(&[(4, 4)])),
+            iterations: 1_000_000,
+            seed: Some(5),
+            every: 0,
+        };
+        block_on(async {
+            let ev = service
+                .analyze(Request::new(request.clone()))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(ev.iterations, 500);
+            assert_eq!(ev.best_move.map(|mve| mve.global), Some(4));
+            assert_eq!(ev.pv.first(), ev.best_move.as_ref());
+            // Seeded searches agree.
+            let again = service
+                .analyze(Request::new(request))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(again, ev);
+        });
+    }
+
+    #[test]
+    fn test_analyze_stream() {
+        let service = EngineService::default();
+        let request = AnalyzeRequest {
+            game: Some(game(&[])),
+            iterations: 1000,
+            seed: Some(2),
+            every: 200,
+        };
+        block_on(async {
+            let updates: Vec<_> = service
+                .analyze_stream(Request::new(request.clone()))
+                .await
+                .unwrap()
+                .into_inner()
+                .map(|update| update.unwrap().update.unwrap())
+                .collect()
+                .await;
+            let Some((Update::Evaluation(ev), frames)) = updates.split_last() else {
+                panic!("The evaluation comes last");
+            };
+            assert!(frames.len() >= 2);
+            let mut iterations = 0;
+            for frame in frames {
+                let Update::Frame(frame) = frame else {
+                    panic!("Only the last update is an evaluation");
+                };
+                assert!(frame.iterations >= iterations);
+                iterations = frame.iterations;
+            }
+            let direct = service
+                .analyze(Request::new(request))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(*ev, direct);
+        });
+    }
+}
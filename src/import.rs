@@ -0,0 +1,184 @@
+//! Streams large files of recorded games, one game per line, into
+//! [`GameRecord`]s. Two line formats are read:
+//!
+//! - CSV: the moves separated by spaces, optionally followed by a comma
+//!   and the result, as in `44 40 04,1-0`. A header line starting with
+//!   `moves` is skipped.
+//! - JSON lines: `{"moves": ["44", "40", "04"], "result": "1-0"}`, where
+//!   `result` may be left out and `swap` may follow the first move.
+//!
+//! Results are written as in [`GameRecord`] and default to unfinished.
+//! Blank lines and lines starting with `#` are skipped in both formats.
+
+use std::io::BufRead;
+
+use serde::Deserialize;
+
+use crate::game::{Board, GameState, Rules};
+use crate::{BookBuilder, GameRecord, StoctopusError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportFormat {
+    Csv,
+    JsonLines,
+}
+
+#[derive(Deserialize)]
+struct JsonGame {
+    moves: Vec<String>,
+    #[serde(default)]
+    result: Option<String>,
+}
+
+/// Iterator over the games of a reader. A line that fails to parse or
+/// holds an illegal game yields an error naming its line number, after
+/// which the following lines are still read.
+pub struct GameImporter<R> {
+    reader: R,
+    format: ImportFormat,
+    rules: Rules,
+    line_number: usize,
+    line: String,
+}
+
+impl<R: BufRead> GameImporter<R> {
+    /// Games played under `rules`, which the file formats don't record.
+    pub fn new(reader: R, format: ImportFormat, rules: Rules) -> Self {
+        Self {
+            reader,
+            format,
+            rules,
+            line_number: 0,
+            line: String::new(),
+        }
+    }
+
+    /// Line number of the last game returned, starting at 1.
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    fn parse_line(&self, line: &str) -> Result<GameRecord, StoctopusError> {
+        let mut record = GameRecord::new(self.rules);
+        let result = match self.format {
+            ImportFormat::Csv => {
+                let (moves, result) = line.split_once(',').unwrap_or((line, ""));
+                if !matches!(result.trim(), "" | "1-0" | "0-1" | "1/2-1/2" | "*") {
+                    return Err(StoctopusError::Protocol(format!(
+                        "Bad result {:?}",
+                        result.trim()
+                    )));
+                }
+                record.parse_movetext(moves.split_whitespace().chain(result.split_whitespace()))?
+            }
+            ImportFormat::JsonLines => {
+                let game: JsonGame = serde_json::from_str(line)
+                    .map_err(|err| StoctopusError::Protocol(err.to_string()))?;
+                let moves = game.moves.iter().map(String::as_str);
+                record.parse_movetext(moves.chain(game.result.as_deref()))?
+            }
+        };
+        record.result = result.unwrap_or(GameState::InProgress);
+        record.board()?;
+        Ok(record)
+    }
+}
+
+impl<R: BufRead> Iterator for GameImporter<R> {
+    type Item = Result<GameRecord, StoctopusError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(err) => return Some(Err(err.into())),
+            }
+            self.line_number += 1;
+            let line = self.line.trim();
+            let header = self.format == ImportFormat::Csv && line.starts_with("moves");
+            if line.is_empty() || line.starts_with('#') || header {
+                continue;
+            }
+            return Some(self.parse_line(line).map_err(|err| {
+                StoctopusError::Protocol(format!("line {}: {err}", self.line_number))
+            }));
+        }
+    }
+}
+
+impl BookBuilder {
+    /// Adds every move of `record` as a book move of weight 1, so moves
+    /// played in more games end up with more weight.
+    pub fn add_record(&mut self, record: &GameRecord) -> Result<(), StoctopusError> {
+        record.board()?;
+        let mut board = Board::with_rules(record.rules);
+        for &(global, local) in &record.moves {
+            let mve = Board::move_from_gl(global, local);
+            self.add(&board, mve, 1);
+            board.make(mve);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod import_tests {
+    use std::io::Cursor;
+
+    use crate::game::{Board, GameState, Player, Rules};
+    use crate::import::{GameImporter, ImportFormat};
+    use crate::{BookBuilder, OpeningBook};
+
+    #[test]
+    fn test_import_csv() {
+        let file = "moves,result\n44 40 04,1-0\n\n# comment\n44 44\n40 04\n";
+        let mut games = GameImporter::new(Cursor::new(file), ImportFormat::Csv, Rules::default());
+        let first = games.next().unwrap().unwrap();
+        assert_eq!(first.moves, vec![(4, 4), (4, 0), (0, 4)]);
+        assert_eq!(first.result, GameState::Won(Player::X));
+        assert_eq!(games.line_number(), 2);
+
+        let err = games.next().unwrap().unwrap_err().to_string();
+        assert!(err.contains("line 5"), "{err}");
+        let last = games.next().unwrap().unwrap();
+        assert_eq!(last.result, GameState::InProgress);
+        assert!(games.next().is_none());
+    }
+
+    #[test]
+    fn test_import_json_lines() {
+        let file = concat!(
+            "{\"moves\": [\"44\", \"40\"], \"result\": \"1/2-1/2\"}\n",
+            "{\"moves\": [\"44\", \"swap\"]}\n",
+            "not json\n",
+            "{\"moves\": [\"44\", \"40\"], \"result\": \"2-0\"}\n",
+        );
+        let games: Vec<_> =
+            GameImporter::new(Cursor::new(file), ImportFormat::JsonLines, Rules::default())
+                .collect();
+        assert_eq!(games.len(), 4);
+        assert_eq!(games[0].as_ref().unwrap().result, GameState::Draw);
+        // Swaps need the pie rule.
+        for (i, game) in games.iter().enumerate().skip(1) {
+            let err = game.as_ref().unwrap_err().to_string();
+            assert!(err.contains(&format!("line {}", i + 1)), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_add_record() {
+        let file = "44 40 04,*\n44 40,*\n";
+        let mut builder = BookBuilder::new();
+        for game in GameImporter::new(Cursor::new(file), ImportFormat::Csv, Rules::default()) {
+            builder.add_record(&game.unwrap()).unwrap();
+        }
+        let mut bytes = vec![];
+        builder.write_to(&mut bytes).unwrap();
+        let book = OpeningBook::from_bytes(bytes).unwrap();
+        assert_eq!(book.len(), 3);
+        let start = Board::default();
+        assert_eq!(book.moves(start.zobrist_hash()), vec![(0x44, 2)]);
+    }
+}
@@ -1,251 +1,1098 @@
 #![feature(portable_simd)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
-use std::fmt::Display;
+#[cfg(feature = "std")]
+use std::sync::Arc;
 
+#[cfg(feature = "std")]
+use best_response::{BestResponse, Exploitation, OpponentPolicy};
+#[cfg(feature = "std")]
 use deepsize::DeepSizeOf;
-use game::GameState;
-use mcts::{MCTSArena, MCTSNode, NodeId};
+#[cfg(feature = "std")]
+use divergence::{fnv1a, FNV_OFFSET};
+#[cfg(feature = "std")]
+use frames::{FrameOutput, FrameSink};
+#[cfg(feature = "std")]
+use mcts::{MCTSArena, MCTSNode, NodeId, RootPriors};
+#[cfg(feature = "std")]
+use rand::{rngs::StdRng, Rng, SeedableRng};
+#[cfg(feature = "std")]
+use rayon::prelude::*;
+#[cfg(feature = "std")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use symmetry::SYMMETRIES;
 
+#[cfg(feature = "std")]
+pub use analysis_cache::{AnalysisCache, CacheEntry};
+#[cfg(feature = "std")]
+pub use book::{BookBuilder, BookProgress, OpeningBook};
+#[cfg(feature = "std")]
+pub use budget::GameBudget;
+#[cfg(feature = "std")]
+pub use calibration::Calibration;
+#[cfg(feature = "std")]
+pub use cancel::CancellationToken;
+#[cfg(feature = "std")]
+pub use config::EngineConfig;
+#[cfg(feature = "std")]
+pub use debug_bundle::DebugBundle;
+#[cfg(feature = "std")]
+pub use divergence::Divergence;
+#[cfg(feature = "std")]
+pub use error::{SearchError, SessionError, StoctopusError};
+#[cfg(feature = "std")]
+pub use experience::{Experience, ExperienceEntry};
+pub use game::{Board, BoardError, GameState, Handicap, Phase, Player, Rules, Undo, Variant};
+#[cfg(feature = "std")]
+pub use import::{GameImporter, ImportFormat};
+#[cfg(feature = "std")]
+pub use mcts::{
+    Backup, BestMoveChange, EarlyStop, HumanModel, MCTSConfig, PlayoutAdaptation, RandomOpening,
+    RootPruning, SearchInfo, SearchLimits, SearchMode, SearchTrace, SelectionPolicy, Teaching,
+    ThreadStats, TraceReplay, TraceStep, TreeStats, Widening,
+};
+#[cfg(feature = "std")]
+pub use notation::Move;
+#[cfg(feature = "std")]
+pub use position::Position;
+#[cfg(feature = "std")]
+pub use probe::{BookProvider, TablebaseProvider};
+#[cfg(feature = "std")]
+pub use record::{Annotation, GameRecord, MoveTag, Variation, VariationPath};
+pub use sized::{Board4, SizedBoard};
+#[cfg(feature = "std")]
+pub use suite::{PositionSuite, SuitePosition};
+#[cfg(feature = "std")]
+pub use summary::{MoveReason, MoveSummary};
+
+#[cfg(feature = "std")]
+mod analysis_cache;
+#[cfg(feature = "std")]
+pub mod best_response;
+#[cfg(feature = "std")]
+mod book;
+#[cfg(feature = "std")]
+pub mod bot;
+#[cfg(feature = "std")]
+mod budget;
+#[cfg(feature = "std")]
+mod calibration;
+#[cfg(feature = "std")]
+mod cancel;
+#[cfg(feature = "std")]
+mod checkpoint;
+#[cfg(feature = "std")]
+mod config;
+#[cfg(feature = "std")]
+mod debug_bundle;
+#[cfg(feature = "std")]
+pub mod distributed;
+#[cfg(feature = "std")]
+mod divergence;
+pub mod embedded;
+#[cfg(feature = "std")]
+mod error;
+#[cfg(feature = "std")]
+mod eval;
+#[cfg(feature = "std")]
+mod experience;
+#[cfg(feature = "std")]
+pub mod explorer;
+#[cfg(feature = "std")]
+pub mod frames;
 mod game;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "std")]
+mod import;
+#[cfg(feature = "std")]
+pub mod match_runner;
+#[cfg(feature = "std")]
 mod mcts;
+#[cfg(feature = "std")]
+pub mod nested;
+#[cfg(feature = "std")]
+mod notation;
+#[cfg(feature = "std")]
+mod position;
+#[cfg(feature = "std")]
+mod probe;
+#[cfg(feature = "std")]
+pub mod rating;
+#[cfg(feature = "std")]
+mod record;
+#[cfg(feature = "std")]
+pub mod relay;
+#[cfg(feature = "std")]
+pub mod session;
+mod sized;
+#[cfg(feature = "std")]
+mod suite;
+#[cfg(feature = "std")]
+mod summary;
+#[cfg(feature = "std")]
+mod symmetry;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+#[cfg(feature = "parquet")]
+pub mod training;
+#[cfg(feature = "std")]
+pub mod ugi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+mod zobrist;
 
+#[cfg(feature = "std")]
 pub struct Engine {
     arena: mcts::MCTSArena,
     current_node: NodeId,
+    config: MCTSConfig,
+    calibration: Calibration,
+    analysis_cache: Option<AnalysisCache>,
+    experience: Option<Arc<Experience>>,
+    frames: Option<FrameOutput>,
+    book: Option<Arc<dyn BookProvider>>,
+    tablebase: Option<Arc<dyn TablebaseProvider>>,
+    /// Seeds choices that should vary from game to game but not within one.
+    game_seed: u64,
 }
 
-#[derive(Debug)]
-pub struct Evaluation {
-    pub confidence: f32,
-    pub best_move: NodeId,
+/// Game seed of a new game: the search seed when deterministic, otherwise
+/// random.
+#[cfg(feature = "std")]
+fn fresh_game_seed(config: &MCTSConfig) -> u64 {
+    match config.mode {
+        SearchMode::Deterministic { seed } => seed,
+        SearchMode::Parallel => rand::random(),
+    }
 }
 
+#[cfg(feature = "std")]
 #[derive(Debug)]
-pub enum Error {
-    IllegalMove,
+pub struct Evaluation {
+    /// Raw win percentage of the side to move.
+    pub confidence: f32,
+    /// Half width of the 95% confidence interval of `confidence`, from the
+    /// variance of the best move's results. Infinite unless the source is a
+    /// search.
+    pub margin: f32,
+    /// Plies the game lasts after the position on average, going by the
+    /// games simulated through the best move. `None` unless the source is a
+    /// search.
+    pub expected_plies: Option<f32>,
+    /// `confidence` passed through the engine's [`Calibration`].
+    pub calibrated: f32,
+    /// `calibrated` on a -100 (lost) to +100 (won) scale for the side to
+    /// move, 0 being balanced. Meant for evaluation bars.
+    pub score: f32,
+    /// `None` when the position hasn't been searched yet.
+    pub best_move: Option<NodeId>,
+    pub source: EvalSource,
+    /// Visits of every root move as `(move, visits)`, sorted by move.
+    pub root_visits: Vec<(u8, u32)>,
+    /// Configuration of the search that produced the evaluation.
+    pub config: MCTSConfig,
+    /// How the search went, empty unless the source is a search.
+    pub info: SearchInfo,
+    /// The position evaluated.
+    pub board: Board,
+    /// Expected line, starting with the best move. Empty when there is no
+    /// best move.
+    pub pv: Vec<(u8, u8)>,
+    /// Result of the position under perfect play, once the tablebase or
+    /// the search's [`MCTSConfig::solver`] proved it.
+    pub proven: Option<GameState>,
 }
 
-impl Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::IllegalMove => f.write_str("Illegal move"),
+#[cfg(feature = "std")]
+impl Evaluation {
+    /// Hash of the root visit distribution and the configuration, seed
+    /// included. Equal fingerprints mean the searches agreed exactly; the
+    /// hash is the same on every machine. The configuration is hashed in
+    /// its JSON form, which only changes along with the configuration
+    /// fields. See [`Engine::explain_divergence`] when they don't match.
+    pub fn fingerprint(&self) -> u64 {
+        let config = serde_json::to_vec(&self.config).expect("Configurations always serialize");
+        let mut hash = fnv1a(FNV_OFFSET, &config);
+        for &(mve, visits) in &self.root_visits {
+            hash = fnv1a(hash, &[mve]);
+            hash = fnv1a(hash, &visits.to_le_bytes());
         }
+        hash
     }
 }
 
-impl std::error::Error for Error {}
+/// The gist of an [`Evaluation`], without the search tree behind it. See
+/// [`Engine::evaluate_positions`].
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PositionEvaluation {
+    /// `(global, local)` of the best move, `None` without iterations.
+    pub best_move: Option<(u8, u8)>,
+    pub confidence: f32,
+    pub calibrated: f32,
+    pub source: EvalSource,
+    pub iterations: u32,
+}
+
+/// Where an [`Evaluation`] came from.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EvalSource {
+    Search,
+    /// Heuristic evaluation of a position that wasn't searched.
+    Static,
+    /// The move came from the opening book, the value is heuristic.
+    Book,
+    /// The tablebase knows the result of the position.
+    Tablebase,
+    /// Reused from the [`AnalysisCache`].
+    Cache,
+}
 
+#[cfg(feature = "std")]
 impl Engine {
     pub fn init() -> Self {
-        let arena = MCTSArena::init();
+        Self::with_config(MCTSConfig::default())
+    }
+
+    pub fn with_config(config: MCTSConfig) -> Self {
+        let arena = MCTSArena::with_config(Default::default(), config);
 
         Self {
             current_node: arena.root(),
             arena,
+            config,
+            calibration: Calibration::default(),
+            analysis_cache: None,
+            experience: None,
+            frames: None,
+            book: None,
+            tablebase: None,
+            game_seed: fresh_game_seed(&config),
         }
     }
 
-    pub fn analyze(&mut self, n_iters: u32) -> Evaluation {
-        self.arena = MCTSArena::from(self.arena.resolve(&self.current_node).board);
-        let (confidence, best_node) = self.arena.analyze(self.arena.root(), n_iters);
+    /// Starts from an arbitrary position, rejecting boards that can't arise
+    /// from legal play.
+    pub fn from_board(board: Board, config: MCTSConfig) -> Result<Self, StoctopusError> {
+        board.validate()?;
+        let arena = MCTSArena::with_config(board, config);
 
-        return Evaluation {
-            confidence,
-            best_move: best_node,
-        };
+        Ok(Self {
+            current_node: arena.root(),
+            arena,
+            config,
+            calibration: Calibration::default(),
+            analysis_cache: None,
+            experience: None,
+            frames: None,
+            book: None,
+            tablebase: None,
+            game_seed: fresh_game_seed(&config),
+        })
     }
 
-    pub fn step(&mut self, r#move: NodeId) {
-        self.current_node = r#move;
+    /// Goes back to the start position, dropping the search tree. The rules
+    /// of the current game are kept.
+    pub fn new_game(&mut self) {
+        let rules = self.board().rules;
+        self.arena = MCTSArena::with_config(Board::with_rules(rules), self.config);
+        self.current_node = self.arena.root();
+        self.game_seed = fresh_game_seed(&self.config);
     }
 
-    pub fn play(&mut self, mve: (u8, u8)) -> Result<(), Error> {
-        let node = self.arena.resolve(&self.current_node);
-        if let Some(children) = &node.children {
-            for child in children {
-                let child_node = self.arena.resolve(child);
-                if let Some(last_move) = child_node.board.last_move {
-                    if last_move == ((mve.0 << 4) | mve.1) {
-                        return Ok(self.step(*child));
+    /// Replaces the seed of the current game, which [`Self::new_game`]
+    /// draws afresh, e.g. to replay a logged game.
+    pub fn set_game_seed(&mut self, seed: u64) {
+        self.game_seed = seed;
+    }
+
+    /// Starts over from `board` with an empty tree.
+    pub fn set_board(&mut self, board: Board) -> Result<(), StoctopusError> {
+        board.validate()?;
+        self.arena = MCTSArena::with_config(board, self.config);
+        self.current_node = self.arena.root();
+        Ok(())
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.current().board
+    }
+
+    /// Node of the current position, which garbage collection keeps.
+    fn current(&self) -> &MCTSNode {
+        self.arena
+            .try_resolve(&self.current_node)
+            .expect("The current node is never collected")
+    }
+
+    pub fn config(&self) -> &MCTSConfig {
+        &self.config
+    }
+
+    /// Play book moves instead of searching while `book` knows the position.
+    pub fn set_book(&mut self, book: Option<Arc<dyn BookProvider>>) {
+        self.book = book;
+    }
+
+    /// Use known results from `tablebase` at the root and instead of
+    /// playouts.
+    pub fn set_tablebase(&mut self, tablebase: Option<Arc<dyn TablebaseProvider>>) {
+        self.tablebase = tablebase;
+    }
+
+    /// Starts reusing results of earlier searches of the same position, and
+    /// recording new ones in `cache`. The cache is kept by [`Self::new_game`].
+    pub fn set_analysis_cache(&mut self, cache: AnalysisCache) {
+        self.analysis_cache = Some(cache);
+    }
+
+    /// Stops caching analysis and hands the cache back, e.g. to save it.
+    pub fn take_analysis_cache(&mut self) -> Option<AnalysisCache> {
+        self.analysis_cache.take()
+    }
+
+    /// Hands snapshots of every search to `sink`, one every `every`
+    /// iterations and one at the end. See [`frames`].
+    pub fn set_frame_sink(&mut self, sink: Option<Arc<dyn FrameSink>>, every: u32) {
+        self.frames = sink.map(|sink| FrameOutput {
+            sink,
+            every: every.max(1),
+        });
+    }
+
+    /// Starts blending `experience` into searches and adding what they
+    /// find to it. Like the analysis cache it is kept by
+    /// [`Self::new_game`].
+    pub fn set_experience(&mut self, experience: Experience) {
+        self.experience = Some(Arc::new(experience));
+    }
+
+    pub fn experience(&self) -> Option<&Experience> {
+        self.experience.as_deref()
+    }
+
+    /// Stops learning from searches and hands the table back, e.g. to save
+    /// it.
+    pub fn take_experience(&mut self) -> Option<Experience> {
+        self.arena.set_experience(None);
+        self.experience.take().map(Arc::unwrap_or_clone)
+    }
+
+    /// Takes effect from the next search on.
+    pub fn set_config(&mut self, config: MCTSConfig) {
+        self.config = config;
+    }
+
+    /// Calibration applied to the values reported in [`Evaluation`].
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    /// Starts analysing `position`, which is validated first.
+    pub fn from_position(position: &Position, config: MCTSConfig) -> Result<Self, StoctopusError> {
+        Self::from_board(*position.board(), config)
+    }
+
+    /// Searches the current position for `n_iters` iterations. With zero
+    /// iterations the tree is left untouched and the statistics gathered so
+    /// far are returned.
+    pub fn analyze(&mut self, n_iters: u32) -> Result<Evaluation, StoctopusError> {
+        self.analyze_cancellable(n_iters, &CancellationToken::new())
+    }
+
+    /// Searches with iterations allocated from a per-game `budget`, which
+    /// is charged for the iterations actually run and learns how long the
+    /// game is expected to last.
+    pub fn analyze_with_budget(
+        &mut self,
+        budget: &mut GameBudget,
+        cancel: &CancellationToken,
+    ) -> Result<Evaluation, StoctopusError> {
+        let iterations = budget.allocate(self.board());
+        let ev = self.analyze_with_limits(SearchLimits::iterations(iterations), cancel)?;
+        budget.spend(ev.info.iterations.max(1));
+        if let Some(plies) = ev.expected_plies {
+            let stones = (ev.board.x | ev.board.o).count_ones();
+            budget.set_expected_length(Some(stones + plies.round() as u32));
+        }
+        Ok(ev)
+    }
+
+    /// Searches every board of `boards` concurrently on the current rayon
+    /// pool, sharing `limits` fairly: each position gets an equal part of
+    /// the iterations, and the time limit bounds the whole batch. Every
+    /// position gets its own engine with this engine's configuration, book,
+    /// tablebase and calibration (but not its analysis cache), which is
+    /// returned with the evaluation so the best move can be resolved.
+    pub fn analyze_batch(
+        &self,
+        boards: &[Board],
+        limits: SearchLimits,
+        cancel: &CancellationToken,
+    ) -> Vec<Result<(Engine, Evaluation), StoctopusError>> {
+        if boards.is_empty() {
+            return vec![];
+        }
+        let len = boards.len() as u32;
+        // Positions searched side by side share the time, the others wait.
+        let parallel = rayon::current_num_threads().min(boards.len()) as u32;
+        let share = SearchLimits {
+            iterations: (limits.iterations / len).max(1),
+            time: limits.time.map(|time| time * parallel / len),
+        };
+        boards
+            .par_iter()
+            .map(|&board| {
+                let mut engine = self.engine_for(board)?;
+                let ev = engine.analyze_with_limits(share, cancel)?;
+                Ok((engine, ev))
+            })
+            .collect()
+    }
+
+    /// Searches each of `boards` for `iterations_each` iterations on the
+    /// current rayon pool, keeping only the gist of each evaluation, e.g.
+    /// for labelling datasets. Unlike [`Self::analyze_batch`] the trees are
+    /// dropped as soon as a position is done.
+    pub fn evaluate_positions(
+        &self,
+        boards: &[Board],
+        iterations_each: u32,
+    ) -> Vec<Result<PositionEvaluation, StoctopusError>> {
+        let cancel = CancellationToken::new();
+        boards
+            .par_iter()
+            .map(|&board| {
+                let mut engine = self.engine_for(board)?;
+                let ev = engine.analyze_cancellable(iterations_each, &cancel)?;
+                let best_move = match ev.best_move {
+                    Some(id) => {
+                        let mve = engine.arena.try_resolve(&id)?.board.last_move;
+                        mve.map(|mve| (mve >> 4, mve & 0b1111))
                     }
-                }
-            }
-            return Err(Error::IllegalMove);
-        } else {
-            self.arena.analyze(self.current_node, 1);
-            return self.play(mve);
+                    None => None,
+                };
+                Ok(PositionEvaluation {
+                    best_move,
+                    confidence: ev.confidence,
+                    calibrated: ev.calibrated,
+                    source: ev.source,
+                    iterations: ev.info.iterations,
+                })
+            })
+            .collect()
+    }
+
+    /// Fresh engine for `board` sharing this one's configuration, book,
+    /// tablebase and calibration.
+    fn engine_for(&self, board: Board) -> Result<Engine, StoctopusError> {
+        let mut engine = Engine::from_board(board, self.config)?;
+        engine.calibration = self.calibration.clone();
+        engine.book = self.book.clone();
+        engine.tablebase = self.tablebase.clone();
+        engine.game_seed = self.game_seed;
+        Ok(engine)
+    }
+
+    /// Whether to take over the opponent's first move under the pie rule,
+    /// judged by a search of `n_iters` iterations: swapping pays when the
+    /// side to move is calibrated below even chances. Always `false` where
+    /// the rules don't allow a swap.
+    pub fn should_swap(&mut self, n_iters: u32) -> Result<bool, StoctopusError> {
+        if !self.board().can_swap() {
+            return Ok(false);
         }
+        // The evaluation is for the side to move, i.e. for not swapping.
+        let ev = self.analyze(n_iters)?;
+        Ok(ev.calibrated < 50.0)
     }
 
-    pub fn print_board(&self) {
-        let board = self.arena.resolve(&self.current_node).board;
+    /// Same as [`Self::analyze`], but stops early once `cancel` is set and
+    /// returns the best move found so far.
+    pub fn analyze_cancellable(
+        &mut self,
+        n_iters: u32,
+        cancel: &CancellationToken,
+    ) -> Result<Evaluation, StoctopusError> {
+        self.analyze_with_limits(SearchLimits::iterations(n_iters), cancel)
+    }
 
-        for i in 0..3 {
-            for j in 0..3 {
-                if board.x & (1 << (i * 9 + j)) != 0 {
-                    print!(" X ");
-                } else if board.o & (1 << (i * 9 + j)) != 0 {
-                    print!(" O ");
-                } else {
-                    print!("   ");
-                }
-            }
-            if i != 2 {
-                print!("|");
-            }
+    /// Searches until an iteration or time limit is reached, or `cancel` is
+    /// set.
+    pub fn analyze_with_limits(
+        &mut self,
+        limits: SearchLimits,
+        cancel: &CancellationToken,
+    ) -> Result<Evaluation, StoctopusError> {
+        self.search(limits, cancel, None)
+    }
+
+    /// Same as [`Self::analyze_with_limits`], but the priors of the moves
+    /// come from `priors` instead of the built-in evaluator, e.g. from an
+    /// earlier analysis or an external model. `priors` is indexed by cell,
+    /// `global * 9 + local`, and needn't sum to 1; only legal moves count.
+    /// The root's children also start with `prior_visits` virtual visits
+    /// at an even value, split in proportion to their prior, so the search
+    /// tries the likely moves first under any selection policy.
+    ///
+    /// The analysis cache is neither read nor written, since the result
+    /// depends on the priors.
+    pub fn analyze_with_priors(
+        &mut self,
+        priors: &[f32; 81],
+        prior_visits: f32,
+        limits: SearchLimits,
+        cancel: &CancellationToken,
+    ) -> Result<Evaluation, StoctopusError> {
+        if priors.iter().any(|p| !p.is_finite() || *p < 0.0) {
+            return Err(StoctopusError::InvalidArgument(
+                "Priors must be finite and non-negative".to_string(),
+            ));
         }
-        println!();
+        if !prior_visits.is_finite() || prior_visits < 0.0 {
+            return Err(StoctopusError::InvalidArgument(
+                "Prior visits must be finite and non-negative".to_string(),
+            ));
+        }
+        let priors = RootPriors {
+            priors: *priors,
+            visits: prior_visits,
+        };
+        self.search(limits, cancel, Some(priors))
+    }
 
-        for i in 0..3 {
-            for j in 3..6 {
-                if board.x & (1 << (i * 9 + j)) != 0 {
-                    print!(" X ");
-                } else if board.o & (1 << (i * 9 + j)) != 0 {
-                    print!(" O ");
-                } else {
-                    print!("   ");
-                }
-            }
-            if i != 2 {
-                print!("|");
-            }
+    /// Searches for the best reply to an opponent playing `policy`, with
+    /// `n_iters` iterations for it and as many for the engine's own
+    /// analysis it is compared with, see [`best_response`].
+    pub fn best_response(
+        &mut self,
+        policy: &dyn OpponentPolicy,
+        n_iters: u32,
+    ) -> Result<Exploitation, StoctopusError> {
+        let board = *self.board();
+        let search = BestResponse {
+            iterations: n_iters,
+            seed: self.game_seed ^ board.zobrist_hash(),
+            ..Default::default()
+        };
+        let response = search.run(&board, policy)?;
+        let evaluation = self.analyze(n_iters)?;
+        Ok(Exploitation {
+            exploitability: response.win_rate - evaluation.confidence,
+            response,
+            evaluation,
+        })
+    }
+
+    fn search(
+        &mut self,
+        limits: SearchLimits,
+        cancel: &CancellationToken,
+        priors: Option<RootPriors>,
+    ) -> Result<Evaluation, StoctopusError> {
+        if limits.iterations == 0 {
+            return Ok(self.current_evaluation()?);
         }
-        println!();
 
-        for i in 0..3 {
-            for j in 6..9 {
-                if board.x & (1 << (i * 9 + j)) != 0 {
-                    print!(" X ");
-                } else if board.o & (1 << (i * 9 + j)) != 0 {
-                    print!(" O ");
-                } else {
-                    print!("   ");
+        let board = self.current().board;
+        self.arena = MCTSArena::with_config(board, self.config);
+        self.current_node = self.arena.root();
+
+        // Books and caches may come from files, don't trust their moves.
+        let legal = |&m: &u8| {
+            let (global, local) = (m >> 4, m & 0b1111);
+            global < 9 && local < 9 && board.get_moves() & (1 << (global * 9 + local)) != 0
+        };
+        // Books only cover openings, later probes would just miss.
+        if let Some(book) = self
+            .book
+            .as_ref()
+            .filter(|_| board.phase() == Phase::Opening)
+        {
+            let book_move = match self.random_opening(&board) {
+                Some((random, mut rng)) => {
+                    let moves: Vec<_> = book
+                        .probe_all(&board)
+                        .into_iter()
+                        .filter(|(m, _)| legal(m))
+                        .map(|(m, weight)| (m, weight as f32))
+                        .collect();
+                    random.pick(&moves, &mut rng)
                 }
+                None => book.probe(&board).filter(legal),
+            };
+            if let Some(m) = book_move {
+                let wins = eval::static_eval(&board);
+                let best_node = self
+                    .arena
+                    .add_searched_child(self.current_node, m, wins, 1.0)?;
+                return Ok(self.evaluation(wins * 100.0, Some(best_node), EvalSource::Book)?);
             }
-            if i != 2 {
-                print!("|");
+        }
+        if let Some(tablebase) = &self.tablebase {
+            if let Some((m, result)) = probe::solve_root(&board, tablebase.as_ref()) {
+                let wins = match result {
+                    GameState::Won(winner) if winner == board.next_player => 1.0,
+                    GameState::Won(_) => 0.0,
+                    GameState::Draw | GameState::InProgress => 0.5,
+                };
+                let best_node = self
+                    .arena
+                    .add_searched_child(self.current_node, m, wins, 1.0)?;
+                let mut ev =
+                    self.evaluation(wins * 100.0, Some(best_node), EvalSource::Tablebase)?;
+                ev.proven = Some(result).filter(|&result| result != GameState::InProgress);
+                return Ok(ev);
             }
+            self.arena.set_tablebase(Some(tablebase.clone()));
         }
-        println!();
-        println!("---------+---------+---------");
-        for i in 3..6 {
-            for j in 0..3 {
-                if board.x & (1 << (i * 9 + j)) != 0 {
-                    print!(" X ");
-                } else if board.o & (1 << (i * 9 + j)) != 0 {
-                    print!(" O ");
-                } else {
-                    print!("   ");
-                }
+
+        let hash = board.zobrist_hash();
+        // The cache only knows the best move, so engines that may choose
+        // another one search instead.
+        let cached = self
+            .analysis_cache
+            .as_ref()
+            .filter(|_| priors.is_none() && !self.varies_choice(&board))
+            .and_then(|cache| cache.get(hash));
+        let cached = cached.filter(|entry| {
+            entry.iterations >= limits.iterations
+                && entry.best_visits > 0.0
+                && legal(&entry.best_move)
+        });
+        if let Some(&entry) = cached {
+            let best_node = self.arena.add_searched_child(
+                self.current_node,
+                entry.best_move,
+                entry.wins,
+                entry.best_visits,
+            )?;
+            return Ok(self.evaluation(
+                entry.wins / entry.best_visits * 100.0,
+                Some(best_node),
+                EvalSource::Cache,
+            )?);
+        }
+
+        self.arena.set_experience(self.experience.clone());
+        self.arena.set_frames(self.frames.clone());
+        self.arena.set_root_priors(priors);
+        let (mut confidence, mut best_node) =
+            self.arena.analyze(self.current_node, limits, cancel)?;
+        if let Some(experience) = &mut self.experience {
+            // Let go of the search's copy so the table isn't cloned.
+            self.arena.set_experience(None);
+            Arc::make_mut(experience).learn(self.arena.nodes());
+        }
+        if let Some(cache) = self.analysis_cache.as_mut().filter(|_| priors.is_none()) {
+            let best = self.arena.try_resolve(&best_node)?;
+            cache.insert(
+                hash,
+                CacheEntry {
+                    best_move: best.board.last_move.ok_or(SearchError::NoMove)?,
+                    wins: best.wins,
+                    best_visits: best.visits,
+                    iterations: self.arena.iterations(),
+                },
+            );
+        }
+        // After caching, which is about the position rather than this game.
+        if let Some((random, mut rng)) = self.random_opening(&board) {
+            let children: Vec<_> = self
+                .child_stats(self.current_node)
+                .into_iter()
+                .map(|child| (child, child.win_rate))
+                .collect();
+            if let Some(child) = random.pick(&children, &mut rng) {
+                (confidence, best_node) = (child.win_rate * 100.0, child.node);
             }
-            if i != 5 {
-                print!("|");
+        } else if let Some(human) = self.config.human {
+            let children = self.child_stats(self.current_node);
+            let moves: Vec<u8> = children
+                .iter()
+                .map(|child| Board::move_from_gl(child.mve.0, child.mve.1))
+                .collect();
+            let candidates: Vec<_> = children
+                .iter()
+                .zip(eval::move_priors(&board, &moves))
+                .map(|(&child, prior)| (child, child.win_rate, prior))
+                .collect();
+            if let Some(child) = human.pick(&candidates, &mut self.position_rng(&board)) {
+                (confidence, best_node) = (child.win_rate * 100.0, child.node);
             }
+        } else if let Some(teaching) = self.config.teaching {
+            let candidates: Vec<_> = self
+                .child_stats(self.current_node)
+                .into_iter()
+                .map(|child| {
+                    let replies = self.arena.try_resolve(&child.node)?.board.get_moves();
+                    Ok((child, child.win_rate, child.visits, replies.count_ones()))
+                })
+                .collect::<Result<Vec<_>, SearchError>>()?;
+            if let Some(child) = teaching.pick(&candidates) {
+                (confidence, best_node) = (child.win_rate * 100.0, child.node);
+            }
+        }
+        if self.config.symmetric_openings {
+            best_node = self.symmetric_alternative(best_node)?;
         }
-        println!();
 
-        for i in 3..6 {
-            for j in 3..6 {
-                if board.x & (1 << (i * 9 + j)) != 0 {
-                    print!(" X ");
-                } else if board.o & (1 << (i * 9 + j)) != 0 {
-                    print!(" O ");
-                } else {
-                    print!("   ");
-                }
+        Ok(self.evaluation(confidence, Some(best_node), EvalSource::Search)?)
+    }
+
+    /// A uniformly drawn move among the moves symmetric to `chosen` in the
+    /// current position, `chosen` included. A move pruned from the search
+    /// as symmetric gets a child with `chosen`'s statistics.
+    fn symmetric_alternative(&mut self, chosen: NodeId) -> Result<NodeId, SearchError> {
+        let board = self.current().board;
+        let chosen_node = self.arena.try_resolve(&chosen)?;
+        let (wins, visits) = (chosen_node.wins, chosen_node.visits);
+        let mve = chosen_node.board.last_move.ok_or(SearchError::NoMove)?;
+        let (global, local) = ((mve >> 4) as usize, (mve & 0b1111) as usize);
+        let mut moves: Vec<u8> = SYMMETRIES
+            .iter()
+            .enumerate()
+            .filter(|&(s, _)| board.transformed(s) == board)
+            .map(|(_, perm)| Board::move_from_gl(perm[global], perm[local]))
+            .collect();
+        moves.sort_unstable();
+        moves.dedup();
+        let mut rng = StdRng::seed_from_u64(self.game_seed ^ board.zobrist_hash());
+        let picked = moves[rng.gen_range(0..moves.len())];
+        if picked == mve {
+            return Ok(chosen);
+        }
+        for &child in self.current().children.iter().flatten() {
+            if self.arena.try_resolve(&child)?.board.last_move == Some(picked) {
+                return Ok(child);
             }
-            if i != 5 {
-                print!("|");
+        }
+        self.arena
+            .add_searched_child(self.current_node, picked, wins, visits)
+    }
+
+    /// Whether the move played in `board` may be other than the best one
+    /// found, because of a random opening, a human model, teaching or
+    /// symmetric openings.
+    fn varies_choice(&self, board: &Board) -> bool {
+        self.random_opening(board).is_some()
+            || self.config.human.is_some()
+            || self.config.teaching.is_some()
+            || self.config.symmetric_openings
+    }
+
+    /// The random opening settings if they apply to `board`, with the RNG
+    /// to pick with.
+    fn random_opening(&self, board: &Board) -> Option<(RandomOpening, StdRng)> {
+        let random = self.config.random_opening.filter(|r| r.applies(board))?;
+        Some((random, self.position_rng(board)))
+    }
+
+    /// RNG for picking a move other than the best in `board`.
+    /// Deterministic searches pick the same move every time.
+    fn position_rng(&self, board: &Board) -> StdRng {
+        match self.config.mode {
+            SearchMode::Deterministic { seed } => {
+                StdRng::seed_from_u64(seed ^ board.zobrist_hash())
             }
+            _ => StdRng::from_entropy(),
         }
-        println!();
+    }
 
-        for i in 3..6 {
-            for j in 6..9 {
-                if board.x & (1 << (i * 9 + j)) != 0 {
-                    print!(" X ");
-                } else if board.o & (1 << (i * 9 + j)) != 0 {
-                    print!(" O ");
-                } else {
-                    print!("   ");
-                }
+    fn evaluation(
+        &self,
+        confidence: f32,
+        best_move: Option<NodeId>,
+        source: EvalSource,
+    ) -> Result<Evaluation, SearchError> {
+        let calibrated = self.calibration.apply(confidence / 100.0) * 100.0;
+        let mut root_visits: Vec<_> = self
+            .child_stats(self.current_node)
+            .into_iter()
+            .map(|child| {
+                (
+                    Board::move_from_gl(child.mve.0, child.mve.1),
+                    child.visits as u32,
+                )
+            })
+            .collect();
+        root_visits.sort_unstable();
+        let best = best_move
+            .map(|best| self.arena.try_resolve(&best))
+            .transpose()?;
+        let pv = match (best_move, best) {
+            (Some(id), Some(best)) => {
+                let mve = best.board.last_move.ok_or(SearchError::NoMove)?;
+                let mut pv = vec![(mve >> 4, mve & 0b1111)];
+                pv.extend(self.line_from(id));
+                pv
             }
-            if i != 5 {
-                print!("|");
+            _ => vec![],
+        };
+        let margin = match (best, source) {
+            (Some(best), EvalSource::Search) => {
+                let (low, high) = best.value_bounds(1.96);
+                (high - low) / 2.0 * 100.0
             }
-        }
-        println!();
-        println!("---------+---------+---------");
-        for i in 6..9 {
-            for j in 0..3 {
-                if board.x & (1 << (i * 9 + j)) != 0 {
-                    print!(" X ");
-                } else if board.o & (1 << (i * 9 + j)) != 0 {
-                    print!(" O ");
-                } else {
-                    print!("   ");
-                }
+            _ => f32::INFINITY,
+        };
+        let current = self.current();
+        let expected_plies = match (best, source) {
+            (Some(best), EvalSource::Search) => {
+                let stones = (current.board.x | current.board.o).count_ones() as f32;
+                best.expected_length().map(|end| end - stones)
             }
-            if i != 8 {
-                print!("|");
+            _ => None,
+        };
+        let proven = match source {
+            EvalSource::Search => current.solved,
+            _ => None,
+        };
+        Ok(Evaluation {
+            confidence,
+            margin,
+            expected_plies,
+            calibrated,
+            score: (calibrated - 50.0) * 2.0,
+            best_move,
+            source,
+            root_visits,
+            config: self.config,
+            info: self.arena.info(),
+            board: current.board,
+            pv,
+            proven,
+        })
+    }
+
+    /// Compares two evaluations of the same position, e.g. to find out why
+    /// runs on two machines disagree.
+    pub fn explain_divergence(a: &Evaluation, b: &Evaluation) -> Divergence {
+        Divergence::between(a, b)
+    }
+
+    fn current_evaluation(&self) -> Result<Evaluation, SearchError> {
+        match self.arena.select_best_child(self.current_node)? {
+            Some(best_move) => {
+                let best = self.arena.try_resolve(&best_move)?;
+                self.evaluation(
+                    best.wins / best.visits * 100.0,
+                    Some(best_move),
+                    EvalSource::Search,
+                )
             }
+            None => self.evaluation(self.static_eval(), None, EvalSource::Static),
+        }
+    }
+
+    /// Heuristic confidence (in percent) of the side to move, computed
+    /// without expanding the tree.
+    pub fn static_eval(&self) -> f32 {
+        eval::static_eval(&self.current().board) * 100.0
+    }
+
+    /// Moves to the node `r#move`, failing if garbage collection freed it
+    /// or a later search replaced its tree.
+    pub fn step(&mut self, r#move: NodeId) -> Result<(), StoctopusError> {
+        self.arena.try_resolve(&r#move)?;
+        self.current_node = r#move;
+        Ok(())
+    }
+
+    pub fn play(&mut self, mve: (u8, u8)) -> Result<(), StoctopusError> {
+        let node = self.current();
+        if mve.0 > 8
+            || mve.1 > 8
+            || node.board.game_over()
+            || node.board.get_moves() & (1 << (mve.0 * 9 + mve.1)) == 0
+        {
+            return Err(StoctopusError::IllegalMove);
         }
-        println!();
 
-        for i in 6..9 {
-            for j in 3..6 {
-                if board.x & (1 << (i * 9 + j)) != 0 {
-                    print!(" X ");
-                } else if board.o & (1 << (i * 9 + j)) != 0 {
-                    print!(" O ");
-                } else {
-                    print!("   ");
+        let m = Board::move_from_gl(mve.0, mve.1);
+        if let Some(children) = &node.children {
+            for child in children {
+                let child_node = self.arena.try_resolve(child)?;
+                if child_node.board.last_move == Some(m) {
+                    self.current_node = *child;
+                    return Ok(());
                 }
             }
-            if i != 8 {
-                print!("|");
-            }
         }
-        println!();
 
-        for i in 6..9 {
-            for j in 6..9 {
-                if board.x & (1 << (i * 9 + j)) != 0 {
+        // Not in the tree, either unexpanded or pruned as symmetric to
+        // another move.
+        self.arena = MCTSArena::with_config(node.board.unchecked_play(m), self.config);
+        self.current_node = self.arena.root();
+        Ok(())
+    }
+
+    pub fn print_board(&self) {
+        let board = self.current().board;
+
+        for row in 0..9 {
+            for col in 0..9 {
+                let cell = 1u128 << Move::from_row_col(row, col).expect("On the grid").index();
+                if board.x & cell != 0 {
                     print!(" X ");
-                } else if board.o & (1 << (i * 9 + j)) != 0 {
+                } else if board.o & cell != 0 {
                     print!(" O ");
                 } else {
                     print!("   ");
                 }
+                if col % 3 == 2 && col != 8 {
+                    print!("|");
+                }
             }
-            if i != 8 {
-                print!("|");
+            println!();
+            if row % 3 == 2 && row != 8 {
+                println!("---------+---------+---------");
             }
         }
-        println!();
     }
 
     pub fn is_game_over(&self) -> bool {
-        let node = self.arena.resolve(&self.current_node);
-        node.board.game_over()
+        self.current().board.game_over()
     }
     pub fn game_state(&self) -> GameState {
-        let node = self.arena.resolve(&self.current_node);
-        node.board.check_game_state()
+        self.current().board.check_game_state()
     }
 
+    /// Heap and stack size of the search tree in bytes. Walks the whole
+    /// tree, prefer [`Self::tree_size`] for frequent checks.
     pub fn memory(&self) -> usize {
         self.arena.deep_size_of()
     }
 
-    pub fn resolve_node(&self, id: &NodeId) -> &MCTSNode {
-        self.arena.resolve(id)
+    /// Iterations recorded since the last call, when `config.trace` is set.
+    /// Every analysis starts a new trace.
+    pub fn take_trace(&mut self) -> Option<SearchTrace> {
+        self.arena.take_trace()
+    }
+
+    /// Shape of the search tree below the current position.
+    pub fn tree_stats(&self) -> TreeStats {
+        self.arena.stats(self.current_node)
+    }
+
+    /// Number of nodes in the search tree, in O(1).
+    pub fn tree_size(&self) -> usize {
+        self.arena.node_count()
+    }
+
+    pub fn current_node(&self) -> NodeId {
+        self.current_node
+    }
+
+    /// Children of `id` with their statistics, most visited first. None
+    /// if `id` is stale.
+    pub fn child_stats(&self, id: NodeId) -> Vec<explorer::ChildStats> {
+        let Ok(node) = self.arena.try_resolve(&id) else {
+            return vec![];
+        };
+        let stones = (node.board.x | node.board.o).count_ones() as f32;
+        let mut stats: Vec<_> = node
+            .children
+            .iter()
+            .flatten()
+            .filter_map(|&child| {
+                let node = self.arena.try_resolve(&child).ok()?;
+                let mve = node.board.last_move?;
+                Some(explorer::ChildStats {
+                    node: child,
+                    mve: (mve >> 4, mve & 0b1111),
+                    visits: node.visits,
+                    win_rate: node.wins / node.visits.max(1.0),
+                    prior: node.prior,
+                    expected_plies: node.expected_length().map(|end| end - stones),
+                })
+            })
+            .collect();
+        stats.sort_by(|a, b| b.visits.total_cmp(&a.visits));
+        stats
+    }
+
+    /// Moves from the current position following the most visited child,
+    /// as far as the tree goes.
+    pub fn principal_variation(&self) -> Vec<(u8, u8)> {
+        self.line_from(self.current_node)
+    }
+
+    /// Most visited line below `node`.
+    fn line_from(&self, mut node: NodeId) -> Vec<(u8, u8)> {
+        let mut line = Vec::new();
+        while let Some(child) = self.child_stats(node).first() {
+            line.push(child.mve);
+            node = child.node;
+        }
+        line
+    }
+
+    /// Visit shares and values of the root moves on the 9x9 grid.
+    pub fn root_heatmap(&self) -> explorer::RootHeatmap {
+        let board = self.current().board;
+        let children = self.child_stats(self.current_node);
+        let total: f32 = children.iter().map(|child| child.visits).sum();
+        let mut heatmap = explorer::RootHeatmap::default();
+        if total == 0.0 {
+            return heatmap;
+        }
+
+        // Legal moves without a child of their own stand in for a symmetric
+        // one that has a child, and split its visits.
+        let moves = board.get_moves();
+        let class = |m: u8| board.unchecked_play(m).canonical_hash();
+        let mut cells: Vec<Vec<u8>> = vec![vec![]; children.len()];
+        for m in (0..81)
+            .filter(|i| moves & (1 << i) != 0)
+            .map(Board::move_from_index)
+        {
+            let own = children
+                .iter()
+                .position(|child| Board::move_from_gl(child.mve.0, child.mve.1) == m);
+            let child = own.or_else(|| {
+                children.iter().position(|child| {
+                    class(Board::move_from_gl(child.mve.0, child.mve.1)) == class(m)
+                })
+            });
+            if let Some(child) = child {
+                cells[child].push(m);
+            }
+        }
+        for (child, cells) in children.iter().zip(cells) {
+            for &m in &cells {
+                let (row, col) = Move::from_packed(m).expect("Legal move").row_col();
+                let cell = row as usize * 9 + col as usize;
+                heatmap.visit_share[cell] = child.visits / total / cells.len() as f32;
+                heatmap.value[cell] = child.win_rate;
+            }
+        }
+        heatmap
+    }
+
+    /// Node of `id`, or [`SearchError::StaleNode`] if garbage collection
+    /// freed it or a new search replaced the tree since the handle was
+    /// handed out.
+    pub fn try_resolve_node(&self, id: &NodeId) -> Result<&MCTSNode, SearchError> {
+        self.arena.try_resolve(id)
     }
 }
 
 #[cfg(test)]
 mod engine_tests {
-    use crate::Engine;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{
+        AnalysisCache, Board, BookProvider, Calibration, CancellationToken, Engine, EvalSource,
+        Experience, GameBudget, GameState, Handicap, HumanModel, MCTSConfig, Player, RandomOpening,
+        Rules, SearchError, SearchLimits, SearchMode, StoctopusError, TablebaseProvider, Teaching,
+    };
 
     #[test]
     fn test_engine() {
@@ -255,15 +1102,16 @@ mod engine_tests {
             move_count += 1;
             println!();
             engine.print_board();
-            let ev = engine.analyze(5000);
-            let node = engine.arena.resolve(&ev.best_move);
+            let ev = engine.analyze(5000).unwrap();
+            let best_move = ev.best_move.unwrap();
+            let node = engine.arena.try_resolve(&best_move).unwrap();
             println!(
                 "\nConfidence {}%, Best Move: {},{}",
                 ev.confidence,
                 (node.board.last_move.unwrap() >> 4) & 0b1111,
                 node.board.last_move.unwrap() & 0b1111
             );
-            engine.step(ev.best_move);
+            engine.step(best_move).unwrap();
         }
 
         println!("\n-----------------------------\n");
@@ -275,15 +1123,650 @@ mod engine_tests {
         );
     }
 
-    // #[test]
+    #[test]
     fn test_play() {
         let mut engine = Engine::init();
         engine.play((4, 4)).unwrap();
         engine.play((4, 0)).unwrap();
         println!(
             "{:?} \nMemory: {}mb",
-            engine.analyze(1000),
+            engine.analyze(1000).unwrap(),
             engine.memory() as f32 / 1000.0 / 1000.0
         );
     }
+
+    #[test]
+    fn test_handle_of_replaced_tree() {
+        let mut engine = Engine::init();
+        let old_best = engine.analyze(100).unwrap().best_move.unwrap();
+        let new_best = engine.analyze(100).unwrap().best_move.unwrap();
+        assert_eq!(
+            engine.try_resolve_node(&old_best).err(),
+            Some(SearchError::StaleNode)
+        );
+        assert!(matches!(
+            engine.step(old_best),
+            Err(StoctopusError::Search(SearchError::StaleNode))
+        ));
+        engine.step(new_best).unwrap();
+    }
+
+    #[test]
+    fn test_analyze_finished_game() {
+        let board = Board::random_position(81, &mut StdRng::seed_from_u64(3));
+        assert!(board.game_over());
+
+        let mut engine = Engine::from_board(board, MCTSConfig::default()).unwrap();
+        assert!(matches!(
+            engine.analyze(10),
+            Err(StoctopusError::Search(SearchError::GameOver))
+        ));
+        assert!(matches!(
+            engine.play((4, 4)),
+            Err(StoctopusError::IllegalMove)
+        ));
+    }
+
+    #[test]
+    fn test_handicap_game() {
+        let rules = Rules {
+            handicap: Some(Handicap {
+                player: Player::O,
+                stones: Handicap::cells(&[(0, 0), (8, 8)]),
+                double_moves: 1,
+            }),
+            ..Rules::default()
+        };
+        let mut engine =
+            Engine::from_board(Board::with_rules(rules), MCTSConfig::default()).unwrap();
+        engine.play((4, 4)).unwrap();
+        engine.play((4, 0)).unwrap();
+        // O's double move: the search answers for O again.
+        assert_eq!(engine.board().next_player, Player::O);
+        let best = engine.analyze(50).unwrap().best_move.unwrap();
+        let mve = engine
+            .try_resolve_node(&best)
+            .unwrap()
+            .board
+            .last_move
+            .unwrap();
+        assert_eq!(mve >> 4, 0);
+        assert_ne!(mve, 0x00);
+
+        engine.new_game();
+        assert_eq!(engine.board(), &Board::with_rules(rules));
+    }
+
+    #[test]
+    fn test_analyze_batch() {
+        let engine = Engine::init();
+        let mut boards = vec![Board::default()];
+        boards.push(boards[0].unchecked_play(Board::move_from_gl(4, 4)));
+        boards.push(Board::random_position(81, &mut StdRng::seed_from_u64(3)));
+        let results = engine.analyze_batch(
+            &boards,
+            SearchLimits::iterations(300),
+            &CancellationToken::new(),
+        );
+        assert_eq!(results.len(), 3);
+        for (board, result) in boards.iter().zip(&results[..2]) {
+            let (searched, ev) = result.as_ref().unwrap();
+            assert_eq!(searched.board(), board);
+            let m = searched
+                .try_resolve_node(&ev.best_move.unwrap())
+                .unwrap()
+                .board
+                .last_move;
+            let m = m.unwrap();
+            assert!(board.get_moves() & (1 << ((m >> 4) * 9 + (m & 0b1111))) != 0);
+            // Each position gets a third of the iterations.
+            assert_eq!(searched.arena.iterations(), 100);
+        }
+        assert!(matches!(
+            results[2],
+            Err(StoctopusError::Search(SearchError::GameOver))
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_positions() {
+        let engine = Engine::init();
+        let mut boards = vec![Board::default()];
+        boards.push(boards[0].unchecked_play(Board::move_from_gl(4, 4)));
+        boards.push(Board::random_position(81, &mut StdRng::seed_from_u64(3)));
+        let results = engine.evaluate_positions(&boards, 150);
+        assert_eq!(results.len(), 3);
+        for (board, result) in boards.iter().zip(&results[..2]) {
+            let ev = result.as_ref().unwrap();
+            let (global, local) = ev.best_move.unwrap();
+            assert!(board.get_moves() & (1 << (global * 9 + local)) != 0);
+            assert_eq!(ev.iterations, 150);
+            assert_eq!(ev.source, EvalSource::Search);
+        }
+        assert!(results[2].is_err());
+        assert!(engine.evaluate_positions(&boards[..1], 0)[0]
+            .as_ref()
+            .unwrap()
+            .best_move
+            .is_none());
+    }
+
+    #[test]
+    fn test_explain_divergence() {
+        let search = |seed| {
+            let mut engine = Engine::with_config(MCTSConfig {
+                mode: SearchMode::Deterministic { seed },
+                ..Default::default()
+            });
+            engine.analyze(200).unwrap()
+        };
+        let (a, b, c) = (search(1), search(1), search(2));
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_ne!(a.fingerprint(), c.fingerprint());
+        // The empty board has 15 moves up to symmetry.
+        assert_eq!(a.root_visits.len(), 15);
+
+        let same = Engine::explain_divergence(&a, &b);
+        assert!(same.is_identical() && !same.config_changed && !same.nondeterministic);
+        let different = Engine::explain_divergence(&a, &c);
+        assert!(different.config_changed && !different.is_identical());
+        assert!(different.to_string().contains("configurations differ"));
+    }
+
+    #[test]
+    fn test_analyze_with_budget() {
+        let mut engine = Engine::init();
+        let mut budget = GameBudget::new(2000);
+        let ev = engine
+            .analyze_with_budget(&mut budget, &CancellationToken::new())
+            .unwrap();
+        assert!(ev.best_move.is_some());
+        assert_eq!(budget.remaining(), 2000 - ev.info.iterations);
+    }
+
+    #[test]
+    fn test_should_swap() {
+        let mut engine = Engine::init();
+        engine.play((4, 4)).unwrap();
+        assert!(!engine.should_swap(10).unwrap());
+
+        let rules = Rules {
+            pie_rule: true,
+            ..Rules::default()
+        };
+        let config = MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 1 },
+            ..MCTSConfig::default()
+        };
+        let mut engine = Engine::from_board(Board::with_rules(rules), config).unwrap();
+        assert!(!engine.should_swap(10).unwrap());
+        // The center is the strongest first move, worth taking over.
+        engine.play((4, 4)).unwrap();
+        assert!(engine.board().can_swap());
+        assert!(engine.should_swap(2000).unwrap());
+        assert!(engine.tree_size() > 1);
+        engine.play((4, 0)).unwrap();
+        assert!(!engine.should_swap(10).unwrap());
+
+        // Against O's handicap stones in the centers of the center and the
+        // top left sub-boards, a corner sending O to the latter is weak.
+        let rules = Rules {
+            handicap: Some(Handicap {
+                player: Player::O,
+                stones: Handicap::cells(&[(4, 4), (0, 4)]),
+                double_moves: 0,
+            }),
+            ..rules
+        };
+        let mut engine = Engine::from_board(Board::with_rules(rules), config).unwrap();
+        engine.play((0, 0)).unwrap();
+        assert!(engine.board().can_swap());
+        let ev = engine.analyze(2000).unwrap();
+        assert!(ev.calibrated > 50.0);
+        assert!(!engine.should_swap(2000).unwrap());
+    }
+
+    #[test]
+    fn test_random_opening() {
+        let first_move = |seed, random_opening| {
+            let mut engine = Engine::with_config(MCTSConfig {
+                mode: SearchMode::Deterministic { seed },
+                random_opening,
+                ..MCTSConfig::default()
+            });
+            let ev = engine.analyze(200).unwrap();
+            let best = engine.try_resolve_node(&ev.best_move.unwrap()).unwrap();
+            best.board.last_move.unwrap()
+        };
+        let random = Some(RandomOpening { plies: 2, top_k: 5 });
+        let moves: HashSet<u8> = (0..10).map(|seed| first_move(seed, random)).collect();
+        assert!(moves.len() > 1);
+        assert_eq!(first_move(3, random), first_move(3, random));
+
+        // Past the random plies the best move is played again.
+        let past = Some(RandomOpening { plies: 0, top_k: 5 });
+        assert_eq!(first_move(4, past), first_move(4, None));
+    }
+
+    #[test]
+    fn test_human_model() {
+        let board = Board::default().unchecked_play(0x44).unchecked_play(0x40);
+        let play = |seed, human| {
+            let mut engine = Engine::from_board(
+                board,
+                MCTSConfig {
+                    mode: SearchMode::Deterministic { seed },
+                    human,
+                    ..MCTSConfig::default()
+                },
+            )
+            .unwrap();
+            let ev = engine.analyze(300).unwrap();
+            let best = engine.try_resolve_node(&ev.best_move.unwrap()).unwrap();
+            best.board.last_move.unwrap()
+        };
+        let human = Some(HumanModel::default());
+        let moves: HashSet<u8> = (0..10).map(|seed| play(seed, human)).collect();
+        assert!(moves.len() > 1);
+        assert_eq!(play(3, human), play(3, human));
+
+        // A cached full strength search doesn't take the choice away.
+        let mut cache = AnalysisCache::new();
+        let mut moves = HashSet::new();
+        for seed in 0..10 {
+            let mut engine = Engine::from_board(
+                board,
+                MCTSConfig {
+                    mode: SearchMode::Deterministic { seed },
+                    human,
+                    ..MCTSConfig::default()
+                },
+            )
+            .unwrap();
+            engine.set_analysis_cache(cache);
+            let ev = engine.analyze(300).unwrap();
+            assert_eq!(ev.source, EvalSource::Search);
+            moves.insert(
+                engine
+                    .try_resolve_node(&ev.best_move.unwrap())
+                    .unwrap()
+                    .board
+                    .last_move,
+            );
+            cache = engine.take_analysis_cache().unwrap();
+            assert_eq!(cache.len(), 1);
+        }
+        assert!(moves.len() > 1);
+
+        // Pure intuition follows the heuristic prior alone.
+        let mut rng = StdRng::seed_from_u64(0);
+        let instinct = HumanModel {
+            temperature: 0.0,
+            intuition: 1.0,
+        };
+        let candidates = [(0, 0.9, 0.1), (1, 0.1, 0.8), (2, 0.5, 0.1)];
+        assert_eq!(instinct.pick(&candidates, &mut rng), Some(1));
+        assert_eq!(HumanModel::default().pick::<u8, _>(&[], &mut rng), None);
+    }
+
+    #[test]
+    fn test_symmetric_openings() {
+        let config = MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 2 },
+            symmetric_openings: true,
+            ..MCTSConfig::default()
+        };
+        // Every symmetry keeps the centre where it is.
+        let board = Board::default().unchecked_play(0x44);
+        let first_move = |game_seed| {
+            let mut engine = Engine::from_board(board, config).unwrap();
+            engine.set_game_seed(game_seed);
+            let ev = engine.analyze(200).unwrap();
+            let mve = engine
+                .try_resolve_node(&ev.best_move.unwrap())
+                .unwrap()
+                .board
+                .last_move;
+            assert_eq!(Some(Board::move_from_gl(ev.pv[0].0, ev.pv[0].1)), mve);
+            mve.unwrap()
+        };
+        let moves: HashSet<u8> = (0..16).map(first_move).collect();
+        assert!(moves.len() > 1);
+        let classes: HashSet<u64> = moves
+            .iter()
+            .map(|&m| board.unchecked_play(m).canonical_hash())
+            .collect();
+        assert_eq!(classes.len(), 1);
+        assert_eq!(first_move(5), first_move(5));
+
+        // The same search without the option plays the canonical move.
+        let config = MCTSConfig {
+            symmetric_openings: false,
+            ..config
+        };
+        let mut engine = Engine::from_board(board, config).unwrap();
+        let ev = engine.analyze(200).unwrap();
+        let canonical = engine
+            .try_resolve_node(&ev.best_move.unwrap())
+            .unwrap()
+            .board
+            .last_move;
+        let class = board.unchecked_play(canonical.unwrap());
+        assert!(classes.contains(&class.canonical_hash()));
+        // Deterministic searches start every game with the same seed.
+        engine.new_game();
+        assert_eq!(engine.game_seed, 2);
+    }
+
+    #[test]
+    fn test_teaching() {
+        let search = |teaching| {
+            let mut engine = Engine::with_config(MCTSConfig {
+                mode: SearchMode::Deterministic { seed: 6 },
+                teaching,
+                ..MCTSConfig::default()
+            });
+            let ev = engine.analyze(300).unwrap();
+            let replies = |id| {
+                engine
+                    .try_resolve_node(&id)
+                    .unwrap()
+                    .board
+                    .get_moves()
+                    .count_ones()
+            };
+            let children = engine.child_stats(engine.current_node());
+            let most = children
+                .iter()
+                .filter(|child| child.visits * 10.0 >= children[0].visits)
+                .map(|child| replies(child.node))
+                .max();
+            (replies(ev.best_move.unwrap()), most.unwrap())
+        };
+        // Everything counts as winning, so the most open move is played.
+        let (replies, most) = search(Some(Teaching { winning: 0.0 }));
+        assert_eq!(replies, most);
+        // The centre leaves 8 replies, every other first move 9.
+        assert_eq!(replies, 9);
+        // Nothing is winning.
+        let (replies, _) = search(Some(Teaching { winning: 1.1 }));
+        assert_eq!(replies, search(None).0);
+
+        let teaching = Teaching { winning: 0.9 };
+        let candidates = [(0, 0.95, 100.0, 7), (1, 0.92, 50.0, 9), (2, 0.99, 5.0, 20)];
+        assert_eq!(teaching.pick(&candidates), Some(1));
+        assert_eq!(teaching.pick(&candidates[2..]), Some(2));
+        assert_eq!(teaching.pick(&[(0, 0.5, 100.0, 7)]), None);
+    }
+
+    #[test]
+    fn test_cancel_analyze() {
+        let mut engine = Engine::init();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(matches!(
+            engine.analyze_cancellable(100, &cancel),
+            Err(StoctopusError::Search(SearchError::Cancelled))
+        ));
+
+        let cancel = CancellationToken::new();
+        let stopper = {
+            let cancel = cancel.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                cancel.cancel();
+            })
+        };
+        let ev = engine.analyze_cancellable(u32::MAX, &cancel).unwrap();
+        assert!(ev.best_move.is_some());
+        stopper.join().unwrap();
+    }
+
+    #[test]
+    fn test_analyze_time_limit() {
+        let mut engine = Engine::init();
+        let start = std::time::Instant::now();
+        let limits = SearchLimits::time(std::time::Duration::from_millis(100));
+        let ev = engine
+            .analyze_with_limits(limits, &CancellationToken::new())
+            .unwrap();
+        assert!(ev.best_move.is_some());
+        assert!(start.elapsed() < std::time::Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_calibrated_evaluation() {
+        let mut engine = Engine::init();
+        let ev = engine.analyze(0).unwrap();
+        assert_eq!(ev.calibrated, ev.confidence);
+        assert_eq!(ev.score, 0.0);
+
+        engine.set_calibration(Calibration::Logistic {
+            slope: 0.5,
+            intercept: 0.0,
+        });
+        engine.play((4, 4)).unwrap();
+        let ev = engine.analyze(50).unwrap();
+        assert!((ev.calibrated - 50.0).abs() <= (ev.confidence - 50.0).abs());
+        assert!((-100.0..=100.0).contains(&ev.score));
+        assert_eq!(ev.score > 0.0, ev.calibrated > 50.0);
+    }
+
+    #[test]
+    fn test_analysis_cache() {
+        let mut engine = Engine::init();
+        engine.set_analysis_cache(AnalysisCache::new());
+        engine.play((4, 4)).unwrap();
+        let first = engine.analyze(300).unwrap();
+        let size = engine.tree_size();
+        assert!(size > 2);
+
+        engine.new_game();
+        engine.play((4, 4)).unwrap();
+        let again = engine.analyze(200).unwrap();
+        assert_eq!(again.confidence, first.confidence);
+        // Served from the cache: only the root and the cached best move.
+        assert_eq!(engine.tree_size(), 2);
+        let best = engine
+            .try_resolve_node(&again.best_move.unwrap())
+            .unwrap()
+            .board;
+        engine.step(again.best_move.unwrap()).unwrap();
+        assert_eq!(*engine.board(), best);
+
+        let cache = engine.take_analysis_cache().unwrap();
+        assert_eq!(cache.len(), 1);
+        engine.new_game();
+        engine.play((4, 4)).unwrap();
+        engine.analyze(200).unwrap();
+        assert!(engine.tree_size() > 2);
+
+        // Entries with a move that isn't legal in the position are ignored.
+        let hash = engine.board().zobrist_hash();
+        let mut bogus = *cache.get(hash).unwrap();
+        bogus.best_move = Board::move_from_gl(0, 0);
+        let mut bogus_cache = AnalysisCache::new();
+        bogus_cache.insert(hash, bogus);
+        engine.set_analysis_cache(bogus_cache);
+        assert_eq!(engine.analyze(200).unwrap().source, EvalSource::Search);
+    }
+
+    #[test]
+    fn test_expected_plies() {
+        let mut engine = Engine::with_config(MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 5 },
+            ..MCTSConfig::default()
+        });
+        assert_eq!(engine.analyze(0).unwrap().expected_plies, None);
+        engine.play((4, 4)).unwrap();
+        let ev = engine.analyze(500).unwrap();
+        let plies = ev.expected_plies.unwrap();
+        // At least the best move itself, at most every cell left.
+        assert!((1.0..=80.0).contains(&plies), "{plies}");
+        let children = engine.child_stats(engine.current_node());
+        assert_eq!(children[0].node, ev.best_move.unwrap());
+        assert_eq!(children[0].expected_plies, Some(plies));
+        assert!(children
+            .iter()
+            .all(|child| child.expected_plies >= Some(1.0)));
+
+        // Merged nodes count lengths along the selected path instead.
+        let mut engine = Engine::with_config(MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 5 },
+            merge_transpositions: true,
+            ..MCTSConfig::default()
+        });
+        engine.play((4, 4)).unwrap();
+        let plies = engine.analyze(500).unwrap().expected_plies.unwrap();
+        assert!((1.0..=80.0).contains(&plies), "{plies}");
+    }
+
+    #[test]
+    fn test_margin() {
+        let mut engine = Engine::init();
+        assert_eq!(engine.analyze(0).unwrap().margin, f32::INFINITY);
+        let ev = engine.analyze(500).unwrap();
+        assert!(ev.margin > 0.0 && ev.margin < 50.0, "{}", ev.margin);
+    }
+
+    #[test]
+    fn test_analyze_with_priors() {
+        let mut engine = Engine::with_config(MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 4 },
+            ..MCTSConfig::default()
+        });
+        engine.set_analysis_cache(AnalysisCache::new());
+        let cancel = CancellationToken::new();
+        let limits = SearchLimits::iterations(200);
+        // All on a corner of a corner, which shares a class with the others.
+        let mut priors = [0.0; 81];
+        priors[80] = 3.0;
+        engine
+            .analyze_with_priors(&priors, 100.0, limits, &cancel)
+            .unwrap();
+        let children = engine.child_stats(engine.current_node());
+        let favoured: Vec<_> = children.iter().filter(|c| c.prior > 0.0).collect();
+        assert_eq!(favoured.len(), 1);
+        assert_eq!(favoured[0].prior, 1.0);
+        assert!(favoured[0].visits >= 100.0);
+        let corner = Board::default().unchecked_play(Board::move_from_gl(8, 8));
+        let favoured_board = engine.try_resolve_node(&favoured[0].node).unwrap().board;
+        assert_eq!(favoured_board.canonical_hash(), corner.canonical_hash());
+        // Results depend on the priors, so they aren't cached.
+        assert!(engine.take_analysis_cache().unwrap().is_empty());
+
+        // No weight on a legal move is no information.
+        engine.play((4, 4)).unwrap();
+        engine
+            .analyze_with_priors(&priors, 0.0, limits, &cancel)
+            .unwrap();
+        let children = engine.child_stats(engine.current_node());
+        let uniform = 1.0 / children.len() as f32;
+        assert!(children.iter().all(|c| (c.prior - uniform).abs() < 1e-6));
+
+        priors[0] = -1.0;
+        assert!(matches!(
+            engine.analyze_with_priors(&priors, 0.0, limits, &cancel),
+            Err(StoctopusError::InvalidArgument(_))
+        ));
+        priors[0] = 1.0;
+        assert!(matches!(
+            engine.analyze_with_priors(&priors, f32::NAN, limits, &cancel),
+            Err(StoctopusError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_experience() {
+        let mut engine = Engine::with_config(MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 2 },
+            ..MCTSConfig::default()
+        });
+        engine.set_experience(Experience::new());
+        engine.play((4, 4)).unwrap();
+        engine.analyze(2000).unwrap();
+        let experience = engine.experience().unwrap();
+        assert!(!experience.is_empty());
+        let known = engine
+            .child_stats(engine.current_node())
+            .into_iter()
+            .filter(|child| {
+                let board = engine.try_resolve_node(&child.node).unwrap().board;
+                experience.get(board.canonical_hash()).is_some()
+            })
+            .count();
+        assert!(known > 0);
+
+        // A new search starts the known children with visits already.
+        engine.new_game();
+        engine.play((4, 4)).unwrap();
+        engine.analyze(1).unwrap();
+        let seeded = engine.child_stats(engine.current_node())[0].visits;
+        assert_eq!(seeded, 1.0 + engine.experience().unwrap().max_prior_visits);
+
+        let experience = engine.take_experience().unwrap();
+        let mut bytes = vec![];
+        experience.write_to(&mut bytes).unwrap();
+        assert_eq!(Experience::read_from(&mut &bytes[..]).unwrap(), experience);
+        engine.analyze(1).unwrap();
+        assert_eq!(engine.child_stats(engine.current_node())[0].visits, 1.0);
+    }
+
+    struct CenterBook;
+
+    impl BookProvider for CenterBook {
+        fn probe(&self, board: &Board) -> Option<u8> {
+            board.last_move.is_none().then(|| Board::move_from_gl(4, 4))
+        }
+    }
+
+    /// Knows that everything is a draw.
+    struct AllDraws;
+
+    impl TablebaseProvider for AllDraws {
+        fn probe(&self, _board: &Board) -> Option<GameState> {
+            Some(GameState::Draw)
+        }
+    }
+
+    #[test]
+    fn test_book_and_tablebase() {
+        let mut engine = Engine::init();
+        engine.set_book(Some(Arc::new(CenterBook)));
+        let ev = engine.analyze(100).unwrap();
+        assert_eq!(ev.source, EvalSource::Book);
+        let best = engine.try_resolve_node(&ev.best_move.unwrap()).unwrap();
+        assert_eq!(best.board.last_move, Some(Board::move_from_gl(4, 4)));
+
+        engine.play((4, 4)).unwrap();
+        assert_eq!(engine.analyze(100).unwrap().source, EvalSource::Search);
+
+        engine.set_tablebase(Some(Arc::new(AllDraws)));
+        let ev = engine.analyze(100).unwrap();
+        assert_eq!(ev.source, EvalSource::Tablebase);
+        assert_eq!(ev.confidence, 50.0);
+        assert_eq!(ev.proven, Some(GameState::Draw));
+    }
+
+    #[test]
+    fn test_analyze_zero_iterations() {
+        let mut engine = Engine::init();
+        let ev = engine.analyze(0).unwrap();
+        assert!(ev.best_move.is_none());
+        assert_eq!(ev.confidence, engine.static_eval());
+        assert_eq!(
+            engine
+                .arena
+                .try_resolve(&engine.current_node)
+                .unwrap()
+                .visits,
+            0.0
+        );
+
+        let searched = engine.analyze(20).unwrap();
+        let nodes = engine.tree_size();
+        let again = engine.analyze(0).unwrap();
+        assert_eq!(again.confidence, searched.confidence);
+        assert_eq!(engine.tree_size(), nodes);
+    }
 }
@@ -0,0 +1,843 @@
+//! Plays engines against each other inside one process, in single matches
+//! or in tournaments between several configurations, and keeps a
+//! [`Ladder`] of results between named configurations over time.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Write};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::game::{Board, GameState, Player, Rules};
+use crate::{
+    CancellationToken, Engine, Evaluation, GameRecord, MCTSConfig, PositionSuite, SearchLimits,
+    StoctopusError,
+};
+
+/// A finished game between two engines.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameOutcome {
+    /// Moves as `(global, local)` pairs from the start position.
+    pub moves: Vec<(u8, u8)>,
+    /// What every move in `moves` cost.
+    pub move_stats: Vec<MoveStats>,
+    /// [`Evaluation::root_visits`] of the search behind every move in
+    /// `moves`.
+    pub root_visits: Vec<Vec<(u8, u32)>>,
+    pub result: GameState,
+    /// Whether the game was stopped early by [`Adjudication`].
+    pub adjudicated: bool,
+}
+
+impl GameOutcome {
+    /// [`MoveStats`] of the game as CSV, see [`MOVE_STATS_HEADER`].
+    pub fn move_stats_csv(&self, x: &str, o: &str) -> String {
+        let mut csv = format!("{MOVE_STATS_HEADER}\n");
+        write_move_stats(&mut csv, 1, x, o, &self.move_stats);
+        csv
+    }
+}
+
+/// Columns of the move statistics CSV. Moves are counted from 1.
+pub const MOVE_STATS_HEADER: &str = "game,engine,move,millis,iterations,nps";
+
+/// Thinking time and work of one move, to spot speed regressions.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MoveStats {
+    pub player: Player,
+    pub elapsed: Duration,
+    /// Search iterations, 0 for book and tablebase moves.
+    pub iterations: u32,
+}
+
+impl MoveStats {
+    /// Iterations per second.
+    pub fn nps(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            0.0 => 0.0,
+            secs => self.iterations as f64 / secs,
+        }
+    }
+}
+
+fn write_move_stats(csv: &mut String, game: u32, x: &str, o: &str, stats: &[MoveStats]) {
+    for (i, stats) in stats.iter().enumerate() {
+        let engine = match stats.player {
+            Player::X => x,
+            Player::O => o,
+        };
+        writeln!(
+            csv,
+            "{game},{engine},{},{:.3},{},{:.0}",
+            i + 1,
+            stats.elapsed.as_secs_f64() * 1000.0,
+            stats.iterations,
+            stats.nps()
+        )
+        .expect("Writing to a String can't fail");
+    }
+}
+
+/// When a game may be decided before it is played out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adjudication {
+    /// Calibrated win probability in percent one side must have in the
+    /// evaluation of both engines.
+    pub win_threshold: f32,
+    /// Consecutive moves of each engine the threshold must hold for.
+    pub moves: u32,
+    /// Also stop once an engine's tablebase or solver proves the result,
+    /// see [`Evaluation::proven`].
+    pub proven: bool,
+}
+
+impl Default for Adjudication {
+    fn default() -> Self {
+        Self {
+            win_threshold: 95.0,
+            moves: 4,
+            proven: true,
+        }
+    }
+}
+
+impl Adjudication {
+    /// The result once it is decided, given the evaluation `mover` just
+    /// searched and the side in the lead with its number of moves so far.
+    fn judge(
+        &self,
+        mover: Player,
+        evaluation: &Evaluation,
+        streak: &mut Option<(Player, u32)>,
+    ) -> Option<GameState> {
+        if let Some(result) = evaluation.proven.filter(|_| self.proven) {
+            return Some(result);
+        }
+        let winning = if evaluation.calibrated >= self.win_threshold {
+            Some(mover)
+        } else if evaluation.calibrated <= 100.0 - self.win_threshold {
+            Some(mover.other())
+        } else {
+            None
+        };
+        *streak = winning.map(|side| match *streak {
+            Some((leader, moves)) if leader == side => (side, moves + 1),
+            _ => (side, 1),
+        });
+        // The engines take turns, so both have agreed for `moves` each.
+        match *streak {
+            Some((side, moves)) if moves >= 2 * self.moves => Some(GameState::Won(side)),
+            _ => None,
+        }
+    }
+}
+
+/// Score of a match from the point of view of the first engine.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchScore {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl MatchScore {
+    pub fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+
+    /// Draws count half.
+    pub fn points(&self) -> f64 {
+        self.wins as f64 + self.draws as f64 / 2.0
+    }
+}
+
+/// How the entrants of a tournament are paired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TournamentFormat {
+    /// Every entrant plays every other one.
+    RoundRobin,
+    /// The first entrant, the baseline, plays every other one.
+    Gauntlet,
+}
+
+impl TournamentFormat {
+    /// Pairs of entrant indices that meet, in playing order.
+    pub fn pairings(self, entrants: usize) -> Vec<(usize, usize)> {
+        match self {
+            Self::RoundRobin => (0..entrants)
+                .flat_map(|a| (a + 1..entrants).map(move |b| (a, b)))
+                .collect(),
+            Self::Gauntlet => (1..entrants).map(|b| (0, b)).collect(),
+        }
+    }
+}
+
+/// A game of a tournament. Displays as a game record with `Round`, `X`
+/// and `O` tags added.
+#[derive(Debug, Clone)]
+pub struct TournamentGame {
+    /// Counting from 1 in playing order.
+    pub round: u32,
+    pub x: String,
+    pub o: String,
+    pub record: GameRecord,
+    pub move_stats: Vec<MoveStats>,
+}
+
+impl Display for TournamentGame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[Round \"{}\"]", self.round)?;
+        writeln!(f, "[X \"{}\"]", self.x)?;
+        writeln!(f, "[O \"{}\"]", self.o)?;
+        write!(f, "{}", self.record)
+    }
+}
+
+/// Result of one entrant of a tournament.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Standing {
+    pub name: String,
+    pub score: MatchScore,
+}
+
+/// Everything that happened in a tournament. Displays as a table of the
+/// standings.
+#[derive(Debug, Clone)]
+pub struct TournamentReport {
+    pub names: Vec<String>,
+    pub games: Vec<TournamentGame>,
+}
+
+impl TournamentReport {
+    /// Score of `a` against `b`, both entrant names.
+    pub fn score(&self, a: &str, b: &str) -> MatchScore {
+        let mut score = MatchScore::default();
+        for game in &self.games {
+            let a_player = match (game.x == a && game.o == b, game.o == a && game.x == b) {
+                (true, _) => Player::X,
+                (_, true) => Player::O,
+                _ => continue,
+            };
+            add_result(&mut score, game.record.result, a_player);
+        }
+        score
+    }
+
+    /// Scores of all entrants, most points first.
+    pub fn standings(&self) -> Vec<Standing> {
+        let mut standings: Vec<_> = self
+            .names
+            .iter()
+            .map(|name| {
+                let mut score = MatchScore::default();
+                for game in &self.games {
+                    if game.x == *name {
+                        add_result(&mut score, game.record.result, Player::X);
+                    } else if game.o == *name {
+                        add_result(&mut score, game.record.result, Player::O);
+                    }
+                }
+                Standing {
+                    name: name.clone(),
+                    score,
+                }
+            })
+            .collect();
+        standings.sort_by(|a, b| b.score.points().total_cmp(&a.score.points()));
+        standings
+    }
+
+    /// [`MoveStats`] of all games as CSV, see [`MOVE_STATS_HEADER`].
+    pub fn move_stats_csv(&self) -> String {
+        let mut csv = format!("{MOVE_STATS_HEADER}\n");
+        for game in &self.games {
+            write_move_stats(&mut csv, game.round, &game.x, &game.o, &game.move_stats);
+        }
+        csv
+    }
+
+    /// Records of all games, one after the other.
+    pub fn dump(&self) -> String {
+        self.games
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl Display for TournamentReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.names.iter().map(String::len).max().unwrap_or(0).max(4);
+        writeln!(
+            f,
+            "  # {:width$}  games  wins  losses  draws  points",
+            "name"
+        )?;
+        for (rank, standing) in self.standings().iter().enumerate() {
+            let score = standing.score;
+            writeln!(
+                f,
+                "{:>3} {:width$}  {:>5}  {:>4}  {:>6}  {:>5}  {:>6.1}",
+                rank + 1,
+                standing.name,
+                score.games(),
+                score.wins,
+                score.losses,
+                score.draws,
+                score.points()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn add_result(score: &mut MatchScore, result: GameState, player: Player) {
+    match result {
+        GameState::Won(winner) if winner == player => score.wins += 1,
+        GameState::Won(_) => score.losses += 1,
+        GameState::Draw | GameState::InProgress => score.draws += 1,
+    }
+}
+
+/// Runs games between engines on a single rayon pool. Searches take turns
+/// on the pool, so two engines never compete for the same cores.
+pub struct MatchRunner {
+    pool: ThreadPool,
+    limits: SearchLimits,
+    adjudication: Option<Adjudication>,
+}
+
+impl MatchRunner {
+    /// A runner whose pool has `threads` threads, giving every search
+    /// `limits`.
+    pub fn new(threads: usize, limits: SearchLimits) -> Result<Self, StoctopusError> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(StoctopusError::ThreadPool)?;
+        Ok(Self {
+            pool,
+            limits,
+            adjudication: None,
+        })
+    }
+
+    /// Ends games early according to `adjudication`.
+    pub fn with_adjudication(mut self, adjudication: Adjudication) -> Self {
+        self.adjudication = Some(adjudication);
+        self
+    }
+
+    /// Plays one game from the start position, `x` moving first.
+    pub fn play_game(&self, x: &mut Engine, o: &mut Engine) -> Result<GameOutcome, StoctopusError> {
+        x.new_game();
+        o.new_game();
+        self.play_out(x, o)
+    }
+
+    /// Plays one game from `start`, `x` playing X.
+    pub fn play_game_from(
+        &self,
+        start: Board,
+        x: &mut Engine,
+        o: &mut Engine,
+    ) -> Result<GameOutcome, StoctopusError> {
+        x.set_board(start)?;
+        o.set_board(start)?;
+        self.play_out(x, o)
+    }
+
+    fn play_out(&self, x: &mut Engine, o: &mut Engine) -> Result<GameOutcome, StoctopusError> {
+        let mut moves = Vec::new();
+        let mut move_stats = Vec::new();
+        let mut root_visits = Vec::new();
+        let mut streak = None;
+        while !x.is_game_over() {
+            let player = x.board().next_player;
+            let (mover, other) = match player {
+                Player::X => (&mut *x, &mut *o),
+                Player::O => (&mut *o, &mut *x),
+            };
+            let start = Instant::now();
+            let (mve, evaluation) = self.search(mover)?;
+            move_stats.push(MoveStats {
+                player,
+                elapsed: start.elapsed(),
+                iterations: evaluation.info.iterations,
+            });
+            mover.play(mve)?;
+            other.play(mve)?;
+            moves.push(mve);
+            root_visits.push(evaluation.root_visits.clone());
+
+            let decided = self
+                .adjudication
+                .filter(|_| !x.is_game_over())
+                .and_then(|adjudication| adjudication.judge(player, &evaluation, &mut streak));
+            if let Some(result) = decided {
+                return Ok(GameOutcome {
+                    moves,
+                    move_stats,
+                    root_visits,
+                    result,
+                    adjudicated: true,
+                });
+            }
+        }
+
+        Ok(GameOutcome {
+            moves,
+            move_stats,
+            root_visits,
+            result: x.game_state(),
+            adjudicated: false,
+        })
+    }
+
+    /// Plays `games` games, swapping colours after every game.
+    pub fn play_match(
+        &self,
+        a: &mut Engine,
+        b: &mut Engine,
+        games: u32,
+    ) -> Result<MatchScore, StoctopusError> {
+        let mut score = MatchScore::default();
+        for game in 0..games {
+            let a_player = if game % 2 == 0 { Player::X } else { Player::O };
+            let outcome = match a_player {
+                Player::X => self.play_game(a, b)?,
+                Player::O => self.play_game(b, a)?,
+            };
+            add_result(&mut score, outcome.result, a_player);
+        }
+        Ok(score)
+    }
+
+    /// Plays every position of `suite` twice, `a` taking each side once,
+    /// so neither engine profits from lopsided openings.
+    pub fn play_suite_match(
+        &self,
+        a: &mut Engine,
+        b: &mut Engine,
+        suite: &PositionSuite,
+    ) -> Result<MatchScore, StoctopusError> {
+        let mut score = MatchScore::default();
+        for position in &suite.positions {
+            for a_player in [Player::X, Player::O] {
+                let outcome = match a_player {
+                    Player::X => self.play_game_from(position.board, a, b)?,
+                    Player::O => self.play_game_from(position.board, b, a)?,
+                };
+                add_result(&mut score, outcome.result, a_player);
+            }
+        }
+        Ok(score)
+    }
+
+    /// Plays a tournament between named configurations, every pairing
+    /// playing `games` games with colours swapped after every game.
+    pub fn play_tournament(
+        &self,
+        entrants: &[(String, MCTSConfig)],
+        format: TournamentFormat,
+        games: u32,
+    ) -> Result<TournamentReport, StoctopusError> {
+        let mut report = TournamentReport {
+            names: entrants.iter().map(|(name, _)| name.clone()).collect(),
+            games: Vec::new(),
+        };
+        for (a, b) in format.pairings(entrants.len()) {
+            for game in 0..games {
+                let (x, o) = if game % 2 == 0 { (a, b) } else { (b, a) };
+                let outcome = self.play_game(
+                    &mut Engine::with_config(entrants[x].1),
+                    &mut Engine::with_config(entrants[o].1),
+                )?;
+                report.games.push(TournamentGame {
+                    round: report.games.len() as u32 + 1,
+                    x: entrants[x].0.clone(),
+                    o: entrants[o].0.clone(),
+                    record: GameRecord {
+                        moves: outcome.moves,
+                        result: outcome.result,
+                        ..GameRecord::new(Rules::default())
+                    },
+                    move_stats: outcome.move_stats,
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    fn search(&self, engine: &mut Engine) -> Result<((u8, u8), Evaluation), StoctopusError> {
+        let cancel = CancellationToken::new();
+        let evaluation = self
+            .pool
+            .install(|| engine.analyze_with_limits(self.limits, &cancel))?;
+        let best_move = evaluation
+            .best_move
+            .map(|id| engine.try_resolve_node(&id))
+            .transpose()?
+            .and_then(|node| node.board.last_move)
+            .ok_or(StoctopusError::IllegalMove)?;
+        Ok(((best_move >> 4, best_move & 0b1111), evaluation))
+    }
+}
+
+/// One match recorded in a [`Ladder`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LadderEntry {
+    pub first: String,
+    pub second: String,
+    /// From the point of view of `first`.
+    pub score: MatchScore,
+    /// Version of the crate that played the match.
+    pub version: String,
+    /// Seconds since the Unix epoch.
+    pub played_at: u64,
+}
+
+/// Rating of one configuration on a [`Ladder`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LadderRating {
+    pub name: String,
+    /// Elo relative to a virtual opponent at 0.
+    pub elo: f64,
+    pub games: u32,
+    /// Points scored, draws counting half.
+    pub points: f64,
+}
+
+/// Match results between named engine configurations, kept in a JSON file
+/// so strength can be compared across versions of the crate.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ladder {
+    pub entries: Vec<LadderEntry>,
+}
+
+/// Virtual draws every configuration gets against an opponent rated 0, so
+/// unbeaten or winless configurations still get finite ratings.
+const PRIOR_DRAWS: f64 = 2.0;
+
+fn invalid_ladder(msg: impl Into<String>) -> StoctopusError {
+    StoctopusError::Protocol(format!("Bad ladder: {}", msg.into()))
+}
+
+impl Ladder {
+    /// Reads a ladder written by [`Self::save`]. A missing file is an
+    /// empty ladder.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, StoctopusError> {
+        match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|err| invalid_ladder(err.to_string())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the ladder to `path`, replacing the old file only once the
+    /// new one is complete.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), StoctopusError> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let json = serde_json::to_string_pretty(self).expect("Ladders always serialize");
+        fs::write(&temporary, json)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Records a match `first` played against `second`.
+    pub fn record(&mut self, first: &str, second: &str, score: MatchScore) {
+        let played_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        self.entries.push(LadderEntry {
+            first: first.to_string(),
+            second: second.to_string(),
+            score,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            played_at,
+        });
+    }
+
+    /// Bradley-Terry ratings over all recorded games, the maximum a
+    /// posteriori estimate as in BayesElo with draws counting as half a
+    /// win for each side. Sorted best first.
+    pub fn ratings(&self) -> Vec<LadderRating> {
+        let mut index = BTreeMap::new();
+        for entry in &self.entries {
+            for name in [&entry.first, &entry.second] {
+                let next = index.len();
+                index.entry(name.as_str()).or_insert(next);
+            }
+        }
+        let count = index.len();
+        // Games between every pair, and points of every configuration.
+        let mut games = vec![vec![0.0; count]; count];
+        let mut points = vec![0.0; count];
+        for entry in &self.entries {
+            let (a, b) = (index[entry.first.as_str()], index[entry.second.as_str()]);
+            let MatchScore {
+                wins,
+                losses,
+                draws,
+            } = entry.score;
+            let total = (wins + losses + draws) as f64;
+            games[a][b] += total;
+            games[b][a] += total;
+            points[a] += wins as f64 + draws as f64 / 2.0;
+            points[b] += losses as f64 + draws as f64 / 2.0;
+        }
+
+        // Minorization-maximization (Hunter 2004) on the strengths.
+        let mut strength = vec![1.0; count];
+        for _ in 0..1000 {
+            let mut largest_change: f64 = 0.0;
+            for i in 0..count {
+                let denominator = PRIOR_DRAWS / (strength[i] + 1.0)
+                    + (0..count)
+                        .filter(|&j| j != i)
+                        .map(|j| games[i][j] / (strength[i] + strength[j]))
+                        .sum::<f64>();
+                let updated = (points[i] + PRIOR_DRAWS / 2.0) / denominator;
+                largest_change = largest_change.max((updated / strength[i]).ln().abs());
+                strength[i] = updated;
+            }
+            if largest_change < 1e-9 {
+                break;
+            }
+        }
+
+        let mut ratings: Vec<_> = index
+            .into_iter()
+            .map(|(name, i)| LadderRating {
+                name: name.to_string(),
+                elo: 400.0 * strength[i].log10(),
+                games: games[i].iter().sum::<f64>() as u32,
+                points: points[i],
+            })
+            .collect();
+        ratings.sort_by(|a, b| b.elo.total_cmp(&a.elo));
+        ratings
+    }
+}
+
+#[cfg(test)]
+mod match_runner_tests {
+    use std::sync::Arc;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::match_runner::{
+        Adjudication, Ladder, MatchRunner, MatchScore, TournamentFormat, MOVE_STATS_HEADER,
+    };
+    use crate::{
+        Backup, Board, Engine, GameRecord, GameState, MCTSConfig, Player, PositionSuite,
+        SearchLimits, SearchMode, SuitePosition, TablebaseProvider,
+    };
+
+    fn config(seed: u64) -> MCTSConfig {
+        MCTSConfig {
+            mode: SearchMode::Deterministic { seed },
+            ..Default::default()
+        }
+    }
+
+    fn engine(seed: u64) -> Engine {
+        Engine::with_config(config(seed))
+    }
+
+    #[test]
+    fn test_play_match() {
+        let runner = MatchRunner::new(2, SearchLimits::iterations(3)).unwrap();
+        let (mut a, mut b) = (engine(1), engine(2));
+
+        let outcome = runner.play_game(&mut a, &mut b).unwrap();
+        assert!(outcome.moves.len() >= 17);
+        assert!(a.is_game_over() && b.is_game_over());
+
+        assert_eq!(outcome.move_stats.len(), outcome.moves.len());
+        assert_eq!(outcome.move_stats[1].player, Player::O);
+        assert!(outcome.move_stats.iter().all(|stats| stats.iterations == 3));
+        let csv = outcome.move_stats_csv("a", "b");
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), outcome.moves.len() + 1);
+        assert_eq!(lines[0], MOVE_STATS_HEADER);
+        assert!(lines[2].starts_with("1,b,2,"));
+
+        let score = runner.play_match(&mut a, &mut b, 2).unwrap();
+        assert_eq!(score.wins + score.losses + score.draws, 2);
+    }
+
+    #[test]
+    fn test_backup_match() {
+        let runner = MatchRunner::new(2, SearchLimits::iterations(50)).unwrap();
+        let mut average = engine(1);
+        let mut max = Engine::with_config(MCTSConfig {
+            backup: Backup::Max,
+            ..config(2)
+        });
+        let score = runner.play_match(&mut average, &mut max, 2).unwrap();
+        assert_eq!(score.games(), 2);
+    }
+
+    #[test]
+    fn test_adjudication() {
+        let adjudication = Adjudication {
+            win_threshold: 90.0,
+            moves: 2,
+            proven: false,
+        };
+        let mut evaluation = engine(1).current_evaluation().unwrap();
+        let mut streak = None;
+        // Both engines see X winning, from their own side.
+        let mut judge = |mover: Player, calibrated| {
+            evaluation.calibrated = calibrated;
+            adjudication.judge(mover, &evaluation, &mut streak)
+        };
+        assert_eq!(judge(Player::X, 95.0), None);
+        assert_eq!(judge(Player::O, 5.0), None);
+        assert_eq!(judge(Player::X, 80.0), None);
+        assert_eq!(judge(Player::O, 5.0), None);
+        assert_eq!(judge(Player::X, 91.0), None);
+        assert_eq!(judge(Player::O, 10.0), None);
+        assert_eq!(judge(Player::X, 99.0), Some(GameState::Won(Player::X)));
+
+        struct AllDraws;
+        impl TablebaseProvider for AllDraws {
+            fn probe(&self, _: &Board) -> Option<GameState> {
+                Some(GameState::Draw)
+            }
+        }
+        let runner = MatchRunner::new(2, SearchLimits::iterations(30))
+            .unwrap()
+            .with_adjudication(Adjudication::default());
+        let (mut a, mut b) = (engine(1), engine(2));
+        a.set_tablebase(Some(Arc::new(AllDraws)));
+        let outcome = runner.play_game(&mut a, &mut b).unwrap();
+        assert!(outcome.adjudicated);
+        assert_eq!((outcome.moves.len(), outcome.result), (1, GameState::Draw));
+
+        // Solver proofs end the game too.
+        let runner = MatchRunner::new(2, SearchLimits::iterations(200))
+            .unwrap()
+            .with_adjudication(Adjudication {
+                win_threshold: 101.0,
+                ..Adjudication::default()
+            });
+        let solver = MCTSConfig {
+            solver: true,
+            endgame_cells: 8,
+            ..config(3)
+        };
+        let (mut c, mut d) = (Engine::with_config(solver), Engine::with_config(solver));
+        let start = Board::random_position(40, &mut StdRng::seed_from_u64(2));
+        assert!(!start.game_over());
+        let outcome = runner.play_game_from(start, &mut c, &mut d).unwrap();
+        assert!(outcome.adjudicated && !c.is_game_over());
+
+        // Without adjudication the game is played out.
+        let runner = MatchRunner::new(2, SearchLimits::iterations(3)).unwrap();
+        let outcome = runner.play_game(&mut a, &mut b).unwrap();
+        assert!(!outcome.adjudicated && a.is_game_over());
+    }
+
+    #[test]
+    fn test_suite_match() {
+        let runner = MatchRunner::new(2, SearchLimits::iterations(3)).unwrap();
+        let (mut a, mut b) = (engine(1), engine(2));
+        let start = Board::default().unchecked_play(0x44).unchecked_play(0x40);
+        let outcome = runner.play_game_from(start, &mut a, &mut b).unwrap();
+        assert_eq!(outcome.moves[0].0, 0);
+
+        let suite = PositionSuite {
+            positions: vec![
+                SuitePosition::new(start),
+                SuitePosition::new(Board::default()),
+            ],
+        };
+        let score = runner.play_suite_match(&mut a, &mut b, &suite).unwrap();
+        assert_eq!(score.games(), 4);
+    }
+
+    #[test]
+    fn test_tournament() {
+        assert_eq!(
+            TournamentFormat::RoundRobin.pairings(3),
+            [(0, 1), (0, 2), (1, 2)]
+        );
+        assert_eq!(TournamentFormat::Gauntlet.pairings(3), [(0, 1), (0, 2)]);
+
+        let runner = MatchRunner::new(2, SearchLimits::iterations(3)).unwrap();
+        let entrants: Vec<_> = (1..=3)
+            .map(|seed| (format!("seed{seed}"), config(seed)))
+            .collect();
+        let report = runner
+            .play_tournament(&entrants, TournamentFormat::RoundRobin, 2)
+            .unwrap();
+        assert_eq!(report.games.len(), 6);
+        let standings = report.standings();
+        assert!(standings.iter().all(|standing| standing.score.games() == 4));
+        let points: f64 = standings
+            .iter()
+            .map(|standing| standing.score.points())
+            .sum();
+        assert_eq!(points, 6.0);
+        let pair = report.score("seed1", "seed3");
+        assert_eq!(pair.games(), 2);
+        assert_eq!(report.score("seed3", "seed1").wins, pair.losses);
+
+        // Every game dumps as a record that reads back.
+        assert_eq!(report.dump().matches("[Round ").count(), 6);
+        let game = &report.games[1];
+        assert_eq!((game.x.as_str(), game.o.as_str()), ("seed2", "seed1"));
+        let record: GameRecord = game.to_string().parse().unwrap();
+        assert_eq!(record, game.record);
+        assert!(report.to_string().contains("seed3"));
+        let moves: usize = report
+            .games
+            .iter()
+            .map(|game| game.record.moves.len())
+            .sum();
+        assert_eq!(report.move_stats_csv().lines().count(), moves + 1);
+
+        let gauntlet = runner
+            .play_tournament(&entrants, TournamentFormat::Gauntlet, 1)
+            .unwrap();
+        assert_eq!(gauntlet.games.len(), 2);
+        assert!(gauntlet.games.iter().all(|game| game.x == "seed1"));
+    }
+
+    #[test]
+    fn test_ladder() {
+        let path = std::env::temp_dir().join(format!("stoctopus-ladder-{}", std::process::id()));
+        let mut ladder = Ladder::load(&path).unwrap();
+        assert!(ladder.ratings().is_empty());
+
+        let score = |wins, losses, draws| MatchScore {
+            wins,
+            losses,
+            draws,
+        };
+        ladder.record("v2", "v1", score(7, 1, 2));
+        ladder.record("v1", "v0", score(6, 2, 2));
+        ladder.record("v2", "v0", score(10, 0, 0));
+        ladder.save(&path).unwrap();
+        let loaded = Ladder::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, ladder);
+
+        let ratings = loaded.ratings();
+        let names: Vec<_> = ratings.iter().map(|rating| rating.name.as_str()).collect();
+        assert_eq!(names, ["v2", "v1", "v0"]);
+        assert_eq!(ratings[0].games, 20);
+        assert_eq!(ratings[0].points, 18.0);
+        // A perfect score still gives a finite rating.
+        assert!(ratings[0].elo.is_finite());
+        assert!(ratings[0].elo - ratings[2].elo > 200.0);
+    }
+}
@@ -1,102 +1,1871 @@
-use crate::game::{Board, GameState, Player};
+use crate::cancel::CancellationToken;
+use crate::error::SearchError;
+use crate::eval;
+use crate::experience::Experience;
+use crate::frames::{FrameMove, FrameOutput, SearchFrame, FRAME_MOVES};
+use crate::game::{find_kth_high_bit_index, Board, GameState, Phase, Player};
+use crate::probe::{Tablebase, TablebaseProvider};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
 
 use deepsize::DeepSizeOf;
-use rand::Rng;
+use rand::distributions::WeightedIndex;
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use rand_distr::{Beta, Distribution};
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(DeepSizeOf, Debug)]
+pub(crate) struct MCTSArena {
+    /// Tells the [`NodeId`]s of this arena from those of others.
+    id: u32,
+    nodes: Vec<MCTSNode>,
+    /// Where the node of each [`NodeId`] slot is.
+    slots: Vec<Slot>,
+    /// Slot of each node, in the order of `nodes`.
+    slot_of: Vec<u32>,
+    /// Slots of collected nodes, reused for new ones.
+    free_slots: Vec<u32>,
+    config: MCTSConfig,
+    trace: Option<SearchTrace>,
+    /// Iterations run over the arena's lifetime.
+    iterations: u32,
+    tablebase: Option<Tablebase>,
+    experience: Option<Arc<Experience>>,
+    root_priors: Option<RootPriors>,
+    best_move_changes: Vec<BestMoveChange>,
+    endgame: EndgameCache,
+    /// Root children no longer visited, see [`RootPruning`].
+    pruned: Vec<NodeId>,
+    /// Nodes dropped by [`MCTSConfig::collect_garbage`].
+    collected: usize,
+    stopped_early: bool,
+    /// Node of every position, by Zobrist hash, for
+    /// [`MCTSConfig::merge_transpositions`].
+    transpositions: HashMap<u64, NodeId>,
+    /// Nodes the last selection went through, from the search root.
+    path: Vec<NodeId>,
+    /// Deepest selection of the current search, in plies below its root.
+    max_depth: usize,
+    frames: Option<FrameOutput>,
+    thread_load: ThreadLoad,
+    /// Seconds the last search took.
+    search_time: f64,
+    /// Learned by [`MCTSConfig::playout_adaptation`].
+    playout_weights: Option<PlayoutWeights>,
+}
+
+/// Exact results of positions close to the end of the game, keyed by
+/// Zobrist hash and shared by all playouts of a search.
+#[derive(Debug, Default)]
+struct EndgameCache(Mutex<HashMap<u64, GameState>>);
+
+impl DeepSizeOf for EndgameCache {
+    fn deep_size_of_children(&self, _context: &mut deepsize::Context) -> usize {
+        let map = self.0.lock().expect("Endgame cache poisoned");
+        map.capacity() * std::mem::size_of::<(u64, GameState)>()
+    }
+}
+
+impl EndgameCache {
+    fn result(&self, board: &Board) -> GameState {
+        let hash = board.zobrist_hash();
+        if let Some(&result) = self.0.lock().expect("Endgame cache poisoned").get(&hash) {
+            return result;
+        }
+        // Solve without holding the lock, other playouts may need it.
+        let mut solved = HashMap::new();
+        let result = solve(&mut { *board }, &mut solved);
+        self.0
+            .lock()
+            .expect("Endgame cache poisoned")
+            .extend(solved);
+        result
+    }
+
+    fn len(&self) -> usize {
+        self.0.lock().expect("Endgame cache poisoned").len()
+    }
+}
+
+/// Work of one thread during a search, see [`SearchInfo::threads`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, DeepSizeOf)]
+pub struct ThreadStats {
+    /// Index in the rayon pool, `None` for the thread that ran the search.
+    pub thread: Option<usize>,
+    pub playouts: u64,
+    /// Moves played in playouts.
+    pub plies: u64,
+    /// Seconds spent in playouts.
+    pub busy: f64,
+    /// Seconds of the search spent elsewhere: waiting for work, or for the
+    /// searching thread, walking the tree.
+    pub idle: f64,
+}
+
+impl ThreadStats {
+    pub fn mean_playout_length(&self) -> f64 {
+        self.plies as f64 / self.playouts.max(1) as f64
+    }
+}
+
+#[derive(Debug, Default)]
+struct ThreadCounters {
+    playouts: AtomicU64,
+    plies: AtomicU64,
+    busy_nanos: AtomicU64,
+}
+
+/// Playout counters per pool thread, the searching thread last. Updated
+/// from the playouts without locking.
+#[derive(Debug, Default)]
+struct ThreadLoad(Vec<ThreadCounters>);
+
+impl DeepSizeOf for ThreadLoad {
+    fn deep_size_of_children(&self, _context: &mut deepsize::Context) -> usize {
+        self.0.capacity() * std::mem::size_of::<ThreadCounters>()
+    }
+}
+
+impl ThreadLoad {
+    /// Fresh counters for the current pool.
+    fn reset(&mut self) {
+        self.0 = (0..=rayon::current_num_threads())
+            .map(|_| ThreadCounters::default())
+            .collect();
+    }
+
+    fn record(&self, plies: u64, busy: Duration) {
+        let Some(last) = self.0.len().checked_sub(1) else {
+            return;
+        };
+        let index = rayon::current_thread_index().filter(|&i| i < last);
+        let counters = &self.0[index.unwrap_or(last)];
+        counters.playouts.fetch_add(1, Ordering::Relaxed);
+        counters.plies.fetch_add(plies, Ordering::Relaxed);
+        counters
+            .busy_nanos
+            .fetch_add(busy.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Statistics of the threads that ran playouts during a search of
+    /// `elapsed` seconds.
+    fn stats(&self, elapsed: f64) -> Vec<ThreadStats> {
+        let last = self.0.len().saturating_sub(1);
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(i, counters)| {
+                let busy = counters.busy_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+                ThreadStats {
+                    thread: (i < last).then_some(i),
+                    playouts: counters.playouts.load(Ordering::Relaxed),
+                    plies: counters.plies.load(Ordering::Relaxed),
+                    busy,
+                    idle: (elapsed - busy).max(0.0),
+                }
+            })
+            .filter(|stats| stats.playouts > 0)
+            .collect()
+    }
+}
+
+/// Cells that can still be played, which bounds the plies left.
+fn open_cells(board: &Board) -> u32 {
+    (!(board.x | board.o | board.global_board_mask()) & 0x1ffffffffffffffffffff).count_ones()
+}
+
+/// Result of `board` under perfect play, by exhaustive search. Only
+/// affordable with few open cells.
+fn solve(board: &mut Board, memo: &mut HashMap<u64, GameState>) -> GameState {
+    let state = board.check_game_state();
+    if state != GameState::InProgress {
+        return state;
+    }
+    let hash = board.zobrist_hash();
+    if let Some(&result) = memo.get(&hash) {
+        return result;
+    }
+    let me = board.next_player;
+    let rank = |state: GameState| match state {
+        GameState::Won(winner) if winner == me => 2,
+        GameState::Draw | GameState::InProgress => 1,
+        GameState::Won(_) => 0,
+    };
+    let moves = board.get_moves();
+    let mut best = GameState::Won(me.other());
+    for i in (0..81).filter(|i| moves & (1 << i) != 0) {
+        let undo = board.make(Board::move_from_index(i));
+        let result = solve(board, memo);
+        board.unmake(undo);
+        if rank(result) > rank(best) {
+            best = result;
+            if rank(best) == 2 {
+                break;
+            }
+        }
+    }
+    memo.insert(hash, best);
+    best
+}
+
+/// How simulations are scheduled during a search.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, DeepSizeOf, Serialize, Deserialize)]
+pub enum SearchMode {
+    /// Simulations of freshly expanded children run on the rayon pool.
+    #[default]
+    Parallel,
+    /// Single threaded with a seeded RNG. Two searches with the same seed
+    /// and iteration count build identical trees.
+    Deterministic { seed: u64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, DeepSizeOf, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MCTSConfig {
+    pub mode: SearchMode,
+    /// Largest number of iterations run between two checks of the clock and
+    /// the cancellation flag.
+    pub batch_size: u32,
+    /// The search stops once expanding another node could grow the tree
+    /// past this many nodes.
+    pub max_nodes: Option<usize>,
+    /// Allocate room for `max_nodes` up front so the tree never reallocates,
+    /// and fail with [`SearchError::OutOfNodes`] instead of stopping quietly
+    /// once it is full.
+    pub fixed_capacity: bool,
+    /// While fewer than this many moves have been played, root moves that
+    /// are symmetric to an earlier one are not searched.
+    pub symmetry_plies: u32,
+    /// Create children gradually as their parent gets visited instead of
+    /// all at once.
+    pub widening: Option<Widening>,
+    pub selection: SelectionPolicy,
+    pub backup: Backup,
+    /// Record every iteration into a [`SearchTrace`].
+    pub trace: bool,
+    /// Playouts reaching a position with at most this many playable cells
+    /// take its exact result instead of playing on. Results are cached for
+    /// the rest of the search, as late playouts keep funnelling into the
+    /// same endings. 0 turns this off; much above 10 makes solving slow.
+    pub endgame_cells: u32,
+    /// Early in the game, play one of the best moves at random instead of
+    /// always the best one.
+    pub random_opening: Option<RandomOpening>,
+    /// Play moves a human might, mistakes included, instead of the best
+    /// one. Applies after the random opening.
+    pub human: Option<HumanModel>,
+    /// When clearly winning, keep the game open rather than win fastest.
+    /// Applies after the human model.
+    pub teaching: Option<Teaching>,
+    /// Play a random move among those symmetric to the chosen one, e.g.
+    /// any corner rather than always the same. The choice only depends on
+    /// the game seed and the position, see [`crate::Engine::set_game_seed`].
+    pub symmetric_openings: bool,
+    /// Stop visiting root moves that have fallen clearly behind.
+    pub root_pruning: Option<RootPruning>,
+    /// MCTS-Solver: nodes whose result is known exactly (finished games,
+    /// endgames within `endgame_cells`, tablebase hits, and nodes whose
+    /// children settle it by minimax) back up that result on every visit
+    /// instead of further playouts, and a proven win is always played.
+    pub solver: bool,
+    /// Once the tree reaches `max_nodes`, drop the subtrees of the least
+    /// visited nodes instead of stopping. The nodes keep their statistics
+    /// and are expanded again if the search comes back to them. Off while
+    /// tracing, as the trace refers to nodes by index.
+    pub collect_garbage: bool,
+    /// Learn which moves win playouts during the search and play them more
+    /// often in later playouts.
+    pub playout_adaptation: Option<PlayoutAdaptation>,
+    /// End the search before its limits once the best move is settled.
+    pub early_stop: Option<EarlyStop>,
+    /// Share one node between moves that transpose into the same position,
+    /// turning the tree into a DAG. Results are backed up along the path
+    /// the selection took; exact results and [`Backup`] values only reach
+    /// the first parent of a shared node until its other parents are
+    /// visited again. Off while tracing, and garbage collection is off
+    /// while it is on.
+    pub merge_transpositions: bool,
+}
+
+/// Formula used to pick which child to descend into.
+#[derive(Clone, Copy, Debug, PartialEq, DeepSizeOf, Serialize, Deserialize)]
+pub enum SelectionPolicy {
+    /// UCB1 with exploration constant `c`.
+    Uct { c: f32 },
+    /// UCB1 with the exploration term scaled by the observed variance.
+    Ucb1Tuned,
+    /// AlphaZero style, exploration proportional to the child's prior.
+    Puct { c: f32 },
+    /// Samples each child's value from its Beta posterior.
+    Thompson,
+}
+
+impl Default for SelectionPolicy {
+    fn default() -> Self {
+        Self::Uct {
+            c: std::f32::consts::SQRT_2,
+        }
+    }
+}
+
+impl SelectionPolicy {
+    fn score<R: Rng + ?Sized>(&self, parent: &MCTSNode, child: &MCTSNode, rng: &mut R) -> f32 {
+        let mean = child.wins / child.visits;
+        match *self {
+            Self::Uct { c } => mean + c * (parent.visits.ln() / child.visits).sqrt(),
+            Self::Ucb1Tuned => {
+                let log_ratio = parent.visits.ln() / child.visits;
+                let variance = child.variance() + (2.0 * log_ratio).sqrt();
+                mean + (log_ratio * variance.min(0.25)).sqrt()
+            }
+            Self::Puct { c } => {
+                mean + c * child.prior * parent.visits.sqrt() / (1.0 + child.visits)
+            }
+            Self::Thompson => {
+                let (alpha, beta) = child.posterior();
+                Beta::new(alpha, beta)
+                    .expect("Parameters are positive")
+                    .sample(rng)
+            }
+        }
+    }
+}
+
+/// How a node's value is computed from its children after every playout.
+/// The player to move at a node maximises its own value, so for the
+/// opponent of the search's root player children are compared by their
+/// value for the opponent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, DeepSizeOf, Serialize, Deserialize)]
+pub enum Backup {
+    /// Average of every playout below the node. Underestimates lines where
+    /// only one reply holds.
+    #[default]
+    Average,
+    /// Value of the best visited child, as in minimax. Sees forced lines
+    /// sooner but trusts children with few visits too much.
+    Max,
+    /// Visit weighted power mean of the children's values: the average for
+    /// `p = 1`, tending to the max as `p` grows.
+    PowerMean { p: f32 },
+}
+
+impl Backup {
+    /// What is wrong with the settings, if they can't be searched with.
+    /// The power mean needs a finite, positive exponent.
+    pub(crate) fn problem(&self) -> Option<&'static str> {
+        match *self {
+            Self::PowerMean { p } if !p.is_finite() || p <= 0.0 => {
+                Some("power mean exponent must be finite and positive")
+            }
+            _ => None,
+        }
+    }
+
+    /// Value of a node from its children's `(value, visits)`, values being
+    /// for the player to move at the node. `None` without visited children.
+    fn value(&self, children: &[(f32, f32)]) -> Option<f32> {
+        let visited = children.iter().filter(|(_, visits)| *visits > 0.0);
+        match *self {
+            Self::Average => None,
+            Self::Max => visited.map(|&(value, _)| value).reduce(f32::max),
+            Self::PowerMean { p } => {
+                let total: f32 = visited.clone().map(|(_, visits)| visits).sum();
+                (total > 0.0).then(|| {
+                    let mean: f32 = visited
+                        .map(|&(value, visits)| visits / total * value.max(0.0).powf(p))
+                        .sum();
+                    mean.powf(1.0 / p)
+                })
+            }
+        }
+    }
+}
+
+/// Progressive widening: a node with `n` visits may have at most
+/// `coefficient * n^exponent` children (and always at least one). Children
+/// are added best prior first.
+#[derive(Clone, Copy, Debug, PartialEq, DeepSizeOf, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Widening {
+    pub coefficient: f32,
+    pub exponent: f32,
+}
+
+impl Default for Widening {
+    fn default() -> Self {
+        Self {
+            coefficient: 2.0,
+            exponent: 0.5,
+        }
+    }
+}
+
+impl Widening {
+    fn allowed_children(&self, visits: f32) -> usize {
+        ((self.coefficient * visits.max(1.0).powf(self.exponent)).ceil() as usize).max(1)
+    }
+}
+
+/// For the first `plies` moves of a game, the move is drawn from the
+/// `top_k` most visited root moves (or book moves) weighted by their win
+/// rate (or book weight), so matches between the same engines don't
+/// replay one game over and over.
+#[derive(Clone, Copy, Debug, PartialEq, DeepSizeOf, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RandomOpening {
+    pub plies: u32,
+    pub top_k: usize,
+}
+
+impl Default for RandomOpening {
+    fn default() -> Self {
+        Self { plies: 8, top_k: 3 }
+    }
+}
+
+impl RandomOpening {
+    pub(crate) fn applies(&self, board: &Board) -> bool {
+        let stones = board.rules.handicap.map_or(0, |h| h.stones.count_ones());
+        (board.x | board.o).count_ones() < self.plies + stones
+    }
+
+    /// Picks from `candidates`, best first, as `(item, weight)`. Weights are
+    /// floored so a hopeless move can still come up now and then.
+    pub(crate) fn pick<T: Copy, R: Rng + ?Sized>(
+        &self,
+        candidates: &[(T, f32)],
+        rng: &mut R,
+    ) -> Option<T> {
+        let candidates = &candidates[..candidates.len().min(self.top_k.max(1))];
+        let weights = candidates.iter().map(|&(_, weight)| weight.max(0.01));
+        let index = WeightedIndex::new(weights).ok()?.sample(rng);
+        Some(candidates[index].0)
+    }
+}
+
+/// Picks the root move the way a club player might, for sparring and
+/// teaching rather than winning. Every searched move is judged by a blend of
+/// its win rate and its heuristic prior, as people mostly go by what looks
+/// locally good and overlook where a move sends the opponent. Moves are then
+/// drawn with probability `exp((judged - best) / temperature)`.
+#[derive(Clone, Copy, Debug, PartialEq, DeepSizeOf, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HumanModel {
+    /// How much worse than the best a move may look and still come up
+    /// often, in win rate. Lower is stronger.
+    pub temperature: f32,
+    /// Share of the judgement going by the prior rather than the search,
+    /// from 0 to 1.
+    pub intuition: f32,
+}
+
+impl Default for HumanModel {
+    fn default() -> Self {
+        Self {
+            temperature: 0.05,
+            intuition: 0.3,
+        }
+    }
+}
+
+impl HumanModel {
+    /// Picks from `candidates` as `(item, win rate, prior)`.
+    pub(crate) fn pick<T: Copy, R: Rng + ?Sized>(
+        &self,
+        candidates: &[(T, f32, f32)],
+        rng: &mut R,
+    ) -> Option<T> {
+        let max_prior = candidates
+            .iter()
+            .map(|&(_, _, prior)| prior)
+            .fold(f32::MIN_POSITIVE, f32::max);
+        let intuition = self.intuition.clamp(0.0, 1.0);
+        let judged: Vec<f32> = candidates
+            .iter()
+            .map(|&(_, value, prior)| (1.0 - intuition) * value + intuition * prior / max_prior)
+            .collect();
+        let best = judged.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let temperature = self.temperature.max(1e-3);
+        let weights = judged
+            .iter()
+            .map(|value| ((value - best) / temperature).exp());
+        let index = WeightedIndex::new(weights).ok()?.sample(rng);
+        Some(candidates[index].0)
+    }
+}
+
+/// For bots giving instructive games: when clearly winning, plays the
+/// winning move that leaves the opponent the most replies rather than the
+/// surest one, so they keep getting chances to go wrong and learn from it.
+#[derive(Clone, Copy, Debug, PartialEq, DeepSizeOf, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Teaching {
+    /// Win rate from which a move counts as winning.
+    pub winning: f32,
+}
+
+impl Default for Teaching {
+    fn default() -> Self {
+        Self { winning: 0.9 }
+    }
+}
+
+impl Teaching {
+    /// Picks from `candidates` as `(item, win rate, visits, replies)`, most
+    /// visited first, or `None` if the most visited isn't winning. Moves
+    /// searched less than a tenth as much as it are too uncertain to count.
+    pub(crate) fn pick<T: Copy>(&self, candidates: &[(T, f32, f32, u32)]) -> Option<T> {
+        let &(_, win_rate, visits, _) = candidates.first()?;
+        if win_rate < self.winning {
+            return None;
+        }
+        candidates
+            .iter()
+            .filter(|&&(_, win_rate, v, _)| win_rate >= self.winning && v * 10.0 >= visits)
+            .max_by(|a, b| a.3.cmp(&b.3).then(a.1.total_cmp(&b.1)))
+            .map(|&(item, ..)| item)
+    }
+}
+
+/// Successive halving at the root: after each of the first `rounds` parts
+/// of the budget (split in `rounds + 1` equal parts, by iterations or by
+/// time, whichever runs out first), root moves whose upper confidence bound
+/// is below the lower bound of the most visited move are no longer visited,
+/// leaving their share to the moves still in contention. Bounds are `z`
+/// standard errors from the mean.
+#[derive(Clone, Copy, Debug, PartialEq, DeepSizeOf, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RootPruning {
+    pub rounds: u32,
+    pub z: f32,
+}
+
+impl Default for RootPruning {
+    fn default() -> Self {
+        Self { rounds: 3, z: 2.0 }
+    }
+}
+
+/// Fewest visits for a root move to be judged by [`RootPruning`] or
+/// [`EarlyStop`].
+const MIN_PRUNING_VISITS: f32 = 16.0;
+
+/// Checked between batches: the search stops once the lower bound of the
+/// most visited root move is above the upper bound of every other one, with
+/// bounds `z` standard errors from the mean. Moves with too few visits to
+/// judge keep the search going, unless [`RootPruning`] dropped them.
+#[derive(Clone, Copy, Debug, PartialEq, DeepSizeOf, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EarlyStop {
+    pub z: f32,
+}
+
+impl Default for EarlyStop {
+    fn default() -> Self {
+        Self { z: 3.0 }
+    }
+}
+
+/// Playout Policy Adaptation: every player has a weight per cell, and
+/// playouts pick among the legal moves with probability proportional to
+/// `exp(weight)`. After a decided playout, each move of the winner gains
+/// `alpha`, taken from the moves it could have played instead in proportion
+/// to their probability. Weights start even for every search.
+#[derive(Clone, Copy, Debug, PartialEq, DeepSizeOf, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlayoutAdaptation {
+    pub alpha: f32,
+}
+
+impl Default for PlayoutAdaptation {
+    fn default() -> Self {
+        Self { alpha: 0.32 }
+    }
+}
+
+/// Bound on the size of a [`PlayoutWeights`] weight, keeping the sum of
+/// their exponentials finite and positive.
+const MAX_PLAYOUT_WEIGHT: f32 = 30.0;
+
+/// Weights of [`PlayoutAdaptation`], 81 per player.
+#[derive(Clone, Debug, DeepSizeOf)]
+struct PlayoutWeights(Vec<f32>);
+
+impl PlayoutWeights {
+    fn new() -> Self {
+        Self(vec![0.0; 2 * 81])
+    }
+
+    fn of(&self, player: Player) -> &[f32] {
+        match player {
+            Player::X => &self.0[..81],
+            Player::O => &self.0[81..],
+        }
+    }
+
+    /// Draws a cell index from the set bits of `moves`, uniformly if the
+    /// weights don't add up to a usable total. `None` without moves.
+    fn pick<R: Rng>(&self, player: Player, moves: u128, rng: &mut R) -> Option<u8> {
+        let weights = self.of(player);
+        let total: f32 = cells(moves).map(|i| weights[i as usize].exp()).sum();
+        if !(total > 0.0 && total.is_finite()) {
+            let count = cells(moves).count();
+            if count == 0 {
+                return None;
+            }
+            return cells(moves).nth(rng.gen_range(0..count));
+        }
+        let mut left = rng.gen_range(0.0..total);
+        let mut last = None;
+        for i in cells(moves) {
+            left -= weights[i as usize].exp();
+            if left < 0.0 {
+                return Some(i);
+            }
+            last = Some(i);
+        }
+        // Rounding can leave a little over.
+        last
+    }
+
+    /// Reinforces the moves `winner` made in a playout, as `(legal moves,
+    /// chosen cell)` pairs. Weights stay within [`MAX_PLAYOUT_WEIGHT`] of 0.
+    fn adapt(&mut self, winner: Player, moves: &[(u128, u8)], alpha: f32) {
+        let old = self.clone();
+        let weights = old.of(winner);
+        let offset = match winner {
+            Player::X => 0,
+            Player::O => 81,
+        };
+        for &(legal, chosen) in moves {
+            let total: f32 = cells(legal).map(|i| weights[i as usize].exp()).sum();
+            for i in cells(legal) {
+                self.0[offset + i as usize] -= alpha * weights[i as usize].exp() / total;
+            }
+            self.0[offset + chosen as usize] += alpha;
+        }
+        for weight in &mut self.0[offset..offset + 81] {
+            *weight = if weight.is_nan() {
+                0.0
+            } else {
+                weight.clamp(-MAX_PLAYOUT_WEIGHT, MAX_PLAYOUT_WEIGHT)
+            };
+        }
+    }
+}
+
+/// Indices of the set bits of a move set.
+fn cells(moves: u128) -> impl Iterator<Item = u8> {
+    (0..81).filter(move |i| moves & (1 << i) != 0)
+}
+
+/// Moves of one playout, per player, as `(legal moves, chosen cell)`.
+#[derive(Debug, Default)]
+struct PlayoutMoves {
+    x: Vec<(u128, u8)>,
+    o: Vec<(u128, u8)>,
+}
+
+impl MCTSConfig {
+    /// Bayesian search: every child's value is a Beta posterior and
+    /// selection samples from it. Tends to beat UCT when only a few hundred
+    /// playouts per move are affordable.
+    pub fn bayesian() -> Self {
+        Self {
+            selection: SelectionPolicy::Thompson,
+            batch_size: 16,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for MCTSConfig {
+    fn default() -> Self {
+        Self {
+            mode: SearchMode::default(),
+            batch_size: 64,
+            max_nodes: None,
+            fixed_capacity: false,
+            symmetry_plies: 4,
+            widening: None,
+            selection: SelectionPolicy::default(),
+            backup: Backup::Average,
+            trace: false,
+            endgame_cells: 0,
+            random_opening: None,
+            human: None,
+            teaching: None,
+            symmetric_openings: false,
+            root_pruning: None,
+            solver: false,
+            collect_garbage: false,
+            playout_adaptation: None,
+            early_stop: None,
+            merge_transpositions: false,
+        }
+    }
+}
+
+/// When a search should stop. Whichever limit is hit first ends it.
+#[derive(Clone, Copy, Debug)]
+pub struct SearchLimits {
+    pub iterations: u32,
+    pub time: Option<Duration>,
+}
+
+impl SearchLimits {
+    pub fn iterations(iterations: u32) -> Self {
+        Self {
+            iterations,
+            time: None,
+        }
+    }
+
+    pub fn time(time: Duration) -> Self {
+        Self {
+            iterations: u32::MAX,
+            time: Some(time),
+        }
+    }
+
+    /// Time for one move with `remaining` on the clock and `increment` added
+    /// after every move.
+    pub fn from_clock(remaining: Duration, increment: Duration) -> Self {
+        Self::time(remaining / CLOCK_FRACTION + increment)
+    }
+}
+
+/// Share of the remaining clock spent on one move.
+const CLOCK_FRACTION: u32 = 30;
+
+/// Shape of a search tree, for choosing budgets and spotting pathologies
+/// like a tree that is all width and no depth.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TreeStats {
+    pub nodes: usize,
+    /// Number of nodes at each depth, the root being depth 0.
+    pub depth_histogram: Vec<usize>,
+    /// Average number of children of expanded nodes.
+    pub branching_factor: f32,
+    /// Fraction of nodes whose game is over.
+    pub terminal_fraction: f32,
+    /// Nodes whose position (by Zobrist hash) is elsewhere in the tree too,
+    /// not counting one node per position. This is how many nodes a
+    /// transposition table would save.
+    pub duplicates: usize,
+}
+
+/// The most visited root move changed during a search.
+#[derive(Clone, Copy, Debug, PartialEq, DeepSizeOf)]
+pub struct BestMoveChange {
+    /// Iterations run when the change happened.
+    pub iteration: u32,
+    /// `None` for the first best move of the search.
+    pub old: Option<u8>,
+    pub new: u8,
+    /// Win rates of the old and the new move at that point.
+    pub old_value: f32,
+    pub new_value: f32,
+}
+
+/// How a search went, beyond its result.
+#[derive(Clone, Debug, Default, PartialEq, DeepSizeOf)]
+pub struct SearchInfo {
+    pub iterations: u32,
+    pub best_move_changes: Vec<BestMoveChange>,
+    /// Positions solved for [`MCTSConfig::endgame_cells`].
+    pub endgame_positions: usize,
+    /// Root moves left alone by [`MCTSConfig::root_pruning`], in the order
+    /// they were pruned.
+    pub pruned_root_moves: Vec<u8>,
+    /// Nodes dropped by [`MCTSConfig::collect_garbage`].
+    pub collected_nodes: usize,
+    /// Whether [`MCTSConfig::early_stop`] ended the search.
+    pub stopped_early: bool,
+    /// Playouts per thread of the last search, for threads that ran any.
+    /// Threads with little work, or much idle time, mean the pool is too
+    /// big for the batch size.
+    pub threads: Vec<ThreadStats>,
+}
+
+impl SearchInfo {
+    /// Changes of the best move after the first `fraction` of the
+    /// iterations. Many late changes mean the search hasn't settled and
+    /// deserves more time.
+    pub fn late_changes(&self, fraction: f32) -> usize {
+        let since = (self.iterations as f32 * fraction) as u32;
+        self.best_move_changes
+            .iter()
+            .filter(|change| change.old.is_some() && change.iteration > since)
+            .count()
+    }
+}
+
+/// What one iteration did. Node ids are those of the recording arena; a
+/// replay creates nodes in the same order, so it finds the same slots in
+/// its own arena.
+#[derive(Clone, Debug, PartialEq, DeepSizeOf)]
+pub struct TraceStep {
+    /// Node the selection phase ended on.
+    pub selected: NodeId,
+    /// Moves of the children created under `selected`.
+    pub expanded: Vec<u8>,
+    /// Results that were backpropagated, per node they started from.
+    pub results: Vec<(NodeId, GameState)>,
+}
+
+/// Log of a search, which can be replayed one iteration at a time.
+#[derive(Clone, Debug, DeepSizeOf)]
+pub struct SearchTrace {
+    /// Arena that recorded the trace, whose ids the steps hold.
+    arena: u32,
+    board: Board,
+    config: MCTSConfig,
+    pub steps: Vec<TraceStep>,
+}
+
+impl SearchTrace {
+    pub fn replay(&self) -> TraceReplay<'_> {
+        let config = MCTSConfig {
+            trace: false,
+            ..self.config
+        };
+        TraceReplay {
+            trace: self,
+            arena: MCTSArena::with_config(self.board, config),
+            next: 0,
+        }
+    }
+}
+
+/// Rebuilds the traced tree step by step.
+pub struct TraceReplay<'a> {
+    trace: &'a SearchTrace,
+    arena: MCTSArena,
+    next: usize,
+}
+
+impl TraceReplay<'_> {
+    /// Applies the next iteration, returning it, or `None` at the end.
+    /// Fails on a step that doesn't fit the tree replayed so far.
+    pub fn step(&mut self) -> Result<Option<&TraceStep>, SearchError> {
+        let Some(step) = self.trace.steps.get(self.next) else {
+            return Ok(None);
+        };
+        self.next += 1;
+        let selected = self.translate(step.selected);
+        let children = step
+            .expanded
+            .iter()
+            .map(|&mve| {
+                self.arena
+                    .push_child(selected, PendingMove { mve, prior: 1.0 })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let node = self.arena.try_resolve_mut(&selected)?;
+        node.children.get_or_insert_with(Vec::new).extend(children);
+        let results: Vec<_> = step
+            .results
+            .iter()
+            .map(|&(id, result)| (self.translate(id), result))
+            .collect();
+        let player = self.trace.board.next_player;
+        self.arena.backpropagate(&results, &player)?;
+        Ok(Some(step))
+    }
+
+    /// The replay's handle of the node the recording arena knew as `id`.
+    fn translate(&self, id: NodeId) -> NodeId {
+        if id.arena == self.trace.arena {
+            NodeId {
+                arena: self.arena.id,
+                ..id
+            }
+        } else {
+            id
+        }
+    }
+
+    /// Number of iterations applied so far.
+    pub fn position(&self) -> usize {
+        self.next
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.arena.root()
+    }
+
+    /// Node of `id`, a handle of the replay or one from the trace's steps.
+    pub fn node(&self, id: NodeId) -> Result<&MCTSNode, SearchError> {
+        self.arena.try_resolve(&self.translate(id))
+    }
+
+    pub fn stats(&self) -> TreeStats {
+        self.arena.stats(self.arena.root())
+    }
+}
+
+/// Handle to a node of a search tree. Handles stay valid when
+/// [`MCTSConfig::collect_garbage`] moves nodes around; once their node is
+/// collected they are stale, and resolving them fails with
+/// [`SearchError::StaleNode`] rather than finding another node. The same
+/// goes for handles into another tree, e.g. one a later search replaced.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, DeepSizeOf)]
+pub struct NodeId {
+    arena: u32,
+    slot: u32,
+    generation: u32,
+}
+
+impl NodeId {
+    /// Handle of the `index`th node of the nodes passed to
+    /// [`MCTSArena::from_nodes`], which adopts it.
+    pub(crate) fn new(index: usize) -> Self {
+        Self {
+            arena: 0,
+            slot: index as u32,
+            generation: 0,
+        }
+    }
+}
+
+/// Source of [`MCTSArena::id`]s. Wrapping around takes billions of arenas,
+/// by then the handles of the old one are long gone.
+static NEXT_ARENA: AtomicU32 = AtomicU32::new(1);
+
+/// Where the node of a [`NodeId`] is. Collecting the node bumps the
+/// generation, so older handles no longer match.
+#[derive(Clone, Copy, Debug, DeepSizeOf)]
+struct Slot {
+    position: usize,
+    generation: u32,
+}
+
+#[derive(Default, Debug, DeepSizeOf)]
+pub struct MCTSNode {
+    pub board: Board,
+    pub wins: f32,
+    /// Sum of squared rewards, for the variance of the node's value.
+    pub wins_squared: f32,
+    pub visits: f32,
+    /// Sum of the lengths of the games simulated through the node, in
+    /// stones on the board at their end.
+    pub plies: f32,
+    /// Games simulated through the node. Unlike `visits` without virtual
+    /// visits, which have no length.
+    pub playouts: f32,
+    // Node specific
+    pub parent: Option<NodeId>,
+    pub children: Option<Vec<NodeId>>,
+    /// Probability the parent's heuristic gave to reaching this node.
+    pub prior: f32,
+    /// Moves without a child node yet, best prior last. Only used with
+    /// progressive widening.
+    pub pending: Vec<PendingMove>,
+    /// Result under perfect play, once proven by [`MCTSConfig::solver`].
+    pub solved: Option<GameState>,
+}
+
+impl MCTSNode {
+    /// Average length of the games simulated through the node, in stones on
+    /// the board at their end. `None` before any was.
+    pub fn expected_length(&self) -> Option<f32> {
+        (self.playouts > 0.0).then(|| self.plies / self.playouts)
+    }
+
+    /// Variance of the rewards backpropagated through the node.
+    pub fn variance(&self) -> f32 {
+        if self.visits == 0.0 {
+            return 0.0;
+        }
+        let mean = self.wins / self.visits;
+        (self.wins_squared / self.visits - mean * mean).max(0.0)
+    }
+
+    /// Bounds on the node's value `z` standard errors below and above its
+    /// mean.
+    pub fn value_bounds(&self, z: f32) -> (f32, f32) {
+        if self.visits == 0.0 {
+            return (0.0, 1.0);
+        }
+        let mean = self.wins / self.visits;
+        let error = z * (self.variance() / self.visits).sqrt();
+        (mean - error, mean + error)
+    }
+
+    /// Parameters `(alpha, beta)` of the Beta posterior over the node's
+    /// value, starting from a uniform prior.
+    pub fn posterior(&self) -> (f32, f32) {
+        let losses = (self.visits - self.wins).max(0.0);
+        (self.wins + 1.0, losses + 1.0)
+    }
+
+    /// Expected value of the node under its posterior.
+    pub fn posterior_mean(&self) -> f32 {
+        let (alpha, beta) = self.posterior();
+        alpha / (alpha + beta)
+    }
+}
+
+/// A move whose child node (and board) hasn't been created yet.
+#[derive(Clone, Copy, Debug, DeepSizeOf)]
+pub struct PendingMove {
+    pub mve: u8,
+    pub prior: f32,
+}
+
+/// Root move priors from outside the search, see
+/// [`crate::Engine::analyze_with_priors`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RootPriors {
+    /// By cell, `global * 9 + local`.
+    pub priors: [f32; 81],
+    /// Virtual visits the root children share in proportion to their prior.
+    pub visits: f32,
+}
+
+impl DeepSizeOf for RootPriors {
+    fn deep_size_of_children(&self, _context: &mut deepsize::Context) -> usize {
+        0
+    }
+}
+
+impl RootPriors {
+    /// Normalized priors of `child_moves`, the children of `board`. Moves
+    /// pruned as symmetric add theirs to the move searched in their place.
+    /// Uniform if none of the legal moves has any weight.
+    fn shares(&self, board: &Board, child_moves: &[u8]) -> Vec<f32> {
+        let mut shares = vec![0.0; child_moves.len()];
+        let classes: Vec<u64> = child_moves
+            .iter()
+            .map(|&m| board.unchecked_play(m).canonical_hash())
+            .collect();
+        let moves = board.get_moves();
+        for i in (0..81).filter(|i| moves & (1 << i) != 0) {
+            let m = Board::move_from_index(i);
+            let k = child_moves.iter().position(|&c| c == m).or_else(|| {
+                let class = board.unchecked_play(m).canonical_hash();
+                classes.iter().position(|&c| c == class)
+            });
+            if let Some(k) = k {
+                shares[k] += self.priors[i as usize];
+            }
+        }
+        let total: f32 = shares.iter().sum();
+        if total > 0.0 {
+            shares.iter_mut().for_each(|share| *share /= total);
+        } else {
+            shares.fill(1.0 / child_moves.len() as f32);
+        }
+        shares
+    }
+}
+
+/// Most moves a position can have, i.e. most nodes a single expansion adds.
+const MAX_CHILDREN: usize = 81;
+
+enum BestNode {
+    Expand(NodeId),
+    /// Add the next pending child of the node.
+    Widen(NodeId),
+    NodeId(NodeId),
+}
+
+impl MCTSArena {
+    pub fn with_config(board: Board, config: MCTSConfig) -> Self {
+        let capacity = match config.max_nodes {
+            Some(max_nodes) if config.fixed_capacity => max_nodes.max(1),
+            _ => 1,
+        };
+        let mut nodes = Vec::with_capacity(capacity);
+        nodes.push(MCTSNode {
+            board,
+            wins: 0.0,
+            wins_squared: 0.0,
+            visits: 0.0,
+            plies: 0.0,
+            playouts: 0.0,
+            prior: 1.0,
+            parent: None,
+            children: None,
+            pending: Vec::new(),
+            solved: None,
+        });
+        let id = NEXT_ARENA.fetch_add(1, Ordering::Relaxed);
+        Self {
+            id,
+            nodes,
+            slots: vec![Slot {
+                position: 0,
+                generation: 0,
+            }],
+            slot_of: vec![0],
+            free_slots: Vec::new(),
+            config,
+            trace: config.trace.then(|| SearchTrace {
+                arena: id,
+                board,
+                config,
+                steps: Vec::new(),
+            }),
+            iterations: 0,
+            tablebase: None,
+            experience: None,
+            root_priors: None,
+            best_move_changes: Vec::new(),
+            endgame: EndgameCache::default(),
+            pruned: Vec::new(),
+            collected: 0,
+            stopped_early: false,
+            transpositions: HashMap::new(),
+            path: Vec::new(),
+            max_depth: 0,
+            frames: None,
+            thread_load: ThreadLoad::default(),
+            search_time: 0.0,
+            playout_weights: config.playout_adaptation.map(|_| PlayoutWeights::new()),
+        }
+    }
+
+    /// Ask `tablebase` for the result of nodes before simulating them.
+    pub(crate) fn set_tablebase(&mut self, tablebase: Option<Arc<dyn TablebaseProvider>>) {
+        self.tablebase = tablebase.map(Tablebase);
+    }
+
+    /// Start new nodes `experience` knows with its visits, see
+    /// [`Experience::max_prior_visits`].
+    pub(crate) fn set_experience(&mut self, experience: Option<Arc<Experience>>) {
+        self.experience = experience;
+    }
+
+    /// Take the priors of the root's children, and their first visits,
+    /// from `priors` rather than from the evaluator.
+    pub(crate) fn set_root_priors(&mut self, priors: Option<RootPriors>) {
+        self.root_priors = priors;
+    }
+
+    /// Hand [`SearchFrame`]s to `frames` while searching.
+    pub(crate) fn set_frames(&mut self, frames: Option<FrameOutput>) {
+        self.frames = frames;
+    }
+
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    pub(crate) fn nodes(&self) -> &[MCTSNode] {
+        &self.nodes
+    }
+
+    /// Arena continuing a search whose tree was `nodes`, the root first,
+    /// after `iterations` iterations.
+    pub(crate) fn from_nodes(nodes: Vec<MCTSNode>, config: MCTSConfig, iterations: u32) -> Self {
+        let mut arena = Self::with_config(nodes[0].board, config);
+        arena.slots = (0..nodes.len())
+            .map(|position| Slot {
+                position,
+                generation: 0,
+            })
+            .collect();
+        arena.slot_of = (0..nodes.len() as u32).collect();
+        arena.nodes = nodes;
+        for node in &mut arena.nodes {
+            let ids = node
+                .parent
+                .iter_mut()
+                .chain(node.children.iter_mut().flatten());
+            for id in ids {
+                id.arena = arena.id;
+            }
+        }
+        arena.iterations = iterations;
+        arena
+    }
+
+    pub fn info(&self) -> SearchInfo {
+        SearchInfo {
+            iterations: self.iterations,
+            best_move_changes: self.best_move_changes.clone(),
+            endgame_positions: self.endgame.len(),
+            pruned_root_moves: self
+                .pruned
+                .iter()
+                .filter_map(|id| self.try_resolve(id).ok()?.board.last_move)
+                .collect(),
+            collected_nodes: self.collected,
+            stopped_early: self.stopped_early,
+            threads: self.thread_load.stats(self.search_time),
+        }
+    }
+
+    /// Records a change of the most visited child of `id`, the search root.
+    fn track_best_move(&mut self, id: NodeId) -> Result<(), SearchError> {
+        let Some(best) = self.select_best_child(id)? else {
+            return Ok(());
+        };
+        let best = self.try_resolve(&best)?;
+        let new = best.board.last_move.ok_or(SearchError::NoMove)?;
+        let last = self.best_move_changes.last();
+        if last.is_some_and(|change| change.new == new) {
+            return Ok(());
+        }
+        let new_value = best.wins / best.visits;
+        let (old, old_value) = match last {
+            Some(change) => {
+                let mut old_value = 0.0;
+                for child in self.try_resolve(&id)?.children.iter().flatten() {
+                    let child = self.try_resolve(child)?;
+                    if child.board.last_move == Some(change.new) {
+                        old_value = child.wins / child.visits.max(1.0);
+                        break;
+                    }
+                }
+                (Some(change.new), old_value)
+            }
+            None => (None, 0.0),
+        };
+        self.best_move_changes.push(BestMoveChange {
+            iteration: self.iterations,
+            old,
+            new,
+            old_value,
+            new_value,
+        });
+        Ok(())
+    }
+
+    /// Hands out the trace recorded so far, if tracing is enabled. Replays
+    /// are only faithful when recording started on a fresh arena.
+    pub fn take_trace(&mut self) -> Option<SearchTrace> {
+        let trace = self.trace.as_mut()?;
+        let steps = std::mem::take(&mut trace.steps);
+        Some(SearchTrace {
+            steps,
+            ..trace.clone()
+        })
+    }
+
+    pub fn root(&self) -> NodeId {
+        // The root is never collected, so its slot is never reused.
+        NodeId {
+            arena: self.id,
+            slot: 0,
+            generation: 0,
+        }
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether the node cap leaves room for one more expansion.
+    fn has_room(&self) -> bool {
+        match self.config.max_nodes {
+            Some(max_nodes) => self.node_count() + MAX_CHILDREN <= max_nodes,
+            None => true,
+        }
+    }
+
+    /// Handle of the `index`th node of an arena built by
+    /// [`Self::from_nodes`], before any garbage collection.
+    pub(crate) fn node_id(&self, index: usize) -> NodeId {
+        NodeId {
+            arena: self.id,
+            ..NodeId::new(index)
+        }
+    }
+
+    /// Index of the node of `id` in [`Self::nodes`], `None` once the node
+    /// was collected or when it belongs to another arena.
+    pub(crate) fn position(&self, id: &NodeId) -> Option<usize> {
+        if id.arena != self.id {
+            return None;
+        }
+        let slot = self.slots.get(id.slot as usize)?;
+        (slot.generation == id.generation).then_some(slot.position)
+    }
+
+    pub(crate) fn try_resolve(&self, id: &NodeId) -> Result<&MCTSNode, SearchError> {
+        let position = self.position(id).ok_or(SearchError::StaleNode)?;
+        Ok(&self.nodes[position])
+    }
+
+    fn try_resolve_mut(&mut self, id: &NodeId) -> Result<&mut MCTSNode, SearchError> {
+        let position = self.position(id).ok_or(SearchError::StaleNode)?;
+        Ok(&mut self.nodes[position])
+    }
+
+    /// Searches from `id` until `limits` are reached or `cancel` is set. A
+    /// stopped search still reports the best move found so far.
+    ///
+    /// Iterations run in batches of at most `config.batch_size`; the clock
+    /// and the cancellation flag are only checked between batches. Batches
+    /// shrink as the deadline gets close so the time limit isn't overshot.
+    pub fn analyze(
+        &mut self,
+        id: NodeId,
+        limits: SearchLimits,
+        cancel: &CancellationToken,
+    ) -> Result<(f32, NodeId), SearchError> {
+        if self.try_resolve(&id)?.board.game_over() {
+            return Err(SearchError::GameOver);
+        }
+
+        let start = Instant::now();
+        let deadline = limits.time.map(|time| start + time);
+        let mut pruning_round = 1;
+        self.pruned.clear();
+        self.stopped_early = false;
+        self.max_depth = 0;
+        self.thread_load.reset();
+        let mut last_frame = self.iterations;
+        let max_batch = self.config.batch_size.max(1);
+        let mut batch = max_batch;
+        let mut time_per_iter = None;
+        let mut remaining = limits.iterations;
+        let mut simulation_results = Vec::new();
+        let mut rng = match self.config.mode {
+            SearchMode::Parallel => None,
+            SearchMode::Deterministic { seed } => Some(StdRng::seed_from_u64(seed)),
+        };
+        while remaining > 0 && !cancel.is_cancelled() {
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                if let Some(time_per_iter) = time_per_iter {
+                    // Aim for half of the remaining time so the last batch
+                    // doesn't run over.
+                    let fit = (deadline - now).as_secs_f64() / time_per_iter / 2.0;
+                    batch = (fit as u32).clamp(1, max_batch);
+                }
+            }
+
+            let batch_start = Instant::now();
+            let n = batch.min(remaining);
+            let mut done = 0;
+            while done < n {
+                let collect = self.config.collect_garbage && self.trace.is_none() && !self.merges();
+                if !self.has_room() && !(collect && self.collect_garbage(id)? && self.has_room()) {
+                    if self.config.fixed_capacity {
+                        return Err(SearchError::OutOfNodes);
+                    }
+                    break;
+                }
+                if !self.iterate(id, &mut rng, cancel, &mut simulation_results)? {
+                    break;
+                }
+                done += 1;
+                self.iterations += 1;
+                self.track_best_move(id)?;
+            }
+            if done < n {
+                break;
+            }
+            time_per_iter = Some(batch_start.elapsed().as_secs_f64() / n as f64);
+            remaining -= n;
 
-#[derive(DeepSizeOf, Debug)]
-pub(crate) struct MCTSArena {
-    nodes: Vec<MCTSNode>,
-}
+            if let Some(pruning) = self.config.root_pruning {
+                let done = 1.0 - remaining as f64 / limits.iterations as f64;
+                let elapsed = limits.time.map_or(0.0, |time| {
+                    start.elapsed().as_secs_f64() / time.as_secs_f64()
+                });
+                let progress = done.max(elapsed) * (pruning.rounds + 1) as f64;
+                while pruning_round <= pruning.rounds && progress >= pruning_round as f64 {
+                    self.prune_root(id, pruning.z)?;
+                    pruning_round += 1;
+                }
+            }
+            if let Some(frames) = &self.frames {
+                // The last frame comes after the loop.
+                if remaining > 0 && self.iterations - last_frame >= frames.every {
+                    last_frame = self.iterations;
+                    frames.sink.frame(&self.frame(id, false)?);
+                }
+            }
+            if let Some(early_stop) = self.config.early_stop {
+                if self.is_settled(id, early_stop.z)? {
+                    self.stopped_early = true;
+                    break;
+                }
+            }
+        }
 
-#[derive(Copy, Clone, Debug, DeepSizeOf)]
-pub struct NodeId(usize);
+        self.search_time = start.elapsed().as_secs_f64();
+        if let Some(frames) = &self.frames {
+            frames.sink.frame(&self.frame(id, true)?);
+        }
+        let best_child_id = self
+            .select_best_child(id)?
+            .ok_or(if cancel.is_cancelled() {
+                SearchError::Cancelled
+            } else {
+                SearchError::NoChildren
+            })?;
+        let best_child = self.try_resolve(&best_child_id)?;
+        Ok((best_child.wins / best_child.visits * 100.0, best_child_id))
+    }
 
-#[derive(Default, Debug, DeepSizeOf)]
-pub struct MCTSNode {
-    pub board: Board,
-    pub wins: f32,
-    pub visits: f32,
-    // Node specific
-    pub parent: Option<NodeId>,
-    pub children: Option<Vec<NodeId>>,
-}
+    /// Snapshot of the search from `id` for visualizers.
+    fn frame(&self, id: NodeId, last: bool) -> Result<SearchFrame, SearchError> {
+        let mut top_moves = self
+            .try_resolve(&id)?
+            .children
+            .iter()
+            .flatten()
+            .map(|child| {
+                let node = self.try_resolve(child)?;
+                let mve = node.board.last_move.ok_or(SearchError::NoMove)?;
+                Ok(FrameMove {
+                    mve: (mve >> 4, mve & 0b1111),
+                    visits: node.visits,
+                    win_rate: node.wins / node.visits.max(1.0),
+                })
+            })
+            .collect::<Result<Vec<_>, SearchError>>()?;
+        top_moves.sort_by(|a, b| b.visits.total_cmp(&a.visits));
+        top_moves.truncate(FRAME_MOVES);
+        let mut pv = vec![];
+        let mut node = id;
+        while let Some(best) = self.select_best_child(node)? {
+            let mve = self.try_resolve(&best)?.board.last_move;
+            let mve = mve.ok_or(SearchError::NoMove)?;
+            pv.push((mve >> 4, mve & 0b1111));
+            node = best;
+        }
+        Ok(SearchFrame {
+            iterations: self.iterations,
+            nodes: self.node_count(),
+            depth: self.max_depth,
+            top_moves,
+            pv,
+            last,
+        })
+    }
 
-enum BestNode {
-    Expand(NodeId),
-    NodeId(NodeId),
-}
+    /// Frees about a quarter of the tree by turning the least visited
+    /// expanded nodes below `id` back into leaves, see
+    /// [`MCTSConfig::collect_garbage`]. Nodes outside the subtree of `id`
+    /// and their indices are left alone. Returns whether anything was freed.
+    fn collect_garbage(&mut self, id: NodeId) -> Result<bool, SearchError> {
+        let count = self.nodes.len();
+        let parents: Vec<Option<usize>> = self
+            .nodes
+            .iter()
+            .map(|node| node.parent.and_then(|parent| self.position(&parent)))
+            .collect();
+        // Children always come after their parent.
+        let mut sizes = vec![1; count];
+        for i in (1..count).rev() {
+            if let Some(parent) = parents[i] {
+                sizes[parent] += sizes[i];
+            }
+        }
+        let mut candidates = vec![];
+        let mut level = self.try_resolve(&id)?.children.clone().unwrap_or_default();
+        while !level.is_empty() {
+            let mut next = vec![];
+            for child in level {
+                let position = self.position(&child).ok_or(SearchError::StaleNode)?;
+                if let Some(children) = &self.nodes[position].children {
+                    candidates.push(position);
+                    next.extend(children);
+                }
+            }
+            level = next;
+        }
+        // Least visited first, the deeper of equally visited nodes first.
+        candidates.sort_by(|&a, &b| {
+            let (a_visits, b_visits) = (self.nodes[a].visits, self.nodes[b].visits);
+            a_visits.total_cmp(&b_visits).then(b.cmp(&a))
+        });
 
-impl MCTSArena {
-    pub fn init() -> Self {
-        Self {
-            nodes: vec![MCTSNode::default()],
+        let target = (count / 4).max(MAX_CHILDREN);
+        let mut freed = 0;
+        let mut collapsed = vec![false; count];
+        for candidate in candidates {
+            if freed >= target {
+                break;
+            }
+            let dropped = sizes[candidate] - 1;
+            if dropped == 0 {
+                continue;
+            }
+            freed += dropped;
+            collapsed[candidate] = true;
+            let mut node = Some(candidate);
+            while let Some(ancestor) = node {
+                sizes[ancestor] -= dropped;
+                node = parents[ancestor];
+            }
+        }
+        if freed == 0 {
+            return Ok(false);
+        }
+
+        // Nodes move, but links between them are handles, so only the slots
+        // of moved nodes change. Freed slots get a new generation to make
+        // outstanding handles to them stale.
+        let mut kept = vec![false; count];
+        let mut nodes = Vec::with_capacity(self.nodes.capacity());
+        let mut slot_of = Vec::with_capacity(self.slot_of.capacity());
+        let old = std::mem::take(&mut self.nodes).into_iter();
+        for (i, (mut node, slot)) in old.zip(std::mem::take(&mut self.slot_of)).enumerate() {
+            let entry = &mut self.slots[slot as usize];
+            if parents[i].is_some_and(|parent| !kept[parent] || collapsed[parent]) {
+                entry.generation = entry.generation.wrapping_add(1);
+                self.free_slots.push(slot);
+                continue;
+            }
+            kept[i] = true;
+            if collapsed[i] {
+                node.children = None;
+                node.pending.clear();
+            }
+            entry.position = nodes.len();
+            nodes.push(node);
+            slot_of.push(slot);
         }
+        self.nodes = nodes;
+        self.slot_of = slot_of;
+        let pruned = std::mem::take(&mut self.pruned);
+        self.pruned = pruned
+            .into_iter()
+            .filter(|id| self.position(id).is_some())
+            .collect();
+        self.collected += freed;
+        Ok(true)
     }
 
-    pub fn from(board: Board) -> Self {
-        Self {
-            nodes: vec![MCTSNode {
-                board: board,
-                wins: 0.0,
-                visits: 0.0,
-                parent: None,
-                children: None,
-            }],
+    /// Stops visiting children of `id` that are clearly worse than the most
+    /// visited one, see [`RootPruning`].
+    fn prune_root(&mut self, id: NodeId, z: f32) -> Result<(), SearchError> {
+        let Some(leader) = self.select_best_child(id)? else {
+            return Ok(());
+        };
+        let (floor, _) = self.try_resolve(&leader)?.value_bounds(z);
+        let mut behind = vec![];
+        for &child in self.try_resolve(&id)?.children.iter().flatten() {
+            if child == leader || self.pruned.contains(&child) {
+                continue;
+            }
+            let node = self.try_resolve(&child)?;
+            if node.visits >= MIN_PRUNING_VISITS && node.value_bounds(z).1 < floor {
+                behind.push(child);
+            }
         }
+        self.pruned.extend(behind);
+        Ok(())
     }
 
-    pub fn root(&self) -> NodeId {
-        NodeId(0)
+    /// Recomputes the values of `id` and its ancestors with
+    /// [`MCTSConfig::backup`]. Wins stay counted for `player`.
+    fn back_up_values(&mut self, id: NodeId, player: Player) -> Result<(), SearchError> {
+        let mut next = Some(id);
+        while let Some(id) = next {
+            let node = self.try_resolve(&id)?;
+            next = node.parent;
+            let maximising = node.board.next_player == player;
+            let children = node
+                .children
+                .iter()
+                .flatten()
+                .map(|child| {
+                    let child = self.try_resolve(child)?;
+                    let value = child.wins / child.visits.max(1.0);
+                    let value = if maximising { value } else { 1.0 - value };
+                    Ok((value, child.visits))
+                })
+                .collect::<Result<Vec<_>, SearchError>>()?;
+            let Some(value) = self.config.backup.value(&children) else {
+                continue;
+            };
+            let value = if maximising { value } else { 1.0 - value };
+            let node = self.try_resolve_mut(&id)?;
+            node.wins = value * node.visits;
+        }
+        Ok(())
+    }
+
+    /// Whether the most visited child of `id` is clearly the best, see
+    /// [`EarlyStop`].
+    fn is_settled(&self, id: NodeId, z: f32) -> Result<bool, SearchError> {
+        let Some(leader) = self.select_best_child(id)? else {
+            return Ok(false);
+        };
+        let (floor, _) = self.try_resolve(&leader)?.value_bounds(z);
+        for child in self.try_resolve(&id)?.children.iter().flatten() {
+            if *child == leader || self.pruned.contains(child) {
+                continue;
+            }
+            let node = self.try_resolve(child)?;
+            if node.visits < MIN_PRUNING_VISITS || node.value_bounds(z).1 >= floor {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Runs a single select, expand, simulate and backpropagate step.
+    /// Returns `false` if it was cancelled before backpropagating.
+    fn iterate(
+        &mut self,
+        id: NodeId,
+        rng: &mut Option<StdRng>,
+        cancel: &CancellationToken,
+        simulation_results: &mut Vec<(NodeId, GameState)>,
+    ) -> Result<bool, SearchError> {
+        let mut path = std::mem::take(&mut self.path);
+        let selected = self.select(id, rng, &mut path)?;
+        let mut lengths = vec![];
+        self.max_depth = self.max_depth.max(path.len() - 1);
+        let selected_id = match selected {
+            BestNode::Expand(id) | BestNode::Widen(id) | BestNode::NodeId(id) => id,
+        };
+        let new_children = match selected {
+            BestNode::Expand(to_expand_id) => self.expand(to_expand_id)?,
+            BestNode::Widen(to_widen_id) => self.widen(to_widen_id)?.into_iter().collect(),
+            BestNode::NodeId(terminal_node_id) => {
+                let terminal_node = self.try_resolve(&terminal_node_id)?;
+                let result = terminal_node
+                    .solved
+                    .unwrap_or_else(|| terminal_node.board.check_game_state());
+                simulation_results.push((terminal_node_id, result));
+                lengths.push((terminal_node_id, stones(&terminal_node.board)));
+                vec![]
+            }
+        };
+        let player = self.try_resolve(&id)?.board.next_player;
+        for &child in &new_children {
+            self.seed_from_experience(child, player)?;
+            self.seed_from_root_priors(child)?;
+        }
+        if !new_children.is_empty() {
+            let results = match rng {
+                None => new_children
+                    .par_iter()
+                    .map(|child_id| {
+                        let mut moves = PlayoutMoves::default();
+                        let result =
+                            self.simulate(child_id, &mut rand::thread_rng(), cancel, &mut moves)?;
+                        Ok((*child_id, result, moves))
+                    })
+                    .collect::<Result<Vec<_>, _>>(),
+                Some(rng) => new_children
+                    .iter()
+                    .map(|child_id| {
+                        let mut moves = PlayoutMoves::default();
+                        let result = self.simulate(child_id, rng, cancel, &mut moves)?;
+                        Ok((*child_id, result, moves))
+                    })
+                    .collect::<Result<Vec<_>, _>>(),
+            };
+            let results = match results {
+                Err(SearchError::Cancelled) => return Ok(false),
+                results => results?,
+            };
+            simulation_results.clear();
+            for (child_id, (result, plies), moves) in results {
+                if let (Some(weights), Some(adaptation), GameState::Won(winner)) = (
+                    &mut self.playout_weights,
+                    self.config.playout_adaptation,
+                    result,
+                ) {
+                    let moves = match winner {
+                        Player::X => &moves.x,
+                        Player::O => &moves.o,
+                    };
+                    weights.adapt(winner, moves, adaptation.alpha);
+                }
+                simulation_results.push((child_id, result));
+                let board = self.try_resolve(&child_id)?.board;
+                lengths.push((child_id, stones(&board) + plies as f32));
+            }
+        }
+        if self.merges() {
+            self.backpropagate_along(simulation_results, &player, &path)?;
+        } else {
+            self.backpropagate(simulation_results, &player)?;
+        }
+        self.record_lengths(&lengths, &path)?;
+        self.path = path;
+        if self.config.backup != Backup::Average {
+            for &(simulated, _) in simulation_results.iter() {
+                self.back_up_values(simulated, player)?;
+            }
+        }
+        if self.config.solver {
+            for &(simulated, _) in simulation_results.iter() {
+                self.solve_upwards(simulated, player)?;
+            }
+        }
+        if let Some(trace) = &mut self.trace {
+            let (nodes, slots) = (&self.nodes, &self.slots);
+            trace.steps.push(TraceStep {
+                selected: selected_id,
+                expanded: new_children
+                    .iter()
+                    .filter_map(|child| nodes[slots[child.slot as usize].position].board.last_move)
+                    .collect(),
+                results: simulation_results.clone(),
+            });
+        }
+        Ok(true)
+    }
+
+    /// Statistics of the subtree rooted at `id`. Stale handles count as
+    /// nothing.
+    pub fn stats(&self, id: NodeId) -> TreeStats {
+        let mut stats = TreeStats::default();
+        let (mut expanded, mut children, mut terminal) = (0, 0, 0);
+        let mut positions = HashSet::new();
+        let mut level = vec![id];
+        let mut seen = HashSet::new();
+        while !level.is_empty() {
+            // Shared nodes are reached through every parent, count them once.
+            level.retain(|id| seen.insert(*id));
+            if level.is_empty() {
+                break;
+            }
+            stats.depth_histogram.push(level.len());
+            let mut next = vec![];
+            for id in level {
+                let Ok(node) = self.try_resolve(&id) else {
+                    continue;
+                };
+                if node.board.game_over() {
+                    terminal += 1;
+                }
+                if !positions.insert(node.board.zobrist_hash()) {
+                    stats.duplicates += 1;
+                }
+                if let Some(node_children) = &node.children {
+                    expanded += 1;
+                    children += node_children.len();
+                    next.extend(node_children);
+                }
+            }
+            level = next;
+        }
+        stats.nodes = stats.depth_histogram.iter().sum();
+        if expanded > 0 {
+            stats.branching_factor = children as f32 / expanded as f32;
+        }
+        stats.terminal_fraction = terminal as f32 / stats.nodes as f32;
+        stats
     }
 
-    pub(crate) fn resolve(&self, id: &NodeId) -> &MCTSNode {
-        &self.nodes[id.0]
+    /// Result of `board` if it is known without searching it.
+    fn exact_result(&self, board: &Board) -> Option<GameState> {
+        let state = board.check_game_state();
+        if state != GameState::InProgress {
+            return Some(state);
+        }
+        if board.phase() == Phase::Endgame && open_cells(board) <= self.config.endgame_cells {
+            return Some(self.endgame.result(board));
+        }
+        let Tablebase(tablebase) = self.tablebase.as_ref()?;
+        tablebase
+            .probe(board)
+            .filter(|&result| result != GameState::InProgress)
     }
 
-    fn resolve_mut(&mut self, id: &NodeId) -> &mut MCTSNode {
-        &mut self.nodes[id.0]
+    /// Result of `id` by minimax over its children, if they settle it: a
+    /// child won by the side to move, or every move solved.
+    fn solve_by_children(&self, id: NodeId) -> Result<Option<GameState>, SearchError> {
+        let node = self.try_resolve(&id)?;
+        let me = node.board.next_player;
+        let mut complete = node.pending.is_empty();
+        let mut draw = false;
+        let Some(children) = &node.children else {
+            return Ok(None);
+        };
+        for child in children {
+            match self.try_resolve(child)?.solved {
+                Some(GameState::Won(winner)) if winner == me => {
+                    return Ok(Some(GameState::Won(me)))
+                }
+                Some(GameState::Draw) => draw = true,
+                Some(_) => {}
+                None => complete = false,
+            }
+        }
+        Ok(complete.then_some(if draw {
+            GameState::Draw
+        } else {
+            GameState::Won(me.other())
+        }))
     }
 
-    pub fn analyze(&mut self, id: NodeId, mut n_iters: u32) -> (f32, NodeId) {
-        let mut simulation_results = Vec::new();
-        while n_iters > 0 {
-            match self.select(id, 2.0f32.sqrt()) {
-                BestNode::Expand(to_expand_id) => {
-                    self.expand(to_expand_id);
-                    let expanded_node = self.resolve(&to_expand_id);
-                    // The vector is cleared before collecting
-                    expanded_node
-                        .children
-                        .as_ref()
-                        .expect("Non terminal node can't have 0 children")
-                        .par_iter()
-                        .map(|child_id| (*child_id, self.simulate(child_id)))
-                        .collect_into_vec(&mut simulation_results);
-                }
-                BestNode::NodeId(terminal_node_id) => {
-                    let terminal_node = self.resolve(&terminal_node_id);
-                    let result = terminal_node.board.check_game_state();
-                    simulation_results.push((terminal_node_id, result));
-                }
-            }
-            let player = self.resolve(&id).board.next_player;
-            self.backpropagate(&simulation_results, &player);
-            n_iters -= 1;
-        }
-
-        let best_child_id = self.select_best_child(id);
-        let best_child = self.resolve(&best_child_id);
-        (best_child.wins / best_child.visits * 100.0, best_child_id)
-    }
-
-    fn select_best_child(&self, mut id: NodeId) -> NodeId {
-        let node = self.resolve(&id);
-        let children = node.children.as_ref().expect("Node is terminal");
+    /// Marks `id` solved if its result is known, then its ancestors as long
+    /// as their children settle them.
+    fn solve_upwards(&mut self, mut id: NodeId, player: Player) -> Result<(), SearchError> {
+        let node = self.try_resolve(&id)?;
+        if node.solved.is_none() {
+            let Some(result) = self.exact_result(&node.board) else {
+                return Ok(());
+            };
+            self.set_solved(id, result, player)?;
+        }
+        while let Some(parent) = self.try_resolve(&id)?.parent {
+            if self.try_resolve(&parent)?.solved.is_some() {
+                break;
+            }
+            let Some(result) = self.solve_by_children(parent)? else {
+                break;
+            };
+            self.set_solved(parent, result, player)?;
+            id = parent;
+        }
+        Ok(())
+    }
+
+    /// Replaces the averaged value of `id` with its exact `result`, scored
+    /// for `player` as in [`Self::backpropagate`].
+    fn set_solved(
+        &mut self,
+        id: NodeId,
+        result: GameState,
+        player: Player,
+    ) -> Result<(), SearchError> {
+        let node = self.try_resolve_mut(&id)?;
+        node.solved = Some(result);
+        let value = match result {
+            GameState::Won(winner) if winner == player => 1.0,
+            GameState::Draw | GameState::InProgress => 1e-8,
+            GameState::Won(_) => 0.0,
+        };
+        node.wins = value * node.visits;
+        node.wins_squared = if value == 1.0 { node.visits } else { 0.0 };
+        Ok(())
+    }
+
+    /// Most visited child of `id`, or `None` if it hasn't been expanded.
+    /// With the solver a proven win comes first.
+    pub(crate) fn select_best_child(&self, mut id: NodeId) -> Result<Option<NodeId>, SearchError> {
+        let node = self.try_resolve(&id)?;
+        let Some(children) = node.children.as_ref().filter(|c| !c.is_empty()) else {
+            return Ok(None);
+        };
+        let win = Some(GameState::Won(node.board.next_player));
+        for &child in children {
+            if self.try_resolve(&child)?.solved == win {
+                return Ok(Some(child));
+            }
+        }
         let mut max_uct = 0.0;
         let mut max_uct_index = 0;
         for i in 0..children.len() {
-            let child = self.resolve(&children[i]);
+            let child = self.try_resolve(&children[i])?;
             let uct = child.visits;
             if uct > max_uct {
                 max_uct = uct;
@@ -105,126 +1874,1152 @@ impl MCTSArena {
             id = children[max_uct_index];
         }
 
-        id
+        Ok(Some(id))
     }
 
-    fn select(&self, mut id: NodeId, c: f32) -> BestNode {
-        let mut node = self.resolve(&id);
-        while !node.board.game_over() {
+    fn select(
+        &self,
+        mut id: NodeId,
+        rng: &mut Option<StdRng>,
+        path: &mut Vec<NodeId>,
+    ) -> Result<BestNode, SearchError> {
+        let mut thread_rng = rand::thread_rng();
+        let rng: &mut dyn RngCore = match rng {
+            Some(rng) => rng,
+            None => &mut thread_rng,
+        };
+        let policy = self.config.selection;
+        let root = id;
+        path.clear();
+        path.push(id);
+        let mut node = self.try_resolve(&id)?;
+        while !node.board.game_over() && node.solved.is_none() {
             match &node.children {
                 None => {
-                    return BestNode::Expand(id);
+                    return Ok(BestNode::Expand(id));
                 }
                 Some(children) => {
+                    if let Some(widening) = &self.config.widening {
+                        if !node.pending.is_empty()
+                            && children.len() < widening.allowed_children(node.visits)
+                        {
+                            return Ok(BestNode::Widen(id));
+                        }
+                    }
+                    // Pruned root children lose to any other child.
+                    let at_root = id == root;
+                    let pruned = |i: usize| at_root && self.pruned.contains(&children[i]);
                     let mut max_uct = 0.0;
-                    let mut max_uct_index = 0;
+                    let mut max_uct_index = (0..children.len()).find(|&i| !pruned(i)).unwrap_or(0);
                     for i in 0..children.len() {
-                        let child = self.resolve(&children[i]);
-                        let uct = child.wins / child.visits
-                            + c * (node.visits.ln() / child.visits).sqrt();
+                        if pruned(i) {
+                            continue;
+                        }
+                        let child = self.try_resolve(&children[i])?;
+                        let uct = policy.score(node, child, rng);
                         if uct > max_uct {
                             max_uct = uct;
                             max_uct_index = i;
                         }
                         id = children[max_uct_index];
                     }
-                    node = self.resolve(&id);
+                    path.push(id);
+                    node = self.try_resolve(&id)?;
                 }
             }
         }
-        BestNode::NodeId(id)
+        Ok(BestNode::NodeId(id))
     }
 
-    fn expand(&mut self, id: NodeId) {
-        let node = self.resolve(&id);
-        let moves = node.board.get_moves();
+    /// Creates the children of `id` and returns them. With progressive
+    /// widening only the first few are created and the other moves are
+    /// left pending.
+    fn expand(&mut self, id: NodeId) -> Result<Vec<NodeId>, SearchError> {
+        let node = self.try_resolve(&id)?;
+        let board = node.board;
+        let moves = board.get_moves();
+        let plies = (board.x | board.o).count_ones();
+        // Symmetric root moves lead to equivalent subtrees, so only one per
+        // class is worth searching. Symmetric positions only show up early.
+        let mut seen_classes =
+            (node.parent.is_none() && plies < self.config.symmetry_plies).then(HashSet::new);
 
-        let mut children = vec![];
+        let mut child_moves = vec![];
         // TODO: Optimize
         for i in 0..81 {
             if (moves >> i) & 1 == 1 {
-                let node = self.resolve_mut(&id);
-                let board = node.board.unchecked_play(Board::move_from_index(i));
-                let child_node = MCTSNode {
-                    board,
-                    wins: 0.0,
-                    visits: 0.0,
-                    parent: Some(id),
-                    children: None,
-                };
-                self.nodes.push(child_node);
-                children.push(NodeId(self.nodes.len() - 1));
+                let m = Board::move_from_index(i);
+                if let Some(seen) = &mut seen_classes {
+                    if !seen.insert(board.unchecked_play(m).canonical_hash()) {
+                        continue;
+                    }
+                }
+                child_moves.push(m);
+            }
+        }
+
+        // Priors are only worth computing when something reads them.
+        let uses_priors = self.config.widening.is_some()
+            || matches!(self.config.selection, SelectionPolicy::Puct { .. });
+        let root_priors = self.root_priors.filter(|_| node.parent.is_none());
+        let priors = if let Some(root_priors) = root_priors {
+            root_priors.shares(&board, &child_moves)
+        } else if uses_priors {
+            eval::move_priors(&board, &child_moves)
+        } else {
+            vec![1.0 / child_moves.len() as f32; child_moves.len()]
+        };
+        let mut candidates: Vec<PendingMove> = child_moves
+            .into_iter()
+            .zip(priors)
+            .map(|(mve, prior)| PendingMove { mve, prior })
+            .collect();
+
+        if let Some(widening) = self.config.widening {
+            // Worst prior first, so the best moves are at the end.
+            candidates.sort_by(|a, b| a.prior.total_cmp(&b.prior));
+            let allowed = widening.allowed_children(node.visits);
+            let created = candidates.split_off(candidates.len().saturating_sub(allowed));
+            self.try_resolve_mut(&id)?.pending = candidates;
+            candidates = created;
+            candidates.reverse();
+        }
+
+        let mut children = vec![];
+        let mut created = vec![];
+        for candidate in candidates {
+            let (child, new) = self.child_for(id, candidate)?;
+            children.push(child);
+            if new {
+                created.push(child);
             }
         }
-        let node = self.resolve_mut(&id);
+        let node = self.try_resolve_mut(&id)?;
         node.children = Some(children);
+        Ok(created)
+    }
+
+    fn merges(&self) -> bool {
+        self.config.merge_transpositions && self.trace.is_none()
+    }
+
+    /// Child of `parent` for `candidate`, and whether it was created rather
+    /// than shared with another parent, see
+    /// [`MCTSConfig::merge_transpositions`].
+    fn child_for(
+        &mut self,
+        parent: NodeId,
+        candidate: PendingMove,
+    ) -> Result<(NodeId, bool), SearchError> {
+        if !self.merges() {
+            return Ok((self.push_child(parent, candidate)?, true));
+        }
+        let hash = self
+            .try_resolve(&parent)?
+            .board
+            .unchecked_play(candidate.mve)
+            .zobrist_hash();
+        if let Some(&existing) = self.transpositions.get(&hash) {
+            return Ok((existing, false));
+        }
+        let child = self.push_child(parent, candidate)?;
+        self.transpositions.insert(hash, child);
+        Ok((child, true))
+    }
+
+    /// Turns the best pending move of `id` into a child node. Returns the
+    /// child unless it was shared with another parent.
+    fn widen(&mut self, id: NodeId) -> Result<Option<NodeId>, SearchError> {
+        let candidate = self
+            .try_resolve_mut(&id)?
+            .pending
+            .pop()
+            .ok_or(SearchError::NoMove)?;
+        let (child, new) = self.child_for(id, candidate)?;
+        self.try_resolve_mut(&id)?
+            .children
+            .as_mut()
+            .ok_or(SearchError::NoChildren)?
+            .push(child);
+        Ok(new.then_some(child))
+    }
+
+    /// Adds a child for `mve` that looks as if it had been searched already,
+    /// to restore results from outside the tree.
+    pub(crate) fn add_searched_child(
+        &mut self,
+        parent: NodeId,
+        mve: u8,
+        wins: f32,
+        visits: f32,
+    ) -> Result<NodeId, SearchError> {
+        let child = self.push_child(parent, PendingMove { mve, prior: 1.0 })?;
+        let node = self.try_resolve_mut(&child)?;
+        node.wins = wins;
+        node.visits = visits;
+        let parent = self.try_resolve_mut(&parent)?;
+        parent.visits += visits;
+        parent.wins += wins;
+        parent.children.get_or_insert_with(Vec::new).push(child);
+        Ok(child)
+    }
+
+    /// Gives a new node the virtual visits [`Experience`] has for its
+    /// position, counting wins for `player` as the search does.
+    fn seed_from_experience(&mut self, id: NodeId, player: Player) -> Result<(), SearchError> {
+        let Some(experience) = &self.experience else {
+            return Ok(());
+        };
+        let node = self.try_resolve(&id)?;
+        let Some((visits, value)) = experience.prior(node.board.canonical_hash()) else {
+            return Ok(());
+        };
+        let value = if node.board.next_player == player {
+            1.0 - value
+        } else {
+            value
+        };
+        let node = self.try_resolve_mut(&id)?;
+        node.visits += visits;
+        node.wins += value * visits;
+        node.wins_squared += value * visits;
+        Ok(())
+    }
+
+    /// Gives a new child of the root its share of [`RootPriors::visits`],
+    /// at an even value since the priors say nothing about it.
+    fn seed_from_root_priors(&mut self, id: NodeId) -> Result<(), SearchError> {
+        let Some(root_priors) = self.root_priors else {
+            return Ok(());
+        };
+        let node = self.try_resolve(&id)?;
+        let Some(parent) = node.parent else {
+            return Ok(());
+        };
+        if self.try_resolve(&parent)?.parent.is_some() {
+            return Ok(());
+        }
+        let visits = root_priors.visits * node.prior;
+        let node = self.try_resolve_mut(&id)?;
+        node.visits += visits;
+        node.wins += 0.5 * visits;
+        node.wins_squared += 0.5 * visits;
+        Ok(())
+    }
+
+    /// Creates the child node, which is the first time its board exists.
+    pub(crate) fn push_child(
+        &mut self,
+        parent: NodeId,
+        candidate: PendingMove,
+    ) -> Result<NodeId, SearchError> {
+        let board = self
+            .try_resolve(&parent)?
+            .board
+            .unchecked_play(candidate.mve);
+        self.nodes.push(MCTSNode {
+            board,
+            wins: 0.0,
+            wins_squared: 0.0,
+            visits: 0.0,
+            plies: 0.0,
+            playouts: 0.0,
+            prior: candidate.prior,
+            parent: Some(parent),
+            children: None,
+            pending: Vec::new(),
+            solved: None,
+        });
+        let position = self.nodes.len() - 1;
+        let slot = match self.free_slots.pop() {
+            Some(slot) => {
+                self.slots[slot as usize].position = position;
+                slot
+            }
+            None => {
+                self.slots.push(Slot {
+                    position,
+                    generation: 0,
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+        self.slot_of.push(slot);
+        Ok(NodeId {
+            arena: self.id,
+            slot,
+            generation: self.slots[slot as usize].generation,
+        })
     }
 
-    fn simulate(&self, id: &NodeId) -> GameState {
-        let node = self.resolve(id);
+    /// Plays out `id`, counting the playout for the thread running it.
+    /// Returns the result and the plies played.
+    fn simulate<R: Rng>(
+        &self,
+        id: &NodeId,
+        rng: &mut R,
+        cancel: &CancellationToken,
+        played: &mut PlayoutMoves,
+    ) -> Result<(GameState, u64), SearchError> {
+        let start = Instant::now();
+        let mut plies = 0;
+        let result = self.playout(id, rng, cancel, played, &mut plies);
+        self.thread_load.record(plies, start.elapsed());
+        Ok((result?, plies))
+    }
+
+    fn playout<R: Rng>(
+        &self,
+        id: &NodeId,
+        rng: &mut R,
+        cancel: &CancellationToken,
+        played: &mut PlayoutMoves,
+        plies: &mut u64,
+    ) -> Result<GameState, SearchError> {
+        let node = self.try_resolve(id)?;
 
-        let mut board = node.board.clone();
+        let mut board = node.board;
+        if let Some(Tablebase(tablebase)) = &self.tablebase {
+            if !board.game_over() {
+                if let Some(result) = tablebase.probe(&board) {
+                    if !matches!(result, GameState::InProgress) {
+                        return Ok(result);
+                    }
+                }
+            }
+        }
 
         // TODO: Repeats check 2 times when game is over. Make it 1.
         while !board.game_over() {
+            if cancel.is_cancelled() {
+                return Err(SearchError::Cancelled);
+            }
+            if board.phase() == Phase::Endgame && open_cells(&board) <= self.config.endgame_cells {
+                return Ok(self.endgame.result(&board));
+            }
             let moves = board.get_moves();
             let num_moves = moves.count_ones();
+            if num_moves == 0 {
+                return Err(SearchError::NoMove);
+            }
 
-            let random_move_number = rand::thread_rng().gen_range(0..num_moves);
-            let move_index =
-                find_kth_high_bit_index(moves, random_move_number).expect("Precalculated");
+            let move_index = match &self.playout_weights {
+                Some(weights) => {
+                    let player = board.next_player;
+                    let index = weights
+                        .pick(player, moves, rng)
+                        .ok_or(SearchError::NoMove)?;
+                    match player {
+                        Player::X => played.x.push((moves, index)),
+                        Player::O => played.o.push((moves, index)),
+                    }
+                    index
+                }
+                None => {
+                    let random_move_number = rng.gen_range(0..num_moves);
+                    find_kth_high_bit_index(moves, random_move_number).ok_or(SearchError::NoMove)?
+                }
+            };
             board = board.unchecked_play(Board::move_from_index(move_index));
+            *plies += 1;
         }
 
-        board.check_game_state()
+        Ok(board.check_game_state())
     }
 
-    fn backpropagate(&mut self, simulation_results: &Vec<(NodeId, GameState)>, player: &Player) {
+    fn backpropagate(
+        &mut self,
+        simulation_results: &Vec<(NodeId, GameState)>,
+        player: &Player,
+    ) -> Result<(), SearchError> {
         for (id, result) in simulation_results {
-            match result {
-                GameState::InProgress => unreachable!(),
-                GameState::Won(winner) => {
-                    let mut node = self.resolve_mut(id);
-                    loop {
-                        node.visits += 1.0;
-                        if player == winner {
-                            node.wins += 1.0;
-                        }
-                        if let Some(parent) = node.parent {
-                            node = self.resolve_mut(&parent);
-                        } else {
-                            break;
-                        }
-                    }
+            let mut next = Some(*id);
+            while let Some(id) = next {
+                let node = self.try_resolve_mut(&id)?;
+                record_result(node, *result, *player)?;
+                next = node.parent;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the lengths of the games simulated from the nodes of `lengths`,
+    /// in stones at their end, to those nodes and the nodes above them, the
+    /// same ones their results were backpropagated through.
+    fn record_lengths(
+        &mut self,
+        lengths: &[(NodeId, f32)],
+        path: &[NodeId],
+    ) -> Result<(), SearchError> {
+        for &(id, plies) in lengths {
+            if self.merges() {
+                if path.last() != Some(&id) {
+                    record_length(self.try_resolve_mut(&id)?, plies);
                 }
-                GameState::Draw => {
-                    let mut node = self.resolve_mut(id);
-                    loop {
-                        node.wins += 1e-8;
-                        node.visits += 1.0;
-                        if let Some(parent) = node.parent {
-                            node = self.resolve_mut(&parent);
-                        } else {
-                            break;
-                        }
-                    }
+                for id in path {
+                    record_length(self.try_resolve_mut(id)?, plies);
                 }
+            } else {
+                let mut next = Some(id);
+                while let Some(id) = next {
+                    let node = self.try_resolve_mut(&id)?;
+                    record_length(node, plies);
+                    next = node.parent;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Like [`Self::backpropagate`], but through the nodes of `path`, the
+    /// selection's path ending with the parent of the simulated nodes (or
+    /// with the simulated node itself), rather than through parent links.
+    fn backpropagate_along(
+        &mut self,
+        simulation_results: &[(NodeId, GameState)],
+        player: &Player,
+        path: &[NodeId],
+    ) -> Result<(), SearchError> {
+        for &(id, result) in simulation_results {
+            if path.last() != Some(&id) {
+                record_result(self.try_resolve_mut(&id)?, result, *player)?;
+            }
+            for id in path {
+                record_result(self.try_resolve_mut(id)?, result, *player)?;
             }
         }
+        Ok(())
     }
 }
 
-fn find_kth_high_bit_index(n: u128, k: u32) -> Option<u8> {
-    let mut count = 0;
+/// Stones on `board`, i.e. plies played unless there was a handicap.
+fn stones(board: &Board) -> f32 {
+    (board.x | board.o).count_ones() as f32
+}
 
-    for i in 0..81 {
-        if n & (1 << i) != 0 {
-            if count == k {
-                return Some(i);
+/// Counts one playout ending in `result` at `node`, wins being for `player`.
+fn record_result(
+    node: &mut MCTSNode,
+    result: GameState,
+    player: Player,
+) -> Result<(), SearchError> {
+    match result {
+        GameState::InProgress => return Err(SearchError::Unfinished),
+        GameState::Won(winner) => {
+            if winner == player {
+                node.wins += 1.0;
+                node.wins_squared += 1.0;
+            }
+        }
+        GameState::Draw => node.wins += 1e-8,
+    }
+    node.visits += 1.0;
+    Ok(())
+}
+
+fn record_length(node: &mut MCTSNode, plies: f32) {
+    node.plies += plies;
+    node.playouts += 1.0;
+}
+
+#[cfg(test)]
+mod mcts_tests {
+    use std::collections::{HashMap, HashSet};
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::cancel::CancellationToken;
+    use crate::error::SearchError;
+    use crate::game::{find_kth_high_bit_index, Board, GameState, Player};
+    use crate::mcts::{
+        open_cells, record_result, solve, Backup, EarlyStop, MCTSArena, MCTSConfig, MCTSNode,
+        PlayoutAdaptation, PlayoutWeights, RootPruning, SearchLimits, SearchMode, SelectionPolicy,
+        Widening, MAX_PLAYOUT_WEIGHT,
+    };
+    use crate::test_support::compare_with_parallel;
+
+    fn search(board: Board, mode: SearchMode, n_iters: u32) -> MCTSArena {
+        let config = MCTSConfig {
+            mode,
+            ..Default::default()
+        };
+        let mut arena = MCTSArena::with_config(board, config);
+        let limits = SearchLimits::iterations(n_iters);
+        arena
+            .analyze(arena.root(), limits, &CancellationToken::new())
+            .unwrap();
+        arena
+    }
+
+    #[test]
+    fn test_deterministic_is_reproducible() {
+        let board = Board::default().unchecked_play(Board::move_from_gl(4, 4));
+        let mode = SearchMode::Deterministic { seed: 7 };
+        let a = search(board, mode, 50);
+        let b = search(board, mode, 50);
+
+        assert_eq!(a.nodes.len(), b.nodes.len());
+        for (x, y) in a.nodes.iter().zip(b.nodes.iter()) {
+            assert_eq!(x.board.last_move, y.board.last_move);
+            assert_eq!(x.visits, y.visits);
+            assert_eq!(x.wins, y.wins);
+        }
+    }
+
+    #[test]
+    fn test_max_nodes() {
+        let config = MCTSConfig {
+            max_nodes: Some(500),
+            ..Default::default()
+        };
+        let mut arena = MCTSArena::with_config(Board::default(), config);
+        let limits = SearchLimits::iterations(1000);
+        arena
+            .analyze(arena.root(), limits, &CancellationToken::new())
+            .unwrap();
+        assert!(arena.node_count() <= 500);
+        assert!(arena.node_count() > 500 - 81);
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let config = MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 2 },
+            max_nodes: Some(500),
+            collect_garbage: true,
+            ..Default::default()
+        };
+        let mut arena = MCTSArena::with_config(Board::default(), config);
+        let limits = SearchLimits::iterations(3000);
+        arena
+            .analyze(arena.root(), limits, &CancellationToken::new())
+            .unwrap();
+        // The search went on where it would have stopped.
+        assert_eq!(arena.iterations(), 3000);
+        assert!(arena.node_count() <= 500);
+        assert!(arena.info().collected_nodes > 0);
+
+        // The tree is still consistent and kept every visit.
+        let root = arena.try_resolve(&arena.root()).unwrap();
+        assert!(root.visits >= 3000.0);
+        for (i, node) in arena.nodes.iter().enumerate() {
+            for child in node.children.iter().flatten() {
+                let parent = arena.try_resolve(child).unwrap().parent.unwrap();
+                assert_eq!(arena.position(&parent), Some(i));
+            }
+        }
+        let children = root.children.as_ref().unwrap();
+        let child_visits: f32 = children
+            .iter()
+            .map(|c| arena.try_resolve(c).unwrap().visits)
+            .sum();
+        assert!(child_visits >= root.visits - 1.0);
+    }
+
+    #[test]
+    fn test_handles_survive_collection() {
+        let config = MCTSConfig {
+            max_nodes: Some(500),
+            collect_garbage: true,
+            ..Default::default()
+        };
+        let mut arena = MCTSArena::with_config(Board::default(), config);
+        let cancel = CancellationToken::new();
+        arena
+            .analyze(arena.root(), SearchLimits::iterations(30), &cancel)
+            .unwrap();
+        assert_eq!(arena.info().collected_nodes, 0);
+        let mut handles = vec![];
+        let mut level = vec![arena.root()];
+        while let Some(id) = level.pop() {
+            let node = arena.try_resolve(&id).unwrap();
+            handles.push((id, node.board));
+            level.extend(node.children.iter().flatten());
+        }
+
+        arena
+            .analyze(arena.root(), SearchLimits::iterations(3000), &cancel)
+            .unwrap();
+        assert!(arena.info().collected_nodes > 0);
+        let (live, stale): (Vec<_>, Vec<_>) = handles
+            .into_iter()
+            .partition(|(id, _)| arena.try_resolve(id).is_ok());
+        assert!(!stale.is_empty());
+        for (id, board) in live {
+            assert_eq!(arena.try_resolve(&id).unwrap().board, board);
+        }
+        for (id, _) in stale {
+            assert_eq!(arena.try_resolve(&id).err(), Some(SearchError::StaleNode));
+        }
+    }
+
+    #[test]
+    fn test_unfinished_playout() {
+        let mut node = MCTSNode::default();
+        assert_eq!(
+            record_result(&mut node, GameState::InProgress, Player::X),
+            Err(SearchError::Unfinished)
+        );
+        assert_eq!(node.visits, 0.0);
+        record_result(&mut node, GameState::Won(Player::X), Player::X).unwrap();
+        assert_eq!((node.wins, node.visits), (1.0, 1.0));
+    }
+
+    #[test]
+    fn test_fixed_capacity() {
+        let config = MCTSConfig {
+            max_nodes: Some(500),
+            fixed_capacity: true,
+            ..Default::default()
+        };
+        let mut arena = MCTSArena::with_config(Board::default(), config);
+        let capacity = arena.nodes.capacity();
+        assert!(capacity >= 500);
+        let cancel = CancellationToken::new();
+        assert_eq!(
+            arena.analyze(arena.root(), SearchLimits::iterations(1000), &cancel),
+            Err(SearchError::OutOfNodes)
+        );
+        assert_eq!(arena.nodes.capacity(), capacity);
+        assert!(arena.node_count() <= 500);
+
+        let mut arena = MCTSArena::with_config(Board::default(), config);
+        assert!(arena
+            .analyze(arena.root(), SearchLimits::iterations(5), &cancel)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_symmetric_root_moves_pruned() {
+        let arena = search(Board::default(), SearchMode::Deterministic { seed: 0 }, 1);
+        let root = arena.try_resolve(&arena.root()).unwrap();
+        assert_eq!(root.children.as_ref().unwrap().len(), 15);
+
+        let board = Board::default().unchecked_play(Board::move_from_gl(4, 1));
+        let arena = search(board, SearchMode::Deterministic { seed: 0 }, 1);
+        let root = arena.try_resolve(&arena.root()).unwrap();
+        assert_eq!(root.children.as_ref().unwrap().len(), 6);
+    }
+
+    #[test]
+    fn test_best_move_changes() {
+        let arena = search(Board::default(), SearchMode::Deterministic { seed: 3 }, 300);
+        let info = arena.info();
+        assert_eq!(info.iterations, 300);
+        let changes = &info.best_move_changes;
+        assert_eq!(changes[0].old, None);
+        for pair in changes.windows(2) {
+            assert_eq!(pair[1].old, Some(pair[0].new));
+            assert!(pair[0].iteration < pair[1].iteration);
+        }
+        let best = arena.select_best_child(arena.root()).unwrap().unwrap();
+        assert_eq!(
+            Some(changes.last().unwrap().new),
+            arena.try_resolve(&best).unwrap().board.last_move
+        );
+        assert_eq!(info.late_changes(0.0), changes.len() - 1);
+        assert_eq!(info.late_changes(1.0), 0);
+    }
+
+    #[test]
+    fn test_endgame_cache() {
+        // Play random games until few cells are left open.
+        let mut rng = StdRng::seed_from_u64(2);
+        let board = loop {
+            let mut board = Board::default();
+            while !board.game_over() && open_cells(&board) > 8 {
+                let moves = board.get_moves();
+                let k = rng.gen_range(0..moves.count_ones());
+                let index = find_kth_high_bit_index(moves, k).unwrap();
+                board = board.unchecked_play(Board::move_from_index(index));
+            }
+            if !board.game_over() {
+                break board;
+            }
+        };
+
+        // The solved result is the best result of the children.
+        let mut memo = HashMap::new();
+        let result = solve(&mut { board }, &mut memo);
+        let moves = board.get_moves();
+        let children: Vec<_> = (0..81)
+            .filter(|i| moves & (1 << i) != 0)
+            .map(|i| {
+                solve(
+                    &mut board.unchecked_play(Board::move_from_index(i)),
+                    &mut memo,
+                )
+            })
+            .collect();
+        assert!(children.contains(&result));
+        if result != GameState::Won(board.next_player) {
+            assert!(!children.contains(&GameState::Won(board.next_player)));
+        }
+
+        let config = MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 1 },
+            endgame_cells: 8,
+            ..Default::default()
+        };
+        let mut arena = MCTSArena::with_config(board, config);
+        let limits = SearchLimits::iterations(20);
+        arena
+            .analyze(arena.root(), limits, &CancellationToken::new())
+            .unwrap();
+        assert!(arena.info().endgame_positions > 0);
+    }
+
+    #[test]
+    fn test_solver() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut solved_roots = 0;
+        for _ in 0..20 {
+            let mut board = Board::default();
+            while !board.game_over() && open_cells(&board) > 14 {
+                let moves = board.get_moves();
+                let k = rng.gen_range(0..moves.count_ones());
+                let index = find_kth_high_bit_index(moves, k).unwrap();
+                board = board.unchecked_play(Board::move_from_index(index));
+            }
+            if board.game_over() {
+                continue;
+            }
+            let config = MCTSConfig {
+                mode: SearchMode::Deterministic { seed: 1 },
+                endgame_cells: 8,
+                solver: true,
+                ..Default::default()
+            };
+            let mut arena = MCTSArena::with_config(board, config);
+            let limits = SearchLimits::iterations(300);
+            arena
+                .analyze(arena.root(), limits, &CancellationToken::new())
+                .unwrap();
+
+            // Every proof agrees with exhaustive search.
+            let mut memo = HashMap::new();
+            for node in &arena.nodes {
+                if let Some(result) = node.solved {
+                    assert_eq!(result, solve(&mut { node.board }, &mut memo));
+                }
+            }
+            let root = arena.try_resolve(&arena.root()).unwrap();
+            if root.solved.is_some() {
+                solved_roots += 1;
+            }
+            // A proven win is played.
+            if root.solved == Some(GameState::Won(board.next_player)) {
+                let best = arena.select_best_child(arena.root()).unwrap().unwrap();
+                assert_eq!(arena.try_resolve(&best).unwrap().solved, root.solved);
+            }
+        }
+        assert!(solved_roots > 0);
+    }
+
+    #[test]
+    fn test_tree_stats() {
+        let mut arena = search(Board::default(), SearchMode::Deterministic { seed: 1 }, 20);
+        let stats = arena.stats(arena.root());
+        assert_eq!(stats.nodes, arena.node_count());
+        assert_eq!(stats.depth_histogram[0], 1);
+        // The empty board has 15 moves up to symmetry.
+        assert_eq!(stats.depth_histogram[1], 15);
+        assert!(stats.branching_factor > 1.0);
+        assert_eq!(stats.terminal_fraction, 0.0);
+
+        let leaf = arena.select_best_child(arena.root()).unwrap().unwrap();
+        let stats = arena.stats(leaf);
+        assert!(stats.nodes < arena.node_count());
+        arena = MCTSArena::with_config(Board::default(), MCTSConfig::default());
+        assert_eq!(arena.stats(arena.root()).depth_histogram, vec![1]);
+        assert_eq!(arena.stats(arena.root()).duplicates, 0);
+
+        // Two move orders reaching the same position.
+        for line in [[0x40, 0x04, 0x41, 0x14], [0x41, 0x14, 0x40, 0x04]] {
+            let mut id = arena.root();
+            for m in line {
+                id = arena.add_searched_child(id, m, 0.0, 1.0).unwrap();
+            }
+        }
+        let stats = arena.stats(arena.root());
+        assert_eq!(stats.nodes, 9);
+        assert_eq!(stats.duplicates, 1);
+    }
+
+    #[test]
+    fn test_trace_replay() {
+        let config = MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 4 },
+            trace: true,
+            ..Default::default()
+        };
+        let mut arena = MCTSArena::with_config(Board::default(), config);
+        arena
+            .analyze(
+                arena.root(),
+                SearchLimits::iterations(25),
+                &CancellationToken::new(),
+            )
+            .unwrap();
+        let trace = arena.take_trace().unwrap();
+        assert_eq!(trace.steps.len(), 25);
+        assert!(arena.take_trace().unwrap().steps.is_empty());
+
+        let mut replay = trace.replay();
+        assert_eq!(replay.stats().nodes, 1);
+        while replay.step().unwrap().is_some() {}
+        assert_eq!(replay.position(), 25);
+        assert_eq!(replay.stats(), arena.stats(arena.root()));
+        let root = arena.try_resolve(&arena.root()).unwrap();
+        assert_eq!(replay.node(replay.root()).unwrap().visits, root.visits);
+        assert_eq!(replay.node(replay.root()).unwrap().wins, root.wins);
+        let last = trace.steps.last().unwrap().selected;
+        assert_eq!(
+            replay.node(last).unwrap().visits,
+            arena.try_resolve(&last).unwrap().visits
+        );
+    }
+
+    #[test]
+    fn test_progressive_widening() {
+        // Play anywhere, so widening keeps most of the 81 moves pending.
+        let board = Board::default();
+        let full = MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 0 },
+            symmetry_plies: 0,
+            ..Default::default()
+        };
+        let widening = MCTSConfig {
+            widening: Some(Widening::default()),
+            ..full
+        };
+        let limits = SearchLimits::iterations(30);
+        let cancel = CancellationToken::new();
+        let mut full = MCTSArena::with_config(board, full);
+        full.analyze(full.root(), limits, &cancel).unwrap();
+        let mut arena = MCTSArena::with_config(board, widening);
+        arena.analyze(arena.root(), limits, &cancel).unwrap();
+
+        let root = arena.try_resolve(&arena.root()).unwrap();
+        let children = root.children.as_ref().unwrap().len();
+        assert!(children < 81);
+        assert_eq!(children + root.pending.len(), 81);
+        // Children were created best prior first.
+        let worst_child = root
+            .children
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|child| arena.try_resolve(child).unwrap().prior)
+            .fold(f32::INFINITY, f32::min);
+        assert!(root.pending.iter().all(|p| p.prior <= worst_child));
+        assert!(arena.node_count() * 2 < full.node_count());
+    }
+
+    #[test]
+    fn test_selection_policies() {
+        let board = Board::default()
+            .unchecked_play(Board::move_from_gl(4, 4))
+            .unchecked_play(Board::move_from_gl(4, 0));
+        for selection in [
+            SelectionPolicy::default(),
+            SelectionPolicy::Ucb1Tuned,
+            SelectionPolicy::Puct { c: 1.5 },
+            SelectionPolicy::Thompson,
+        ] {
+            let config = MCTSConfig {
+                mode: SearchMode::Deterministic { seed: 0 },
+                selection,
+                ..Default::default()
+            };
+            let mut arena = MCTSArena::with_config(board, config);
+            let limits = SearchLimits::iterations(40);
+            arena
+                .analyze(arena.root(), limits, &CancellationToken::new())
+                .unwrap();
+            let root = arena.try_resolve(&arena.root()).unwrap();
+            assert!(root.visits > 40.0, "{selection:?}");
+            assert!((0.0..=0.25).contains(&root.variance()), "{selection:?}");
+        }
+    }
+
+    #[test]
+    fn test_playout_adaptation() {
+        let mut weights = PlayoutWeights::new();
+        // X chose cell 0 out of 0, 1, 2 and 3.
+        weights.adapt(Player::X, &[(0b1111, 0)], 0.4);
+        let x = weights.of(Player::X);
+        assert!((x[0] - 0.3).abs() < 1e-6);
+        assert!(x[1..4].iter().all(|w| (w + 0.1).abs() < 1e-6));
+        assert!(weights.of(Player::O).iter().all(|&w| w == 0.0));
+
+        weights.0[5] = 20.0;
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!((0..100).all(|_| weights.pick(Player::X, 0b10_0011, &mut rng) == Some(5)));
+        assert_eq!(weights.pick(Player::O, 1 << 80, &mut rng), Some(80));
+        assert_eq!(weights.pick(Player::O, 0, &mut rng), None);
+
+        // Runaway weights stay bounded, and broken ones fall back to an
+        // even draw.
+        let mut runaway = PlayoutWeights::new();
+        runaway.adapt(Player::O, &[(0b111, 1)], 1e30);
+        assert!(runaway
+            .of(Player::O)
+            .iter()
+            .all(|w| w.abs() <= MAX_PLAYOUT_WEIGHT));
+        assert!(runaway.pick(Player::O, 0b111, &mut rng).is_some());
+        runaway.0[81..].fill(f32::NAN);
+        runaway.0[..81].fill(f32::NEG_INFINITY);
+        for player in [Player::X, Player::O] {
+            let picked = runaway.pick(player, 0b1010, &mut rng).unwrap();
+            assert!(picked == 1 || picked == 3);
+        }
+
+        let config = MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 6 },
+            playout_adaptation: Some(PlayoutAdaptation::default()),
+            ..Default::default()
+        };
+        let mut arena = MCTSArena::with_config(Board::default(), config);
+        arena
+            .analyze(
+                arena.root(),
+                SearchLimits::iterations(300),
+                &CancellationToken::new(),
+            )
+            .unwrap();
+        let learned = arena.playout_weights.as_ref().unwrap();
+        assert!(learned.0.iter().any(|&w| w != 0.0));
+        // Every adaptation moves weight around without creating any.
+        assert!(learned.0.iter().sum::<f32>().abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_backup() {
+        let children = [(0.2, 10.0), (0.8, 30.0), (1.0, 0.0)];
+        assert_eq!(Backup::Average.value(&children), None);
+        assert_eq!(Backup::Max.value(&children), Some(0.8));
+        let mean = Backup::PowerMean { p: 1.0 }.value(&children).unwrap();
+        assert!((mean - 0.65).abs() < 1e-6);
+        let sharp = Backup::PowerMean { p: 8.0 }.value(&children).unwrap();
+        assert!(sharp > mean && sharp < 0.8);
+        assert_eq!(Backup::Max.value(&[]), None);
+
+        for backup in [Backup::Max, Backup::PowerMean { p: 2.0 }] {
+            let config = MCTSConfig {
+                mode: SearchMode::Deterministic { seed: 2 },
+                backup,
+                ..Default::default()
+            };
+            let board = Board::default().unchecked_play(0x44);
+            let mut arena = MCTSArena::with_config(board, config);
+            arena
+                .analyze(
+                    arena.root(),
+                    SearchLimits::iterations(300),
+                    &CancellationToken::new(),
+                )
+                .unwrap();
+            // The root takes its value from its children.
+            let root = arena.try_resolve(&arena.root()).unwrap();
+            let values: Vec<f32> = root
+                .children
+                .iter()
+                .flatten()
+                .map(|child| arena.try_resolve(child).unwrap())
+                .filter(|child| child.visits > 0.0)
+                .map(|child| child.wins / child.visits)
+                .collect();
+            let value = root.wins / root.visits;
+            let best = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            assert!(value <= best + 1e-4, "{backup:?}");
+            if backup == Backup::Max {
+                assert!((value - best).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_transpositions() {
+        let board = Board::default().unchecked_play(0x44);
+        let search = |merge_transpositions| {
+            let config = MCTSConfig {
+                mode: SearchMode::Deterministic { seed: 4 },
+                merge_transpositions,
+                ..Default::default()
+            };
+            let mut arena = MCTSArena::with_config(board, config);
+            arena
+                .analyze(
+                    arena.root(),
+                    SearchLimits::iterations(2000),
+                    &CancellationToken::new(),
+                )
+                .unwrap();
+            arena
+        };
+        let tree = search(false);
+        assert!(tree.stats(tree.root()).duplicates > 0);
+
+        let arena = search(true);
+        let edges: usize = arena
+            .nodes
+            .iter()
+            .map(|node| node.children.as_ref().map_or(0, Vec::len))
+            .sum();
+        // Some nodes have several parents.
+        assert!(edges > arena.node_count() - 1);
+        let positions: HashSet<u64> = arena
+            .nodes
+            .iter()
+            .map(|node| node.board.zobrist_hash())
+            .collect();
+        assert_eq!(positions.len(), arena.node_count());
+        let stats = arena.stats(arena.root());
+        assert_eq!(stats.duplicates, 0);
+        assert_eq!(stats.nodes, arena.node_count());
+    }
+
+    #[test]
+    fn test_thread_stats() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let arena = pool.install(|| search(Board::default(), SearchMode::Parallel, 300));
+        let threads = arena.info().threads;
+        assert!(!threads.is_empty());
+        assert!(threads.iter().all(|t| t.thread.is_none_or(|i| i < 2)));
+        let playouts: u64 = threads.iter().map(|t| t.playouts).sum();
+        // Every expansion plays out all the new children.
+        assert!(playouts >= 300);
+        assert!(threads.iter().all(|t| t.busy > 0.0 && t.idle >= 0.0));
+        assert!(threads.iter().any(|t| t.mean_playout_length() > 10.0));
+
+        let arena = search(Board::default(), SearchMode::Deterministic { seed: 0 }, 100);
+        let threads = arena.info().threads;
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].thread, None);
+    }
+
+    #[test]
+    fn test_early_stop() {
+        // The position before the winning move of a random game.
+        let mut rng = StdRng::seed_from_u64(3);
+        let board = loop {
+            let mut board = Board::default();
+            let mut before = board;
+            while !board.game_over() {
+                let moves = board.get_moves();
+                let k = rng.gen_range(0..moves.count_ones());
+                before = board;
+                let index = find_kth_high_bit_index(moves, k).unwrap();
+                board = board.unchecked_play(Board::move_from_index(index));
+            }
+            if matches!(board.check_game_state(), GameState::Won(_)) {
+                break before;
             }
-            count += 1;
+        };
+        let config = MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 1 },
+            early_stop: Some(EarlyStop::default()),
+            ..Default::default()
+        };
+        let mut arena = MCTSArena::with_config(board, config);
+        let limits = SearchLimits::iterations(20_000);
+        let (_, best) = arena
+            .analyze(arena.root(), limits, &CancellationToken::new())
+            .unwrap();
+        let info = arena.info();
+        assert!(info.stopped_early);
+        assert!(info.iterations < 20_000, "{}", info.iterations);
+        let after = arena.try_resolve(&best).unwrap().board;
+        assert_eq!(after.check_game_state(), GameState::Won(board.next_player));
+
+        // Nothing settles the opening that fast.
+        let mut arena = MCTSArena::with_config(Board::default(), config);
+        let limits = SearchLimits::iterations(200);
+        arena
+            .analyze(arena.root(), limits, &CancellationToken::new())
+            .unwrap();
+        assert!(!arena.info().stopped_early);
+        assert_eq!(arena.iterations(), 200);
+    }
+
+    #[test]
+    fn test_root_pruning() {
+        let board = Board::default()
+            .unchecked_play(Board::move_from_gl(4, 4))
+            .unchecked_play(Board::move_from_gl(4, 0));
+        let search = |root_pruning| {
+            let config = MCTSConfig {
+                mode: SearchMode::Deterministic { seed: 3 },
+                root_pruning,
+                ..Default::default()
+            };
+            let mut arena = MCTSArena::with_config(board, config);
+            let limits = SearchLimits::iterations(2000);
+            arena
+                .analyze(arena.root(), limits, &CancellationToken::new())
+                .unwrap();
+            arena
+        };
+        let visits = |arena: &MCTSArena, mve| {
+            let root = arena.try_resolve(&arena.root()).unwrap();
+            let children = root.children.iter().flatten();
+            let child = children
+                .map(|child| arena.try_resolve(child).unwrap())
+                .find(|child| child.board.last_move == Some(mve));
+            child.unwrap().visits
+        };
+
+        let plain = search(None);
+        assert!(plain.info().pruned_root_moves.is_empty());
+        let pruned = search(Some(RootPruning { rounds: 3, z: 1.0 }));
+        let moves = pruned.info().pruned_root_moves;
+        assert!(!moves.is_empty());
+        let best = pruned.select_best_child(pruned.root()).unwrap().unwrap();
+        let best = pruned.try_resolve(&best).unwrap().board.last_move.unwrap();
+        assert!(!moves.contains(&best));
+        // Pruned moves stop gaining visits, the leader gets them instead.
+        for &mve in &moves {
+            assert!(visits(&pruned, mve) <= visits(&plain, mve));
         }
+        let pruned_total: f32 = moves.iter().map(|&mve| visits(&pruned, mve)).sum();
+        let plain_total: f32 = moves.iter().map(|&mve| visits(&plain, mve)).sum();
+        assert!(pruned_total < plain_total);
     }
 
-    None
+    #[test]
+    fn test_bayesian_posterior() {
+        let config = MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 5 },
+            ..MCTSConfig::bayesian()
+        };
+        let mut arena = MCTSArena::with_config(Board::default(), config);
+        let root = arena.root();
+        assert_eq!(arena.try_resolve(&root).unwrap().posterior(), (1.0, 1.0));
+        assert_eq!(arena.try_resolve(&root).unwrap().posterior_mean(), 0.5);
+
+        arena
+            .analyze(
+                root,
+                SearchLimits::iterations(50),
+                &CancellationToken::new(),
+            )
+            .unwrap();
+        let node = arena.try_resolve(&root).unwrap();
+        let (alpha, beta) = node.posterior();
+        assert!((alpha + beta - node.visits - 2.0).abs() < 1e-3);
+        assert!((0.0..=1.0).contains(&node.posterior_mean()));
+    }
+
+    #[test]
+    fn test_deterministic_matches_parallel() {
+        let board = Board::default()
+            .unchecked_play(Board::move_from_gl(4, 4))
+            .unchecked_play(Board::move_from_gl(4, 0));
+        let distance = compare_with_parallel(board, MCTSConfig::default(), 60, 8).unwrap();
+        assert!(distance < 0.25, "distributions diverge: {distance}");
+    }
 }
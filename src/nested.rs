@@ -0,0 +1,206 @@
+//! Nested Monte Carlo Search, for "find the win" questions about a
+//! position rather than picking a move against an opponent.
+//!
+//! NMCS treats the game as a puzzle for the side to move and chooses the
+//! moves of both sides: a level `n` search tries every move, rates each by a
+//! level `n - 1` search from the resulting position and follows the best
+//! line found so far, with level 0 being a random playout. The result is a
+//! winning line where one exists within reach, shortest wins first, but the
+//! opponent's moves in it are the ones that help most. Whether the win is
+//! forced is for a search with [`crate::MCTSConfig::solver`] to prove.
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::error::SearchError;
+use crate::game::{find_kth_high_bit_index, Board, GameState, Player};
+
+/// Most plies a game can last, which bounds the length of a line.
+const MAX_PLIES: f32 = 81.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct NestedSearch {
+    /// Nesting level. Every level multiplies the work by about the number
+    /// of moves per position times the length of a line, so 2 is plenty
+    /// for positions with a few dozen plies left.
+    pub level: u32,
+    pub seed: u64,
+}
+
+impl Default for NestedSearch {
+    fn default() -> Self {
+        Self { level: 2, seed: 0 }
+    }
+}
+
+/// Best line a [`NestedSearch`] found.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NestedResult {
+    /// Above 1 for a win of the side to move, the shorter the higher; 0.5
+    /// for a draw and below for a loss.
+    pub score: f32,
+    /// Moves of both sides to the end of the game, as `(global, local)`.
+    pub line: Vec<(u8, u8)>,
+    /// Random playouts run.
+    pub playouts: u64,
+}
+
+impl NestedResult {
+    pub fn is_win(&self) -> bool {
+        self.score > 1.0
+    }
+}
+
+impl NestedSearch {
+    /// Searches `board` for the best line of its side to move.
+    pub fn run(&self, board: &Board) -> Result<NestedResult, SearchError> {
+        if board.game_over() {
+            return Err(SearchError::GameOver);
+        }
+        let mut driver = Driver {
+            player: board.next_player,
+            rng: StdRng::seed_from_u64(self.seed),
+            playouts: 0,
+        };
+        let (score, line) = driver.nested(*board, 0, self.level)?;
+        Ok(NestedResult {
+            score,
+            line: line.into_iter().map(|m| (m >> 4, m & 0b1111)).collect(),
+            playouts: driver.playouts,
+        })
+    }
+}
+
+struct Driver {
+    player: Player,
+    rng: StdRng,
+    playouts: u64,
+}
+
+impl Driver {
+    /// Score of a finished game `plies` plies after the search's position.
+    fn score(&self, board: &Board, plies: usize) -> f32 {
+        let length = plies as f32 / MAX_PLIES;
+        match board.check_game_state() {
+            GameState::Won(winner) if winner == self.player => 2.0 - length,
+            GameState::Draw | GameState::InProgress => 0.5,
+            // Losing later leaves the opponent more chances to go wrong.
+            GameState::Won(_) => 0.25 * length,
+        }
+    }
+
+    fn playout(&mut self, mut board: Board, plies: usize) -> Result<(f32, Vec<u8>), SearchError> {
+        self.playouts += 1;
+        let mut line = vec![];
+        while !board.game_over() {
+            let moves = board.get_moves();
+            let k = self.rng.gen_range(0..moves.count_ones());
+            let index = find_kth_high_bit_index(moves, k).ok_or(SearchError::NoMove)?;
+            let m = Board::move_from_index(index);
+            board = board.unchecked_play(m);
+            line.push(m);
+        }
+        Ok((self.score(&board, plies + line.len()), line))
+    }
+
+    /// Best line from `board`, which is `plies` plies into the search.
+    fn nested(
+        &mut self,
+        mut board: Board,
+        plies: usize,
+        level: u32,
+    ) -> Result<(f32, Vec<u8>), SearchError> {
+        if level == 0 {
+            return self.playout(board, plies);
+        }
+        let mut best = (f32::NEG_INFINITY, vec![]);
+        let mut played = vec![];
+        while !board.game_over() {
+            let moves = board.get_moves();
+            for m in (0..81)
+                .filter(|i| moves & (1 << i) != 0)
+                .map(Board::move_from_index)
+            {
+                let child = board.unchecked_play(m);
+                let depth = plies + played.len() + 1;
+                let (score, rest) = if child.game_over() {
+                    (self.score(&child, depth), vec![])
+                } else {
+                    self.nested(child, depth, level - 1)?
+                };
+                if score > best.0 {
+                    let mut line = played.clone();
+                    line.push(m);
+                    line.extend(rest);
+                    best = (score, line);
+                }
+            }
+            // Follow the best line, which may have been found earlier.
+            let m = best.1[played.len()];
+            board = board.unchecked_play(m);
+            played.push(m);
+        }
+        Ok(best)
+    }
+}
+
+#[cfg(test)]
+mod nested_tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::game::{Board, GameState};
+    use crate::nested::NestedSearch;
+    use crate::SearchError;
+
+    #[test]
+    fn test_finds_win() {
+        // The position before the winning move of a random game.
+        let mut rng = StdRng::seed_from_u64(3);
+        let (board, winner) = loop {
+            let mut board = Board::default();
+            let mut before = board;
+            while !board.game_over() {
+                let moves = board.get_moves();
+                let index = (0..81)
+                    .filter(|i| moves & (1 << i) != 0)
+                    .nth(rng.gen_range(0..moves.count_ones()) as usize);
+                before = board;
+                board = board.unchecked_play(Board::move_from_index(index.unwrap()));
+            }
+            if let GameState::Won(winner) = board.check_game_state() {
+                break (before, winner);
+            }
+        };
+        assert_eq!(board.next_player, winner);
+
+        let search = NestedSearch {
+            level: 1,
+            ..Default::default()
+        };
+        let result = search.run(&board).unwrap();
+        assert!(result.is_win());
+        // Winning at once is the shortest win.
+        assert_eq!(result.line.len(), 1);
+        let (global, local) = result.line[0];
+        let after = board.unchecked_play(Board::move_from_gl(global, local));
+        assert_eq!(after.check_game_state(), GameState::Won(winner));
+
+        assert_eq!(search.run(&after), Err(SearchError::GameOver));
+    }
+
+    #[test]
+    fn test_line_is_legal() {
+        let board = [0x44, 0x40, 0x04]
+            .into_iter()
+            .fold(Board::default(), |board, m| board.unchecked_play(m));
+        let result = NestedSearch { level: 1, seed: 5 }.run(&board).unwrap();
+        let mut end = board;
+        for &(global, local) in &result.line {
+            assert!(end.get_moves() & (1 << (global * 9 + local)) != 0);
+            end = end.unchecked_play(Board::move_from_gl(global, local));
+        }
+        assert!(end.game_over());
+        assert!(result.playouts > 0);
+        // Level 1 finds a win for the side to move this early.
+        assert!(result.is_win());
+    }
+}
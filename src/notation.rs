@@ -0,0 +1,238 @@
+//! Move coordinates and text forms of positions used by other tools.
+//!
+//! A cell can be named three ways: `(global, local)`, the sub-board and
+//! the cell within it, both in reading order; `(row, col)` on the 9x9 grid;
+//! and the index `global * 9 + local` of the 81-bit cell sets. [`Move`]
+//! converts between them.
+//!
+//! The grid string has one character per cell of the 9x9 grid in reading
+//! order (`X`, `O` or `.`), a space, the side to move (`x` or `o`) and the
+//! sub-board it is sent to (`0` to `8`, or `-` when it may play anywhere):
+//!
+//! ```text
+//! ....X....(72 more cells) o4
+//! ```
+
+use crate::game::{Board, Player};
+use crate::StoctopusError;
+
+/// A cell of the board, see the module documentation for its coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Move {
+    global: u8,
+    local: u8,
+}
+
+impl Move {
+    /// `None` unless both are below 9.
+    pub fn new(global: u8, local: u8) -> Option<Self> {
+        (global < 9 && local < 9).then_some(Self { global, local })
+    }
+
+    pub fn from_row_col(row: u8, col: u8) -> Option<Self> {
+        if row >= 9 || col >= 9 {
+            return None;
+        }
+        Self::new(row / 3 * 3 + col / 3, row % 3 * 3 + col % 3)
+    }
+
+    /// From an index into the 81-bit cell sets.
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::new(index / 9, index % 9)
+    }
+
+    /// From the `(global << 4) | local` byte used by [`Board::last_move`].
+    pub fn from_packed(packed: u8) -> Option<Self> {
+        Self::new(packed >> 4, packed & 0b1111)
+    }
+
+    pub fn global(self) -> u8 {
+        self.global
+    }
+
+    pub fn local(self) -> u8 {
+        self.local
+    }
+
+    pub fn row_col(self) -> (u8, u8) {
+        (
+            self.global / 3 * 3 + self.local / 3,
+            self.global % 3 * 3 + self.local % 3,
+        )
+    }
+
+    pub fn index(self) -> u8 {
+        self.global * 9 + self.local
+    }
+
+    pub fn packed(self) -> u8 {
+        Board::move_from_gl(self.global, self.local)
+    }
+}
+
+impl From<Move> for (u8, u8) {
+    fn from(mve: Move) -> Self {
+        (mve.global, mve.local)
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> StoctopusError {
+    StoctopusError::Protocol(format!("Bad grid string: {}", msg.into()))
+}
+
+impl Board {
+    pub fn to_grid_string(&self) -> String {
+        let mut grid = String::with_capacity(84);
+        for row in 0..9 {
+            for col in 0..9 {
+                let cell = 1u128 << Move::from_row_col(row, col).expect("On the grid").index();
+                grid.push(if self.x & cell != 0 {
+                    'X'
+                } else if self.o & cell != 0 {
+                    'O'
+                } else {
+                    '.'
+                });
+            }
+        }
+        grid.push(' ');
+        grid.push(match self.next_player {
+            Player::X => 'x',
+            Player::O => 'o',
+        });
+        grid.push(match self.forced_board() {
+            Some(global) => (b'0' + global) as char,
+            None => '-',
+        });
+        grid
+    }
+
+    /// Parses a grid string, checking that the position can arise from
+    /// legal play. The format doesn't say which move was last, so any mark
+    /// of the previous player that sends the side to move to the right
+    /// sub-board stands in for it.
+    pub fn from_grid_string(text: &str) -> Result<Self, StoctopusError> {
+        let (cells, suffix) = text
+            .trim()
+            .split_once(' ')
+            .ok_or_else(|| invalid("missing side to move"))?;
+        let cells: Vec<char> = cells.chars().collect();
+        if cells.len() != 81 {
+            return Err(invalid(format!("{} cells instead of 81", cells.len())));
+        }
+        let mut board = Board::default();
+        for (i, &c) in cells.iter().enumerate() {
+            let mve = Move::from_row_col(i as u8 / 9, i as u8 % 9).expect("On the grid");
+            let cell = 1u128 << mve.index();
+            match c {
+                'X' | 'x' => board.x |= cell,
+                'O' | 'o' => board.o |= cell,
+                '.' | '-' => {}
+                _ => return Err(invalid(format!("unknown cell {c:?}"))),
+            }
+        }
+        let (next_player, forced) = match suffix.as_bytes() {
+            [side, forced] => (*side, *forced),
+            _ => return Err(invalid(format!("bad suffix {suffix:?}"))),
+        };
+        board.next_player = match next_player {
+            b'x' => Player::X,
+            b'o' => Player::O,
+            _ => return Err(invalid("side to move must be x or o")),
+        };
+        let forced = match forced {
+            b'0'..=b'8' => Some(forced - b'0'),
+            b'-' => None,
+            _ => return Err(invalid("forced board must be 0 to 8 or -")),
+        };
+        board.recompute_macro();
+
+        if board.x | board.o != 0 {
+            let marks = match board.next_player.other() {
+                Player::X => board.x,
+                Player::O => board.o,
+            };
+            let last_move = (0..81u8)
+                .filter(|&i| marks & (1 << i) != 0)
+                .map(Board::move_from_index)
+                .find(|&m| {
+                    let sent_to_decided = (board.gx | board.go) & (1 << (m & 0b1111)) != 0;
+                    match forced {
+                        Some(global) => m & 0b1111 == global && !sent_to_decided,
+                        None => sent_to_decided,
+                    }
+                });
+            board.last_move = Some(last_move.ok_or_else(|| invalid("no possible last move"))?);
+        } else if forced.is_some() {
+            return Err(invalid("forced board before the first move"));
+        }
+        board.validate()?;
+        Ok(board)
+    }
+}
+
+#[cfg(test)]
+mod notation_tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::game::Board;
+    use crate::notation::Move;
+
+    #[test]
+    fn test_move_round_trips() {
+        for index in 0..81 {
+            let mve = Move::from_index(index).unwrap();
+            assert_eq!(mve.index(), index);
+            let (row, col) = mve.row_col();
+            assert_eq!(Move::from_row_col(row, col), Some(mve));
+            assert_eq!(Move::new(mve.global(), mve.local()), Some(mve));
+            assert_eq!(Move::from_packed(mve.packed()), Some(mve));
+            assert_eq!(Board::move_from_index(index), mve.packed());
+        }
+        // The center sub-board's top-left cell.
+        let mve = Move::new(4, 0).unwrap();
+        assert_eq!(mve.row_col(), (3, 3));
+        assert_eq!(mve.index(), 36);
+        assert_eq!(<(u8, u8)>::from(mve), (4, 0));
+        assert_eq!(Move::from_index(81), None);
+        assert_eq!(Move::from_row_col(9, 0), None);
+        assert_eq!(Move::from_packed(0x49), None);
+    }
+
+    #[test]
+    fn test_grid_string_round_trip() {
+        let start = Board::default().to_grid_string();
+        assert_eq!(start, format!("{} x-", ".".repeat(81)));
+        assert_eq!(Board::from_grid_string(&start).unwrap(), Board::default());
+
+        let board = Board::default().unchecked_play(Board::move_from_gl(1, 3));
+        let grid = board.to_grid_string();
+        // Sub-board 1, cell 3 is row 1, column 3 of the grid.
+        assert_eq!(&grid[9..13], "...X");
+        assert!(grid.ends_with(" o3"));
+
+        let mut rng = StdRng::seed_from_u64(5);
+        for plies in 0..60 {
+            let board = Board::random_position(plies, &mut rng);
+            let parsed = Board::from_grid_string(&board.to_grid_string()).unwrap();
+            assert_eq!(parsed.to_grid_string(), board.to_grid_string());
+            assert_eq!(parsed.get_moves(), board.get_moves());
+            assert_eq!(parsed.zobrist_hash(), board.zobrist_hash());
+        }
+    }
+
+    #[test]
+    fn test_grid_string_errors() {
+        let empty = ".".repeat(81);
+        for text in [
+            empty.clone(),
+            format!("{empty} z-"),
+            format!("{empty} x4"),
+            format!("X{} x-", ".".repeat(80)),
+            format!("{} x-", ".".repeat(80)),
+            format!("?{} o-", ".".repeat(80)),
+        ] {
+            assert!(Board::from_grid_string(&text).is_err(), "{text}");
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! A game position that can be manipulated independently of any search tree.
+
+use crate::game::Board;
+use crate::zobrist;
+use crate::StoctopusError;
+
+/// A board together with the moves that led to it and its hash.
+#[derive(Clone, Debug)]
+pub struct Position {
+    board: Board,
+    hash: u64,
+    /// Boards and hashes before each move, so moves can be taken back.
+    history: Vec<(Board, u64)>,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::from_board(Board::default())
+    }
+}
+
+impl Position {
+    /// Starts a position (with empty history) from `board`, which is assumed
+    /// to be valid. Use [`Board::validate`] for untrusted input.
+    pub fn from_board(board: Board) -> Self {
+        Self {
+            hash: board.zobrist_hash(),
+            board,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Moves played since the position was created, oldest first, as
+    /// `(global, local)` pairs.
+    pub fn moves(&self) -> Vec<(u8, u8)> {
+        self.history
+            .iter()
+            .map(|(board, _)| board)
+            .skip(1)
+            .chain(std::iter::once(&self.board))
+            .take(self.history.len())
+            .filter_map(|board| board.last_move)
+            .map(|m| (m >> 4, m & 0b1111))
+            .collect()
+    }
+
+    pub fn is_legal(&self, mve: (u8, u8)) -> bool {
+        mve.0 < 9
+            && mve.1 < 9
+            && !self.board.game_over()
+            && self.board.get_moves() & (1 << (mve.0 * 9 + mve.1)) != 0
+    }
+
+    /// Legal moves as `(global, local)` pairs.
+    pub fn legal_moves(&self) -> Vec<(u8, u8)> {
+        if self.board.game_over() {
+            return vec![];
+        }
+        let moves = self.board.get_moves();
+        (0..81)
+            .filter(|i| moves & (1 << i) != 0)
+            .map(|i| (i / 9, i % 9))
+            .collect()
+    }
+
+    pub fn make_move(&mut self, mve: (u8, u8)) -> Result<(), StoctopusError> {
+        if !self.is_legal(mve) {
+            return Err(StoctopusError::IllegalMove);
+        }
+
+        let player = self.board.next_player;
+        let next = self.board.unchecked_play(Board::move_from_gl(mve.0, mve.1));
+        let hash = self.hash
+            ^ zobrist::cell_key(player, mve.0 * 9 + mve.1)
+            ^ zobrist::side_key(player)
+            ^ zobrist::side_key(next.next_player)
+            ^ zobrist::forced_key(&self.board)
+            ^ zobrist::forced_key(&next);
+        debug_assert_eq!(hash, next.zobrist_hash());
+
+        let previous = std::mem::replace(&mut self.board, next);
+        self.history.push((previous, self.hash));
+        self.hash = hash;
+        Ok(())
+    }
+
+    /// Takes back the last move, returning it as `(global, local)`.
+    pub fn unmake_move(&mut self) -> Option<(u8, u8)> {
+        let (previous, hash) = self.history.pop()?;
+        let m = self
+            .board
+            .last_move
+            .expect("Board after a move has a last move");
+        self.board = previous;
+        self.hash = hash;
+        Some((m >> 4, m & 0b1111))
+    }
+}
+
+#[cfg(test)]
+mod position_tests {
+    use crate::game::Board;
+    use crate::position::Position;
+
+    #[test]
+    fn test_make_unmake() {
+        let mut position = Position::default();
+        let start = position.hash();
+
+        position.make_move((4, 4)).unwrap();
+        position.make_move((4, 0)).unwrap();
+        assert!(position.make_move((4, 1)).is_err());
+        assert!(position.is_legal((0, 1)));
+        assert_eq!(position.legal_moves().len(), 9);
+        assert_eq!(position.moves(), vec![(4, 4), (4, 0)]);
+        assert_eq!(position.hash(), position.board().zobrist_hash());
+
+        assert_eq!(position.unmake_move(), Some((4, 0)));
+        assert_eq!(position.unmake_move(), Some((4, 4)));
+        assert_eq!(position.unmake_move(), None);
+        assert_eq!(position.hash(), start);
+        assert_eq!(position.board().x, Board::default().x);
+    }
+}
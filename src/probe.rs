@@ -0,0 +1,110 @@
+//! Hooks for knowledge from outside the search. An opening book is asked
+//! for a move at the root; a tablebase or solver is asked for the exact
+//! result of the root's children and of every node before it is simulated.
+//! Known answers replace the search or the random playout.
+
+use std::sync::Arc;
+
+use crate::game::{Board, GameState};
+
+pub trait BookProvider: Send + Sync {
+    /// Move to play in `board`, or `None` when out of book. Illegal moves
+    /// are ignored.
+    fn probe(&self, board: &Board) -> Option<u8>;
+
+    /// Every book move of `board` with its weight, best first, for picking
+    /// among them at random. Just the [`Self::probe`] move by default.
+    fn probe_all(&self, board: &Board) -> Vec<(u8, u16)> {
+        self.probe(board).map(|m| (m, 1)).into_iter().collect()
+    }
+}
+
+pub trait TablebaseProvider: Send + Sync {
+    /// Result of `board` under perfect play, or `None` if it isn't known.
+    fn probe(&self, board: &Board) -> Option<GameState>;
+}
+
+/// Tablebase shared with the search threads.
+#[derive(Clone)]
+pub(crate) struct Tablebase(pub Arc<dyn TablebaseProvider>);
+
+impl std::fmt::Debug for Tablebase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Tablebase")
+    }
+}
+
+impl deepsize::DeepSizeOf for Tablebase {
+    fn deep_size_of_children(&self, _context: &mut deepsize::Context) -> usize {
+        // Owned by whoever supplied it, not by the search tree.
+        0
+    }
+}
+
+/// Move of `board` with the best result according to `tablebase`, along
+/// with that result. `None` unless a win is known or every move is.
+pub(crate) fn solve_root(
+    board: &Board,
+    tablebase: &dyn TablebaseProvider,
+) -> Option<(u8, GameState)> {
+    let me = board.next_player;
+    let moves = board.get_moves();
+    let mut best: Option<(u8, GameState)> = None;
+    let mut all_known = true;
+    for i in 0..81 {
+        if (moves >> i) & 1 == 0 {
+            continue;
+        }
+        let m = Board::move_from_index(i);
+        let child = board.unchecked_play(m);
+        let result = match child.check_game_state() {
+            GameState::InProgress => tablebase.probe(&child),
+            over => Some(over),
+        };
+        let Some(result) = result else {
+            all_known = false;
+            continue;
+        };
+        let rank = |state: GameState| match state {
+            GameState::Won(winner) if winner == me => 2,
+            GameState::Draw | GameState::InProgress => 1,
+            GameState::Won(_) => 0,
+        };
+        if rank(result) == 2 {
+            return Some((m, result));
+        }
+        if best.is_none_or(|(_, b)| rank(result) > rank(b)) {
+            best = Some((m, result));
+        }
+    }
+    best.filter(|_| all_known)
+}
+
+#[cfg(test)]
+mod probe_tests {
+    use crate::game::{Board, GameState, Player};
+    use crate::probe::{solve_root, TablebaseProvider};
+
+    /// Claims every position where X owns the center board is won by X.
+    struct CenterWins;
+
+    impl TablebaseProvider for CenterWins {
+        fn probe(&self, board: &Board) -> Option<GameState> {
+            (board.gx & !board.go & (1 << 4) != 0).then_some(GameState::Won(Player::X))
+        }
+    }
+
+    #[test]
+    fn test_solve_root() {
+        // X holds 0 and 1 of the center board and is sent there.
+        let mut board = Board::default();
+        for (g, l) in [(4, 0), (0, 4), (4, 1), (1, 4)] {
+            board = board.unchecked_play(Board::move_from_gl(g, l));
+        }
+        assert_eq!(
+            solve_root(&board, &CenterWins),
+            Some((Board::move_from_gl(4, 2), GameState::Won(Player::X)))
+        );
+        assert_eq!(solve_root(&Board::default(), &CenterWins), None);
+    }
+}
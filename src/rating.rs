@@ -0,0 +1,225 @@
+//! Estimates how strong a player is from their games, e.g. for a "your
+//! estimated rating" screen. Every move of the player is graded against the
+//! engine by the win rate it gave away, see [`grade_game`], and the average
+//! loss is mapped onto a rating scale.
+//!
+//! The rating is a rough, Elo-like number rather than a calibrated one:
+//! [`PERFECT_RATING`] for a player who never loses anything by the engine's
+//! judgement, minus [`LOSS_RATING`] per unit of average loss. It only means
+//! something relative to other players graded by the same engine settings.
+
+use crate::game::{Board, GameState, Player};
+use crate::{Engine, GameRecord, SearchError, StoctopusError};
+
+/// Rating of a player who always plays the engine's move.
+pub const PERFECT_RATING: f32 = 2500.0;
+/// Rating lost per unit of average win rate given away per move, i.e. 100
+/// points per percentage point.
+pub const LOSS_RATING: f32 = 10_000.0;
+
+/// How one move compared to the engine's choice.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MoveGrade {
+    /// Index of the move in the record.
+    pub index: usize,
+    pub played: (u8, u8),
+    pub best: (u8, u8),
+    /// Win rate the engine expected from its own move and from the one
+    /// played, for the player, from 0 to 1.
+    pub best_value: f32,
+    pub played_value: f32,
+}
+
+impl MoveGrade {
+    /// Win rate given away by the move, never negative.
+    pub fn loss(&self) -> f32 {
+        (self.best_value - self.played_value).max(0.0)
+    }
+}
+
+/// Estimated strength of a player, see the module documentation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RatingEstimate {
+    pub rating: f32,
+    /// Half width of the 95% confidence interval of `rating`. Infinite with
+    /// fewer than two moves graded.
+    pub margin: f32,
+    /// Moves graded.
+    pub moves: usize,
+    /// Average of [`MoveGrade::loss`].
+    pub mean_loss: f32,
+}
+
+impl RatingEstimate {
+    pub fn from_grades(grades: &[MoveGrade]) -> Self {
+        let moves = grades.len();
+        let n = moves as f32;
+        let mean_loss = grades.iter().map(MoveGrade::loss).sum::<f32>() / n.max(1.0);
+        let margin = if moves < 2 {
+            f32::INFINITY
+        } else {
+            let variance = grades
+                .iter()
+                .map(|grade| (grade.loss() - mean_loss).powi(2))
+                .sum::<f32>()
+                / (n - 1.0);
+            1.96 * LOSS_RATING * (variance / n).sqrt()
+        };
+        Self {
+            rating: (PERFECT_RATING - LOSS_RATING * mean_loss).max(0.0),
+            margin,
+            moves,
+            mean_loss,
+        }
+    }
+
+    /// Grades the moves of the given side in each game, searching each
+    /// position for `iterations` iterations with `engine`'s settings.
+    pub fn from_games(
+        engine: &mut Engine,
+        games: &[(GameRecord, Player)],
+        iterations: u32,
+    ) -> Result<Self, StoctopusError> {
+        let mut grades = Vec::new();
+        for (record, player) in games {
+            grades.extend(grade_game(engine, record, *player, iterations)?);
+        }
+        Ok(Self::from_grades(&grades))
+    }
+}
+
+/// Value for the player who just moved of a finished game.
+fn result_value(board: &Board, mover: Player) -> f32 {
+    match board.check_game_state() {
+        GameState::Won(winner) if winner == mover => 1.0,
+        GameState::Won(_) => 0.0,
+        GameState::Draw | GameState::InProgress => 0.5,
+    }
+}
+
+/// Grades every move `player` made in `record`. The engine searches the
+/// position before each move and, unless the engine would have played it
+/// too, the position after it. Sides are by colour: a swap under the pie
+/// rule doesn't change whose moves are graded. Searches need at least one
+/// iteration to find a best move.
+pub fn grade_game(
+    engine: &mut Engine,
+    record: &GameRecord,
+    player: Player,
+    iterations: u32,
+) -> Result<Vec<MoveGrade>, StoctopusError> {
+    if iterations == 0 {
+        return Err(StoctopusError::Protocol(
+            "Grading needs at least one iteration per move".to_string(),
+        ));
+    }
+    // Also checks that the moves are legal.
+    record.board()?;
+    let mut board = Board::with_rules(record.rules);
+    let mut grades = Vec::new();
+    for (index, &played) in record.moves.iter().enumerate() {
+        let mve = Board::move_from_gl(played.0, played.1);
+        let after = board.unchecked_play(mve);
+        if board.next_player != player {
+            board = after;
+            continue;
+        }
+
+        engine.set_board(board)?;
+        let ev = engine.analyze(iterations)?;
+        let best = ev
+            .best_move
+            .map(|id| engine.try_resolve_node(&id))
+            .transpose()?
+            .and_then(|node| node.board.last_move)
+            .ok_or(SearchError::NoMove)?;
+        let best_value = ev.confidence / 100.0;
+        let played_value = if best == mve {
+            best_value
+        } else if after.game_over() {
+            result_value(&after, player)
+        } else {
+            engine.set_board(after)?;
+            1.0 - engine.analyze(iterations)?.confidence / 100.0
+        };
+        grades.push(MoveGrade {
+            index,
+            played,
+            best: (best >> 4, best & 0b1111),
+            best_value,
+            played_value,
+        });
+        board = after;
+    }
+    Ok(grades)
+}
+
+#[cfg(test)]
+mod rating_tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::game::{Board, Player};
+    use crate::rating::{grade_game, MoveGrade, RatingEstimate, PERFECT_RATING};
+    use crate::{Engine, GameRecord, MCTSConfig, SearchMode};
+
+    #[test]
+    fn test_rating_estimate() {
+        let config = MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 4 },
+            ..MCTSConfig::default()
+        };
+        // The engine plays X, O plays at random.
+        let mut engine = Engine::with_config(config);
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut record = GameRecord::default();
+        while !engine.is_game_over() {
+            let board = *engine.board();
+            let mve = if board.next_player == Player::X {
+                let ev = engine.analyze(100).unwrap();
+                engine
+                    .try_resolve_node(&ev.best_move.unwrap())
+                    .unwrap()
+                    .board
+                    .last_move
+                    .unwrap()
+            } else {
+                let moves = board.get_moves();
+                let k = rng.gen_range(0..moves.count_ones());
+                let index = (0..81).filter(|i| moves & (1 << i) != 0).nth(k as usize);
+                Board::move_from_index(index.unwrap())
+            };
+            let mve = (mve >> 4, mve & 0b1111);
+            engine.play(mve).unwrap();
+            record.moves.push(mve);
+        }
+
+        let mut grader = Engine::with_config(config);
+        let engine_grades = grade_game(&mut grader, &record, Player::X, 100).unwrap();
+        assert_eq!(engine_grades.len(), record.moves.len().div_ceil(2));
+        assert!(grade_game(&mut grader, &record, Player::X, 0).is_err());
+        // Same settings, same searches: every move is the engine's.
+        assert!(engine_grades.iter().all(|grade| grade.played == grade.best));
+        let engine_rating = RatingEstimate::from_grades(&engine_grades);
+        assert_eq!(
+            (engine_rating.rating, engine_rating.margin),
+            (PERFECT_RATING, 0.0)
+        );
+
+        let games = [(record, Player::O)];
+        let random = RatingEstimate::from_games(&mut grader, &games, 100).unwrap();
+        assert_eq!(random.moves, games[0].0.moves.len() / 2);
+        assert!(random.mean_loss > 0.0, "{random:?}");
+        assert!(random.rating < engine_rating.rating);
+        assert!(random.margin > 0.0);
+
+        let grade = MoveGrade {
+            index: 0,
+            played: (4, 4),
+            best: (4, 0),
+            best_value: 0.5,
+            played_value: 0.6,
+        };
+        assert_eq!(grade.loss(), 0.0);
+        assert_eq!(RatingEstimate::from_grades(&[]).margin, f32::INFINITY);
+    }
+}
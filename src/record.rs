@@ -0,0 +1,818 @@
+//! Game records in a PGN-like text format. Tag lines describe the rules,
+//! followed by the moves and the result:
+//!
+//! ```text
+//! [Variant "nine-board"]
+//! [PieRule "yes"]
+//!
+//! 44 swap 40?! {[%eval -12.5] [%pv 04 40] Too slow} 04 *
+//! ```
+//!
+//! Moves are written as in UGI. `swap` after the first move records that
+//! the second player took it over under the pie rule. The result is `1-0`
+//! (X won), `0-1` (O won), `1/2-1/2` or `*` (unfinished). Known tags are
+//! `Variant` (`ultimate` or `nine-board`), `Misere` and `PieRule` (`yes` or
+//! `no`) and `Handicap`: the side, its double moves and its stones, as in
+//! `[Handicap "o 1 00 88"]`. Unknown tags are ignored.
+//!
+//! A move can carry an [`Annotation`]: a tag (`!`, `!?`, `?!`, `?` or `??`)
+//! right after it and a comment in braces holding the evaluation, the
+//! principal variation and free text. Comments can't contain `}`.
+//!
+//! A variation in parentheses after a move replaces it with another line,
+//! which may have annotations and variations of its own, as in
+//! `44 40 (04 40 (00)) 04 *`.
+//!
+//! Records also convert to and from JSON with the same information, see
+//! [`GameRecord::to_json`].
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Write};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::{Board, GameState, Handicap, Player, Rules, Variant};
+use crate::ugi::{format_move, parse_move};
+use crate::StoctopusError;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GameRecord {
+    pub rules: Rules,
+    /// Moves as `(global, local)` pairs, X first.
+    pub moves: Vec<(u8, u8)>,
+    /// The second player swapped sides after the first move.
+    pub swapped: bool,
+    /// [`GameState::InProgress`] for unfinished games.
+    pub result: GameState,
+    /// Annotations by index into `moves`.
+    pub annotations: BTreeMap<usize, Annotation>,
+    /// Alternative lines by the index into `moves` of the move they
+    /// replace.
+    pub variations: BTreeMap<usize, Vec<Variation>>,
+}
+
+/// Steps from the main line to a variation, each being the index of the
+/// move the variation replaces in its line and its index among the
+/// variations there.
+pub type VariationPath = [(usize, usize)];
+
+/// Judgement of a move, written after it as in chess.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MoveTag {
+    #[serde(rename = "!")]
+    Good,
+    #[serde(rename = "!?")]
+    Interesting,
+    #[serde(rename = "?!")]
+    Dubious,
+    #[serde(rename = "?")]
+    Mistake,
+    #[serde(rename = "??")]
+    Blunder,
+}
+
+impl MoveTag {
+    const ALL: [MoveTag; 5] = [
+        MoveTag::Blunder,
+        MoveTag::Interesting,
+        MoveTag::Dubious,
+        MoveTag::Good,
+        MoveTag::Mistake,
+    ];
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            MoveTag::Good => "!",
+            MoveTag::Interesting => "!?",
+            MoveTag::Dubious => "?!",
+            MoveTag::Mistake => "?",
+            MoveTag::Blunder => "??",
+        }
+    }
+}
+
+/// What analysis had to say about a move.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Annotation {
+    /// [`Evaluation::score`](crate::Evaluation::score) after the move, from
+    /// the point of view of the player who made it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eval: Option<f32>,
+    /// Expected continuation after the move.
+    #[serde(skip_serializing_if = "Vec::is_empty", with = "json_moves")]
+    pub pv: Vec<(u8, u8)>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub comment: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<MoveTag>,
+}
+
+impl Annotation {
+    fn has_comment(&self) -> bool {
+        self.eval.is_some() || !self.pv.is_empty() || !self.comment.is_empty()
+    }
+
+    /// Parses the inside of a `{...}` comment.
+    fn parse_comment(&mut self, text: &str) -> Result<(), StoctopusError> {
+        let mut rest = text.trim();
+        while let Some(command) = rest.strip_prefix("[%") {
+            let (command, after) = command
+                .split_once(']')
+                .ok_or_else(|| protocol(format!("Unclosed command in {text:?}")))?;
+            match command.split_once(' ').unwrap_or((command, "")) {
+                ("eval", value) => {
+                    let eval = value.trim().parse();
+                    self.eval = Some(eval.map_err(|_| protocol(format!("Bad eval {value:?}")))?);
+                }
+                ("pv", moves) => {
+                    self.pv = moves
+                        .split_whitespace()
+                        .map(parse_move)
+                        .collect::<Result<_, _>>()?
+                }
+                _ => return Err(protocol(format!("Unknown command {command:?}"))),
+            }
+            rest = after.trim_start();
+        }
+        self.comment = rest.to_string();
+        Ok(())
+    }
+}
+
+impl Display for Annotation {
+    /// The comment, without the tag which goes with the move.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+        if let Some(eval) = self.eval {
+            parts.push(format!("[%eval {eval}]"));
+        }
+        if !self.pv.is_empty() {
+            let pv: Vec<String> = self.pv.iter().map(|&m| format_move(m)).collect();
+            parts.push(format!("[%pv {}]", pv.join(" ")));
+        }
+        if !self.comment.is_empty() {
+            parts.push(self.comment.clone());
+        }
+        write!(f, "{{{}}}", parts.join(" "))
+    }
+}
+
+/// A line played instead of a move, see [`GameRecord::variations`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Variation {
+    #[serde(with = "json_moves")]
+    pub moves: Vec<(u8, u8)>,
+    /// Annotations by index into `moves`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<usize, Annotation>,
+    /// Alternative lines by the index into `moves` of the move they
+    /// replace.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub variations: BTreeMap<usize, Vec<Variation>>,
+}
+
+impl Variation {
+    /// Parses the tokens after an opening parenthesis up to the closing one.
+    fn parse<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<Self, StoctopusError> {
+        let mut variation = Variation::default();
+        loop {
+            match tokens.next() {
+                None => return Err(protocol("Unclosed variation")),
+                Some(")") => break,
+                Some("(") => {
+                    let index = last_index(&variation.moves, "Variation")?;
+                    let nested = Variation::parse(tokens)?;
+                    variation.variations.entry(index).or_default().push(nested);
+                }
+                Some(token) => push_token(&mut variation.moves, &mut variation.annotations, token)?,
+            }
+        }
+        if variation.moves.is_empty() {
+            return Err(protocol("Empty variation"));
+        }
+        Ok(variation)
+    }
+}
+
+fn last_index(moves: &[(u8, u8)], what: &str) -> Result<usize, StoctopusError> {
+    moves
+        .len()
+        .checked_sub(1)
+        .ok_or_else(|| protocol(format!("{what} before the first move")))
+}
+
+/// Appends a move, with its tag if any, or annotates the last move with a
+/// `{...}` comment.
+fn push_token(
+    moves: &mut Vec<(u8, u8)>,
+    annotations: &mut BTreeMap<usize, Annotation>,
+    token: &str,
+) -> Result<(), StoctopusError> {
+    if let Some(comment) = token.strip_prefix('{') {
+        let comment = comment
+            .strip_suffix('}')
+            .ok_or_else(|| protocol(format!("Unclosed comment {token:?}")))?;
+        let index = last_index(moves, "Comment")?;
+        return annotations.entry(index).or_default().parse_comment(comment);
+    }
+    let tag = MoveTag::ALL
+        .into_iter()
+        .find(|tag| token.ends_with(tag.symbol()));
+    moves.push(parse_move(token.trim_end_matches(['!', '?']))?);
+    if let Some(tag) = tag {
+        annotations.entry(moves.len() - 1).or_default().tag = Some(tag);
+    }
+    Ok(())
+}
+
+/// Plays `moves` from `board`, checking them and the variations along the
+/// way. The first move may be swapped if `swapped`.
+fn play_line(
+    mut board: Board,
+    moves: &[(u8, u8)],
+    annotations: &BTreeMap<usize, Annotation>,
+    variations: &BTreeMap<usize, Vec<Variation>>,
+    swapped: bool,
+) -> Result<Board, StoctopusError> {
+    for (i, &(global, local)) in moves.iter().enumerate() {
+        for variation in variations.get(&i).into_iter().flatten() {
+            let Variation {
+                moves,
+                annotations,
+                variations,
+            } = variation;
+            play_line(board, moves, annotations, variations, false)?;
+        }
+        if global > 8
+            || local > 8
+            || board.game_over()
+            || board.get_moves() & (1 << (global * 9 + local)) == 0
+        {
+            return Err(StoctopusError::IllegalMove);
+        }
+        board.make(Board::move_from_gl(global, local));
+        if i == 0 && swapped && !board.can_swap() {
+            return Err(protocol("Swap without the pie rule"));
+        }
+    }
+    if let Some(&i) = annotations.range(moves.len()..).next().map(|(i, _)| i) {
+        return Err(protocol(format!("Annotation of missing move {i}")));
+    }
+    if let Some(&i) = variations.range(moves.len()..).next().map(|(i, _)| i) {
+        return Err(protocol(format!("Variation of missing move {i}")));
+    }
+    Ok(board)
+}
+
+/// Writes the movetext of a line, each token followed by a space.
+fn write_line(
+    out: &mut String,
+    moves: &[(u8, u8)],
+    annotations: &BTreeMap<usize, Annotation>,
+    variations: &BTreeMap<usize, Vec<Variation>>,
+    swapped: bool,
+) -> fmt::Result {
+    for (i, &mve) in moves.iter().enumerate() {
+        out.push_str(&format_move(mve));
+        if let Some(annotation) = annotations.get(&i) {
+            if let Some(tag) = annotation.tag {
+                out.push_str(tag.symbol());
+            }
+            if annotation.has_comment() {
+                write!(out, " {annotation}")?;
+            }
+        }
+        out.push(' ');
+        for variation in variations.get(&i).into_iter().flatten() {
+            out.push('(');
+            write_line(
+                out,
+                &variation.moves,
+                &variation.annotations,
+                &variation.variations,
+                false,
+            )?;
+            out.pop();
+            out.push_str(") ");
+        }
+        if i == 0 && swapped {
+            out.push_str("swap ");
+        }
+    }
+    Ok(())
+}
+
+/// Moves as UGI strings in JSON.
+mod json_moves {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::ugi::{format_move, parse_move};
+
+    pub fn serialize<S: Serializer>(moves: &[(u8, u8)], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(moves.iter().map(|&m| format_move(m)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(u8, u8)>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|m| parse_move(m).map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonRecord {
+    variant: String,
+    #[serde(default)]
+    misere: bool,
+    #[serde(default)]
+    pie_rule: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    handicap: Option<String>,
+    #[serde(with = "json_moves")]
+    moves: Vec<(u8, u8)>,
+    #[serde(default)]
+    swapped: bool,
+    result: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    annotations: BTreeMap<usize, Annotation>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    variations: BTreeMap<usize, Vec<Variation>>,
+}
+
+fn protocol(msg: impl Into<String>) -> StoctopusError {
+    StoctopusError::Protocol(msg.into())
+}
+
+impl GameRecord {
+    pub fn new(rules: Rules) -> Self {
+        Self {
+            rules,
+            ..Self::default()
+        }
+    }
+
+    /// Position after the recorded moves, checking that every move is
+    /// legal and that a swap is only recorded where the rules allow it.
+    pub fn board(&self) -> Result<Board, StoctopusError> {
+        play_line(
+            Board::with_rules(self.rules),
+            &self.moves,
+            &self.annotations,
+            &self.variations,
+            self.swapped,
+        )
+    }
+
+    /// The variation at `path`, `None` if there is none.
+    pub fn variation(&self, path: &VariationPath) -> Option<&Variation> {
+        let (&(index, n), rest) = path.split_first()?;
+        let mut variation = self.variations.get(&index)?.get(n)?;
+        for &(index, n) in rest {
+            variation = variation.variations.get(&index)?.get(n)?;
+        }
+        Some(variation)
+    }
+
+    pub fn variation_mut(&mut self, path: &VariationPath) -> Option<&mut Variation> {
+        let (&(index, n), rest) = path.split_first()?;
+        let mut variation = self.variations.get_mut(&index)?.get_mut(n)?;
+        for &(index, n) in rest {
+            variation = variation.variations.get_mut(&index)?.get_mut(n)?;
+        }
+        Some(variation)
+    }
+
+    /// Moves from the start of the game to the end of the variation at
+    /// `path`, or of the main line for an empty path.
+    pub fn line(&self, path: &VariationPath) -> Option<Vec<(u8, u8)>> {
+        let mut line = self.moves.clone();
+        let (mut start, mut variations) = (0, &self.variations);
+        for &(index, n) in path {
+            let variation = variations.get(&index)?.get(n)?;
+            start += index;
+            line.truncate(start);
+            line.extend(&variation.moves);
+            variations = &variation.variations;
+        }
+        Some(line)
+    }
+
+    /// Position at the end of the line at `path`.
+    pub fn board_at(&self, path: &VariationPath) -> Result<Board, StoctopusError> {
+        let moves = self
+            .line(path)
+            .ok_or_else(|| protocol("No variation there"))?;
+        GameRecord {
+            rules: self.rules,
+            moves,
+            // The first step replaces the first move, swap and all.
+            swapped: self.swapped && path.first().is_none_or(|&(index, _)| index > 0),
+            ..GameRecord::default()
+        }
+        .board()
+    }
+
+    /// Adds `moves` in place of move `index` of the line at `path`,
+    /// returning the path of the new variation. The moves must be legal.
+    pub fn add_variation(
+        &mut self,
+        path: &VariationPath,
+        index: usize,
+        moves: Vec<(u8, u8)>,
+    ) -> Result<Vec<(usize, usize)>, StoctopusError> {
+        let mut record = self.clone();
+        let (line_len, variations) = if path.is_empty() {
+            (record.moves.len(), &mut record.variations)
+        } else {
+            let variation = record
+                .variation_mut(path)
+                .ok_or_else(|| protocol("No variation there"))?;
+            (variation.moves.len(), &mut variation.variations)
+        };
+        if index >= line_len || moves.is_empty() {
+            return Err(protocol(format!("Can't replace move {index} of the line")));
+        }
+        let siblings = variations.entry(index).or_default();
+        siblings.push(Variation {
+            moves,
+            ..Variation::default()
+        });
+        let mut new_path = path.to_vec();
+        new_path.push((index, siblings.len() - 1));
+        record.board_at(&new_path)?;
+        *self = record;
+        Ok(new_path)
+    }
+
+    /// Annotation of move `index`, added if there is none yet.
+    pub fn annotate(&mut self, index: usize) -> &mut Annotation {
+        self.annotations.entry(index).or_default()
+    }
+
+    /// Appends the moves, annotations, variations and swap of movetext
+    /// `tokens`, returning the result if it ends with one. Legality is left
+    /// to [`Self::board`].
+    pub(crate) fn parse_movetext<'a>(
+        &mut self,
+        tokens: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Option<GameState>, StoctopusError> {
+        let mut tokens = tokens.into_iter();
+        let mut result = None;
+        while let Some(token) = tokens.next() {
+            if result.is_some() {
+                return Err(protocol(format!("{token:?} after the result")));
+            }
+            match token {
+                "swap" if self.moves.len() == 1 && !self.swapped => self.swapped = true,
+                "swap" => return Err(protocol("Swap is only allowed after the first move")),
+                "(" => {
+                    let index = last_index(&self.moves, "Variation")?;
+                    let variation = Variation::parse(&mut tokens)?;
+                    self.variations.entry(index).or_default().push(variation);
+                }
+                ")" => return Err(protocol("Unopened variation")),
+                token => match parse_result(token) {
+                    Some(state) => result = Some(state),
+                    None => push_token(&mut self.moves, &mut self.annotations, token)?,
+                },
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn to_json(&self) -> String {
+        let json = JsonRecord {
+            variant: variant_name(self.rules.variant).to_string(),
+            misere: self.rules.misere,
+            pie_rule: self.rules.pie_rule,
+            handicap: self.rules.handicap.map(format_handicap),
+            moves: self.moves.clone(),
+            swapped: self.swapped,
+            result: result_name(self.result).to_string(),
+            annotations: self.annotations.clone(),
+            variations: self.variations.clone(),
+        };
+        serde_json::to_string(&json).expect("Records always serialize")
+    }
+
+    /// Parses a record written by [`Self::to_json`] and checks that its
+    /// moves are legal.
+    pub fn from_json(text: &str) -> Result<Self, StoctopusError> {
+        let json: JsonRecord =
+            serde_json::from_str(text).map_err(|err| protocol(format!("Bad record: {err}")))?;
+        let record = GameRecord {
+            rules: Rules {
+                variant: parse_variant(&json.variant)?,
+                misere: json.misere,
+                handicap: json.handicap.as_deref().map(parse_handicap).transpose()?,
+                pie_rule: json.pie_rule,
+            },
+            moves: json.moves,
+            swapped: json.swapped,
+            result: parse_result(&json.result)
+                .ok_or_else(|| protocol(format!("Bad result {:?}", json.result)))?,
+            annotations: json.annotations,
+            variations: json.variations,
+        };
+        record.board()?;
+        Ok(record)
+    }
+}
+
+/// Splits movetext on whitespace, keeping `{...}` comments whole and
+/// making parentheses tokens of their own.
+fn movetext_tokens(text: &str) -> Result<Vec<&str>, StoctopusError> {
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let end = if rest.starts_with('{') {
+            rest.find('}').ok_or_else(|| protocol("Unclosed comment"))? + 1
+        } else if rest.starts_with(['(', ')']) {
+            1
+        } else {
+            rest.find(|c: char| c.is_whitespace() || "{()".contains(c))
+                .unwrap_or(rest.len())
+        };
+        tokens.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    Ok(tokens)
+}
+
+fn yes_no(value: &str) -> Result<bool, StoctopusError> {
+    match value {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(protocol(format!("Expected yes or no, got {value:?}"))),
+    }
+}
+
+pub(crate) fn variant_name(variant: Variant) -> &'static str {
+    match variant {
+        Variant::Ultimate => "ultimate",
+        Variant::NineBoard => "nine-board",
+    }
+}
+
+pub(crate) fn parse_variant(value: &str) -> Result<Variant, StoctopusError> {
+    match value {
+        "ultimate" => Ok(Variant::Ultimate),
+        "nine-board" => Ok(Variant::NineBoard),
+        _ => Err(protocol(format!("Unknown variant {value:?}"))),
+    }
+}
+
+fn result_name(result: GameState) -> &'static str {
+    match result {
+        GameState::Won(Player::X) => "1-0",
+        GameState::Won(Player::O) => "0-1",
+        GameState::Draw => "1/2-1/2",
+        GameState::InProgress => "*",
+    }
+}
+
+fn parse_result(value: &str) -> Option<GameState> {
+    match value {
+        "1-0" => Some(GameState::Won(Player::X)),
+        "0-1" => Some(GameState::Won(Player::O)),
+        "1/2-1/2" => Some(GameState::Draw),
+        "*" => Some(GameState::InProgress),
+        _ => None,
+    }
+}
+
+fn format_handicap(handicap: Handicap) -> String {
+    let player = match handicap.player {
+        Player::X => "x",
+        Player::O => "o",
+    };
+    let mut text = format!("{player} {}", handicap.double_moves);
+    for cell in (0..81).filter(|i| handicap.stones & (1 << i) != 0) {
+        text.push(' ');
+        text.push_str(&format_move((cell / 9, cell % 9)));
+    }
+    text
+}
+
+fn parse_handicap(value: &str) -> Result<Handicap, StoctopusError> {
+    let bad = || protocol(format!("Bad handicap {value:?}"));
+    let words: Vec<&str> = value.split_whitespace().collect();
+    let [player, double_moves, stones @ ..] = words.as_slice() else {
+        return Err(bad());
+    };
+    let player = match *player {
+        "x" => Player::X,
+        "o" => Player::O,
+        _ => return Err(bad()),
+    };
+    let stones = stones
+        .iter()
+        .map(|stone| parse_move(stone))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Handicap {
+        player,
+        stones: Handicap::cells(&stones),
+        double_moves: double_moves.parse().map_err(|_| bad())?,
+    })
+}
+
+impl Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+        writeln!(f, "[Variant \"{}\"]", variant_name(self.rules.variant))?;
+        writeln!(f, "[Misere \"{}\"]", yes_no(self.rules.misere))?;
+        writeln!(f, "[PieRule \"{}\"]", yes_no(self.rules.pie_rule))?;
+        if let Some(handicap) = self.rules.handicap {
+            writeln!(f, "[Handicap \"{}\"]", format_handicap(handicap))?;
+        }
+        writeln!(f)?;
+
+        let mut movetext = String::new();
+        write_line(
+            &mut movetext,
+            &self.moves,
+            &self.annotations,
+            &self.variations,
+            self.swapped,
+        )?;
+        movetext.push_str(result_name(self.result));
+        writeln!(f, "{movetext}")
+    }
+}
+
+impl FromStr for GameRecord {
+    type Err = StoctopusError;
+
+    /// Parses a record and checks that its moves are legal.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut record = GameRecord::default();
+        let mut movetext = String::new();
+        for line in text.lines().map(str::trim) {
+            if let Some(tag) = line.strip_prefix('[') {
+                let (name, value) = tag
+                    .strip_suffix("\"]")
+                    .and_then(|tag| tag.split_once(" \""))
+                    .ok_or_else(|| protocol(format!("Bad tag {line:?}")))?;
+                match name {
+                    "Variant" => record.rules.variant = parse_variant(value)?,
+                    "Misere" => record.rules.misere = yes_no(value)?,
+                    "PieRule" => record.rules.pie_rule = yes_no(value)?,
+                    "Handicap" => record.rules.handicap = Some(parse_handicap(value)?),
+                    _ => {}
+                }
+            } else {
+                movetext.push_str(line);
+                movetext.push('\n');
+            }
+        }
+
+        record.result = record
+            .parse_movetext(movetext_tokens(&movetext)?)?
+            .ok_or_else(|| protocol("Missing result"))?;
+        record.board()?;
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod record_tests {
+    use crate::game::{GameState, Handicap, Player, Rules};
+    use crate::record::{Annotation, GameRecord, MoveTag, Variation};
+
+    #[test]
+    fn test_round_trip() {
+        let record = GameRecord {
+            rules: Rules {
+                pie_rule: true,
+                handicap: Some(Handicap {
+                    player: Player::O,
+                    stones: Handicap::cells(&[(8, 8)]),
+                    double_moves: 0,
+                }),
+                ..Rules::default()
+            },
+            moves: vec![(4, 4), (4, 0), (0, 4)],
+            swapped: true,
+            result: GameState::Won(Player::O),
+            ..GameRecord::default()
+        };
+        let text = record.to_string();
+        assert!(text.contains("[Handicap \"o 0 88\"]"));
+        assert!(text.ends_with("44 swap 40 04 0-1\n"));
+        assert_eq!(text.parse::<GameRecord>().unwrap(), record);
+        assert_eq!(GameRecord::from_json(&record.to_json()).unwrap(), record);
+    }
+
+    #[test]
+    fn test_annotations() {
+        let mut record = GameRecord::new(Rules::default());
+        record.moves = vec![(4, 4), (4, 0), (0, 4)];
+        record.annotate(1).tag = Some(MoveTag::Dubious);
+        *record.annotate(2) = Annotation {
+            eval: Some(-12.5),
+            pv: vec![(4, 0), (0, 0)],
+            comment: "Too slow, see 44".to_string(),
+            tag: Some(MoveTag::Blunder),
+        };
+        let text = record.to_string();
+        assert!(
+            text.ends_with("44 40?! 04?? {[%eval -12.5] [%pv 40 00] Too slow, see 44} *\n"),
+            "{text}"
+        );
+        assert_eq!(text.parse::<GameRecord>().unwrap(), record);
+
+        let json = record.to_json();
+        assert!(json.contains("\"tag\":\"??\""), "{json}");
+        assert_eq!(GameRecord::from_json(&json).unwrap(), record);
+
+        // Comments may span lines and need not be separated by spaces.
+        let record: GameRecord = "44{first\nmove}40! *".parse().unwrap();
+        assert_eq!(record.annotations[&0].comment, "first\nmove");
+        assert_eq!(record.annotations[&1].tag, Some(MoveTag::Good));
+
+        let mut dangling = record;
+        dangling.annotate(5).comment = "?".to_string();
+        assert!(dangling.board().is_err());
+    }
+
+    #[test]
+    fn test_variations() {
+        let mut record = GameRecord::new(Rules::default());
+        record.moves = vec![(4, 4), (4, 0), (0, 4)];
+        let alt = record.add_variation(&[], 1, vec![(4, 8), (8, 4)]).unwrap();
+        assert_eq!(alt, vec![(1, 0)]);
+        let nested = record.add_variation(&alt, 1, vec![(8, 0)]).unwrap();
+        assert_eq!(nested, vec![(1, 0), (1, 0)]);
+        record.annotate(2).tag = Some(MoveTag::Good);
+        record.variation_mut(&alt).unwrap().annotations.insert(
+            0,
+            Annotation {
+                comment: "sharper".to_string(),
+                ..Annotation::default()
+            },
+        );
+
+        assert_eq!(record.line(&[]).unwrap(), record.moves);
+        assert_eq!(record.line(&nested).unwrap(), vec![(4, 4), (4, 8), (8, 0)]);
+        assert_eq!(record.variation(&nested).unwrap().moves, vec![(8, 0)]);
+        assert!(record.variation(&[(0, 0)]).is_none());
+        let board = record.board_at(&nested).unwrap();
+        assert_eq!(board.forced_board(), Some(0));
+
+        let text = record.to_string();
+        assert!(
+            text.ends_with("44 40 (48 {sharper} 84 (80)) 04! *\n"),
+            "{text}"
+        );
+        assert_eq!(text.parse::<GameRecord>().unwrap(), record);
+        assert_eq!(GameRecord::from_json(&record.to_json()).unwrap(), record);
+
+        // Illegal lines and missing moves leave the record untouched.
+        let before = record.clone();
+        assert!(record.add_variation(&[], 1, vec![(0, 0)]).is_err());
+        assert!(record.add_variation(&[], 3, vec![(0, 4)]).is_err());
+        assert!(record.add_variation(&[(2, 0)], 0, vec![(0, 4)]).is_err());
+        assert_eq!(record, before);
+
+        record.variations.insert(
+            7,
+            vec![Variation {
+                moves: vec![(4, 4)],
+                ..Variation::default()
+            }],
+        );
+        assert!(record.board().is_err());
+        for text in ["(44) *", "44 (40 *", "44 () *", "44 40) *", "44 (40 44) *"] {
+            assert!(text.parse::<GameRecord>().is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_records() {
+        // Missing result, illegal move, swap without the pie rule, swap too
+        // late, moves after the result and comments that are unclosed, come
+        // before any move or hold unknown commands.
+        for text in [
+            "44 40",
+            "44 44 *",
+            "44 swap 40 *",
+            "[PieRule \"yes\"]\n44 40 swap *",
+            "44 1-0 40",
+            "[Variant \"chess\"]\n*",
+            "44 {open *",
+            "{early} 44 *",
+            "44 {[%clk 1:00]} *",
+        ] {
+            assert!(text.parse::<GameRecord>().is_err(), "{text}");
+        }
+        let record: GameRecord = "[Event \"casual\"]\n44 40 *".parse().unwrap();
+        assert_eq!(record.moves, vec![(4, 4), (4, 0)]);
+        assert!(GameRecord::from_json("{\"variant\": \"ultimate\"}").is_err());
+    }
+}
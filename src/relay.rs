@@ -0,0 +1,227 @@
+//! Live commentary on a relayed game, e.g. between two humans. The engine
+//! doesn't play: it keeps analysing the current position in rounds of
+//! growing length and publishes a [`Commentary`] after each round, until the
+//! next move is relayed.
+//!
+//! [`Relay::run`] speaks a line protocol. Incoming lines:
+//!
+//! - `move <move>`, moves as in UGI
+//! - `position [moves...]`, the game so far from the start, e.g. when
+//!   joining late
+//! - `end`
+//!
+//! Outgoing lines are `info ply <n> score <s> nodes <k> pv <moves...>`, or
+//! `info string <error>` for lines that couldn't be relayed. Once the input
+//! is closed the last position is analysed to the end and `run` returns.
+
+use std::fmt::{self, Display};
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+use crate::ugi::{format_move, parse_move};
+use crate::{Engine, StoctopusError};
+
+/// What the engine thinks of the relayed position after a round.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Commentary {
+    /// Moves played so far.
+    pub ply: usize,
+    /// As [`crate::Evaluation::score`], for the side to move.
+    pub score: f32,
+    /// Iterations of the round.
+    pub iterations: u32,
+    /// Best line from the position, as `(global, local)` pairs.
+    pub pv: Vec<(u8, u8)>,
+}
+
+impl Display for Commentary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "info ply {} score {:.0} nodes {} pv",
+            self.ply, self.score, self.iterations
+        )?;
+        for &mve in &self.pv {
+            write!(f, " {}", format_move(mve))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Relay {
+    engine: Engine,
+    ply: usize,
+    /// Iterations of the first round after a move. Every round doubles
+    /// them, up to `max_round`.
+    pub first_round: u32,
+    pub max_round: u32,
+}
+
+impl Relay {
+    pub fn new(engine: Engine) -> Self {
+        Self {
+            engine,
+            ply: 0,
+            first_round: 1000,
+            max_round: 256_000,
+        }
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Plays a relayed move.
+    pub fn play(&mut self, mve: (u8, u8)) -> Result<(), StoctopusError> {
+        self.engine.play(mve)?;
+        self.ply += 1;
+        Ok(())
+    }
+
+    /// Starts over from the start position and plays `moves`.
+    pub fn set_moves(&mut self, moves: &[(u8, u8)]) -> Result<(), StoctopusError> {
+        self.engine.new_game();
+        self.ply = 0;
+        moves.iter().try_for_each(|&mve| self.play(mve))
+    }
+
+    /// Analyses the current position for `iterations` iterations.
+    pub fn comment(&mut self, iterations: u32) -> Result<Commentary, StoctopusError> {
+        let ev = self.engine.analyze(iterations)?;
+        Ok(Commentary {
+            ply: self.ply,
+            score: ev.score,
+            iterations: ev.info.iterations,
+            pv: self.engine.principal_variation(),
+        })
+    }
+
+    /// Applies an incoming line, returning `false` on `end`.
+    fn apply(&mut self, line: &str) -> Result<bool, StoctopusError> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {}
+            ["move", mve] => self.play(parse_move(mve)?)?,
+            ["position", moves @ ..] => {
+                let moves = moves
+                    .iter()
+                    .map(|m| parse_move(m))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.set_moves(&moves)?;
+            }
+            ["end"] => return Ok(false),
+            _ => return Err(StoctopusError::Protocol(format!("Bad relay line {line:?}"))),
+        }
+        Ok(true)
+    }
+
+    /// Relays the game read from `input`, writing commentary to `output`
+    /// while waiting for moves. Returns on `end` or once the input is
+    /// closed and the last position analysed.
+    pub fn run(
+        &mut self,
+        input: impl BufRead + Send + 'static,
+        mut output: impl Write,
+    ) -> Result<(), StoctopusError> {
+        // Reading blocks, so it happens on its own thread while searching.
+        // The thread is left behind when the relay ends first.
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in input.lines() {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut round = Some(self.first_round);
+        let mut closed = false;
+        loop {
+            let line = match (round, closed) {
+                (_, true) => None,
+                (Some(_), false) => match receiver.try_recv() {
+                    Ok(line) => Some(line),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => {
+                        closed = true;
+                        None
+                    }
+                },
+                (None, false) => match receiver.recv() {
+                    Ok(line) => Some(line),
+                    Err(_) => return Ok(()),
+                },
+            };
+            if let Some(line) = line {
+                match self.apply(line?.trim()) {
+                    Ok(true) => round = Some(self.first_round),
+                    Ok(false) => return Ok(()),
+                    Err(err) => writeln!(output, "info string {err}")?,
+                }
+                output.flush()?;
+                continue;
+            }
+            let iterations = match round {
+                Some(iterations) if !self.engine.is_game_over() => iterations,
+                _ if closed => return Ok(()),
+                _ => {
+                    round = None;
+                    continue;
+                }
+            };
+            writeln!(output, "{}", self.comment(iterations)?)?;
+            output.flush()?;
+            round = iterations
+                .checked_mul(2)
+                .filter(|&iterations| iterations <= self.max_round);
+        }
+    }
+}
+
+#[cfg(test)]
+mod relay_tests {
+    use std::io::Cursor;
+
+    use crate::relay::Relay;
+    use crate::ugi::parse_move;
+    use crate::{Engine, MCTSConfig, SearchMode};
+
+    #[test]
+    fn test_relay() {
+        let engine = Engine::with_config(MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 1 },
+            ..Default::default()
+        });
+        let mut relay = Relay::new(engine);
+        relay.first_round = 25;
+        relay.max_round = 100;
+
+        let input = Cursor::new("position 44\nmove 44\nmove 40\n");
+        let mut output = Vec::new();
+        relay.run(input, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        // The second 44 is illegal.
+        assert!(
+            lines.iter().any(|line| line.starts_with("info string")),
+            "{output}"
+        );
+        // Every round of the last position is published.
+        let last: Vec<&str> = lines.iter().rev().take(3).copied().collect();
+        for (line, nodes) in last.iter().zip([100, 50, 25]) {
+            assert!(line.starts_with("info ply 2 score "), "{line}");
+            assert!(line.contains(&format!(" nodes {nodes} pv ")), "{line}");
+        }
+        let best = last[0].split(" pv ").nth(1).unwrap().split(' ').next();
+        let (global, local) = parse_move(best.unwrap()).unwrap();
+        assert_eq!(global, 0);
+        assert!(relay.engine().board().get_moves() & (1 << (global * 9 + local)) != 0);
+
+        let commentary = relay.comment(50).unwrap();
+        assert_eq!((commentary.ply, commentary.iterations), (2, 50));
+        assert!(commentary.pv.len() > 1);
+        relay.set_moves(&[]).unwrap();
+        assert_eq!(relay.comment(10).unwrap().ply, 0);
+    }
+}
@@ -0,0 +1,814 @@
+//! Many independent games served from one process, each with its own
+//! engine. This is the part of a game server that doesn't depend on the
+//! transport: a front end maps its requests onto [`SessionManager`] calls.
+//!
+//! Games played to the end through [`SessionManager::play`] can be appended
+//! to an audit log, one JSON object per line:
+//!
+//! ```text
+//! {"session":3,"client":"web-7","started_at":1760000000,"finished_at":1760000600,"record":{...}}
+//! ```
+//!
+//! Times are seconds since the Unix epoch, `client` is `null` for sessions
+//! made by [`SessionManager::create`] and `record` is the game in the JSON
+//! form of [`GameRecord::to_json`]. Every move searched with
+//! [`SessionManager::analyze`] first carries the score of that search.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::{SessionError, StoctopusError};
+use crate::{CancellationToken, Engine, Evaluation, GameRecord, MCTSConfig, SearchLimits};
+
+#[derive(Clone, Copy, Debug)]
+pub struct SessionConfig {
+    /// Most sessions alive at once. Creating one more fails.
+    pub max_sessions: usize,
+    /// Sessions unused for this long are dropped by
+    /// [`SessionManager::evict_idle`].
+    pub idle_timeout: Duration,
+    /// Configuration of every session's engine.
+    pub engine: MCTSConfig,
+    /// Requested search limits are clamped to these.
+    pub max_iterations: u32,
+    pub max_time: Duration,
+    /// Most searches running at once, over all sessions.
+    pub max_concurrent_searches: usize,
+    /// What happens to a search beyond `max_concurrent_searches`.
+    pub overload: Overload,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overload {
+    /// Fail with [`SessionError::Overloaded`].
+    #[default]
+    Reject,
+    /// Wait for a running search to finish.
+    Queue,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            max_sessions: 64,
+            idle_timeout: Duration::from_secs(30 * 60),
+            engine: MCTSConfig::default(),
+            max_iterations: 100_000,
+            max_time: Duration::from_secs(10),
+            max_concurrent_searches: 4,
+            overload: Overload::default(),
+        }
+    }
+}
+
+/// Upper bounds of the request latency histogram in [`SessionMetrics`].
+pub const LATENCY_BUCKETS: [Duration; 8] = [
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+    Duration::from_secs(30),
+];
+
+/// What a [`SessionManager`] has done since it started, see
+/// [`SessionManager::metrics`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SessionMetrics {
+    pub sessions: usize,
+    /// Searches that got a search slot.
+    pub searches_started: u64,
+    pub searches_completed: u64,
+    pub iterations: u64,
+    /// Time spent in completed searches.
+    pub search_time: Duration,
+    /// Nodes and bytes of the search trees of sessions that weren't busy
+    /// when the metrics were taken.
+    pub tree_nodes: usize,
+    pub memory: usize,
+    /// Calls to [`SessionManager::analyze`], including rejected ones.
+    pub requests: u64,
+    /// Requests by latency: the first `LATENCY_BUCKETS.len()` entries count
+    /// those within the matching bound and above the one before, the last
+    /// one those above every bound.
+    pub latency_buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    pub latency_sum: Duration,
+}
+
+impl SessionMetrics {
+    /// Average iterations per second of completed searches.
+    pub fn nps(&self) -> f64 {
+        match self.search_time.as_secs_f64() {
+            0.0 => 0.0,
+            secs => self.iterations as f64 / secs,
+        }
+    }
+
+    fn record_request(&mut self, latency: Duration) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| latency <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency_buckets[bucket] += 1;
+        self.latency_sum += latency;
+        self.requests += 1;
+    }
+
+    /// The metrics in the Prometheus text format, for a `/metrics`
+    /// endpoint.
+    pub fn to_prometheus(&self) -> String {
+        let metrics = [
+            (
+                "sessions",
+                "gauge",
+                "Sessions alive.",
+                self.sessions.to_string(),
+            ),
+            (
+                "searches_started_total",
+                "counter",
+                "Searches started.",
+                self.searches_started.to_string(),
+            ),
+            (
+                "searches_completed_total",
+                "counter",
+                "Searches that returned an evaluation.",
+                self.searches_completed.to_string(),
+            ),
+            (
+                "iterations_total",
+                "counter",
+                "Iterations of completed searches.",
+                self.iterations.to_string(),
+            ),
+            (
+                "search_seconds_total",
+                "counter",
+                "Time spent in completed searches.",
+                self.search_time.as_secs_f64().to_string(),
+            ),
+            (
+                "nps",
+                "gauge",
+                "Average iterations per second of completed searches.",
+                format!("{:.1}", self.nps()),
+            ),
+            (
+                "tree_nodes",
+                "gauge",
+                "Nodes in the search trees of idle sessions.",
+                self.tree_nodes.to_string(),
+            ),
+            (
+                "memory_bytes",
+                "gauge",
+                "Size of the search trees of idle sessions.",
+                self.memory.to_string(),
+            ),
+        ];
+        let mut lines = Vec::new();
+        for (name, kind, help, value) in metrics {
+            lines.push(format!("# HELP stoctopus_{name} {help}"));
+            lines.push(format!("# TYPE stoctopus_{name} {kind}"));
+            lines.push(format!("stoctopus_{name} {value}"));
+        }
+
+        let name = "stoctopus_request_latency_seconds";
+        lines.push(format!("# HELP {name} Time to answer analysis requests."));
+        lines.push(format!("# TYPE {name} histogram"));
+        let mut count = 0;
+        for (bound, requests) in LATENCY_BUCKETS.iter().zip(&self.latency_buckets) {
+            count += requests;
+            let le = bound.as_secs_f64();
+            lines.push(format!("{name}_bucket{{le=\"{le}\"}} {count}"));
+        }
+        lines.push(format!("{name}_bucket{{le=\"+Inf\"}} {}", self.requests));
+        lines.push(format!("{name}_sum {}", self.latency_sum.as_secs_f64()));
+        lines.push(format!("{name}_count {}", self.requests));
+        lines.join("\n") + "\n"
+    }
+}
+
+/// A session's engine and what the audit log needs about its game.
+struct Game {
+    engine: Engine,
+    client: Option<String>,
+    started_at: u64,
+    record: GameRecord,
+    /// Score of the last search, with the number of moves at the time.
+    last_score: Option<(usize, f32)>,
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+struct Session {
+    game: Arc<Mutex<Game>>,
+    last_used: Instant,
+    /// Configuration generation the engine was last set up with.
+    generation: u64,
+}
+
+/// Thread-safe registry of game sessions. Requests on different sessions
+/// run concurrently; requests on the same session take turns.
+pub struct SessionManager {
+    config: RwLock<SessionConfig>,
+    /// Bumped by every [`Self::reload`].
+    generation: AtomicU64,
+    sessions: Mutex<HashMap<u64, Session>>,
+    next_id: AtomicU64,
+    /// Searches running right now.
+    searches: Mutex<usize>,
+    search_finished: Condvar,
+    metrics: Mutex<SessionMetrics>,
+    /// Cancellation tokens of the running searches, by search number.
+    running: Mutex<HashMap<u64, CancellationToken>>,
+    next_search: AtomicU64,
+    shutting_down: AtomicBool,
+    audit_log: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+/// Holds one of the concurrent search slots until dropped.
+struct SearchSlot<'a>(&'a SessionManager);
+
+impl Drop for SearchSlot<'_> {
+    fn drop(&mut self) {
+        *self.0.searches.lock().expect("Search count poisoned") -= 1;
+        // Queued searches and a shutdown may both be waiting.
+        self.0.search_finished.notify_all();
+    }
+}
+
+/// Keeps a search's cancellation token reachable by
+/// [`SessionManager::shutdown`] until dropped.
+struct RunningSearch<'a>(&'a SessionManager, u64);
+
+impl Drop for RunningSearch<'_> {
+    fn drop(&mut self) {
+        let mut running = self.0.running.lock().expect("Running searches poisoned");
+        running.remove(&self.1);
+    }
+}
+
+impl SessionManager {
+    pub fn new(config: SessionConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+            generation: AtomicU64::new(0),
+            sessions: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            searches: Mutex::new(0),
+            search_finished: Condvar::new(),
+            metrics: Mutex::new(SessionMetrics::default()),
+            running: Mutex::new(HashMap::new()),
+            next_search: AtomicU64::new(0),
+            shutting_down: AtomicBool::new(false),
+            audit_log: None,
+        }
+    }
+
+    /// Appends every game played to the end to `log`, see the module
+    /// documentation.
+    pub fn with_audit_log(mut self, log: impl Write + Send + 'static) -> Self {
+        self.audit_log = Some(Mutex::new(Box::new(log)));
+        self
+    }
+
+    pub fn config(&self) -> SessionConfig {
+        *self.config.read().expect("Config poisoned")
+    }
+
+    /// Switches to `config` without dropping any session. Limits and
+    /// budgets apply to the next request; session engines take the new
+    /// engine configuration before their next request, so a search already
+    /// running finishes as it started. Sessions beyond a lowered
+    /// `max_sessions` stay until they are closed or evicted.
+    pub fn reload(&self, config: SessionConfig) {
+        *self.config.write().expect("Config poisoned") = config;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        // Queued searches may fit under a raised limit.
+        self.search_finished.notify_all();
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.lock().expect("Session map poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Starts a new game and returns its session id.
+    pub fn create(&self) -> Result<u64, StoctopusError> {
+        self.create_session(None)
+    }
+
+    /// As [`Self::create`], naming the client in the audit log.
+    pub fn create_for(&self, client: &str) -> Result<u64, StoctopusError> {
+        self.create_session(Some(client.to_string()))
+    }
+
+    fn create_session(&self, client: Option<String>) -> Result<u64, StoctopusError> {
+        if self.is_shutting_down() {
+            return Err(SessionError::ShuttingDown.into());
+        }
+        let mut sessions = self.sessions.lock().expect("Session map poisoned");
+        let generation = self.generation.load(Ordering::SeqCst);
+        let config = self.config();
+        if sessions.len() >= config.max_sessions {
+            return Err(SessionError::TooManySessions.into());
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        sessions.insert(
+            id,
+            Session {
+                game: Arc::new(Mutex::new(Game {
+                    engine: Engine::with_config(config.engine),
+                    client,
+                    started_at: unix_time(),
+                    record: GameRecord::default(),
+                    last_score: None,
+                })),
+                last_used: Instant::now(),
+                generation,
+            },
+        );
+        Ok(id)
+    }
+
+    pub fn close(&self, id: u64) -> Result<(), StoctopusError> {
+        let mut sessions = self.sessions.lock().expect("Session map poisoned");
+        sessions.remove(&id).ok_or(SessionError::Unknown(id))?;
+        Ok(())
+    }
+
+    /// Runs `f` on the session's engine. The map is only locked to look the
+    /// session up, so long searches don't block other sessions. Moves made
+    /// here aren't recorded for the audit log.
+    pub fn with_engine<T>(
+        &self,
+        id: u64,
+        f: impl FnOnce(&mut Engine) -> Result<T, StoctopusError>,
+    ) -> Result<T, StoctopusError> {
+        self.with_game(id, |game| f(&mut game.engine))
+    }
+
+    fn with_game<T>(
+        &self,
+        id: u64,
+        f: impl FnOnce(&mut Game) -> Result<T, StoctopusError>,
+    ) -> Result<T, StoctopusError> {
+        let (game, stale) = {
+            let mut sessions = self.sessions.lock().expect("Session map poisoned");
+            let session = sessions.get_mut(&id).ok_or(SessionError::Unknown(id))?;
+            session.last_used = Instant::now();
+            let generation = self.generation.load(Ordering::SeqCst);
+            let stale = session.generation != generation;
+            session.generation = generation;
+            (session.game.clone(), stale)
+        };
+        let mut game = game.lock().expect("Session engine poisoned");
+        if stale {
+            game.engine.set_config(self.config().engine);
+        }
+        f(&mut game)
+    }
+
+    /// Plays `mve` in the session's game. When that ends the game and
+    /// there is an audit log, the game is written to it; an error doing so
+    /// is returned, but the move stands.
+    pub fn play(&self, id: u64, mve: (u8, u8)) -> Result<(), StoctopusError> {
+        self.with_game(id, |game| {
+            game.engine.play(mve)?;
+            let index = game.record.moves.len();
+            game.record.moves.push(mve);
+            if let Some((_, score)) = game.last_score.take().filter(|&(at, _)| at == index) {
+                game.record.annotate(index).eval = Some(score);
+            }
+            if !game.engine.is_game_over() {
+                return Ok(());
+            }
+            game.record.result = game.engine.game_state();
+            self.write_audit(id, game)
+        })
+    }
+
+    fn write_audit(&self, id: u64, game: &Game) -> Result<(), StoctopusError> {
+        let Some(log) = &self.audit_log else {
+            return Ok(());
+        };
+        let record: serde_json::Value =
+            serde_json::from_str(&game.record.to_json()).expect("Records are valid JSON");
+        let entry = serde_json::json!({
+            "session": id,
+            "client": game.client,
+            "started_at": game.started_at,
+            "finished_at": unix_time(),
+            "record": record,
+        });
+        let mut log = log.lock().expect("Audit log poisoned");
+        writeln!(log, "{entry}")?;
+        log.flush()?;
+        Ok(())
+    }
+
+    /// `limits` clamped to the per-request budget.
+    pub fn budget(&self, limits: SearchLimits) -> SearchLimits {
+        let config = self.config();
+        SearchLimits {
+            iterations: limits.iterations.min(config.max_iterations),
+            time: Some(
+                limits
+                    .time
+                    .map_or(config.max_time, |time| time.min(config.max_time)),
+            ),
+        }
+    }
+
+    fn acquire_search(&self) -> Result<SearchSlot<'_>, StoctopusError> {
+        let mut searches = self.searches.lock().expect("Search count poisoned");
+        loop {
+            if self.is_shutting_down() {
+                return Err(SessionError::ShuttingDown.into());
+            }
+            let config = self.config();
+            if *searches < config.max_concurrent_searches {
+                break;
+            }
+            match config.overload {
+                Overload::Reject => return Err(SessionError::Overloaded.into()),
+                Overload::Queue => {
+                    searches = self
+                        .search_finished
+                        .wait(searches)
+                        .expect("Search count poisoned");
+                }
+            }
+        }
+        *searches += 1;
+        Ok(SearchSlot(self))
+    }
+
+    /// Searches the session's position within the per-request budget,
+    /// subject to the concurrent search limit.
+    pub fn analyze(
+        &self,
+        id: u64,
+        limits: SearchLimits,
+        cancel: &CancellationToken,
+    ) -> Result<Evaluation, StoctopusError> {
+        let start = Instant::now();
+        let result = self.search(id, self.budget(limits), cancel);
+        let mut metrics = self.metrics.lock().expect("Metrics poisoned");
+        metrics.record_request(start.elapsed());
+        result
+    }
+
+    fn search(
+        &self,
+        id: u64,
+        limits: SearchLimits,
+        cancel: &CancellationToken,
+    ) -> Result<Evaluation, StoctopusError> {
+        let _slot = self.acquire_search()?;
+        let _running = self.register(cancel);
+        self.metrics
+            .lock()
+            .expect("Metrics poisoned")
+            .searches_started += 1;
+        let start = Instant::now();
+        let evaluation = self.with_game(id, |game| {
+            let evaluation = game.engine.analyze_with_limits(limits, cancel)?;
+            game.last_score = Some((game.record.moves.len(), evaluation.score));
+            Ok(evaluation)
+        })?;
+        let mut metrics = self.metrics.lock().expect("Metrics poisoned");
+        metrics.searches_completed += 1;
+        metrics.iterations += evaluation.info.iterations as u64;
+        metrics.search_time += start.elapsed();
+        Ok(evaluation)
+    }
+
+    fn register(&self, cancel: &CancellationToken) -> RunningSearch<'_> {
+        let number = self.next_search.fetch_add(1, Ordering::Relaxed);
+        let mut running = self.running.lock().expect("Running searches poisoned");
+        running.insert(number, cancel.clone());
+        // A shutdown that started after the slot was taken has already
+        // cancelled the searches it could see.
+        if self.is_shutting_down() {
+            cancel.cancel();
+        }
+        RunningSearch(self, number)
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Stops taking new sessions and searches, cancels the running
+    /// searches so they return their best move so far, and waits up to
+    /// `deadline` for them to finish. Returns whether they all did.
+    /// Sessions stay available for reading until the manager is dropped.
+    pub fn shutdown(&self, deadline: Duration) -> bool {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        for cancel in self
+            .running
+            .lock()
+            .expect("Running searches poisoned")
+            .values()
+        {
+            cancel.cancel();
+        }
+        let searches = self.searches.lock().expect("Search count poisoned");
+        // Wakes queued searches too, which now fail.
+        self.search_finished.notify_all();
+        let (searches, _) = self
+            .search_finished
+            .wait_timeout_while(searches, deadline, |searches| *searches > 0)
+            .expect("Search count poisoned");
+        *searches == 0
+    }
+
+    /// Counters and gauges for monitoring. Sessions busy with a request
+    /// are left out of the tree sizes rather than waited for.
+    pub fn metrics(&self) -> SessionMetrics {
+        let games: Vec<_> = {
+            let sessions = self.sessions.lock().expect("Session map poisoned");
+            sessions
+                .values()
+                .map(|session| session.game.clone())
+                .collect()
+        };
+        let mut metrics = self.metrics.lock().expect("Metrics poisoned").clone();
+        metrics.sessions = games.len();
+        for game in games {
+            if let Ok(game) = game.try_lock() {
+                metrics.tree_nodes += game.engine.tree_size();
+                metrics.memory += game.engine.memory();
+            }
+        }
+        metrics
+    }
+
+    /// Drops sessions that have been idle for longer than the timeout at
+    /// `now`, returning how many were dropped. A request already running on
+    /// a dropped session still finishes.
+    pub fn evict_idle(&self, now: Instant) -> usize {
+        let idle_timeout = self.config().idle_timeout;
+        let mut sessions = self.sessions.lock().expect("Session map poisoned");
+        let before = sessions.len();
+        sessions
+            .retain(|_, session| now.saturating_duration_since(session.last_used) <= idle_timeout);
+        before - sessions.len()
+    }
+}
+
+#[cfg(test)]
+mod session_tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use crate::error::SessionError;
+    use crate::session::{Overload, SessionConfig, SessionManager, LATENCY_BUCKETS};
+    use crate::{
+        CancellationToken, GameRecord, MCTSConfig, SearchLimits, SearchMode, StoctopusError,
+    };
+
+    fn manager(max_sessions: usize) -> SessionManager {
+        SessionManager::new(SessionConfig {
+            max_sessions,
+            idle_timeout: Duration::from_secs(60),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_sessions_are_independent() {
+        let manager = manager(4);
+        let a = manager.create().unwrap();
+        let b = manager.create().unwrap();
+        manager.play(a, (4, 4)).unwrap();
+        manager.play(b, (0, 0)).unwrap();
+        let moved = |id| manager.with_engine(id, |engine| Ok(engine.board().last_move));
+        assert_eq!(moved(a).unwrap(), Some(0x44));
+        assert_eq!(moved(b).unwrap(), Some(0x00));
+
+        let ev = manager
+            .analyze(a, SearchLimits::iterations(20), &CancellationToken::new())
+            .unwrap();
+        assert!(ev.best_move.is_some());
+
+        manager.close(a).unwrap();
+        assert!(matches!(
+            manager.play(a, (4, 0)),
+            Err(StoctopusError::Session(SessionError::Unknown(_)))
+        ));
+    }
+
+    #[test]
+    fn test_limit_and_eviction() {
+        let manager = manager(2);
+        manager.create().unwrap();
+        manager.create().unwrap();
+        assert!(matches!(
+            manager.create(),
+            Err(StoctopusError::Session(SessionError::TooManySessions))
+        ));
+
+        assert_eq!(manager.evict_idle(Instant::now()), 0);
+        let later = Instant::now() + Duration::from_secs(61);
+        assert_eq!(manager.evict_idle(later), 2);
+        assert!(manager.is_empty());
+        manager.create().unwrap();
+    }
+
+    #[test]
+    fn test_reload() {
+        let manager = manager(1);
+        let id = manager.create().unwrap();
+        manager.play(id, (4, 4)).unwrap();
+        manager.reload(SessionConfig {
+            max_sessions: 2,
+            max_iterations: 50,
+            engine: MCTSConfig {
+                mode: SearchMode::Deterministic { seed: 3 },
+                ..MCTSConfig::default()
+            },
+            ..manager.config()
+        });
+        assert_eq!(manager.budget(SearchLimits::iterations(80)).iterations, 50);
+        // The game goes on under the new engine configuration.
+        let mode = |id| manager.with_engine(id, |engine| Ok(engine.config().mode));
+        assert_eq!(mode(id).unwrap(), SearchMode::Deterministic { seed: 3 });
+        let last_move = manager.with_engine(id, |engine| Ok(engine.board().last_move));
+        assert_eq!(last_move.unwrap(), Some(0x44));
+        let other = manager.create().unwrap();
+        assert_eq!(mode(other).unwrap(), SearchMode::Deterministic { seed: 3 });
+    }
+
+    #[test]
+    fn test_metrics() {
+        let manager = manager(2);
+        let id = manager.create().unwrap();
+        manager.create().unwrap();
+        let cancel = CancellationToken::new();
+        manager
+            .analyze(id, SearchLimits::iterations(30), &cancel)
+            .unwrap();
+        assert!(manager
+            .analyze(99, SearchLimits::iterations(30), &cancel)
+            .is_err());
+
+        let metrics = manager.metrics();
+        assert_eq!(metrics.sessions, 2);
+        assert_eq!(metrics.searches_started, 2);
+        assert_eq!(metrics.searches_completed, 1);
+        assert_eq!(metrics.iterations, 30);
+        assert_eq!(metrics.requests, 2);
+        assert_eq!(metrics.latency_buckets.iter().sum::<u64>(), 2);
+        assert!(metrics.tree_nodes > 2 && metrics.memory > 0);
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains("stoctopus_searches_completed_total 1\n"));
+        assert!(text.contains("# TYPE stoctopus_request_latency_seconds histogram"));
+        assert!(text.contains("stoctopus_request_latency_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert_eq!(text.matches("_bucket{").count(), LATENCY_BUCKETS.len() + 1);
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let manager = manager(2);
+        let id = manager.create().unwrap();
+        manager.play(id, (4, 4)).unwrap();
+        let cancel = CancellationToken::new();
+        std::thread::scope(|scope| {
+            let search =
+                scope.spawn(|| manager.analyze(id, SearchLimits::iterations(u32::MAX), &cancel));
+            // Let the search expand the root, so it has a move to return.
+            std::thread::sleep(Duration::from_millis(50));
+            assert!(manager.shutdown(Duration::from_secs(10)));
+            assert!(cancel.is_cancelled());
+            assert!(search.join().unwrap().unwrap().best_move.is_some());
+        });
+
+        assert!(manager.is_shutting_down());
+        assert!(matches!(
+            manager.create(),
+            Err(StoctopusError::Session(SessionError::ShuttingDown))
+        ));
+        let search = manager.analyze(id, SearchLimits::iterations(5), &CancellationToken::new());
+        assert!(matches!(
+            search,
+            Err(StoctopusError::Session(SessionError::ShuttingDown))
+        ));
+        // Finished games can still be read.
+        let last_move = manager.with_engine(id, |engine| Ok(engine.board().last_move));
+        assert_eq!(last_move.unwrap(), Some(0x44));
+        assert!(manager.shutdown(Duration::ZERO));
+    }
+
+    /// Shares what is written with the test.
+    #[derive(Clone, Default)]
+    struct SharedLog(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedLog {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let log = SharedLog::default();
+        let manager = manager(2).with_audit_log(log.clone());
+        let id = manager.create_for("web-7").unwrap();
+        let other = manager.create().unwrap();
+        manager.play(other, (4, 4)).unwrap();
+
+        let cancel = CancellationToken::new();
+        let score = manager
+            .analyze(id, SearchLimits::iterations(30), &cancel)
+            .unwrap()
+            .score;
+        let mut moves = Vec::new();
+        loop {
+            let legal = manager.with_engine(id, |engine| Ok(engine.board().get_moves()));
+            let index = legal.unwrap().trailing_zeros() as u8;
+            let mve = (index / 9, index % 9);
+            manager.play(id, mve).unwrap();
+            moves.push(mve);
+            if manager
+                .with_engine(id, |engine| Ok(engine.is_game_over()))
+                .unwrap()
+            {
+                break;
+            }
+        }
+
+        // Only the finished game is logged.
+        let text = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(text.lines().count(), 1);
+        let entry: serde_json::Value = serde_json::from_str(text.trim()).unwrap();
+        assert_eq!(entry["session"], id);
+        assert_eq!(entry["client"], "web-7");
+        assert!(entry["finished_at"].as_u64() >= entry["started_at"].as_u64());
+        let record = GameRecord::from_json(&entry["record"].to_string()).unwrap();
+        assert_eq!(record.moves, moves);
+        assert_eq!(record.annotations[&0].eval, Some(score));
+        assert_eq!(record.annotations.len(), 1);
+        let result = manager.with_engine(id, |engine| Ok(engine.game_state()));
+        assert_eq!(record.result, result.unwrap());
+    }
+
+    #[test]
+    fn test_budget() {
+        let manager = manager(1);
+        let limits = manager.budget(SearchLimits::iterations(u32::MAX));
+        assert_eq!(limits.iterations, 100_000);
+        assert_eq!(limits.time, Some(Duration::from_secs(10)));
+        let limits = manager.budget(SearchLimits::time(Duration::from_millis(5)));
+        assert_eq!(limits.time, Some(Duration::from_millis(5)));
+    }
+
+    #[test]
+    fn test_concurrent_search_limit() {
+        for overload in [Overload::Reject, Overload::Queue] {
+            let manager = SessionManager::new(SessionConfig {
+                max_concurrent_searches: 1,
+                overload,
+                ..Default::default()
+            });
+            let id = manager.create().unwrap();
+            let slot = manager.acquire_search().unwrap();
+            std::thread::scope(|scope| {
+                let search = scope.spawn(|| {
+                    manager.analyze(id, SearchLimits::iterations(5), &CancellationToken::new())
+                });
+                if overload == Overload::Queue {
+                    std::thread::sleep(Duration::from_millis(20));
+                    drop(slot);
+                    assert!(search.join().unwrap().is_ok());
+                } else {
+                    assert!(matches!(
+                        search.join().unwrap(),
+                        Err(StoctopusError::Session(SessionError::Overloaded))
+                    ));
+                }
+            });
+        }
+    }
+}
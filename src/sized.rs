@@ -0,0 +1,292 @@
+//! Ultimate tic-tac-toe on `N`×`N` sub-boards of `N`×`N` cells, for the 4×4
+//! variant some communities play. [`Board`](crate::Board) stays the board
+//! of the engine: the search, the SIMD win checks and every file format are
+//! built on its 81-cell bit layout. [`SizedBoard`] has the rules for either
+//! size, with the win lines of each computed at compile time, and
+//! `SizedBoard<3>` plays exactly like `Board` under the default rules.
+//!
+//! Sub-boards and their cells are both numbered in reading order, so a move
+//! is `(global, local)` with both below `N * N`.
+
+use rand::Rng;
+
+use crate::game::{GameState, Player};
+
+const MAX_N: usize = 4;
+/// Cells of the largest sub-board, which is also the most sub-boards.
+const MAX_CELLS: usize = MAX_N * MAX_N;
+const MAX_LINES: usize = 2 * MAX_N + 2;
+
+/// Rows, columns and diagonals of an `n`×`n` grid whose cell `row * n + col`
+/// is bit `row * n + col`. Only the first `2 * n + 2` are lines.
+const fn win_lines(n: usize) -> [u16; MAX_LINES] {
+    let mut lines = [0; MAX_LINES];
+    let mut i = 0;
+    while i < n {
+        let mut j = 0;
+        while j < n {
+            lines[i] |= 1 << (i * n + j);
+            lines[n + i] |= 1 << (j * n + i);
+            j += 1;
+        }
+        lines[2 * n] |= 1 << (i * n + i);
+        lines[2 * n + 1] |= 1 << (i * n + n - 1 - i);
+        i += 1;
+    }
+    lines
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizedBoard<const N: usize = 3> {
+    /// Marks of X in every sub-board, cell `local` being bit `local`.
+    x: [u16; MAX_CELLS],
+    o: [u16; MAX_CELLS],
+    /// Sub-boards won by X and by O, both for a drawn one.
+    gx: u16,
+    go: u16,
+    next_player: Player,
+    last_move: Option<(u8, u8)>,
+}
+
+/// The 4×4 variant.
+pub type Board4 = SizedBoard<4>;
+
+impl<const N: usize> Default for SizedBoard<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> SizedBoard<N> {
+    const SIZE: () = assert!(N == 3 || N == 4, "Sub-boards are 3x3 or 4x4");
+    /// Cells of a sub-board, and sub-boards of the game.
+    pub const CELLS: usize = N * N;
+    /// Win lines of a sub-board, and of the macro board.
+    const LINES: [u16; MAX_LINES] = win_lines(N);
+    const LINE_COUNT: usize = 2 * N + 2;
+    const FULL: u16 = ((1u32 << (N * N)) - 1) as u16;
+
+    /// The empty board, X to move.
+    pub fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::SIZE;
+        Self {
+            x: [0; MAX_CELLS],
+            o: [0; MAX_CELLS],
+            gx: 0,
+            go: 0,
+            next_player: Player::X,
+            last_move: None,
+        }
+    }
+
+    pub fn next_player(&self) -> Player {
+        self.next_player
+    }
+
+    pub fn last_move(&self) -> Option<(u8, u8)> {
+        self.last_move
+    }
+
+    /// Who marked the cell, if anyone did.
+    pub fn cell(&self, global: u8, local: u8) -> Option<Player> {
+        let bit = 1 << local;
+        if self.x[global as usize] & bit != 0 {
+            Some(Player::X)
+        } else if self.o[global as usize] & bit != 0 {
+            Some(Player::O)
+        } else {
+            None
+        }
+    }
+
+    /// Sub-board the side to move has to play in, `None` when any undecided
+    /// one will do. Same rule as [`Board::forced_board`](crate::Board::forced_board).
+    pub fn forced_board(&self) -> Option<u8> {
+        let (_, local) = self.last_move?;
+        ((self.gx | self.go) & (1 << local) == 0).then_some(local)
+    }
+
+    /// Cells of sub-board `global` the side to move may play, as bits.
+    pub fn moves_in(&self, global: u8) -> u16 {
+        let decided = (self.gx | self.go) & (1 << global) != 0;
+        let allowed = self.forced_board().is_none_or(|forced| forced == global);
+        if self.game_over() || decided || !allowed {
+            0
+        } else {
+            !(self.x[global as usize] | self.o[global as usize]) & Self::FULL
+        }
+    }
+
+    /// Legal moves in order of sub-board, then cell.
+    pub fn legal_moves(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        (0..Self::CELLS as u8).flat_map(move |global| {
+            let moves = self.moves_in(global);
+            (0..Self::CELLS as u8)
+                .filter(move |&local| moves & (1 << local) != 0)
+                .map(move |local| (global, local))
+        })
+    }
+
+    pub fn is_legal(&self, (global, local): (u8, u8)) -> bool {
+        (global as usize) < Self::CELLS
+            && (local as usize) < Self::CELLS
+            && self.moves_in(global) & (1 << local) != 0
+    }
+
+    /// The board after `mve`, or `None` if it isn't legal.
+    pub fn play(&self, mve: (u8, u8)) -> Option<Self> {
+        self.is_legal(mve).then(|| self.unchecked_play(mve))
+    }
+
+    /// Does not check validity of the move.
+    pub fn unchecked_play(&self, (global, local): (u8, u8)) -> Self {
+        let mut board = *self;
+        let g = global as usize;
+        let marks = match board.next_player {
+            Player::X => &mut board.x[g],
+            Player::O => &mut board.o[g],
+        };
+        *marks |= 1 << local;
+        if Self::has_line(*marks) {
+            match board.next_player {
+                Player::X => board.gx |= 1 << global,
+                Player::O => board.go |= 1 << global,
+            }
+        } else if board.x[g] | board.o[g] == Self::FULL {
+            board.gx |= 1 << global;
+            board.go |= 1 << global;
+        }
+        board.last_move = Some((global, local));
+        board.next_player = board.next_player.other();
+        board
+    }
+
+    pub fn check_game_state(&self) -> GameState {
+        let drawn = self.gx & self.go;
+        if Self::has_line(self.gx & !drawn) {
+            GameState::Won(Player::X)
+        } else if Self::has_line(self.go & !drawn) {
+            GameState::Won(Player::O)
+        } else if self.gx | self.go == Self::FULL {
+            GameState::Draw
+        } else {
+            GameState::InProgress
+        }
+    }
+
+    pub fn game_over(&self) -> bool {
+        self.check_game_state() != GameState::InProgress
+    }
+
+    /// A position reached by `plies` random moves, fewer if the game ends
+    /// first.
+    pub fn random_position<R: Rng>(plies: u32, rng: &mut R) -> Self {
+        let mut board = Self::new();
+        for _ in 0..plies {
+            let count = board.legal_moves().count();
+            if count == 0 {
+                break;
+            }
+            let k = rng.gen_range(0..count);
+            let mve = board
+                .legal_moves()
+                .nth(k)
+                .expect("k is below the move count");
+            board = board.unchecked_play(mve);
+        }
+        board
+    }
+
+    fn has_line(bits: u16) -> bool {
+        Self::LINES[..Self::LINE_COUNT]
+            .iter()
+            .any(|&line| line & !bits == 0)
+    }
+}
+
+#[cfg(test)]
+mod sized_tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::game::{Board, GameState, Player};
+    use crate::sized::{win_lines, Board4, SizedBoard};
+
+    #[test]
+    fn test_win_lines() {
+        assert_eq!(
+            win_lines(3)[..8],
+            [
+                0b111,
+                0b111_000,
+                0b111_000_000,
+                0b1_001_001,
+                0b10_010_010,
+                0b100_100_100,
+                0b100_010_001,
+                0b1_010_100
+            ]
+        );
+        let lines = win_lines(4);
+        assert_eq!(lines[0], 0xf);
+        assert_eq!(lines[4], 0x1111);
+        assert_eq!(lines[8], 0x8421);
+        assert_eq!(lines[9], 0x1248);
+        assert!(lines[..10].iter().all(|line| line.count_ones() == 4));
+    }
+
+    #[test]
+    fn test_matches_board() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..50 {
+            let mut board = Board::default();
+            let mut sized = SizedBoard::<3>::new();
+            loop {
+                let moves = if board.game_over() {
+                    0
+                } else {
+                    board.get_moves()
+                };
+                let legal: Vec<_> = (0..81u8)
+                    .filter(|&cell| moves & (1 << cell) != 0)
+                    .map(|cell| (cell / 9, cell % 9))
+                    .collect();
+                assert_eq!(sized.legal_moves().collect::<Vec<_>>(), legal);
+                assert_eq!(sized.check_game_state(), board.check_game_state());
+                if legal.is_empty() {
+                    break;
+                }
+                let (global, local) = legal[rng.gen_range(0..legal.len())];
+                board = board.unchecked_play(Board::move_from_gl(global, local));
+                sized = sized.play((global, local)).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_four_by_four() {
+        let board = Board4::new();
+        assert_eq!(board.legal_moves().count(), 256);
+        assert!(!board.is_legal((16, 0)));
+        assert!(!board.is_legal((0, 16)));
+
+        // X takes the top row of sub-board 5, O sending it back every time.
+        let mut board = board;
+        for mve in [(5, 0), (0, 5), (5, 1), (1, 5), (5, 2), (2, 5), (5, 3)] {
+            board = board.play(mve).unwrap();
+        }
+        assert_eq!(board.cell(5, 3), Some(Player::X));
+        assert_eq!(board.forced_board(), Some(3));
+        assert!(board.play((0, 0)).is_none());
+        // Sent to the won sub-board, O may play anywhere else.
+        let board = board.play((3, 5)).unwrap();
+        assert_eq!(board.forced_board(), None);
+        assert_eq!(board.moves_in(5), 0);
+        assert_eq!(board.check_game_state(), GameState::InProgress);
+
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..20 {
+            assert!(Board4::random_position(256, &mut rng).game_over());
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Suites of positions in an EPD-like text format, for starting engine
+//! matches from varied openings and for testing engines on known
+//! positions. Every line holds a position in the grid notation of
+//! [`Board::to_grid_string`], followed by operations that each end in `;`:
+//!
+//! ```text
+//! # The centre opening.
+//! ........................................X........................................ o4 id "centre"; bm 40 48;
+//! ```
+//!
+//! `id` names the position and `bm` lists its best moves in UGI notation.
+//! Other operations are ignored. Blank lines and lines starting with `#`
+//! are skipped.
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use crate::game::Board;
+use crate::ugi::{format_move, parse_move};
+use crate::StoctopusError;
+
+/// A position of a [`PositionSuite`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SuitePosition {
+    pub board: Board,
+    pub id: Option<String>,
+    /// Moves as `(global, local)` pairs.
+    pub best_moves: Vec<(u8, u8)>,
+}
+
+impl SuitePosition {
+    pub fn new(board: Board) -> Self {
+        Self {
+            board,
+            id: None,
+            best_moves: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PositionSuite {
+    pub positions: Vec<SuitePosition>,
+}
+
+fn invalid(line: usize, msg: impl Display) -> StoctopusError {
+    StoctopusError::Protocol(format!("Bad suite line {line}: {msg}"))
+}
+
+fn parse_position(text: &str) -> Result<SuitePosition, String> {
+    let mut parts = text.splitn(3, ' ');
+    let (cells, suffix) = parts.next().zip(parts.next()).ok_or("missing position")?;
+    let board =
+        Board::from_grid_string(&format!("{cells} {suffix}")).map_err(|err| err.to_string())?;
+    let mut position = SuitePosition::new(board);
+
+    let operations = parts.next().unwrap_or("").trim();
+    let operations = match operations.strip_suffix(';') {
+        Some(operations) => operations,
+        None if operations.is_empty() => return Ok(position),
+        None => return Err("operation without ;".to_string()),
+    };
+    for operation in operations.split(';').map(str::trim) {
+        let (name, value) = operation.split_once(' ').unwrap_or((operation, ""));
+        match name {
+            "id" => {
+                let id = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .ok_or("id must be quoted")?;
+                position.id = Some(id.to_string());
+            }
+            "bm" => {
+                position.best_moves = value
+                    .split_whitespace()
+                    .map(|mve| {
+                        parse_move(mve)
+                            .ok()
+                            .filter(|&(global, local)| {
+                                board.get_moves() & (1 << (global * 9 + local)) != 0
+                            })
+                            .ok_or_else(|| format!("bad best move {mve:?}"))
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            _ => {}
+        }
+    }
+    Ok(position)
+}
+
+impl FromStr for PositionSuite {
+    type Err = StoctopusError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let positions = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(number, line)| parse_position(line).map_err(|msg| invalid(number, msg)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { positions })
+    }
+}
+
+impl Display for SuitePosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.board.to_grid_string())?;
+        if let Some(id) = &self.id {
+            write!(f, " id \"{id}\";")?;
+        }
+        if !self.best_moves.is_empty() {
+            let moves: Vec<_> = self
+                .best_moves
+                .iter()
+                .map(|&mve| format_move(mve))
+                .collect();
+            write!(f, " bm {};", moves.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for PositionSuite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for position in &self.positions {
+            writeln!(f, "{position}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod suite_tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::game::Board;
+    use crate::suite::{PositionSuite, SuitePosition};
+
+    #[test]
+    fn test_suite_round_trip() {
+        let board = Board::default().unchecked_play(0x44);
+        let text = format!(
+            "# Openings\n\n{} id \"centre\"; bm 40 48; ce 12;\n{}\n",
+            board.to_grid_string(),
+            Board::default().to_grid_string()
+        );
+        let suite: PositionSuite = text.parse().unwrap();
+        assert_eq!(suite.positions.len(), 2);
+        assert_eq!(suite.positions[0].board, board);
+        assert_eq!(suite.positions[0].id.as_deref(), Some("centre"));
+        assert_eq!(suite.positions[0].best_moves, [(4, 0), (4, 8)]);
+        assert_eq!(suite.positions[1], SuitePosition::new(Board::default()));
+        assert_eq!(suite.to_string().parse::<PositionSuite>().unwrap(), suite);
+
+        // The grid notation doesn't keep the last move, only the cells.
+        let suite = PositionSuite {
+            positions: (0..4)
+                .map(|seed| {
+                    SuitePosition::new(Board::random_position(10, &mut StdRng::seed_from_u64(seed)))
+                })
+                .collect(),
+        };
+        let text = suite.to_string();
+        assert_eq!(text.parse::<PositionSuite>().unwrap().to_string(), text);
+    }
+
+    #[test]
+    fn test_rejects_bad_suites() {
+        let grid = Board::default().to_grid_string();
+        for text in [
+            "x4".to_string(),
+            format!("{grid} id centre;"),
+            format!("{grid} bm 44"),
+            format!("{grid} bm 99;"),
+        ] {
+            assert!(text.parse::<PositionSuite>().is_err(), "{text}");
+        }
+        // Errors point at the line.
+        let error = format!("{grid}\n{grid} bm 9;").parse::<PositionSuite>();
+        assert!(error.unwrap_err().to_string().contains("line 2"));
+    }
+}
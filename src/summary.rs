@@ -0,0 +1,212 @@
+//! Short explanations of the best move, for UIs showing why a move was
+//! chosen. [`Evaluation::summary`] lists what the move does in terms of the
+//! threats on the board, e.g. "X 42 (+35): wins sub-board 4, sends O to
+//! sub-board 2; expected 42 20 04".
+
+use std::fmt::{self, Display};
+
+use crate::game::{Board, GameState, Player};
+use crate::ugi::format_move;
+use crate::Evaluation;
+
+/// One thing a move does. Sub-boards and cells are numbered as in UGI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveReason {
+    WinsGame,
+    WinsBoard(u8),
+    /// Takes the cell where the opponent would have won the sub-board.
+    BlocksBoard(u8),
+    /// New cells of the sub-board that would win it next time, one bit per
+    /// cell.
+    Threatens {
+        board: u8,
+        cells: u16,
+    },
+    /// The opponent has to play in `board`, where the mover and the
+    /// opponent can win it at the given cells.
+    SendsTo {
+        board: u8,
+        own_threats: u16,
+        opponent_threats: u16,
+    },
+    /// The opponent may play in any undecided sub-board.
+    FreeMove,
+}
+
+/// What the best move of an [`Evaluation`] does, see the module
+/// documentation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MoveSummary {
+    pub player: Player,
+    pub mve: (u8, u8),
+    /// As [`Evaluation::score`].
+    pub score: f32,
+    pub reasons: Vec<MoveReason>,
+    /// Expected line, starting with the move.
+    pub pv: Vec<(u8, u8)>,
+}
+
+impl MoveSummary {
+    /// Explains playing `mve` in `board`, which must be legal.
+    pub fn new(board: &Board, mve: (u8, u8), score: f32, pv: Vec<(u8, u8)>) -> Self {
+        let player = board.next_player;
+        let opponent = player.other();
+        let (global, local) = mve;
+        let after = board.unchecked_play(Board::move_from_gl(global, local));
+        let mut reasons = vec![];
+
+        if after.check_game_state() == GameState::Won(player) {
+            reasons.push(MoveReason::WinsGame);
+        }
+        let (before_mine, after_mine) = match player {
+            Player::X => (board.gx & !board.go, after.gx & !after.go),
+            Player::O => (board.go & !board.gx, after.go & !after.gx),
+        };
+        if (after_mine & !before_mine) & (1 << global) != 0 {
+            reasons.push(MoveReason::WinsBoard(global));
+        } else if board.sub_board_threats(global, opponent) & (1 << local) != 0 {
+            reasons.push(MoveReason::BlocksBoard(global));
+        }
+        let cells =
+            after.sub_board_threats(global, player) & !board.sub_board_threats(global, player);
+        if cells != 0 {
+            reasons.push(MoveReason::Threatens {
+                board: global,
+                cells,
+            });
+        }
+        if !after.game_over() {
+            reasons.push(match after.forced_board() {
+                Some(board) => MoveReason::SendsTo {
+                    board,
+                    own_threats: after.sub_board_threats(board, player),
+                    opponent_threats: after.sub_board_threats(board, opponent),
+                },
+                None => MoveReason::FreeMove,
+            });
+        }
+
+        Self {
+            player,
+            mve,
+            score,
+            reasons,
+            pv,
+        }
+    }
+}
+
+impl Evaluation {
+    /// Explains the best move, `None` when there is none.
+    pub fn summary(&self) -> Option<MoveSummary> {
+        let &mve = self.pv.first()?;
+        Some(MoveSummary::new(
+            &self.board,
+            mve,
+            self.score,
+            self.pv.clone(),
+        ))
+    }
+}
+
+fn player_name(player: Player) -> &'static str {
+    match player {
+        Player::X => "X",
+        Player::O => "O",
+    }
+}
+
+impl Display for MoveSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (me, them) = (player_name(self.player), player_name(self.player.other()));
+        write!(f, "{me} {} ({:+.0}):", format_move(self.mve), self.score)?;
+        for (i, reason) in self.reasons.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            f.write_str(separator)?;
+            match *reason {
+                MoveReason::WinsGame => write!(f, "wins the game")?,
+                MoveReason::WinsBoard(board) => write!(f, "wins sub-board {board}")?,
+                MoveReason::BlocksBoard(board) => write!(f, "blocks {them} in sub-board {board}")?,
+                MoveReason::Threatens { board, .. } => {
+                    write!(f, "threatens to win sub-board {board}")?
+                }
+                MoveReason::SendsTo {
+                    board,
+                    own_threats,
+                    opponent_threats,
+                } => {
+                    write!(f, "sends {them} to sub-board {board}")?;
+                    if opponent_threats != 0 {
+                        write!(f, " where {them} can win it")?;
+                    } else if own_threats != 0 {
+                        write!(f, " where {me} threatens")?;
+                    }
+                }
+                MoveReason::FreeMove => write!(f, "lets {them} play anywhere")?,
+            }
+        }
+        if self.pv.len() > 1 {
+            let pv: Vec<String> = self.pv.iter().map(|&mve| format_move(mve)).collect();
+            write!(f, "; expected {}", pv.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod summary_tests {
+    use crate::game::{Board, Player};
+    use crate::summary::{MoveReason, MoveSummary};
+    use crate::{Engine, MCTSConfig, SearchMode};
+
+    fn board_after(moves: &[u8]) -> Board {
+        moves
+            .iter()
+            .fold(Board::default(), |board, &m| board.unchecked_play(m))
+    }
+
+    #[test]
+    fn test_summary() {
+        // X owns cells 0 and 1 of sub-board 4.
+        let board = board_after(&[0x40, 0x04, 0x41]);
+        let summary = MoveSummary::new(&board, (1, 4), -20.0, vec![(1, 4)]);
+        assert_eq!(summary.player, Player::O);
+        assert_eq!(
+            summary.reasons,
+            [MoveReason::SendsTo {
+                board: 4,
+                own_threats: 0,
+                opponent_threats: 1 << 2,
+            }]
+        );
+        assert_eq!(
+            summary.to_string(),
+            "O 14 (-20): sends X to sub-board 4 where X can win it"
+        );
+
+        let board = board_after(&[0x40, 0x04, 0x41, 0x14]);
+        let summary = MoveSummary::new(&board, (4, 2), 35.0, vec![(4, 2), (2, 0)]);
+        assert_eq!(
+            summary.to_string(),
+            "X 42 (+35): wins sub-board 4, sends O to sub-board 2; expected 42 20"
+        );
+        let blocked = board_after(&[0x40, 0x04, 0x41, 0x12, 0x24]);
+        let summary = MoveSummary::new(&blocked, (4, 2), 0.0, vec![]);
+        assert_eq!(summary.reasons[0], MoveReason::BlocksBoard(4));
+
+        let mut engine = Engine::from_board(
+            board,
+            MCTSConfig {
+                mode: SearchMode::Deterministic { seed: 1 },
+                ..MCTSConfig::default()
+            },
+        )
+        .unwrap();
+        assert!(engine.analyze(0).unwrap().summary().is_none());
+        let ev = engine.analyze(200).unwrap();
+        let summary = ev.summary().unwrap();
+        assert_eq!(summary.mve, ev.pv[0]);
+        assert_eq!(summary.score, ev.score);
+        assert!(summary.to_string().starts_with("X "));
+    }
+}
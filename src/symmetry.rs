@@ -0,0 +1,108 @@
+//! The eight symmetries of the board. Rotating or reflecting the 9x9 grid
+//! applies the same transformation to the macro board and to every
+//! sub-board, so a symmetry is a permutation of the nine squares of a 3x3
+//! board applied at both levels.
+
+use crate::game::Board;
+
+/// `SYMMETRIES[s][i]` is where square `i` of a 3x3 board goes under
+/// symmetry `s`. Index 0 is the identity.
+pub const SYMMETRIES: [[u8; 9]; 8] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8], // identity
+    [2, 5, 8, 1, 4, 7, 0, 3, 6], // rotate 90
+    [8, 7, 6, 5, 4, 3, 2, 1, 0], // rotate 180
+    [6, 3, 0, 7, 4, 1, 8, 5, 2], // rotate 270
+    [2, 1, 0, 5, 4, 3, 8, 7, 6], // mirror columns
+    [6, 7, 8, 3, 4, 5, 0, 1, 2], // mirror rows
+    [0, 3, 6, 1, 4, 7, 2, 5, 8], // main diagonal
+    [8, 5, 2, 7, 4, 1, 6, 3, 0], // anti diagonal
+];
+
+fn permute_cells(cells: u128, perm: &[u8; 9]) -> u128 {
+    let mut out = 0;
+    for global in 0..9 {
+        for local in 0..9 {
+            if cells & (1 << (global * 9 + local)) != 0 {
+                out |= 1 << (perm[global] as usize * 9 + perm[local] as usize);
+            }
+        }
+    }
+    out
+}
+
+fn permute_boards(boards: u16, perm: &[u8; 9]) -> u16 {
+    (0..9)
+        .filter(|g| boards & (1 << g) != 0)
+        .fold(0, |out, g| out | (1 << perm[g]))
+}
+
+impl Board {
+    /// The board under symmetry `symmetry` (an index into [`SYMMETRIES`]).
+    pub fn transformed(&self, symmetry: usize) -> Board {
+        let perm = &SYMMETRIES[symmetry];
+        let mut board = *self;
+        board.x = permute_cells(self.x, perm);
+        board.o = permute_cells(self.o, perm);
+        board.gx = permute_boards(self.gx, perm);
+        board.go = permute_boards(self.go, perm);
+        board.last_move = self
+            .last_move
+            .map(|m| Board::move_from_gl(perm[(m >> 4) as usize], perm[(m & 0b1111) as usize]));
+        board.refresh_completed();
+        board
+    }
+
+    /// Representative of the board's symmetry class: the transformation with
+    /// the smallest hash. Symmetric positions share a canonical board.
+    pub fn canonical(&self) -> Board {
+        (0..SYMMETRIES.len())
+            .map(|s| self.transformed(s))
+            .min_by_key(Board::zobrist_hash)
+            .expect("There is at least the identity")
+    }
+
+    /// Hash shared by all boards in the same symmetry class.
+    pub fn canonical_hash(&self) -> u64 {
+        (0..SYMMETRIES.len())
+            .map(|s| self.transformed(s).zobrist_hash())
+            .min()
+            .expect("There is at least the identity")
+    }
+}
+
+#[cfg(test)]
+mod symmetry_tests {
+    use std::collections::HashSet;
+
+    use crate::game::Board;
+    use crate::symmetry::SYMMETRIES;
+
+    #[test]
+    fn test_first_move_classes() {
+        let classes: HashSet<u64> = (0..81)
+            .map(|i| {
+                Board::default()
+                    .unchecked_play(Board::move_from_index(i))
+                    .canonical_hash()
+            })
+            .collect();
+        assert_eq!(classes.len(), 15);
+    }
+
+    #[test]
+    fn test_transformed_positions_stay_valid() {
+        let mut board = Board::default();
+        for m in [(4, 0), (0, 4), (4, 1), (1, 4), (4, 2)] {
+            board = board.unchecked_play(Board::move_from_gl(m.0, m.1));
+        }
+        for s in 0..SYMMETRIES.len() {
+            let transformed = board.transformed(s);
+            assert_eq!(transformed.validate(), Ok(()));
+            assert_eq!(
+                transformed.get_moves().count_ones(),
+                board.get_moves().count_ones()
+            );
+            assert_eq!(transformed.canonical_hash(), board.canonical_hash());
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Proptest strategies for generating reachable game positions, and a
+//! statistical comparison of the deterministic and parallel search modes
+//! for differential tests.
+//!
+//! Positions are produced by random playouts from a starting state rather
+//! than by sampling raw bitboards, so every generated value is one the
+//! engine can actually meet during a search.
+
+use proptest::prelude::*;
+
+use crate::game::Board;
+use crate::{Engine, MCTSConfig, SearchMode, StoctopusError};
+
+/// Number of proptest cases per property, sized for CI.
+pub const CI_CASES: u32 = 64;
+
+/// Plays up to `max_plies` moves from `start`, each ply picking one of the
+/// moves returned by `legal` using a generated choice byte. Stops early when
+/// no moves are left. Works for any game that can list and play moves.
+pub fn reachable<S, M, L, P>(start: S, max_plies: usize, legal: L, play: P) -> BoxedStrategy<S>
+where
+    S: Clone + std::fmt::Debug + 'static,
+    L: Fn(&S) -> Vec<M> + 'static,
+    P: Fn(&S, &M) -> S + 'static,
+{
+    prop::collection::vec(any::<u8>(), 0..=max_plies)
+        .prop_map(move |choices| {
+            let mut state = start.clone();
+            for choice in choices {
+                let moves = legal(&state);
+                if moves.is_empty() {
+                    break;
+                }
+                state = play(&state, &moves[choice as usize % moves.len()]);
+            }
+            state
+        })
+        .boxed()
+}
+
+/// Legal moves of `board`, encoded as in [`Board::move_from_index`].
+pub fn legal_moves(board: &Board) -> Vec<u8> {
+    if board.game_over() {
+        return vec![];
+    }
+    let moves = board.get_moves();
+    (0..81)
+        .filter(|i| (moves >> i) & 1 == 1)
+        .map(Board::move_from_index)
+        .collect()
+}
+
+/// Ultimate tic tac toe positions reachable within `max_plies` moves.
+pub fn reachable_board(max_plies: usize) -> BoxedStrategy<Board> {
+    reachable(Board::default(), max_plies, legal_moves, |board, m| {
+        board.unchecked_play(*m)
+    })
+}
+
+/// A reachable position that is still in progress together with one of its
+/// legal moves.
+pub fn board_and_move(max_plies: usize) -> BoxedStrategy<(Board, u8)> {
+    reachable_board(max_plies)
+        .prop_filter("game is over", |board| !board.game_over())
+        .prop_flat_map(|board| {
+            let moves = legal_moves(&board);
+            (Just(board), prop::sample::select(moves))
+        })
+        .boxed()
+}
+
+/// Share of the root visits of a search of `board` by cell, `global * 9 +
+/// local`.
+pub fn root_distribution(
+    board: Board,
+    config: MCTSConfig,
+    iterations: u32,
+) -> Result<[f32; 81], StoctopusError> {
+    let mut engine = Engine::from_board(board, config)?;
+    engine.analyze(iterations)?;
+    let children = engine.child_stats(engine.current_node());
+    let total: f32 = children.iter().map(|child| child.visits).sum();
+    let mut distribution = [0.0; 81];
+    for child in children {
+        distribution[(child.mve.0 * 9 + child.mve.1) as usize] = child.visits / total.max(1.0);
+    }
+    Ok(distribution)
+}
+
+/// Averages the root visit distribution of `runs` searches of `board` in
+/// each mode, the deterministic ones seeded `0..runs`, and returns the
+/// total variation distance between the two averages. `config`'s own mode
+/// is ignored. Parallel searches sample the same distribution as
+/// deterministic ones, so a large distance points at a parallel search bug.
+pub fn compare_with_parallel(
+    board: Board,
+    config: MCTSConfig,
+    iterations: u32,
+    runs: u64,
+) -> Result<f32, StoctopusError> {
+    let mut parallel = [0.0; 81];
+    let mut deterministic = [0.0; 81];
+    for seed in 0..runs {
+        let modes = [
+            (SearchMode::Parallel, &mut parallel),
+            (SearchMode::Deterministic { seed }, &mut deterministic),
+        ];
+        for (mode, sum) in modes {
+            let distribution = root_distribution(board, MCTSConfig { mode, ..config }, iterations)?;
+            sum.iter_mut()
+                .zip(distribution)
+                .for_each(|(acc, share)| *acc += share);
+        }
+    }
+    Ok(parallel
+        .iter()
+        .zip(deterministic)
+        .map(|(p, d)| (p - d).abs() / runs as f32)
+        .sum::<f32>()
+        / 2.0)
+}
@@ -0,0 +1,232 @@
+//! Writes games as training data in Parquet, so Python training stacks
+//! (Polars, PyArrow) can read it without a decoder of their own. Needs the
+//! `parquet` feature. Pages are written uncompressed.
+//!
+//! Every row is one position a move was searched in, with the columns:
+//!
+//! | Column      | Type            | Contents                                     |
+//! |-------------|-----------------|----------------------------------------------|
+//! | `game`      | `UInt32`        | Game within the file, counted from 0         |
+//! | `ply`       | `UInt32`        | Moves played before the position             |
+//! | `variant`   | `Utf8`          | `ultimate` or `nine-board`                   |
+//! | `to_move`   | `Int8`          | 1 when X is to move, -1 when O is            |
+//! | `cells`     | `List<Int8>`    | 81 cells: 1 for X, -1 for O, 0 when empty    |
+//! | `last_move` | `UInt8`, null   | Cell of the move before, null at the start   |
+//! | `policy`    | `List<Float32>` | 81 shares of the root visits, summing to 1   |
+//! | `outcome`   | `Float32`       | 1 if the side to move won, 0.5 drawn, 0 lost |
+//!
+//! Cells are indexed `global * 9 + local`, like the priors of
+//! [`Engine::analyze_with_priors`](crate::Engine::analyze_with_priors).
+//! `last_move` tells which sub-board the side to move has to play in.
+
+use std::io::Write;
+use std::sync::Arc;
+
+use arrow_array::builder::{Float32Builder, Int8Builder, ListBuilder};
+use arrow_array::{
+    ArrayRef, Float32Array, Int8Array, RecordBatch, StringArray, UInt32Array, UInt8Array,
+};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+
+use crate::game::{Board, GameState, Player};
+use crate::match_runner::GameOutcome;
+use crate::record::variant_name;
+use crate::StoctopusError;
+
+fn write_error(err: impl std::error::Error + Send + Sync + 'static) -> StoctopusError {
+    StoctopusError::Io(std::io::Error::other(err))
+}
+
+/// Schema of the rows, see the [module docs](self).
+pub fn schema() -> Schema {
+    let list = |item: DataType| DataType::List(Arc::new(Field::new("item", item, true)));
+    Schema::new(vec![
+        Field::new("game", DataType::UInt32, false),
+        Field::new("ply", DataType::UInt32, false),
+        Field::new("variant", DataType::Utf8, false),
+        Field::new("to_move", DataType::Int8, false),
+        Field::new("cells", list(DataType::Int8), false),
+        Field::new("last_move", DataType::UInt8, true),
+        Field::new("policy", list(DataType::Float32), false),
+        Field::new("outcome", DataType::Float32, false),
+    ])
+}
+
+/// One position of a game, as written to a row.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrainingSample {
+    pub ply: u32,
+    pub board: Board,
+    /// Share of the root visits by cell.
+    pub policy: [f32; 81],
+    /// Result for the side to move: 1 won, 0.5 drawn, 0 lost.
+    pub outcome: f32,
+}
+
+impl TrainingSample {
+    /// Samples of the positions of `outcome`, a game played from `start`.
+    /// Moves without root visits, which weren't searched, are left out.
+    pub fn from_game(start: Board, outcome: &GameOutcome) -> Result<Vec<Self>, StoctopusError> {
+        let mut board = start;
+        let mut samples = vec![];
+        for (ply, (&(global, local), visits)) in
+            outcome.moves.iter().zip(&outcome.root_visits).enumerate()
+        {
+            let total: u32 = visits.iter().map(|&(_, visits)| visits).sum();
+            if total > 0 {
+                let mut policy = [0.0; 81];
+                for &(mve, visits) in visits {
+                    policy[((mve >> 4) * 9 + (mve & 0b1111)) as usize] =
+                        visits as f32 / total as f32;
+                }
+                samples.push(Self {
+                    ply: ply as u32,
+                    board,
+                    policy,
+                    outcome: match outcome.result {
+                        GameState::Won(winner) if winner == board.next_player => 1.0,
+                        GameState::Won(_) => 0.0,
+                        GameState::Draw | GameState::InProgress => 0.5,
+                    },
+                });
+            }
+            if global >= 9 || local >= 9 || board.get_moves() & (1 << (global * 9 + local)) == 0 {
+                return Err(StoctopusError::IllegalMove);
+            }
+            board = board.unchecked_play(Board::move_from_gl(global, local));
+        }
+        Ok(samples)
+    }
+}
+
+/// Writes games to a Parquet file, one row group per game. The file is
+/// only complete after [`Self::finish`].
+pub struct TrainingWriter<W: Write + Send> {
+    writer: ArrowWriter<W>,
+    games: u32,
+}
+
+impl<W: Write + Send> TrainingWriter<W> {
+    pub fn new(out: W) -> Result<Self, StoctopusError> {
+        let writer = ArrowWriter::try_new(out, Arc::new(schema()), None).map_err(write_error)?;
+        Ok(Self { writer, games: 0 })
+    }
+
+    /// Adds the positions of `outcome`, a game played from `start`.
+    pub fn write_game(
+        &mut self,
+        start: Board,
+        outcome: &GameOutcome,
+    ) -> Result<(), StoctopusError> {
+        let samples = TrainingSample::from_game(start, outcome)?;
+        self.write_samples(&samples)
+    }
+
+    /// Adds `samples` as the next game.
+    pub fn write_samples(&mut self, samples: &[TrainingSample]) -> Result<(), StoctopusError> {
+        let mut cells = ListBuilder::new(Int8Builder::with_capacity(samples.len() * 81));
+        let mut policy = ListBuilder::new(Float32Builder::with_capacity(samples.len() * 81));
+        for sample in samples {
+            let board = &sample.board;
+            for cell in 0..81 {
+                let value = if board.x & (1 << cell) != 0 {
+                    1
+                } else if board.o & (1 << cell) != 0 {
+                    -1
+                } else {
+                    0
+                };
+                cells.values().append_value(value);
+            }
+            cells.append(true);
+            policy.values().append_slice(&sample.policy);
+            policy.append(true);
+        }
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt32Array::from(vec![self.games; samples.len()])),
+            Arc::new(UInt32Array::from_iter_values(
+                samples.iter().map(|sample| sample.ply),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                samples
+                    .iter()
+                    .map(|sample| variant_name(sample.board.rules.variant)),
+            )),
+            Arc::new(Int8Array::from_iter_values(samples.iter().map(|sample| {
+                match sample.board.next_player {
+                    Player::X => 1,
+                    Player::O => -1,
+                }
+            }))),
+            Arc::new(cells.finish()),
+            Arc::new(UInt8Array::from_iter(samples.iter().map(|sample| {
+                sample
+                    .board
+                    .last_move
+                    .map(|mve| (mve >> 4) * 9 + (mve & 0b1111))
+            }))),
+            Arc::new(policy.finish()),
+            Arc::new(Float32Array::from_iter_values(
+                samples.iter().map(|sample| sample.outcome),
+            )),
+        ];
+        let batch = RecordBatch::try_new(Arc::new(schema()), columns).map_err(write_error)?;
+        self.writer.write(&batch).map_err(write_error)?;
+        self.writer.flush().map_err(write_error)?;
+        self.games += 1;
+        Ok(())
+    }
+
+    /// Writes the footer and hands back the output.
+    pub fn finish(self) -> Result<W, StoctopusError> {
+        self.writer.into_inner().map_err(write_error)
+    }
+}
+
+#[cfg(test)]
+mod training_tests {
+    use std::fs::File;
+
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use crate::match_runner::MatchRunner;
+    use crate::training::{schema, TrainingSample, TrainingWriter};
+    use crate::{Board, Engine, MCTSConfig, SearchLimits, SearchMode};
+
+    #[test]
+    fn test_write_games() {
+        let runner = MatchRunner::new(1, SearchLimits::iterations(30)).unwrap();
+        let config = MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 1 },
+            ..MCTSConfig::default()
+        };
+        let (mut x, mut o) = (Engine::with_config(config), Engine::with_config(config));
+        let game = runner.play_game(&mut x, &mut o).unwrap();
+        let samples = TrainingSample::from_game(Board::default(), &game).unwrap();
+        assert_eq!(samples.len(), game.moves.len());
+        assert_eq!(samples[0].board, Board::default());
+        for sample in &samples {
+            let total: f32 = sample.policy.iter().sum();
+            assert!((total - 1.0).abs() < 1e-4, "{total}");
+        }
+
+        let mut writer = TrainingWriter::new(vec![]).unwrap();
+        writer.write_game(Board::default(), &game).unwrap();
+        writer.write_samples(&samples[..2]).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let path = std::env::temp_dir().join(format!("stoctopus-train-{}", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let file = File::open(&path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        assert_eq!(reader.schema().as_ref(), &schema());
+        let rows: usize = reader
+            .build()
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum();
+        assert_eq!(rows, samples.len() + 2);
+        std::fs::remove_file(path).unwrap();
+    }
+}
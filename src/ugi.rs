@@ -0,0 +1,443 @@
+//! The Universal Game Interface, a UCI-like text protocol spoken by match
+//! managers and GUIs. [`Ugi::handle`] takes one command line and returns the
+//! lines to answer with; the `stoctopus` binary connects it to stdin and
+//! stdout.
+//!
+//! Moves are written as two digits, the sub-board then the cell, both
+//! counted from 0 in reading order. `44` is the very center.
+//!
+//! `ugi` answers with the [`PROTOCOL_VERSION`] as `id protocol`, and
+//! `capabilities` lists what this build supports, see [`Capabilities`].
+//!
+//! `tree <command>` walks the tree of the last search, answering with the
+//! output of [`TreeExplorer::command`]. Where it stands is kept until the
+//! position or the tree changes.
+
+use std::time::Duration;
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::explorer::TreeExplorer;
+use crate::game::{Board, GameState, Player, Rules, Variant};
+use crate::mcts::NodeId;
+use crate::record::{parse_variant, variant_name};
+use crate::{
+    CancellationToken, Engine, EngineConfig, MCTSConfig, OpeningBook, SearchLimits,
+    SelectionPolicy, StoctopusError,
+};
+
+pub const ENGINE_NAME: &str = env!("CARGO_PKG_NAME");
+pub const ENGINE_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const ENGINE_AUTHOR: &str = env!("CARGO_PKG_AUTHORS");
+/// Raised whenever commands or answers change incompatibly.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Iterations per move when `go` doesn't limit the search.
+const DEFAULT_ITERATIONS: u32 = 20_000;
+/// Highest `Strength`. Every level below halves the iterations per move.
+pub(crate) const MAX_STRENGTH: u32 = 10;
+/// Iterations per move at `Strength` 1.
+const WEAKEST_ITERATIONS: u32 = 100;
+
+fn protocol(msg: impl Into<String>) -> StoctopusError {
+    StoctopusError::Protocol(msg.into())
+}
+
+/// What this build of the engine supports, so front ends can check for
+/// features instead of assuming them from the crate version. Servers can
+/// send it as JSON.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub protocol_version: u32,
+    pub engine_version: String,
+    /// Parameters understood by `go`.
+    pub limits: Vec<String>,
+    /// Variants as named in game records.
+    pub variants: Vec<String>,
+    /// Optional features, such as `book` or `strength`. Features like
+    /// `multipv` or `ponder` are only listed once supported.
+    pub features: Vec<String>,
+}
+
+impl Capabilities {
+    pub fn current() -> Self {
+        let strings = |items: &[&str]| items.iter().map(|item| item.to_string()).collect();
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            engine_version: ENGINE_VERSION.to_string(),
+            limits: strings(&[
+                "nodes", "movetime", "p1time", "p2time", "p1inc", "p2inc", "infinite",
+            ]),
+            variants: [Variant::Ultimate, Variant::NineBoard]
+                .into_iter()
+                .map(|variant| variant_name(variant).to_string())
+                .collect(),
+            features: strings(&["book", "strength", "query", "tree"]),
+        }
+    }
+
+    /// The answer to `capabilities`.
+    fn to_ugi(&self) -> Vec<String> {
+        vec![
+            format!("capabilities protocol {}", self.protocol_version),
+            format!("capabilities version {}", self.engine_version),
+            format!("capabilities limits {}", self.limits.join(" ")),
+            format!("capabilities variants {}", self.variants.join(" ")),
+            format!("capabilities features {}", self.features.join(" ")),
+            "capabilitiesok".to_string(),
+        ]
+    }
+}
+
+pub fn format_move((global, local): (u8, u8)) -> String {
+    format!("{global}{local}")
+}
+
+pub fn parse_move(text: &str) -> Result<(u8, u8), StoctopusError> {
+    match text.as_bytes() {
+        &[g @ b'0'..=b'8', l @ b'0'..=b'8'] => Ok((g - b'0', l - b'0')),
+        _ => Err(protocol(format!("Bad move {text}"))),
+    }
+}
+
+pub struct Ugi {
+    engine: Engine,
+    pool: ThreadPool,
+    threads: usize,
+    /// 0 for full strength, otherwise caps the iterations per move.
+    strength: u32,
+    /// Where `tree` left off, see [`TreeExplorer::into_path`].
+    tree_path: Vec<NodeId>,
+}
+
+fn build_pool(threads: usize) -> Result<ThreadPool, StoctopusError> {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(StoctopusError::ThreadPool)
+}
+
+impl Ugi {
+    pub fn new() -> Result<Self, StoctopusError> {
+        Self::with_config(&EngineConfig::default())
+    }
+
+    /// Starts with the settings of `config` instead of the defaults.
+    pub fn with_config(config: &EngineConfig) -> Result<Self, StoctopusError> {
+        let threads = config.threads();
+        Ok(Self {
+            engine: config.engine()?,
+            pool: build_pool(threads)?,
+            threads,
+            strength: config.strength,
+            tree_path: vec![],
+        })
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Answers one command. Unknown commands are ignored, as the protocol
+    /// asks.
+    pub fn handle(&mut self, line: &str) -> Result<Vec<String>, StoctopusError> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if let ["setoption" | "uginewgame" | "position" | "go", ..] = words.as_slice() {
+            // The nodes explored may not survive the command.
+            self.tree_path.clear();
+        }
+        match words.as_slice() {
+            ["ugi"] => Ok(self.identify()),
+            ["capabilities"] => Ok(Capabilities::current().to_ugi()),
+            ["isready"] => Ok(vec!["readyok".to_string()]),
+            ["setoption", "name", rest @ ..] => {
+                self.set_option(rest)?;
+                Ok(vec![])
+            }
+            ["uginewgame"] => {
+                self.engine.new_game();
+                Ok(vec![])
+            }
+            ["position", rest @ ..] => {
+                self.position(rest)?;
+                Ok(vec![])
+            }
+            ["go", rest @ ..] => self.go(rest),
+            ["query", what] => self.query(what).map(|answer| vec![answer]),
+            ["tree", rest @ ..] => Ok(self.tree(&rest.join(" "))),
+            _ => Ok(vec![]),
+        }
+    }
+
+    fn identify(&self) -> Vec<String> {
+        let config = self.engine.config();
+        let exploration = match config.selection {
+            SelectionPolicy::Uct { c } | SelectionPolicy::Puct { c } => (c * 100.0).round(),
+            _ => 141.0,
+        };
+        vec![
+            format!("id name {ENGINE_NAME} {ENGINE_VERSION}"),
+            format!("id author {ENGINE_AUTHOR}"),
+            format!("id protocol {PROTOCOL_VERSION}"),
+            format!(
+                "option name Threads type spin default {} min 1 max 256",
+                self.threads
+            ),
+            format!("option name Exploration type spin default {exploration} min 1 max 1000"),
+            "option name BookPath type string default <empty>".to_string(),
+            format!("option name Strength type spin default 0 min 0 max {MAX_STRENGTH}"),
+            "option name Variant type combo default ultimate var ultimate var nine-board"
+                .to_string(),
+            "ugiok".to_string(),
+        ]
+    }
+
+    /// `words` is everything after `setoption name`. Option names may
+    /// contain spaces, the value may not be missing.
+    fn set_option(&mut self, words: &[&str]) -> Result<(), StoctopusError> {
+        let split = words.iter().position(|&w| w == "value");
+        let (name, value) = match split {
+            Some(i) => (words[..i].join(" "), words[i + 1..].join(" ")),
+            None => (words.join(" "), String::new()),
+        };
+        let spin = |min: u32, max: u32| {
+            value
+                .parse::<u32>()
+                .ok()
+                .filter(|v| (min..=max).contains(v))
+                .ok_or_else(|| protocol(format!("Bad value {value:?} for {name}")))
+        };
+        match name.to_lowercase().as_str() {
+            "threads" => {
+                let threads = spin(1, 256)? as usize;
+                self.pool = build_pool(threads)?;
+                self.threads = threads;
+            }
+            "exploration" => {
+                let c = spin(1, 1000)? as f32 / 100.0;
+                self.engine.set_config(MCTSConfig {
+                    selection: SelectionPolicy::Uct { c },
+                    ..*self.engine.config()
+                });
+            }
+            "bookpath" => {
+                let book = match value.as_str() {
+                    "" | "<empty>" => None,
+                    path => Some(std::sync::Arc::new(OpeningBook::open(path)?) as _),
+                };
+                self.engine.set_book(book);
+            }
+            "strength" => self.strength = spin(0, MAX_STRENGTH)?,
+            "variant" => {
+                let rules = Rules {
+                    variant: parse_variant(&value)?,
+                    ..self.engine.board().rules
+                };
+                self.engine.set_board(Board::with_rules(rules))?;
+            }
+            _ => return Err(protocol(format!("Unknown option {name}"))),
+        }
+        Ok(())
+    }
+
+    fn position(&mut self, words: &[&str]) -> Result<(), StoctopusError> {
+        let moves = match words {
+            ["startpos"] => &[][..],
+            ["startpos", "moves", moves @ ..] => moves,
+            _ => return Err(protocol("Only startpos positions are supported")),
+        };
+        self.engine.new_game();
+        for mve in moves {
+            self.engine.play(parse_move(mve)?)?;
+        }
+        Ok(())
+    }
+
+    fn limits(&self, words: &[&str]) -> Result<SearchLimits, StoctopusError> {
+        let (own_time, own_inc) = match self.engine.board().next_player {
+            Player::X => ("p1time", "p1inc"),
+            Player::O => ("p2time", "p2inc"),
+        };
+        let (mut nodes, mut movetime, mut clock, mut increment) = (None, None, None, 0);
+        // `infinite` is the only parameter without a value. Searches always
+        // end on their own here, so it gets the default budget.
+        let words: Vec<&str> = words.iter().copied().filter(|&w| w != "infinite").collect();
+        for pair in words.chunks(2) {
+            let [key, value] = pair else {
+                return Err(protocol("go parameters come in pairs"));
+            };
+            let value: u64 = value
+                .parse()
+                .map_err(|_| protocol(format!("Bad value {value:?} for {key}")))?;
+            match *key {
+                "nodes" => nodes = Some(value.min(u32::MAX as u64) as u32),
+                "movetime" => movetime = Some(value),
+                key if key == own_time => clock = Some(value),
+                key if key == own_inc => increment = value,
+                _ => {}
+            }
+        }
+        let clock = clock.map(|clock| {
+            SearchLimits::from_clock(
+                Duration::from_millis(clock),
+                Duration::from_millis(increment),
+            )
+        });
+        let mut limits = match (movetime, clock) {
+            (Some(movetime), _) => SearchLimits::time(Duration::from_millis(movetime)),
+            (None, Some(clock)) => clock,
+            (None, None) => SearchLimits::iterations(DEFAULT_ITERATIONS),
+        };
+        if let Some(nodes) = nodes {
+            limits.iterations = nodes;
+        }
+        if self.strength > 0 {
+            let cap = WEAKEST_ITERATIONS << (self.strength - 1);
+            limits.iterations = limits.iterations.min(cap);
+        }
+        Ok(limits)
+    }
+
+    fn go(&mut self, words: &[&str]) -> Result<Vec<String>, StoctopusError> {
+        let limits = self.limits(words)?;
+        let engine = &mut self.engine;
+        let ev = self
+            .pool
+            .install(|| engine.analyze_with_limits(limits, &CancellationToken::new()))?;
+        let best = ev
+            .best_move
+            .map(|id| self.engine.try_resolve_node(&id))
+            .transpose()?
+            .and_then(|node| node.board.last_move)
+            .ok_or_else(|| protocol("Search found no move"))?;
+        Ok(vec![
+            format!(
+                "info score {:.0} nodes {}",
+                ev.score,
+                self.engine.tree_size()
+            ),
+            format!("bestmove {}", format_move((best >> 4, best & 0b1111))),
+        ])
+    }
+
+    fn tree(&mut self, command: &str) -> Vec<String> {
+        let path = std::mem::take(&mut self.tree_path);
+        let mut explorer = TreeExplorer::resume(&self.engine, path);
+        let output = explorer.command(command);
+        self.tree_path = explorer.into_path();
+        output.lines().map(str::to_string).collect()
+    }
+
+    fn query(&self, what: &str) -> Result<String, StoctopusError> {
+        let answer = match what {
+            "gameover" => self.engine.is_game_over().to_string(),
+            "p1turn" => (self.engine.board().next_player == Player::X).to_string(),
+            "result" => match self.engine.game_state() {
+                GameState::Won(Player::X) => "p1win",
+                GameState::Won(Player::O) => "p2win",
+                GameState::Draw => "draw",
+                GameState::InProgress => "none",
+            }
+            .to_string(),
+            _ => return Err(protocol(format!("Unknown query {what}"))),
+        };
+        Ok(format!("response {answer}"))
+    }
+}
+
+#[cfg(test)]
+mod ugi_tests {
+    use std::time::Duration;
+
+    use crate::ugi::{parse_move, Capabilities, Ugi, PROTOCOL_VERSION};
+    use crate::{SelectionPolicy, Variant};
+
+    #[test]
+    fn test_handshake() {
+        let mut ugi = Ugi::new().unwrap();
+        let lines = ugi.handle("ugi").unwrap();
+        assert!(lines[0].starts_with("id name stoctopus"));
+        assert!(lines.iter().any(|l| l.starts_with("option name Threads")));
+        assert_eq!(lines.last().unwrap(), "ugiok");
+        assert!(lines.contains(&"id protocol 1".to_string()));
+        assert_eq!(ugi.handle("isready").unwrap(), vec!["readyok"]);
+        assert!(ugi.handle("frobnicate").unwrap().is_empty());
+
+        let lines = ugi.handle("capabilities").unwrap();
+        assert_eq!(
+            lines[0],
+            format!("capabilities protocol {PROTOCOL_VERSION}")
+        );
+        assert!(lines.contains(&"capabilities variants ultimate nine-board".to_string()));
+        assert_eq!(lines.last().unwrap(), "capabilitiesok");
+        let json = serde_json::to_string(&Capabilities::current()).unwrap();
+        let capabilities: Capabilities = serde_json::from_str(&json).unwrap();
+        assert!(capabilities.limits.contains(&"movetime".to_string()));
+
+        ugi.handle("setoption name Variant value nine-board")
+            .unwrap();
+        ugi.handle("position startpos moves 44").unwrap();
+        assert_eq!(ugi.engine().board().rules.variant, Variant::NineBoard);
+        assert!(ugi.handle("setoption name Variant value chess").is_err());
+    }
+
+    #[test]
+    fn test_options() {
+        let mut ugi = Ugi::new().unwrap();
+        ugi.handle("setoption name Threads value 2").unwrap();
+        assert_eq!(ugi.pool.current_num_threads(), 2);
+        ugi.handle("setoption name Exploration value 50").unwrap();
+        assert_eq!(
+            ugi.engine().config().selection,
+            SelectionPolicy::Uct { c: 0.5 }
+        );
+        ugi.handle("setoption name Strength value 1").unwrap();
+        assert_eq!(ugi.limits(&["nodes", "5000"]).unwrap().iterations, 100);
+        ugi.handle("setoption name Strength value 0").unwrap();
+        let limits = ugi.limits(&["p1time", "3000", "p2time", "10", "infinite"]);
+        assert_eq!(limits.unwrap().time, Some(Duration::from_millis(100)));
+        assert!(ugi.limits(&["movetime"]).is_err());
+        ugi.handle("setoption name BookPath value <empty>").unwrap();
+        assert!(ugi.handle("setoption name Threads value 0").is_err());
+        assert!(ugi.handle("setoption name Hash value 16").is_err());
+        assert!(ugi
+            .handle("setoption name BookPath value /no/such/book")
+            .is_err());
+    }
+
+    #[test]
+    fn test_play() {
+        let mut ugi = Ugi::new().unwrap();
+        ugi.handle("position startpos moves 44 40").unwrap();
+        assert_eq!(ugi.engine().board().get_moves().count_ones(), 9);
+        let lines = ugi.handle("go nodes 50").unwrap();
+        let best = lines.last().unwrap().strip_prefix("bestmove ").unwrap();
+        let (global, local) = parse_move(best).unwrap();
+        assert_eq!(global, 0);
+        assert!(ugi.engine().board().get_moves() & (1 << (global * 9 + local)) != 0);
+        assert_eq!(ugi.handle("query p1turn").unwrap(), vec!["response true"]);
+        assert_eq!(ugi.handle("query result").unwrap(), vec!["response none"]);
+        assert!(ugi.handle("position startpos moves 44 44").is_err());
+        assert!(parse_move("9").is_err());
+    }
+
+    #[test]
+    fn test_tree() {
+        let mut ugi = Ugi::new().unwrap();
+        ugi.handle("go nodes 300").unwrap();
+        let lines = ugi.handle("tree ls").unwrap();
+        assert!(lines[0].starts_with("depth 0"));
+        assert_eq!(lines.len(), 16);
+        assert!(ugi.handle("tree cd 0").unwrap()[0].starts_with("depth 1"));
+        // The explorer stays where it was between commands.
+        assert!(ugi.handle("tree ls").unwrap()[0].starts_with("depth 1"));
+        let lines = ugi.handle("tree pv").unwrap();
+        assert!(!lines[0].starts_with("depth 0") && !lines[0].starts_with("depth 1"));
+        assert!(ugi.handle("tree up").unwrap()[0].starts_with("depth"));
+        assert!(ugi.handle("tree").unwrap()[0].starts_with("commands"));
+
+        ugi.handle("position startpos moves 44").unwrap();
+        assert!(ugi.handle("tree ls").unwrap()[0].starts_with("depth 0"));
+    }
+}
@@ -0,0 +1,632 @@
+//! JavaScript bindings for the `cdylib`, built with
+//! `wasm-pack build --features wasm`. Results are plain objects, converted
+//! with `serde-wasm-bindgen` and typed in the generated `.d.ts` file, so
+//! TypeScript front ends get checked engine results instead of tuples.
+//!
+//! Moves are `[global, local]` pairs, both counted from 0 in reading order,
+//! and cells are indexed `global * 9 + local`.
+//!
+//! Searches go through rayon, which falls back to the calling thread where
+//! the platform can't spawn threads, as in browsers without wasm threads.
+//! The `wasm-threads` feature runs them on Web Workers sharing the module's
+//! memory, through `wasm-bindgen-rayon`. That needs a nightly build with
+//! atomics,
+//!
+//! ```sh
+//! RUSTFLAGS='-C target-feature=+atomics,+bulk-memory,+mutable-globals' \
+//!     wasm-pack build --target web --features wasm-threads \
+//!     -- -Z build-std=panic_abort,std
+//! ```
+//!
+//! and a cross-origin isolated page. `initThreads(n)` checks for isolation
+//! and only then starts the pool with `initThreadPool(n)`, resolving to the
+//! threads searches get; [`WasmEngine::threads`] tells the same later.
+//! [`WasmEngine::analyze_async`] searches in slices instead, giving the
+//! event loop a turn in between, so a long search in Node or on a page
+//! doesn't block everything else.
+//!
+//! [`WasmEngine::save_state`] packs the game into bytes a web app can keep
+//! in IndexedDB or, base64 encoded, in `localStorage`:
+//!
+//! - magic `STWS`, format version (`u32`, little-endian)
+//! - header length (`u32`) and the header as JSON: the start position, the
+//!   configuration, the moves played and the evaluations so far
+//! - the search tree as written by
+//!   [`Engine::save_checkpoint`](crate::Engine::save_checkpoint), or
+//!   nothing when it was left out
+
+use std::future::Future;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use crate::checkpoint;
+use crate::debug_bundle::BoardData;
+use crate::explorer::ChildStats;
+use crate::game::{Board, GameState, Player};
+use crate::{
+    CancellationToken, Engine, EvalSource, Evaluation, MCTSConfig, SearchLimits, SearchMode,
+    StoctopusError,
+};
+
+#[wasm_bindgen(typescript_custom_section)]
+const TYPES: &str = r#"
+export type Player = "X" | "O";
+export type Move = [number, number];
+
+export interface BoardView {
+    /** 81 cells: 1 for X, -1 for O, 0 when empty. */
+    cells: number[];
+    /** Winner of each sub-board, "draw" when full, null while open. */
+    subBoards: (Player | "draw" | null)[];
+    nextPlayer: Player;
+    lastMove: Move | null;
+    legalMoves: Move[];
+    result: Player | "draw" | null;
+}
+
+export interface ChildStats {
+    move: Move;
+    visits: number;
+    /** Wins per visit of the side to move. */
+    winRate: number;
+    prior: number;
+    expectedPlies: number | null;
+}
+
+export interface Evaluation {
+    bestMove: Move | null;
+    /** Win percentage of the side to move. */
+    confidence: number;
+    calibrated: number;
+    /** -100 (lost) to +100 (won) for the side to move. */
+    score: number;
+    /** Half width of the 95% confidence interval, null when unknown. */
+    margin: number | null;
+    expectedPlies: number | null;
+    source: "Search" | "Static" | "Book" | "Tablebase" | "Cache";
+    iterations: number;
+    pv: Move[];
+    proven: Player | "draw" | null;
+}
+"#;
+
+const MAGIC: &[u8; 4] = b"STWS";
+const VERSION: u32 = 1;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "BoardView")]
+    pub type BoardViewJs;
+    #[wasm_bindgen(typescript_type = "Evaluation")]
+    pub type EvaluationJs;
+    #[wasm_bindgen(typescript_type = "Evaluation[]")]
+    pub type EvaluationsJs;
+    #[wasm_bindgen(typescript_type = "Move[]")]
+    pub type MovesJs;
+    #[wasm_bindgen(typescript_type = "ChildStats[]")]
+    pub type ChildStatsJs;
+
+    // Both Node and browsers have it.
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(callback: &js_sys::Function, millis: i32);
+}
+
+/// Resolves once the event loop has run the tasks that were waiting.
+async fn yield_to_event_loop() {
+    let timeout = js_sys::Promise::new(&mut |resolve, _| set_timeout(&resolve, 0));
+    // The promise is never rejected.
+    let _ = wasm_bindgen_futures::JsFuture::from(timeout).await;
+}
+
+#[cfg(all(feature = "wasm-threads", target_arch = "wasm32"))]
+pub use wasm_bindgen_rayon::init_thread_pool;
+
+/// Starts a pool of `threads` Web Workers for the searches, if the page is
+/// cross-origin isolated, and resolves to the threads searches run on. That
+/// is 1 without isolation, which shared memory needs: the searches then
+/// stay on the calling thread instead of failing.
+#[cfg(all(feature = "wasm-threads", target_arch = "wasm32"))]
+#[wasm_bindgen(js_name = initThreads)]
+pub async fn init_threads(threads: usize) -> Result<usize, JsError> {
+    let isolated = js_sys::Reflect::get(&js_sys::global(), &"crossOriginIsolated".into())
+        .is_ok_and(|isolated| isolated.is_truthy());
+    let threads = pool_threads(isolated, threads);
+    if threads > 1 {
+        wasm_bindgen_futures::JsFuture::from(init_thread_pool(threads))
+            .await
+            .map_err(|err| JsError::new(&format!("No thread pool: {err:?}")))?;
+    }
+    Ok(threads)
+}
+
+/// Threads [`init_threads`] starts when asked for `requested`.
+#[cfg(any(test, all(feature = "wasm-threads", target_arch = "wasm32")))]
+fn pool_threads(cross_origin_isolated: bool, requested: usize) -> usize {
+    if cross_origin_isolated {
+        requested.max(1)
+    } else {
+        1
+    }
+}
+
+fn js_error(err: StoctopusError) -> JsError {
+    JsError::new(&err.to_string())
+}
+
+fn invalid(msg: &str) -> StoctopusError {
+    StoctopusError::Protocol(format!("Bad engine state: {msg}"))
+}
+
+/// `value` as a plain JavaScript object of type `T`.
+fn to_js<T: JsCast>(value: &impl Serialize) -> Result<T, JsError> {
+    let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+    value
+        .serialize(&serializer)
+        .map(JsCast::unchecked_into)
+        .map_err(|err| JsError::new(&err.to_string()))
+}
+
+fn move_pair(mve: u8) -> [u8; 2] {
+    [mve >> 4, mve & 0b1111]
+}
+
+/// A finished game or sub-board, "X", "O" or "draw" in JavaScript.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Outcome {
+    X,
+    O,
+    #[serde(rename = "draw")]
+    Draw,
+}
+
+impl Outcome {
+    /// `None` while the game is in progress.
+    fn of(state: GameState) -> Option<Self> {
+        match state {
+            GameState::Won(Player::X) => Some(Self::X),
+            GameState::Won(Player::O) => Some(Self::O),
+            GameState::Draw => Some(Self::Draw),
+            GameState::InProgress => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BoardView {
+    cells: Vec<i8>,
+    sub_boards: Vec<Option<Outcome>>,
+    next_player: Player,
+    last_move: Option<[u8; 2]>,
+    legal_moves: Vec<[u8; 2]>,
+    result: Option<Outcome>,
+}
+
+impl BoardView {
+    pub(crate) fn new(board: &Board) -> Self {
+        let cells = (0..81)
+            .map(|cell| {
+                if board.x & (1 << cell) != 0 {
+                    1
+                } else if board.o & (1 << cell) != 0 {
+                    -1
+                } else {
+                    0
+                }
+            })
+            .collect();
+        let completed = board.global_board_mask();
+        let sub_boards = (0..9)
+            .map(|global| {
+                if board.gx & (1 << global) != 0 {
+                    Some(Outcome::X)
+                } else if board.go & (1 << global) != 0 {
+                    Some(Outcome::O)
+                } else if completed & (1 << (global * 9)) != 0 {
+                    Some(Outcome::Draw)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let moves = if board.game_over() {
+            0
+        } else {
+            board.get_moves()
+        };
+        Self {
+            cells,
+            sub_boards,
+            next_player: board.next_player,
+            last_move: board.last_move.map(move_pair),
+            legal_moves: (0..81u8)
+                .filter(|&cell| moves & (1 << cell) != 0)
+                .map(|cell| [cell / 9, cell % 9])
+                .collect(),
+            result: Outcome::of(board.check_game_state()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ChildStatsView {
+    #[serde(rename = "move")]
+    mve: [u8; 2],
+    visits: f32,
+    win_rate: f32,
+    prior: f32,
+    expected_plies: Option<f32>,
+}
+
+impl From<ChildStats> for ChildStatsView {
+    fn from(child: ChildStats) -> Self {
+        Self {
+            mve: [child.mve.0, child.mve.1],
+            visits: child.visits,
+            win_rate: child.win_rate,
+            prior: child.prior,
+            expected_plies: child.expected_plies,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EvaluationView {
+    best_move: Option<[u8; 2]>,
+    confidence: f32,
+    calibrated: f32,
+    score: f32,
+    margin: Option<f32>,
+    expected_plies: Option<f32>,
+    source: EvalSource,
+    iterations: u32,
+    pv: Vec<[u8; 2]>,
+    proven: Option<Outcome>,
+}
+
+impl From<&Evaluation> for EvaluationView {
+    fn from(ev: &Evaluation) -> Self {
+        Self {
+            best_move: ev.pv.first().map(|&(global, local)| [global, local]),
+            confidence: ev.confidence,
+            calibrated: ev.calibrated,
+            score: ev.score,
+            margin: ev.margin.is_finite().then_some(ev.margin),
+            expected_plies: ev.expected_plies,
+            source: ev.source,
+            iterations: ev.info.iterations,
+            pv: ev
+                .pv
+                .iter()
+                .map(|&(global, local)| [global, local])
+                .collect(),
+            proven: ev.proven.and_then(Outcome::of),
+        }
+    }
+}
+
+/// What [`WasmEngine::save_state`] keeps besides the tree.
+#[derive(Serialize, Deserialize)]
+struct StateHeader {
+    start: BoardData,
+    config: MCTSConfig,
+    moves: Vec<[u8; 2]>,
+    history: Vec<EvaluationView>,
+}
+
+/// An engine playing one game at a time.
+#[wasm_bindgen(js_name = Engine)]
+pub struct WasmEngine {
+    engine: Engine,
+    start: Board,
+    moves: Vec<[u8; 2]>,
+    history: Vec<EvaluationView>,
+}
+
+#[wasm_bindgen(js_class = Engine)]
+impl WasmEngine {
+    /// An engine with the default settings. A `seed` makes its searches
+    /// reproducible.
+    #[wasm_bindgen(constructor)]
+    pub fn new(seed: Option<u64>) -> Self {
+        let mode = seed.map_or(SearchMode::Parallel, |seed| SearchMode::Deterministic {
+            seed,
+        });
+        Self {
+            engine: Engine::with_config(MCTSConfig {
+                mode,
+                ..MCTSConfig::default()
+            }),
+            start: Board::default(),
+            moves: vec![],
+            history: vec![],
+        }
+    }
+
+    /// Threads searches run on, 1 when they fall back to the calling
+    /// thread, e.g. before `initThreads` or without cross-origin isolation.
+    pub fn threads(&self) -> usize {
+        rayon::current_num_threads()
+    }
+
+    #[wasm_bindgen(js_name = newGame)]
+    pub fn new_game(&mut self) {
+        self.engine.new_game();
+        self.start = *self.engine.board();
+        self.moves.clear();
+        self.history.clear();
+    }
+
+    pub fn board(&self) -> Result<BoardViewJs, JsError> {
+        to_js(&BoardView::new(self.engine.board()))
+    }
+
+    pub fn play(&mut self, global: u8, local: u8) -> Result<(), JsError> {
+        self.engine.play((global, local)).map_err(js_error)?;
+        self.moves.push([global, local]);
+        Ok(())
+    }
+
+    /// Searches the current position for `iterations` iterations.
+    pub fn analyze(&mut self, iterations: u32) -> Result<EvaluationJs, JsError> {
+        let ev = self.engine.analyze(iterations).map_err(js_error)?;
+        self.record(&ev)
+    }
+
+    /// Same as [`Self::analyze`], but returns a promise and lets the event
+    /// loop run after every `slice` iterations. Other calls on the engine
+    /// throw until the promise settles.
+    #[wasm_bindgen(js_name = analyzeAsync)]
+    pub async fn analyze_async(
+        &mut self,
+        iterations: u32,
+        slice: u32,
+    ) -> Result<EvaluationJs, JsError> {
+        let ev = self
+            .analyze_in_slices(iterations, slice, yield_to_event_loop)
+            .await
+            .map_err(js_error)?;
+        self.record(&ev)
+    }
+
+    /// Every evaluation of this game, oldest first.
+    pub fn history(&self) -> Result<EvaluationsJs, JsError> {
+        to_js(&self.history)
+    }
+
+    /// The moves of this game, in order.
+    pub fn moves(&self) -> Result<MovesJs, JsError> {
+        to_js(&self.moves)
+    }
+
+    /// Root moves of the last search, most visited first.
+    pub fn children(&self) -> Result<ChildStatsJs, JsError> {
+        let children: Vec<ChildStatsView> = self
+            .engine
+            .child_stats(self.engine.current_node())
+            .into_iter()
+            .map(ChildStatsView::from)
+            .collect();
+        to_js(&children)
+    }
+
+    /// The game and its evaluations as bytes for [`Self::load_state`],
+    /// with the search tree if `include_tree`. Trees grow by a few dozen
+    /// bytes per iteration searched, so leave them out after long searches.
+    #[wasm_bindgen(js_name = saveState)]
+    pub fn save_state(&self, include_tree: bool) -> Vec<u8> {
+        let header = StateHeader {
+            start: BoardData::new(&self.start),
+            config: *self.engine.config(),
+            moves: self.moves.clone(),
+            history: self.history.clone(),
+        };
+        let header = serde_json::to_vec(&header).expect("Headers always serialize");
+        let mut bytes = Vec::with_capacity(header.len() + 12);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&header);
+        if include_tree {
+            bytes.extend_from_slice(&checkpoint::encode(&self.engine));
+        }
+        bytes
+    }
+
+    /// An engine back at the game saved by [`Self::save_state`].
+    #[wasm_bindgen(js_name = loadState)]
+    pub fn load_state(bytes: &[u8]) -> Result<WasmEngine, JsError> {
+        Self::restore(bytes).map_err(js_error)
+    }
+}
+
+impl WasmEngine {
+    /// Adds `ev` to the history and hands it to JavaScript.
+    fn record(&mut self, ev: &Evaluation) -> Result<EvaluationJs, JsError> {
+        let view = EvaluationView::from(ev);
+        let js = to_js(&view);
+        self.history.push(view);
+        js
+    }
+
+    /// Searches `slice` iterations at a time, awaiting `pause` in between.
+    /// A book, tablebase or cache answer ends it after the first slice.
+    async fn analyze_in_slices<F: Future<Output = ()>>(
+        &mut self,
+        iterations: u32,
+        slice: u32,
+        mut pause: impl FnMut() -> F,
+    ) -> Result<Evaluation, StoctopusError> {
+        let slice = slice.max(1);
+        let mut ev = self.engine.analyze(iterations.min(slice))?;
+        let engine = &mut self.engine;
+        while ev.source == EvalSource::Search && engine.arena.iterations() < iterations {
+            pause().await;
+            let done = engine.arena.iterations();
+            let limits = SearchLimits::iterations((iterations - done).min(slice));
+            let (confidence, best_node) =
+                engine
+                    .arena
+                    .analyze(engine.current_node, limits, &CancellationToken::new())?;
+            ev = engine.evaluation(confidence, Some(best_node), EvalSource::Search)?;
+            if engine.arena.iterations() == done {
+                // The tree is full or the search can't go on.
+                break;
+            }
+        }
+        Ok(ev)
+    }
+
+    fn restore(bytes: &[u8]) -> Result<Self, StoctopusError> {
+        let (magic, rest) = bytes.split_at_checked(4).ok_or(invalid("truncated"))?;
+        if magic != MAGIC {
+            return Err(invalid("not an engine state"));
+        }
+        let (version, rest) = rest.split_at_checked(4).ok_or(invalid("truncated"))?;
+        if version != VERSION.to_le_bytes() {
+            return Err(invalid("unsupported version"));
+        }
+        let (len, rest) = rest.split_at_checked(4).ok_or(invalid("truncated"))?;
+        let len = u32::from_le_bytes(len.try_into().expect("4 bytes")) as usize;
+        let (header, tree) = rest.split_at_checked(len).ok_or(invalid("truncated"))?;
+        let header: StateHeader =
+            serde_json::from_slice(header).map_err(|err| invalid(&err.to_string()))?;
+        if let Some(problem) = header.config.backup.problem() {
+            return Err(invalid(problem));
+        }
+
+        let start = header.start.board()?;
+        let mut board = start;
+        for &[global, local] in &header.moves {
+            if global > 8
+                || local > 8
+                || board.game_over()
+                || board.get_moves() & (1 << (global * 9 + local)) == 0
+            {
+                return Err(invalid("illegal move"));
+            }
+            board = board.unchecked_play(Board::move_from_gl(global, local));
+        }
+        let engine = if tree.is_empty() {
+            Engine::from_board(board, header.config)?
+        } else {
+            let engine = checkpoint::decode(tree)?;
+            if *engine.board() != board {
+                return Err(invalid("the tree is of another position"));
+            }
+            engine
+        };
+        Ok(Self {
+            engine,
+            start,
+            moves: header.moves,
+            history: header.history,
+        })
+    }
+}
+
+#[cfg(test)]
+mod wasm_tests {
+    use std::future::{ready, Future};
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    use crate::wasm::{pool_threads, BoardView, ChildStatsView, EvaluationView, WasmEngine};
+    use crate::{Board, Engine, EvalSource, MCTSConfig, SearchMode};
+
+    #[test]
+    fn test_pool_threads() {
+        assert_eq!(pool_threads(true, 8), 8);
+        assert_eq!(pool_threads(true, 0), 1);
+        assert_eq!(pool_threads(false, 8), 1);
+    }
+
+    #[test]
+    fn test_views() {
+        let board = Board::default().unchecked_play(0x44);
+        let view = BoardView::new(&board);
+        assert_eq!(view.cells[40], 1);
+        assert_eq!(view.cells.iter().filter(|&&c| c != 0).count(), 1);
+        assert_eq!(view.last_move, Some([4, 4]));
+        assert_eq!(view.legal_moves.len(), 8);
+        assert!(view.legal_moves.iter().all(|&[global, _]| global == 4));
+        assert_eq!(view.result, None);
+        let json = serde_json::to_value(&view).unwrap();
+        assert_eq!(json["nextPlayer"], "O");
+        assert_eq!(json["subBoards"][0], serde_json::Value::Null);
+
+        let mut engine = Engine::with_config(MCTSConfig {
+            mode: SearchMode::Deterministic { seed: 1 },
+            ..MCTSConfig::default()
+        });
+        let ev = engine.analyze(100).unwrap();
+        let view = EvaluationView::from(&ev);
+        assert_eq!(view.best_move, Some([ev.pv[0].0, ev.pv[0].1]));
+        assert_eq!(view.iterations, 100);
+        let json = serde_json::to_value(&view).unwrap();
+        assert_eq!(json["source"], "Search");
+        assert!(json["expectedPlies"].is_number());
+
+        let children = engine.child_stats(engine.current_node());
+        let json = serde_json::to_value(ChildStatsView::from(children[0])).unwrap();
+        assert_eq!(
+            json["move"],
+            serde_json::json!([children[0].mve.0, children[0].mve.1])
+        );
+        assert!(json["winRate"].is_number());
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut engine = WasmEngine::new(Some(3));
+        engine.play(4, 4).unwrap();
+        let ev = engine.engine.analyze(200).unwrap();
+        engine.history.push(EvaluationView::from(&ev));
+        engine.play(4, 0).unwrap();
+
+        let bytes = engine.save_state(false);
+        let restored = WasmEngine::restore(&bytes).unwrap();
+        assert_eq!(restored.moves, [[4, 4], [4, 0]]);
+        assert_eq!(restored.history, engine.history);
+        assert_eq!(restored.engine.board(), engine.engine.board());
+        assert_eq!(restored.engine.config(), engine.engine.config());
+        assert_eq!(restored.engine.tree_size(), 1);
+
+        let with_tree = engine.save_state(true);
+        assert!(with_tree.len() > bytes.len());
+        let restored = WasmEngine::restore(&with_tree).unwrap();
+        assert_eq!(restored.engine.board(), engine.engine.board());
+        assert_eq!(restored.engine.tree_size(), engine.engine.tree_size());
+
+        // A tree that doesn't match the moves.
+        let mut other = WasmEngine::new(Some(3));
+        other.play(0, 0).unwrap();
+        let mut mixed = bytes.clone();
+        mixed.extend_from_slice(&crate::checkpoint::encode(&other.engine));
+        assert!(WasmEngine::restore(&mixed).is_err());
+        assert!(WasmEngine::restore(&bytes[..bytes.len() - 1]).is_err());
+        assert!(WasmEngine::restore(b"STCP").is_err());
+    }
+
+    #[test]
+    fn test_analyze_in_slices() {
+        let mut engine = WasmEngine::new(Some(5));
+        let mut pauses = 0;
+        let search = engine.analyze_in_slices(1000, 300, || {
+            pauses += 1;
+            ready(())
+        });
+        let Poll::Ready(ev) = pin!(search).poll(&mut Context::from_waker(Waker::noop())) else {
+            panic!("Ready pauses don't suspend the search");
+        };
+        let ev = ev.unwrap();
+        assert_eq!(pauses, 3);
+        assert_eq!(ev.source, EvalSource::Search);
+        assert_eq!(ev.info.iterations, 1000);
+        assert!(engine.engine.board().get_moves() & (1 << (ev.pv[0].0 * 9 + ev.pv[0].1)) != 0);
+    }
+}
@@ -0,0 +1,108 @@
+//! Zobrist hashing of positions. Two boards hash equal when they have the
+//! same marks, the same side to move, the same sub-board constraint and the
+//! same rules, so transpositions reached through different move orders
+//! collide on purpose.
+
+use crate::game::{Board, Player, Rules, Variant};
+
+const fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+const fn keys<const N: usize>(offset: u64) -> [u64; N] {
+    let mut keys = [0; N];
+    let mut i = 0;
+    while i < N {
+        keys[i] = splitmix64(offset + i as u64);
+        i += 1;
+    }
+    keys
+}
+
+const X_KEYS: [u64; 81] = keys(0);
+const O_KEYS: [u64; 81] = keys(81);
+/// Indexed by the forced sub-board, with index 9 meaning "play anywhere".
+const FORCED_KEYS: [u64; 10] = keys(162);
+const O_TO_MOVE: u64 = splitmix64(172);
+const NINE_BOARD: u64 = splitmix64(173);
+const MISERE: u64 = splitmix64(174);
+const HANDICAP: u64 = splitmix64(175);
+const PIE_RULE: u64 = splitmix64(176);
+
+pub(crate) fn cell_key(player: Player, index: u8) -> u64 {
+    match player {
+        Player::X => X_KEYS[index as usize],
+        Player::O => O_KEYS[index as usize],
+    }
+}
+
+pub(crate) fn side_key(player: Player) -> u64 {
+    match player {
+        Player::X => 0,
+        Player::O => O_TO_MOVE,
+    }
+}
+
+pub(crate) fn forced_key(board: &Board) -> u64 {
+    FORCED_KEYS[board.forced_board().unwrap_or(9) as usize]
+}
+
+/// Zero for the default rules, so their hashes don't depend on this key.
+pub(crate) fn rules_key(rules: Rules) -> u64 {
+    let mut key = match rules.variant {
+        Variant::Ultimate => 0,
+        Variant::NineBoard => NINE_BOARD,
+    };
+    if rules.misere {
+        key ^= MISERE;
+    }
+    if rules.pie_rule {
+        key ^= PIE_RULE;
+    }
+    if let Some(handicap) = rules.handicap {
+        let (low, high) = (handicap.stones as u64, (handicap.stones >> 64) as u64);
+        let extra = (handicap.player as u64) << 8 | handicap.double_moves as u64;
+        key ^= splitmix64(HANDICAP ^ extra) ^ splitmix64(low) ^ splitmix64(!high);
+    }
+    key
+}
+
+impl Board {
+    /// Hash of the position, computed from scratch.
+    pub fn zobrist_hash(&self) -> u64 {
+        let mut hash = side_key(self.next_player) ^ forced_key(self) ^ rules_key(self.rules);
+        for i in 0..81 {
+            if self.x & (1 << i) != 0 {
+                hash ^= cell_key(Player::X, i);
+            } else if self.o & (1 << i) != 0 {
+                hash ^= cell_key(Player::O, i);
+            }
+        }
+        hash
+    }
+}
+
+#[cfg(test)]
+mod zobrist_tests {
+    use crate::game::Board;
+
+    fn play(moves: &[(u8, u8)]) -> Board {
+        moves.iter().fold(Board::default(), |board, m| {
+            board.unchecked_play(Board::move_from_gl(m.0, m.1))
+        })
+    }
+
+    #[test]
+    fn test_transpositions_collide() {
+        let a = play(&[(4, 0), (0, 4), (4, 1), (1, 4)]);
+        let b = play(&[(4, 1), (1, 4), (4, 0), (0, 4)]);
+        assert_eq!(a.zobrist_hash(), b.zobrist_hash());
+
+        let c = play(&[(4, 0), (0, 4), (4, 1)]);
+        assert_ne!(a.zobrist_hash(), c.zobrist_hash());
+        assert_ne!(Board::default().zobrist_hash(), c.zobrist_hash());
+    }
+}